@@ -21,31 +21,33 @@ fn main() {
     host.connect(&Address::new(Ipv4Addr::LOCALHOST, 9001), 10, 0)
         .expect("connect failed");
 
-    let peer_id = loop {
-        let e = host
-            .service(Duration::from_secs(1))
-            .expect("service failed");
+    // Block until ENet reports whether the connection attempt succeeded.
+    let e = host
+        .service(None)
+        .expect("service failed")
+        .expect("blocking service returned no event");
 
-        let e = match e {
-            Some(ev) => ev,
-            _ => continue,
-        };
+    println!("[client] event: {:#?}", e);
 
-        println!("[client] event: {:#?}", e);
-
-        match e.kind {
-            EventKind::Connect => break e.peer_id,
-            EventKind::Disconnect { data } => {
-                println!(
-                    "connection NOT successful, peer: {:?}, reason: {}",
-                    e.peer_id, data
-                );
-                std::process::exit(0);
-            }
-            EventKind::Receive { .. } => {
-                panic!("unexpected Receive-event while waiting for connection")
-            }
-        };
+    let peer_id = match e.kind {
+        EventKind::Connect => e.peer_id,
+        EventKind::Disconnect { data } => {
+            println!(
+                "connection NOT successful, peer: {:?}, reason: {}",
+                e.peer_id, data
+            );
+            std::process::exit(0);
+        }
+        EventKind::ConnectTimeout => {
+            println!(
+                "connection NOT successful, peer: {:?}, timed out",
+                e.peer_id
+            );
+            std::process::exit(0);
+        }
+        EventKind::Receive { .. } | EventKind::RateLimited { .. } => {
+            panic!("unexpected Receive-event while waiting for connection")
+        }
     };
 
     // send a "hello"-like packet
@@ -60,7 +62,7 @@ fn main() {
     host[peer_id].disconnect_later(5);
 
     loop {
-        let e = host.service(Duration::from_secs(1)).unwrap();
+        let e = host.service(Some(Duration::from_secs(1))).unwrap();
         println!("received event: {:#?}", e);
     }
 }
@@ -0,0 +1,114 @@
+//! Measures the cost of receiving packets through `Host::service`, and the heap allocations made
+//! on the way.
+//!
+//! Run with `cargo run --release --example receive_benchmark`. Receive events hand out the packet
+//! ENet received, without copying it or boxing anything. The only allocations happen while the
+//! round trip time history of the peer grows, see `Host::set_latency_history`, and not per event.
+
+extern crate enet;
+
+use enet::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Counts the allocations of this program. ENet itself allocates with `malloc`, so it isn't counted.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const PACKETS: usize = 100_000;
+const BURST: usize = 100;
+
+fn main() {
+    let enet = Enet::new().expect("could not initialize ENet");
+    let create_host = |address: Option<Address>| {
+        enet.create_host::<()>(
+            address.as_ref(),
+            1,
+            ChannelLimit::Maximum,
+            BandwidthLimit::Unlimited,
+            BandwidthLimit::Unlimited,
+        )
+        .expect("could not create host")
+    };
+    let server_address = Address::new(Ipv4Addr::LOCALHOST, 9003);
+    let mut server = create_host(Some(server_address.clone()));
+    let mut client = create_host(None);
+    let peer_id = client
+        .connect(&server_address, 1, 0)
+        .expect("connect failed")
+        .1;
+
+    let timeout = Some(Duration::from_millis(1));
+    let mut connected = false;
+    while !connected {
+        server.service(timeout).expect("service failed");
+        if let Some(Event {
+            kind: EventKind::Connect,
+            ..
+        }) = client.service(timeout).expect("service failed")
+        {
+            connected = true;
+        }
+    }
+
+    let (mut received, mut allocations, mut servicing) = (0, 0, Duration::ZERO);
+    while received < PACKETS {
+        for _ in 0..BURST {
+            let packet = Packet::new(vec![0; 32], PacketMode::UnreliableUnsequenced).unwrap();
+            client[peer_id].send_packet(packet, 0).expect("send failed");
+        }
+        client.flush();
+
+        // Only the receiving side is measured, from servicing the host to dropping the event.
+        let mut idle = 0;
+        while idle < 10 {
+            let before = ALLOCATIONS.load(Ordering::Relaxed);
+            let start = Instant::now();
+            let event = server
+                .service(Some(Duration::ZERO))
+                .expect("service failed");
+            let is_receive = matches!(
+                event,
+                Some(Event {
+                    kind: EventKind::Receive { .. },
+                    ..
+                })
+            );
+            drop(event);
+            servicing += start.elapsed();
+
+            if is_receive {
+                received += 1;
+                allocations += ALLOCATIONS.load(Ordering::Relaxed) - before;
+                idle = 0;
+            } else {
+                idle += 1;
+                std::thread::sleep(Duration::from_micros(100));
+            }
+        }
+    }
+
+    println!(
+        "{} receive events, {:.0} ns per service call on average, {} heap allocations",
+        received,
+        servicing.as_nanos() as f64 / received as f64,
+        allocations
+    );
+}
@@ -22,12 +22,14 @@ fn main() {
     loop {
         // Wait 500 ms for any events.
         if let Some(Event { kind, .. }) = host
-            .service(Duration::from_millis(500))
+            .service(Some(Duration::from_millis(500)))
             .expect("service failed")
         {
             match kind {
                 EventKind::Connect => println!("new connection!"),
                 EventKind::Disconnect { .. } => println!("disconnect!"),
+                EventKind::ConnectTimeout => unreachable!("the server never connects itself"),
+                EventKind::RateLimited { .. } => unreachable!("the server sets no rate limits"),
                 EventKind::Receive { channel_id, packet } => println!(
                     "got packet on channel {}, content: '{}'",
                     channel_id,
@@ -1,4 +1,5 @@
 use std::ffi::CStr;
+use std::fmt::{self, Display, Formatter};
 use std::net::{Ipv4Addr, SocketAddrV4};
 
 use crate::Error;
@@ -56,15 +57,7 @@ impl Address {
     }
 
     pub(crate) fn from_enet_address(addr: &ENetAddress) -> Address {
-        Address::new(
-            Ipv4Addr::new(
-                (addr.host >> 24) as u8,
-                (addr.host >> 16) as u8,
-                (addr.host >> 8) as u8,
-                (addr.host >> 0) as u8,
-            ),
-            addr.port,
-        )
+        Address::new(Ipv4Addr::from(u32::from_be(addr.host)), addr.port)
     }
 }
 
@@ -74,6 +67,13 @@ impl From<SocketAddrV4> for Address {
     }
 }
 
+/// Formats the address as `ip:port`, e.g. `203.0.113.5:7777`.
+impl Display for Address {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        Display::fmt(&self.addr, f)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::Address;
@@ -88,6 +88,18 @@ mod tests {
         assert_eq!(addr.addr.port(), 0);
     }
 
+    #[test]
+    fn test_enet_address_roundtrip() {
+        let addr = Address::new(Ipv4Addr::new(203, 0, 113, 5), 7777);
+        assert_eq!(Address::from_enet_address(&addr.to_enet_address()), addr);
+    }
+
+    #[test]
+    fn test_display() {
+        let addr = Address::new(Ipv4Addr::new(203, 0, 113, 5), 7777);
+        assert_eq!(addr.to_string(), "203.0.113.5:7777");
+    }
+
     #[test]
     fn test_from_invalid_hostname() {
         assert!(Address::from_hostname(&CString::new("").unwrap(), 0).is_err());
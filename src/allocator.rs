@@ -0,0 +1,293 @@
+use std::alloc::{self, Layout};
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use enet_sys::ENetCallbacks;
+
+/// Memory allocation functions for ENet to use instead of the C library's `malloc`/`free`.
+///
+/// Install an implementation with [Enet::new_with_allocator](struct.Enet.html#method.new_with_allocator).
+/// ENet calls these from whichever thread is currently using it, hence the `Send + Sync` bound.
+pub trait Allocator: Send + Sync + 'static {
+    /// Allocates `size` bytes of memory suitably aligned for any C type, returning null on failure.
+    fn malloc(&self, size: usize) -> *mut c_void;
+
+    /// Frees memory previously returned by `malloc`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` is either null or was returned by `malloc` of this allocator, and is not used afterwards.
+    unsafe fn free(&self, ptr: *mut c_void);
+
+    /// Called by ENet when `malloc` returned null.
+    ///
+    /// The default implementation aborts the process, just like ENet does by default.
+    fn no_memory(&self) {
+        std::process::abort();
+    }
+}
+
+/// The allocator in use. ENet can only be initialized once, so this is set at most once as well.
+static ALLOCATOR: OnceLock<Box<dyn Allocator>> = OnceLock::new();
+
+/// Stores `allocator` and returns the callbacks that forward to it, for `enet_initialize_with_callbacks`.
+pub(crate) fn install(allocator: Box<dyn Allocator>) -> ENetCallbacks {
+    if ALLOCATOR.set(allocator).is_err() {
+        panic!("enet-rs internal error; allocator installed twice");
+    }
+
+    ENetCallbacks {
+        malloc: Some(malloc_callback),
+        free: Some(free_callback),
+        no_memory: Some(no_memory_callback),
+    }
+}
+
+fn allocator() -> &'static dyn Allocator {
+    ALLOCATOR
+        .get()
+        .expect("enet-rs internal error; allocator callback called without an allocator")
+        .as_ref()
+}
+
+unsafe extern "C" fn malloc_callback(size: usize) -> *mut c_void {
+    allocator().malloc(size)
+}
+
+unsafe extern "C" fn free_callback(ptr: *mut c_void) {
+    allocator().free(ptr)
+}
+
+unsafe extern "C" fn no_memory_callback() {
+    allocator().no_memory()
+}
+
+/// Bytes in front of every block of a `PoolAllocator`, holding its size and its size class.
+const HEADER: usize = 16;
+
+/// The block sizes of a `PoolAllocator`, including the header.
+///
+/// Acknowledgements and packet structs fit the smallest class, outgoing commands the next one,
+/// and the data of packets up to the usual MTU the largest.
+const CLASSES: [usize; 6] = [64, 128, 256, 512, 1024, 2048];
+
+/// Marks blocks too large for any class, which go straight to the system allocator.
+const UNPOOLED: usize = usize::MAX;
+
+/// The usage of a `PoolAllocator`, see `PoolAllocator::stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct PoolStats {
+    /// The calls to `malloc`.
+    pub allocations: usize,
+    /// The allocations served from a pool instead of the system allocator.
+    pub reused: usize,
+    /// The allocations too large for any pool.
+    pub unpooled: usize,
+    /// The bytes currently allocated by ENet, including the unused rest of pooled blocks.
+    pub bytes_in_use: usize,
+    /// The bytes kept in the pools for later allocations.
+    pub bytes_pooled: usize,
+}
+
+struct Pools {
+    /// The free blocks of each class, as addresses so they can be shared between threads.
+    free: [Mutex<Vec<usize>>; CLASSES.len()],
+    max_pooled: usize,
+    allocations: AtomicUsize,
+    reused: AtomicUsize,
+    unpooled: AtomicUsize,
+    bytes_in_use: AtomicUsize,
+}
+
+/// An `Allocator` that keeps the blocks ENet frees, and hands them out again instead of asking the
+/// system allocator.
+///
+/// ENet allocates and frees small structs for every packet, command and acknowledgement. On busy
+/// servers that is a large share of all `malloc` calls, and fragments the heap. A `PoolAllocator`
+/// rounds allocations up to a few size classes, and keeps up to `max_pooled` free blocks of each
+/// class. Allocations larger than 2 KiB go to the system allocator.
+///
+/// Clones share their pools, so keep one to read `PoolAllocator::stats`:
+///
+/// ```
+/// use enet::{Enet, PoolAllocator};
+///
+/// let pool = PoolAllocator::new(1024);
+/// let enet = Enet::new_with_allocator(pool.clone()).unwrap();
+/// println!("{:?}", pool.stats());
+/// ```
+#[derive(Clone)]
+pub struct PoolAllocator {
+    pools: Arc<Pools>,
+}
+
+impl PoolAllocator {
+    /// Creates a pool allocator that keeps at most `max_pooled` free blocks of each size class.
+    pub fn new(max_pooled: usize) -> PoolAllocator {
+        PoolAllocator {
+            pools: Arc::new(Pools {
+                free: Default::default(),
+                max_pooled,
+                allocations: AtomicUsize::new(0),
+                reused: AtomicUsize::new(0),
+                unpooled: AtomicUsize::new(0),
+                bytes_in_use: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Returns the usage of the pools so far.
+    pub fn stats(&self) -> PoolStats {
+        let pools = &*self.pools;
+        let bytes_pooled = pools
+            .free
+            .iter()
+            .zip(CLASSES.iter())
+            .map(|(free, size)| free.lock().unwrap_or_else(|e| e.into_inner()).len() * size)
+            .sum();
+        PoolStats {
+            allocations: pools.allocations.load(Ordering::Relaxed),
+            reused: pools.reused.load(Ordering::Relaxed),
+            unpooled: pools.unpooled.load(Ordering::Relaxed),
+            bytes_in_use: pools.bytes_in_use.load(Ordering::Relaxed),
+            bytes_pooled,
+        }
+    }
+
+    fn layout(size: usize) -> Option<Layout> {
+        Layout::from_size_align(size, HEADER).ok()
+    }
+}
+
+impl Allocator for PoolAllocator {
+    fn malloc(&self, size: usize) -> *mut c_void {
+        let pools = &*self.pools;
+        pools.allocations.fetch_add(1, Ordering::Relaxed);
+
+        let total = match size.checked_add(HEADER) {
+            Some(total) => total,
+            None => return std::ptr::null_mut(),
+        };
+        let class = CLASSES.iter().position(|&class_size| total <= class_size);
+        let (tag, block_size) = match class {
+            Some(class) => (class, CLASSES[class]),
+            None => {
+                pools.unpooled.fetch_add(1, Ordering::Relaxed);
+                (UNPOOLED, total)
+            }
+        };
+
+        let reused = class.and_then(|class| {
+            pools.free[class]
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .pop()
+        });
+        let block = match reused {
+            Some(address) => {
+                pools.reused.fetch_add(1, Ordering::Relaxed);
+                address as *mut u8
+            }
+            None => match PoolAllocator::layout(block_size) {
+                Some(layout) => unsafe { alloc::alloc(layout) },
+                None => return std::ptr::null_mut(),
+            },
+        };
+        if block.is_null() {
+            return std::ptr::null_mut();
+        }
+
+        pools.bytes_in_use.fetch_add(block_size, Ordering::Relaxed);
+        unsafe {
+            (block as *mut usize).write(block_size);
+            (block as *mut usize).add(1).write(tag);
+            block.add(HEADER) as *mut c_void
+        }
+    }
+
+    unsafe fn free(&self, ptr: *mut c_void) {
+        if ptr.is_null() {
+            return;
+        }
+        let pools = &*self.pools;
+        let block = (ptr as *mut u8).sub(HEADER);
+        let tag = (block as *mut usize).add(1).read();
+
+        if tag == UNPOOLED {
+            let total = (block as *mut usize).read();
+            pools.bytes_in_use.fetch_sub(total, Ordering::Relaxed);
+            if let Some(layout) = PoolAllocator::layout(total) {
+                alloc::dealloc(block, layout);
+            }
+            return;
+        }
+
+        pools
+            .bytes_in_use
+            .fetch_sub(CLASSES[tag], Ordering::Relaxed);
+        let mut free = pools.free[tag].lock().unwrap_or_else(|e| e.into_inner());
+        if free.len() < pools.max_pooled {
+            free.push(block as usize);
+        } else if let Some(layout) = PoolAllocator::layout(CLASSES[tag]) {
+            alloc::dealloc(block, layout);
+        }
+    }
+}
+
+impl Drop for Pools {
+    fn drop(&mut self) {
+        for (free, &size) in self.free.iter_mut().zip(CLASSES.iter()) {
+            let free = free.get_mut().unwrap_or_else(|e| e.into_inner());
+            if let Some(layout) = PoolAllocator::layout(size) {
+                for address in free.drain(..) {
+                    unsafe { alloc::dealloc(address as *mut u8, layout) };
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Allocator, PoolAllocator, PoolStats};
+
+    #[test]
+    fn test_pool_allocator() {
+        let pool = PoolAllocator::new(1);
+        let small = pool.malloc(40);
+        let large = pool.malloc(10_000);
+        assert_eq!(small as usize % 16, 0);
+        unsafe {
+            std::ptr::write_bytes(small as *mut u8, 0xab, 40);
+            std::ptr::write_bytes(large as *mut u8, 0xcd, 10_000);
+        }
+        assert_eq!(pool.stats().bytes_in_use, 64 + 10_016);
+
+        unsafe {
+            pool.free(small);
+            pool.free(large);
+            pool.free(std::ptr::null_mut());
+        }
+        assert_eq!(pool.stats().bytes_pooled, 64);
+
+        // The freed block is handed out again, a second one of the class isn't kept.
+        let again = pool.malloc(48);
+        assert_eq!(again, small);
+        let other = pool.malloc(20);
+        unsafe {
+            pool.free(again);
+            pool.free(other);
+        }
+        assert_eq!(
+            pool.stats(),
+            PoolStats {
+                allocations: 4,
+                reused: 1,
+                unpooled: 1,
+                bytes_in_use: 0,
+                bytes_pooled: 64,
+            }
+        );
+    }
+}
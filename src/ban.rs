@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::logging::{self, log_debug};
+use crate::{Address, Datagram, Intercept, InterceptAction, PeerID};
+
+/// What `Host::ban` bans: an IP address, or the address of a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BanTarget {
+    /// Bans the IP address, on all ports.
+    Address(Ipv4Addr),
+    /// Bans the IP address of the peer.
+    Peer(PeerID),
+}
+
+impl From<Ipv4Addr> for BanTarget {
+    fn from(address: Ipv4Addr) -> BanTarget {
+        BanTarget::Address(address)
+    }
+}
+
+impl From<Address> for BanTarget {
+    fn from(address: Address) -> BanTarget {
+        BanTarget::Address(*address.ip())
+    }
+}
+
+impl From<PeerID> for BanTarget {
+    fn from(peer_id: PeerID) -> BanTarget {
+        BanTarget::Peer(peer_id)
+    }
+}
+
+/// An entry of a `Host`'s ban list.
+///
+/// The expiry is wall clock time, so bans can be saved, e.g. with `Host::bans`, and restored
+/// after a restart with `Host::add_ban`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Ban {
+    /// The banned IP address.
+    pub address: Ipv4Addr,
+    /// When the ban expires, `None` if it never does.
+    pub until: Option<SystemTime>,
+    /// The reason, sent as the disconnect data to banned peers.
+    pub reason: u32,
+}
+
+impl Ban {
+    pub(crate) fn is_active(&self, now: SystemTime) -> bool {
+        self.until.map_or(true, |until| until > now)
+    }
+}
+
+/// The bans of a `Host`, shared with the intercept that enforces them.
+pub(crate) type BanList = Arc<Mutex<HashMap<Ipv4Addr, Ban>>>;
+
+/// Drops every datagram from a banned address, so banned peers can't connect.
+pub(crate) struct BanIntercept(pub(crate) BanList);
+
+impl Intercept for BanIntercept {
+    fn intercept(&mut self, datagram: &mut Datagram) -> InterceptAction {
+        let address = *datagram.address().ip();
+        let mut bans = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        match bans
+            .get(&address)
+            .map(|ban| ban.is_active(SystemTime::now()))
+        {
+            Some(true) => {
+                log_debug!(
+                    target: logging::DROP,
+                    "dropped a datagram from {}, which is banned",
+                    address
+                );
+                InterceptAction::Drop
+            }
+            Some(false) => {
+                bans.remove(&address);
+                InterceptAction::Continue
+            }
+            None => InterceptAction::Continue,
+        }
+    }
+}
+
+/// Returns the active bans, removing the expired ones.
+pub(crate) fn active_bans(list: &BanList) -> Vec<Ban> {
+    let now = SystemTime::now();
+    let mut bans = list.lock().unwrap_or_else(|e| e.into_inner());
+    bans.retain(|_, ban| ban.is_active(now));
+    bans.values().cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{create_host, pump_until};
+    use crate::{Address, EventKind, PeerState};
+
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    #[test]
+    fn test_ban() {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12377);
+        let mut server = create_host(Some(&server_address), 2);
+        let mut client = create_host(None, 1);
+        client.connect(&server_address, 1, 0).unwrap();
+
+        let mut disconnect = None;
+        pump_until(&mut [&mut server, &mut client], |index, host, event| {
+            match (index, event.kind) {
+                (0, EventKind::Connect) => assert_eq!(
+                    host.ban(event.peer_id, Duration::from_millis(200), 7),
+                    Some(Ipv4Addr::LOCALHOST)
+                ),
+                (1, EventKind::Disconnect { data }) => disconnect = Some(data),
+                _ => (),
+            }
+            disconnect.is_some()
+        });
+        assert_eq!(disconnect, Some(7));
+        let bans = server.bans();
+        assert_eq!(bans.len(), 1);
+        assert_eq!((bans[0].address, bans[0].reason), (Ipv4Addr::LOCALHOST, 7));
+
+        // Banned addresses can't reconnect.
+        let server_peer = client.connect(&server_address, 1, 0).unwrap().1;
+        let timeout = Some(Duration::from_millis(2));
+        for _ in 0..50 {
+            server.service(timeout).unwrap();
+            client.service(timeout).unwrap();
+        }
+        assert_eq!(client[server_peer].state(), PeerState::Connecting);
+
+        // Until the ban expires.
+        std::thread::sleep(Duration::from_millis(200));
+        pump_until(&mut [&mut server, &mut client], |index, _, event| {
+            index == 1 && matches!(event.kind, EventKind::Connect)
+        });
+        assert!(server.bans().is_empty());
+        assert_eq!(server.unban(&Ipv4Addr::LOCALHOST), None);
+    }
+    #[test]
+    fn test_permanent_ban() {
+        let mut host = create_host(None, 1);
+        let address = Ipv4Addr::new(192, 0, 2, 1);
+        assert_eq!(host.ban(address, Duration::MAX, 1), Some(address));
+        assert_eq!(host.bans()[0].until, None);
+        assert_eq!(host.unban(&address).map(|ban| ban.reason), Some(1));
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::time::Instant;
+
+use crate::{Event, Host, Packet, PacketMode, PeerID, PeerState};
+
+/// How much unused bandwidth a peer may save up, in seconds of its bandwidth.
+const MAX_BURST_SECONDS: f64 = 0.1;
+
+/// How far a `BroadcastScheduler` got with the packets of one peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct BroadcastProgress {
+    /// The bytes handed to ENet for the peer so far.
+    pub sent_bytes: u64,
+    /// The bytes still waiting in the scheduler.
+    pub queued_bytes: u64,
+    /// The packets still waiting in the scheduler.
+    pub queued_packets: usize,
+}
+
+#[derive(Debug)]
+struct PeerQueue {
+    packets: VecDeque<Rc<[u8]>>,
+    progress: BroadcastProgress,
+    /// The bytes the peer may be sent now, negative after a packet larger than the allowance.
+    allowance: f64,
+    refilled: Instant,
+}
+
+/// Paces broadcasts to every peer at the pace of its own connection.
+///
+/// `Host::broadcast` queues a packet for all peers at once, so a burst of large packets fills the
+/// queues of slow peers and competes with everything else sent to them. The scheduler instead
+/// keeps a queue per peer, and every `BroadcastScheduler::pump` hands each peer only as much as
+/// its `Peer::estimated_bandwidth` allows since the last pump. Slow peers then fall behind on
+/// their own, without delaying the others.
+#[derive(Debug)]
+pub struct BroadcastScheduler {
+    channel_id: u8,
+    mode: PacketMode,
+    queues: HashMap<PeerID, PeerQueue>,
+    next_first: usize,
+}
+
+impl BroadcastScheduler {
+    /// Creates a scheduler that sends on `channel_id` with `mode`.
+    pub fn new(channel_id: u8, mode: PacketMode) -> BroadcastScheduler {
+        BroadcastScheduler {
+            channel_id,
+            mode,
+            queues: HashMap::new(),
+            next_first: 0,
+        }
+    }
+
+    /// Queues `data` for every connected peer of `host`.
+    ///
+    /// The data is shared between the queues, not copied per peer.
+    pub fn broadcast<T>(&mut self, host: &Host<T>, data: &[u8]) {
+        let data: Rc<[u8]> = data.into();
+        let peer_ids: Vec<_> = host
+            .peer_ids()
+            .filter(|&peer_id| host[peer_id].state() == PeerState::Connected)
+            .collect();
+        for peer_id in peer_ids {
+            self.enqueue(peer_id, data.clone());
+        }
+    }
+
+    /// Queues `data` for `peer_id` only.
+    pub fn send_to(&mut self, peer_id: PeerID, data: &[u8]) {
+        self.enqueue(peer_id, data.into());
+    }
+
+    fn enqueue(&mut self, peer_id: PeerID, data: Rc<[u8]>) {
+        let queue = self.queues.entry(peer_id).or_insert_with(|| PeerQueue {
+            packets: VecDeque::new(),
+            progress: BroadcastProgress::default(),
+            allowance: 0.0,
+            refilled: Instant::now(),
+        });
+        queue.progress.queued_bytes += data.len() as u64;
+        queue.progress.queued_packets += 1;
+        queue.packets.push_back(data);
+    }
+
+    /// Returns how far the scheduler got with the packets of `peer_id`.
+    pub fn progress(&self, peer_id: PeerID) -> Option<BroadcastProgress> {
+        self.queues.get(&peer_id).map(|queue| queue.progress)
+    }
+
+    /// Returns the number of packets waiting for any peer.
+    pub fn pending(&self) -> usize {
+        self.queues
+            .values()
+            .map(|queue| queue.progress.queued_packets)
+            .sum()
+    }
+
+    /// Hands every peer the queued packets its bandwidth allows, call this before every
+    /// `Host::service`.
+    ///
+    /// Returns the number of packets handed to ENet. Packets for peers that can't be sent to,
+    /// e.g. because they disconnect, are dropped.
+    pub fn pump<T>(&mut self, host: &mut Host<T>) -> usize {
+        let now = Instant::now();
+        let mut peer_ids: Vec<_> = self
+            .queues
+            .iter()
+            .filter(|(_, queue)| !queue.packets.is_empty())
+            .map(|(&peer_id, _)| peer_id)
+            .collect();
+        // Take turns at going first, so no peer is always served last.
+        peer_ids.sort_by_key(|peer_id| peer_id.index);
+        if !peer_ids.is_empty() {
+            let first = self.next_first % peer_ids.len();
+            peer_ids.rotate_left(first);
+            self.next_first = self.next_first.wrapping_add(1);
+        }
+
+        let (channel_id, mode) = (self.channel_id, self.mode);
+        let mut sent = 0;
+        for peer_id in peer_ids {
+            let (queue, peer) = match (self.queues.get_mut(&peer_id), host.peer_mut(peer_id)) {
+                (Some(queue), Some(peer)) => (queue, peer),
+                _ => continue,
+            };
+
+            let bandwidth = f64::from(peer.estimated_bandwidth());
+            let elapsed = now.duration_since(queue.refilled).as_secs_f64();
+            queue.refilled = now;
+            queue.allowance = (queue.allowance + bandwidth * elapsed)
+                .min((bandwidth * MAX_BURST_SECONDS).max(0.0));
+
+            while queue.allowance >= 0.0 {
+                let data = match queue.packets.pop_front() {
+                    Some(data) => data,
+                    None => break,
+                };
+                queue.allowance -= data.len() as f64;
+                queue.progress.queued_bytes -= data.len() as u64;
+                queue.progress.queued_packets -= 1;
+
+                let sent_packet = Packet::new(data.to_vec(), mode)
+                    .is_ok_and(|packet| peer.send_packet(packet, channel_id).is_ok());
+                if sent_packet {
+                    queue.progress.sent_bytes += data.len() as u64;
+                    sent += 1;
+                }
+            }
+        }
+        sent
+    }
+
+    /// Processes an event returned by `Host::service`, discarding the queue of peers that
+    /// disconnect.
+    ///
+    /// Always returns `false`, the event still needs handling.
+    pub fn handle_event(&mut self, event: &Event) -> bool {
+        if event.kind.is_disconnect() {
+            self.queues.remove(&event.peer_id);
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BroadcastScheduler;
+    use crate::tests::{pump_until, ENET};
+    use crate::{Address, BandwidthLimit, ChannelLimit, EventKind, PacketMode};
+
+    use std::net::Ipv4Addr;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_fair_broadcast() {
+        let create_host = |address: Option<Address>, incoming| {
+            ENET.create_host::<()>(
+                address.as_ref(),
+                2,
+                ChannelLimit::Maximum,
+                incoming,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap()
+        };
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12385);
+        let mut server = create_host(Some(server_address.clone()), BandwidthLimit::Unlimited);
+        let mut fast = create_host(None, BandwidthLimit::Unlimited);
+        let mut slow = create_host(None, BandwidthLimit::Limited(2000));
+        fast.connect(&server_address, 1, 0).unwrap();
+        slow.connect(&server_address, 1, 0).unwrap();
+
+        let mut peers = Vec::new();
+        pump_until(
+            &mut [&mut server, &mut fast, &mut slow],
+            |index, _, event| {
+                if let (0, EventKind::Connect) = (index, &event.kind) {
+                    peers.push(event.peer_id);
+                }
+                peers.len() == 2
+            },
+        );
+        let slow_peer = peers
+            .iter()
+            .cloned()
+            .find(|&peer_id| server[peer_id].estimated_bandwidth() <= 2000)
+            .unwrap();
+        let fast_peer = peers.into_iter().find(|&peer| peer != slow_peer).unwrap();
+
+        let mut scheduler = BroadcastScheduler::new(0, PacketMode::ReliableSequenced);
+        for _ in 0..20 {
+            scheduler.broadcast(&server, &[0; 1000]);
+        }
+        assert_eq!(scheduler.pending(), 40);
+
+        let timeout = Some(Duration::from_millis(2));
+        let mut fast_received = 0;
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(500) && fast_received < 20 {
+            scheduler.pump(&mut server);
+            server.service(timeout).unwrap();
+            if let Some(event) = fast.service(timeout).unwrap() {
+                if let EventKind::Receive { .. } = event.kind {
+                    fast_received += 1;
+                }
+            }
+            slow.service(timeout).unwrap();
+        }
+
+        assert_eq!(fast_received, 20);
+        assert_eq!(scheduler.progress(fast_peer).unwrap().queued_packets, 0);
+        let slow_progress = scheduler.progress(slow_peer).unwrap();
+        assert!(slow_progress.queued_packets > 10);
+        assert_eq!(
+            slow_progress.sent_bytes + slow_progress.queued_bytes,
+            20 * 1000
+        );
+    }
+}
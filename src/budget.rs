@@ -0,0 +1,163 @@
+use std::time::{Duration, Instant};
+
+/// How long a budget epoch lasts, the same as ENet's bandwidth throttle epoch.
+const EPOCH: Duration = Duration::from_secs(1);
+
+/// The state of the budget of one channel of a peer, see `Peer::budget_usage`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BudgetUsage {
+    /// The bytes the channel may send per epoch of one second.
+    pub bytes_per_second: u32,
+    /// The bytes sent in the current epoch.
+    pub used: u32,
+    /// The bytes the channel tried to send in the last complete epoch, as a share of its budget.
+    ///
+    /// Above 1, packets were rejected: the subsystem using the channel should send less.
+    pub pressure: f32,
+    /// The packets rejected because they exceeded the budget, in total.
+    pub rejected_packets: u64,
+}
+
+/// The budget of one channel of a peer, replenished at the start of every epoch.
+#[derive(Debug)]
+pub(crate) struct Budget {
+    bytes_per_second: u32,
+    epoch_start: Instant,
+    used: u32,
+    /// The bytes sent or rejected in the current epoch.
+    demanded: u64,
+    pressure: f32,
+    rejected_packets: u64,
+}
+
+impl Budget {
+    pub(crate) fn new(bytes_per_second: u32, now: Instant) -> Budget {
+        Budget {
+            bytes_per_second,
+            epoch_start: now,
+            used: 0,
+            demanded: 0,
+            pressure: 0.0,
+            rejected_packets: 0,
+        }
+    }
+
+    pub(crate) fn set_bytes_per_second(&mut self, bytes_per_second: u32) {
+        self.bytes_per_second = bytes_per_second;
+    }
+
+    fn advance(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.epoch_start);
+        if elapsed < EPOCH {
+            return;
+        }
+
+        // After an idle epoch, the last complete one had no demand at all.
+        self.pressure = if elapsed < EPOCH * 2 {
+            self.demanded as f32 / self.bytes_per_second.max(1) as f32
+        } else {
+            0.0
+        };
+        self.epoch_start += EPOCH * (elapsed.as_secs() as u32);
+        self.used = 0;
+        self.demanded = 0;
+    }
+
+    /// Returns whether a packet of `len` bytes fits the budget, and takes it from it if so.
+    pub(crate) fn admit(&mut self, now: Instant, len: usize) -> bool {
+        self.advance(now);
+        self.demanded += len as u64;
+
+        let fits = u64::from(self.used) + len as u64 <= u64::from(self.bytes_per_second);
+        if fits {
+            self.used += len as u32;
+        } else {
+            self.rejected_packets += 1;
+        }
+        fits
+    }
+
+    /// Gives back the `len` bytes admitted for a packet that ENet failed to queue.
+    pub(crate) fn refund(&mut self, len: usize) {
+        self.used = self.used.saturating_sub(len as u32);
+        self.demanded = self.demanded.saturating_sub(len as u64);
+    }
+
+    pub(crate) fn usage(&mut self, now: Instant) -> BudgetUsage {
+        self.advance(now);
+        BudgetUsage {
+            bytes_per_second: self.bytes_per_second,
+            used: self.used,
+            pressure: self.pressure,
+            rejected_packets: self.rejected_packets,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Budget;
+    use crate::tests::{create_host, pump_until};
+    use crate::{Address, EventKind, Packet, PacketMode, SendError};
+
+    use std::net::Ipv4Addr;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_budget_epochs() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut budget = Budget::new(1000, start);
+
+        assert!(budget.admit(at(0), 600));
+        assert!(!budget.admit(at(100), 600));
+        assert!(budget.admit(at(200), 400));
+        assert_eq!(budget.usage(at(300)).used, 1000);
+
+        let usage = budget.usage(at(1000));
+        assert_eq!(usage.used, 0);
+        assert_eq!(usage.pressure, 1.6);
+        assert_eq!(usage.rejected_packets, 1);
+        assert!(budget.admit(at(1100), 1000));
+
+        assert_eq!(budget.usage(at(5000)).pressure, 0.0);
+    }
+
+    #[test]
+    fn test_budget_refund() {
+        let now = Instant::now();
+        let mut budget = Budget::new(1000, now);
+        assert!(budget.admit(now, 800));
+        budget.refund(800);
+        assert!(budget.admit(now, 1000));
+        assert_eq!(budget.usage(now + Duration::from_secs(1)).pressure, 1.0);
+    }
+
+    #[test]
+    fn test_channel_budgets() {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12386);
+        let mut server = create_host(Some(&server_address), 1);
+        let mut client = create_host(None, 1);
+        client.set_channel_budget(0, Some(1500));
+        let peer_id = client.connect(&server_address, 2, 0).unwrap().1;
+
+        pump_until(&mut [&mut server, &mut client], |index, _, event| {
+            index == 1 && matches!(event.kind, EventKind::Connect)
+        });
+
+        let peer = &mut client[peer_id];
+        let packet = || Packet::new(vec![0; 1000], PacketMode::ReliableSequenced).unwrap();
+        peer.send_packet(packet(), 0).unwrap();
+        match peer.send_packet(packet(), 0) {
+            Err(SendError::OverBudget(0)) => (),
+            result => panic!("unexpected result {:?}", result),
+        }
+        // Channel 1 has no budget.
+        peer.send_packet(packet(), 1).unwrap();
+        peer.send_packet(packet(), 1).unwrap();
+
+        let usage = peer.budget_usage(0).unwrap();
+        assert_eq!((usage.used, usage.rejected_packets), (1000, 1));
+        assert!(peer.budget_usage(1).is_none());
+    }
+}
@@ -0,0 +1,295 @@
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use enet_sys::ENET_PROTOCOL_MAXIMUM_MTU;
+
+use crate::Transport;
+
+/// Link type of captures that start with the IP header, without link layer.
+const LINKTYPE_RAW: u32 = 101;
+const IPPROTO_UDP: u8 = 17;
+const UDP_HEADER_LEN: usize = 8;
+
+/// Writes datagrams to a capture file in the pcap format, e.g. to analyze them in Wireshark.
+///
+/// Each datagram is written as an UDP packet between its source and destination, so the
+/// direction of the traffic shows in the addresses.
+#[derive(Debug)]
+pub struct PcapWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Creates a writer, writing the file header to `writer` right away.
+    pub fn new(mut writer: W) -> io::Result<PcapWriter<W>> {
+        writer.write_all(&0xa1b2_c3d4u32.to_le_bytes())?;
+        writer.write_all(&2u16.to_le_bytes())?;
+        writer.write_all(&4u16.to_le_bytes())?;
+        // Timestamps are in UTC, and their accuracy is unknown.
+        writer.write_all(&[0; 8])?;
+        writer.write_all(&(ENET_PROTOCOL_MAXIMUM_MTU + 48).to_le_bytes())?;
+        writer.write_all(&LINKTYPE_RAW.to_le_bytes())?;
+        Ok(PcapWriter { writer })
+    }
+
+    /// Writes one datagram from `source` to `destination`, received or sent at `time`.
+    ///
+    /// Datagrams between IPv4 and IPv6 addresses can't be represented, and are skipped.
+    pub fn write_datagram(
+        &mut self,
+        time: SystemTime,
+        source: SocketAddr,
+        destination: SocketAddr,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let mut packet = match (source.ip(), destination.ip()) {
+            (IpAddr::V4(source), IpAddr::V4(destination)) => {
+                let total_len = 20 + UDP_HEADER_LEN + data.len();
+                let mut header = vec![0x45, 0];
+                header.extend_from_slice(&(total_len as u16).to_be_bytes());
+                header.extend_from_slice(&[0, 0, 0x40, 0, 64, IPPROTO_UDP, 0, 0]);
+                header.extend_from_slice(&source.octets());
+                header.extend_from_slice(&destination.octets());
+                let checksum = ipv4_checksum(&header);
+                header[10..12].copy_from_slice(&checksum.to_be_bytes());
+                header
+            }
+            (IpAddr::V6(source), IpAddr::V6(destination)) => {
+                let mut header = vec![0x60, 0, 0, 0];
+                header.extend_from_slice(&((UDP_HEADER_LEN + data.len()) as u16).to_be_bytes());
+                header.extend_from_slice(&[IPPROTO_UDP, 64]);
+                header.extend_from_slice(&source.octets());
+                header.extend_from_slice(&destination.octets());
+                header
+            }
+            _ => return Ok(()),
+        };
+
+        packet.extend_from_slice(&source.port().to_be_bytes());
+        packet.extend_from_slice(&destination.port().to_be_bytes());
+        packet.extend_from_slice(&((UDP_HEADER_LEN + data.len()) as u16).to_be_bytes());
+        // A zero UDP checksum means none was computed, which IPv4 allows. Wireshark doesn't
+        // validate UDP checksums by default, so this is fine for IPv6 captures as well.
+        packet.extend_from_slice(&[0, 0]);
+        packet.extend_from_slice(data);
+
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        self.writer
+            .write_all(&(since_epoch.as_secs() as u32).to_le_bytes())?;
+        self.writer
+            .write_all(&since_epoch.subsec_micros().to_le_bytes())?;
+        self.writer
+            .write_all(&(packet.len() as u32).to_le_bytes())?;
+        self.writer
+            .write_all(&(packet.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&packet)
+    }
+
+    /// Returns the underlying writer, e.g. to flush it.
+    pub fn writer_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let sum = header
+        .chunks(2)
+        .map(|word| u32::from(u16::from_be_bytes([word[0], word[1]])))
+        .sum::<u32>();
+    let folded = (sum & 0xffff) + (sum >> 16);
+    !((folded & 0xffff) + (folded >> 16)) as u16
+}
+
+/// Captures all datagrams a `Transport` sends and receives with a `PcapWriter`.
+///
+/// To capture the traffic of a `Host`, wrap its socket and create the host with
+/// `Enet::create_host_with_transport`:
+///
+/// ```no_run
+/// # use enet::*;
+/// # use std::fs::File;
+/// # use std::io::BufWriter;
+/// # use std::net::UdpSocket;
+/// # fn create(enet: &Enet) -> Result<(), Box<dyn std::error::Error>> {
+/// let socket = UdpSocket::bind("0.0.0.0:9001")?;
+/// socket.set_nonblocking(true)?;
+/// let capture = PcapWriter::new(BufWriter::new(File::create("session.pcap")?))?;
+/// let transport = CaptureTransport::new(socket, capture)?;
+///
+/// let host = enet.create_host_with_transport::<(), _>(
+///     transport,
+///     32,
+///     ChannelLimit::Maximum,
+///     BandwidthLimit::Unlimited,
+///     BandwidthLimit::Unlimited,
+/// );
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Errors writing the capture don't affect the traffic. The first one stops the capture, and is
+/// kept for `capture_error`.
+#[derive(Debug)]
+pub struct CaptureTransport<Tr, W: Write> {
+    transport: Tr,
+    local_addr: SocketAddr,
+    capture: PcapWriter<W>,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> CaptureTransport<UdpSocket, W> {
+    /// Captures the traffic of `socket`, which is recorded with its local address.
+    pub fn new(
+        socket: UdpSocket,
+        capture: PcapWriter<W>,
+    ) -> io::Result<CaptureTransport<UdpSocket, W>> {
+        let local_addr = socket.local_addr()?;
+        Ok(CaptureTransport::with_local_addr(
+            socket, local_addr, capture,
+        ))
+    }
+}
+
+impl<Tr, W: Write> CaptureTransport<Tr, W> {
+    /// Captures the traffic of `transport`, which is recorded with `local_addr` as its address.
+    pub fn with_local_addr(
+        transport: Tr,
+        local_addr: SocketAddr,
+        capture: PcapWriter<W>,
+    ) -> CaptureTransport<Tr, W> {
+        CaptureTransport {
+            transport,
+            local_addr,
+            capture,
+            error: None,
+        }
+    }
+
+    /// Returns the error that stopped the capture, if any.
+    pub fn capture_error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    /// Returns the capture, e.g. to flush its writer.
+    pub fn capture_mut(&mut self) -> &mut PcapWriter<W> {
+        &mut self.capture
+    }
+
+    /// Returns the underlying transport and the capture.
+    pub fn into_inner(self) -> (Tr, PcapWriter<W>) {
+        (self.transport, self.capture)
+    }
+
+    fn record(&mut self, source: SocketAddr, destination: SocketAddr, data: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let result = self
+            .capture
+            .write_datagram(SystemTime::now(), source, destination, data);
+        self.error = result.err();
+    }
+}
+
+impl<Tr, W> Transport for CaptureTransport<Tr, W>
+where
+    Tr: Transport<Endpoint = SocketAddr>,
+    W: Write,
+{
+    type Endpoint = SocketAddr;
+    type Error = Tr::Error;
+
+    fn send_to(&mut self, data: &[u8], endpoint: &SocketAddr) -> Result<(), Tr::Error> {
+        self.transport.send_to(data, endpoint)?;
+        self.record(self.local_addr, *endpoint, data);
+        Ok(())
+    }
+
+    fn recv_from(&mut self, buffer: &mut [u8]) -> Result<Option<(usize, SocketAddr)>, Tr::Error> {
+        let received = self.transport.recv_from(buffer)?;
+        if let Some((len, endpoint)) = received {
+            self.record(endpoint, self.local_addr, &buffer[..len]);
+        }
+        Ok(received)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CaptureTransport, PcapWriter};
+    use crate::Transport;
+
+    use std::net::{SocketAddr, UdpSocket};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_pcap_records() {
+        let mut capture = PcapWriter::new(Vec::new()).unwrap();
+        let source: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+        let destination: SocketAddr = "10.0.0.2:2000".parse().unwrap();
+        let time = UNIX_EPOCH + Duration::new(5, 250_000);
+        capture
+            .write_datagram(time, source, destination, b"abc")
+            .unwrap();
+        capture
+            .write_datagram(time, source, "[::1]:3000".parse().unwrap(), b"skipped")
+            .unwrap();
+        let file = capture.into_inner();
+
+        assert_eq!(&file[..4], &[0xd4, 0xc3, 0xb2, 0xa1]);
+        assert_eq!(&file[20..24], &101u32.to_le_bytes());
+        let record = &file[24..];
+        assert_eq!(&record[..4], &5u32.to_le_bytes());
+        assert_eq!(&record[4..8], &250u32.to_le_bytes());
+        assert_eq!(&record[8..12], &31u32.to_le_bytes());
+        assert_eq!(record.len(), 16 + 31);
+
+        let ip = &record[16..36];
+        assert_eq!(&ip[12..16], &[10, 0, 0, 1]);
+        assert_eq!(&ip[16..20], &[10, 0, 0, 2]);
+        // The checksum over a header including its checksum is 0.
+        assert_eq!(super::ipv4_checksum(ip), 0);
+        let udp = &record[36..];
+        assert_eq!(&udp[..4], &[0x03, 0xe8, 0x07, 0xd0]);
+        assert_eq!(&udp[8..], b"abc");
+    }
+
+    #[test]
+    fn test_capture_transport() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let local_addr = socket.local_addr().unwrap();
+        let remote = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut transport =
+            CaptureTransport::new(socket, PcapWriter::new(Vec::new()).unwrap()).unwrap();
+
+        Transport::send_to(&mut transport, b"out", &remote.local_addr().unwrap()).unwrap();
+        remote.send_to(b"in", local_addr).unwrap();
+        let mut buffer = [0; 16];
+        let mut received = None;
+        for _ in 0..100 {
+            received = Transport::recv_from(&mut transport, &mut buffer).unwrap();
+            if received.is_some() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(received, Some((2, remote.local_addr().unwrap())));
+
+        let (_, capture) = transport.into_inner();
+        let file = capture.into_inner();
+        let first = &file[24..];
+        let first_len = u32::from_le_bytes([first[8], first[9], first[10], first[11]]) as usize;
+        assert_eq!(&first[16 + 28..16 + first_len], b"out");
+        let second = &first[16 + first_len..];
+        assert_eq!(&second[16 + 28..], b"in");
+        assert_eq!(&second[16 + 12..16 + 16], &[127, 0, 0, 1]);
+    }
+}
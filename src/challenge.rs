@@ -0,0 +1,220 @@
+//! A stateless cookie exchange that keeps spoofed connection attempts away from ENet.
+//!
+//! ENet allocates a peer for every connection attempt it receives, so a flood of connects with
+//! spoofed source addresses can occupy all peer slots. With a
+//! [ConnectChallenge](struct.ConnectChallenge.html) on the server, connects from unknown sources
+//! never reach ENet. Instead, the source receives a cookie, an HMAC of its address and the
+//! current time, and only once it echoes the cookie back is its traffic passed on. Verifying a
+//! cookie needs no state, so only sources that can receive at their address cost memory.
+//!
+//! Clients answer the challenge with a [ChallengeClient](struct.ChallengeClient.html). Their
+//! connect then succeeds with the next retransmission, about half a second later.
+
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use crate::intercept::is_connect;
+use crate::sha256::{constant_time_eq, hmac_sha256};
+use crate::{Datagram, Intercept, InterceptAction};
+
+/// Marks challenge datagrams. The session bits of an ENet header are never all set for connects.
+const MAGIC: &[u8] = b"\xff\xff\xff\xffENETCOOKIE";
+const KIND_CHALLENGE: u8 = b'?';
+const KIND_RESPONSE: u8 = b'!';
+
+/// The length of a cookie, a truncated HMAC.
+const COOKIE_LEN: usize = 16;
+
+/// The length of challenges and responses: the marker, kind, timestamp and cookie.
+const DATAGRAM_LEN: usize = MAGIC.len() + 1 + 4 + COOKIE_LEN;
+
+/// An `Intercept` that passes traffic to ENet only from sources that answered a cookie challenge.
+///
+/// Add it to the host before any other intercept.
+#[derive(Debug)]
+pub struct ConnectChallenge {
+    secret: Vec<u8>,
+    validity: Duration,
+    epoch: Instant,
+    admitted: HashMap<(Ipv4Addr, u16), Instant>,
+    next_sweep: Instant,
+}
+
+impl ConnectChallenge {
+    /// Creates a challenge whose cookies are keyed with `secret`, and valid for `validity`.
+    ///
+    /// Admitted sources are forgotten once they were silent for `validity`, so it should be longer
+    /// than the time ENet waits between pings.
+    pub fn new(secret: &[u8], validity: Duration) -> ConnectChallenge {
+        let epoch = Instant::now();
+        ConnectChallenge {
+            secret: secret.to_vec(),
+            validity,
+            epoch,
+            admitted: HashMap::new(),
+            next_sweep: epoch + validity,
+        }
+    }
+
+    /// Returns the number of sources currently admitted.
+    pub fn admitted(&self) -> usize {
+        self.admitted.len()
+    }
+
+    fn cookie(&self, source: (Ipv4Addr, u16), timestamp: u32) -> [u8; COOKIE_LEN] {
+        let mac = hmac_sha256(
+            &self.secret,
+            &[
+                &source.0.octets(),
+                &source.1.to_be_bytes(),
+                &timestamp.to_be_bytes(),
+            ],
+        );
+        let mut cookie = [0; COOKIE_LEN];
+        cookie.copy_from_slice(&mac[..COOKIE_LEN]);
+        cookie
+    }
+
+    fn verify(&self, source: (Ipv4Addr, u16), response: &[u8], now: u32) -> bool {
+        if response.len() != 4 + COOKIE_LEN {
+            return false;
+        }
+
+        let timestamp = u32::from_be_bytes([response[0], response[1], response[2], response[3]]);
+        let age = Duration::from_secs(u64::from(now.wrapping_sub(timestamp)));
+        age <= self.validity && constant_time_eq(&response[4..], &self.cookie(source, timestamp))
+    }
+}
+
+impl Intercept for ConnectChallenge {
+    fn intercept(&mut self, datagram: &mut Datagram) -> InterceptAction {
+        let now = Instant::now();
+        if now >= self.next_sweep {
+            let validity = self.validity;
+            self.admitted
+                .retain(|_, last_seen| now.duration_since(*last_seen) < validity);
+            self.next_sweep = now + validity;
+        }
+
+        let address = datagram.address();
+        let source = (*address.ip(), address.port());
+        if let Some(last_seen) = self.admitted.get_mut(&source) {
+            *last_seen = now;
+            return InterceptAction::Continue;
+        }
+
+        let timestamp = now.duration_since(self.epoch).as_secs() as u32;
+        let data = datagram.data();
+        if let Some(response) = data
+            .strip_prefix(MAGIC)
+            .and_then(|data| data.strip_prefix(&[KIND_RESPONSE]))
+        {
+            if self.verify(source, response, timestamp) {
+                self.admitted.insert(source, now);
+            }
+            return InterceptAction::Drop;
+        }
+        if !is_connect(data) {
+            return InterceptAction::Continue;
+        }
+
+        // Challenges are never larger than connects, so they can't amplify a spoofed flood.
+        if data.len() >= DATAGRAM_LEN {
+            let mut challenge = MAGIC.to_vec();
+            challenge.push(KIND_CHALLENGE);
+            challenge.extend_from_slice(&timestamp.to_be_bytes());
+            challenge.extend_from_slice(&self.cookie(source, timestamp));
+            let _ = datagram.reply(&challenge);
+        }
+        InterceptAction::Drop
+    }
+}
+
+/// An `Intercept` for client hosts, that answers the challenges of a `ConnectChallenge`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChallengeClient;
+
+impl Intercept for ChallengeClient {
+    fn intercept(&mut self, datagram: &mut Datagram) -> InterceptAction {
+        let cookie = match datagram
+            .data()
+            .strip_prefix(MAGIC)
+            .and_then(|data| data.strip_prefix(&[KIND_CHALLENGE]))
+        {
+            Some(cookie) => cookie.to_vec(),
+            None => return InterceptAction::Continue,
+        };
+
+        let mut response = MAGIC.to_vec();
+        response.push(KIND_RESPONSE);
+        response.extend_from_slice(&cookie);
+        let _ = datagram.reply(&response);
+        InterceptAction::Drop
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChallengeClient, ConnectChallenge, DATAGRAM_LEN, KIND_CHALLENGE, MAGIC};
+    use crate::tests::{create_host, pump_until};
+    use crate::{Address, EventKind, PeerState};
+
+    use std::net::{Ipv4Addr, UdpSocket};
+    use std::time::Duration;
+
+    #[test]
+    fn test_cookies() {
+        let challenge = ConnectChallenge::new(b"secret", Duration::from_secs(10));
+        let source = (Ipv4Addr::LOCALHOST, 1234);
+        let mut response = 5u32.to_be_bytes().to_vec();
+        response.extend_from_slice(&challenge.cookie(source, 5));
+
+        assert!(challenge.verify(source, &response, 5));
+        assert!(challenge.verify(source, &response, 15));
+        assert!(!challenge.verify(source, &response, 16));
+        assert!(!challenge.verify((Ipv4Addr::LOCALHOST, 1235), &response, 5));
+        let other = ConnectChallenge::new(b"other secret", Duration::from_secs(10));
+        assert!(!other.verify(source, &response, 5));
+    }
+
+    #[test]
+    fn test_connect_challenge() {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12378);
+        let mut server = create_host(Some(&server_address), 2);
+        server.add_intercept(ConnectChallenge::new(b"secret", Duration::from_secs(10)));
+
+        // A source that doesn't answer the challenge never gets a peer.
+        let mut spoofed = create_host(None, 1);
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let mut connect = vec![0x8f, 0xff];
+        connect.resize(52, 0);
+        socket
+            .send_to(&connect, (Ipv4Addr::LOCALHOST, 12378))
+            .unwrap();
+        server.service(Some(Duration::from_millis(50))).unwrap();
+        let mut buffer = [0; 64];
+        let (len, _) = socket.recv_from(&mut buffer).unwrap();
+        assert_eq!(len, DATAGRAM_LEN);
+        assert!(buffer.starts_with(MAGIC));
+        assert_eq!(buffer[MAGIC.len()], KIND_CHALLENGE);
+        spoofed.connect(&server_address, 1, 0).unwrap();
+
+        let mut client = create_host(None, 1);
+        client.add_intercept(ChallengeClient);
+        client.connect(&server_address, 1, 0).unwrap();
+
+        pump_until(
+            &mut [&mut server, &mut spoofed, &mut client],
+            |index, _, event| index == 2 && matches!(event.kind, EventKind::Connect),
+        );
+        let connected_peers = server
+            .peers()
+            .filter(|peer| peer.state() != PeerState::Disconnected)
+            .count();
+        assert_eq!(connected_peers, 1);
+    }
+}
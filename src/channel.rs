@@ -0,0 +1,458 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use enet_sys::ENET_PROTOCOL_MAXIMUM_CHANNEL_COUNT;
+
+use crate::{ChannelLimit, Error, Event, EventKind, Host, PacketMode, PeerID};
+
+/// A channel with a fixed id, mode and message type, for type-checked sending and receiving.
+///
+/// Implement this on a marker type for each channel, then send with `Peer::send::<Chat>` and
+/// receive with `Event::message::<Chat>` or a [ChannelRouter](struct.ChannelRouter.html).
+///
+/// ## Example
+/// ```
+/// use enet::{Channel, PacketMode};
+///
+/// struct Chat;
+///
+/// impl Channel for Chat {
+///     const ID: u8 = 1;
+///     const MODE: PacketMode = PacketMode::ReliableSequenced;
+///     type Message = String;
+///
+///     fn encode(message: &String) -> Vec<u8> {
+///         message.as_bytes().to_vec()
+///     }
+///
+///     fn decode(data: &[u8]) -> Option<String> {
+///         String::from_utf8(data.to_vec()).ok()
+///     }
+/// }
+/// ```
+pub trait Channel {
+    /// The id of the channel.
+    const ID: u8;
+    /// The mode messages are sent with.
+    const MODE: PacketMode;
+    /// The type of the messages sent on the channel.
+    type Message;
+
+    /// Encodes `message` as packet data.
+    fn encode(message: &Self::Message) -> Vec<u8>;
+
+    /// Decodes packet data, returning `None` if it is not a valid message.
+    fn decode(data: &[u8]) -> Option<Self::Message>;
+}
+
+type Route = Box<dyn FnMut(PeerID, &[u8]) -> bool>;
+
+/// Routes received packets to a handler for their `Channel`.
+///
+/// Either pass every event to `ChannelRouter::route`, or service the host through
+/// `ChannelRouter::service`, which only returns the events left to the application.
+#[derive(Default)]
+pub struct ChannelRouter {
+    routes: HashMap<u8, Route>,
+    malformed: u64,
+}
+
+impl ChannelRouter {
+    /// Creates a router without any routes.
+    pub fn new() -> ChannelRouter {
+        ChannelRouter::default()
+    }
+
+    /// Routes the messages of channel `C` to `handler`, replacing the previous handler for its id.
+    pub fn on<C, F>(&mut self, mut handler: F)
+    where
+        C: Channel + 'static,
+        F: FnMut(PeerID, C::Message) + 'static,
+    {
+        self.routes.insert(
+            C::ID,
+            Box::new(move |peer_id, data| match C::decode(data) {
+                Some(message) => {
+                    handler(peer_id, message);
+                    true
+                }
+                None => false,
+            }),
+        );
+    }
+
+    /// Returns the number of packets that were dropped, because they did not decode.
+    pub fn malformed(&self) -> u64 {
+        self.malformed
+    }
+
+    /// Processes an event returned by `Host::service`.
+    ///
+    /// Returns `true` if the event was a packet on a routed channel, which was passed to its
+    /// handler, or dropped if it did not decode.
+    pub fn route(&mut self, event: &Event) -> bool {
+        let (channel_id, packet) = match event.kind {
+            EventKind::Receive {
+                channel_id,
+                ref packet,
+            } => (channel_id, packet),
+            _ => return false,
+        };
+
+        let route = match self.routes.get_mut(&channel_id) {
+            Some(route) => route,
+            None => return false,
+        };
+        if !route(event.peer_id, packet.data()) {
+            self.malformed += 1;
+        }
+        true
+    }
+
+    /// Services `host` like `Host::service`, and passes the messages on routed channels to their
+    /// handlers.
+    ///
+    /// Waits at most `timeout` for the first event, then handles the events that are already
+    /// queued. Returns the first event that was not routed, e.g. a `Connect`, or `None` once
+    /// there are no more events.
+    pub fn service<T>(
+        &mut self,
+        host: &mut Host<T>,
+        timeout: Option<Duration>,
+    ) -> Result<Option<Event>, Error> {
+        let mut event = host.service(timeout)?;
+        while let Some(routed) = event {
+            if !self.route(&routed) {
+                return Ok(Some(routed));
+            }
+            event = host.check_events()?;
+        }
+        Ok(None)
+    }
+}
+
+/// A channel count that is part of the type, so hosts, connections and channel ids agree on it.
+///
+/// Pass it to `Enet::create_host` as the channel limit, and to `Host::connect_with_channels`.
+/// Channel ids are checked against the count at compile time with `Channels::id`.
+///
+/// ## Example
+/// ```
+/// use enet::Channels;
+///
+/// type GameChannels = Channels<3>;
+///
+/// let chat = GameChannels::new().id::<2>();
+/// assert_eq!(u8::from(chat), 2);
+/// ```
+///
+/// Ids out of range don't compile:
+/// ```compile_fail
+/// let chat = enet::Channels::<3>::new().id::<3>();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Channels<const N: usize>(());
+
+impl<const N: usize> Channels<N> {
+    const VALID: () = assert!(
+        N >= 1 && N <= ENET_PROTOCOL_MAXIMUM_CHANNEL_COUNT as usize,
+        "ENet supports between 1 and 255 channels"
+    );
+
+    /// Creates the token, failing to compile unless ENet supports `N` channels.
+    pub fn new() -> Channels<N> {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID;
+        Channels(())
+    }
+
+    /// Returns the number of channels, `N`.
+    pub fn count(self) -> usize {
+        N
+    }
+
+    /// Returns the id of channel `I`, failing to compile unless it is below `N`.
+    pub fn id<const I: u8>(self) -> ChannelId<N> {
+        #[allow(clippy::let_unit_value)]
+        let () = InRange::<I, N>::VALID;
+        ChannelId(I)
+    }
+
+    /// Returns the id of channel `id`, if it is below `N`.
+    pub fn get(self, id: u8) -> Option<ChannelId<N>> {
+        if usize::from(id) < N {
+            Some(ChannelId(id))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the ids of all `N` channels.
+    pub fn ids(self) -> impl Iterator<Item = ChannelId<N>> {
+        (0..N).map(|id| ChannelId(id as u8))
+    }
+}
+
+/// Checks at compile time that channel `I` is below `N`, see `Channels::id`.
+struct InRange<const I: u8, const N: usize>;
+
+impl<const I: u8, const N: usize> InRange<I, N> {
+    const VALID: () = assert!((I as usize) < N, "channel id out of range");
+}
+
+impl<const N: usize> Default for Channels<N> {
+    fn default() -> Channels<N> {
+        Channels::new()
+    }
+}
+
+impl<const N: usize> From<Channels<N>> for ChannelLimit {
+    fn from(_: Channels<N>) -> ChannelLimit {
+        ChannelLimit::Limited(N)
+    }
+}
+
+/// The id of one of `N` channels, see `Channels`.
+///
+/// Converts into the `u8` that `Peer::send_packet` takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChannelId<const N: usize>(u8);
+
+impl<const N: usize> From<ChannelId<N>> for u8 {
+    fn from(id: ChannelId<N>) -> u8 {
+        id.0
+    }
+}
+
+/// The defaults of one channel, see `Host::set_channel_configs` and `Peer::send_on`.
+///
+/// Sending through the defaults keeps call sites from picking the wrong mode, e.g. sending chat
+/// unreliably.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChannelConfig {
+    /// The mode packets are sent with.
+    pub mode: PacketMode,
+    /// Whether unreliable packets larger than the MTU are sent as unreliable fragments, instead
+    /// of reliably like ENet does by default. Ignored for reliable channels.
+    pub unreliable_fragment: bool,
+    /// The bytes sent to each peer on the channel per second, see `Peer::set_channel_budget`.
+    pub budget: Option<u32>,
+}
+
+impl ChannelConfig {
+    /// Creates the config of a channel sending with `mode`, without fragments or a budget.
+    pub const fn new(mode: PacketMode) -> ChannelConfig {
+        ChannelConfig {
+            mode,
+            unreliable_fragment: false,
+            budget: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Channel, ChannelConfig, ChannelRouter, Channels};
+    use crate::tests::{connected_pair, create_host, pump_until, ENET};
+    use crate::{Address, BandwidthLimit, EventKind, Packet, PacketMode, SendError};
+
+    use std::cell::RefCell;
+    use std::net::Ipv4Addr;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    struct Chat;
+
+    impl Channel for Chat {
+        const ID: u8 = 1;
+        const MODE: PacketMode = PacketMode::ReliableSequenced;
+        type Message = String;
+
+        fn encode(message: &String) -> Vec<u8> {
+            message.as_bytes().to_vec()
+        }
+
+        fn decode(data: &[u8]) -> Option<String> {
+            String::from_utf8(data.to_vec()).ok()
+        }
+    }
+
+    struct Position;
+
+    impl Channel for Position {
+        const ID: u8 = 0;
+        const MODE: PacketMode = PacketMode::UnreliableSequenced;
+        type Message = (u8, u8);
+
+        fn encode(&(x, y): &(u8, u8)) -> Vec<u8> {
+            vec![x, y]
+        }
+
+        fn decode(data: &[u8]) -> Option<(u8, u8)> {
+            match *data {
+                [x, y] => Some((x, y)),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn test_typed_channels() {
+        let (mut server, mut client, _, peer_id) = connected_pair(12369, 2);
+
+        let chats = Rc::new(RefCell::new(Vec::new()));
+        let positions = Rc::new(RefCell::new(Vec::new()));
+        let mut router = ChannelRouter::new();
+        let routed_chats = chats.clone();
+        router.on::<Chat, _>(move |_, message| routed_chats.borrow_mut().push(message));
+        let routed_positions = positions.clone();
+        router.on::<Position, _>(move |_, position| routed_positions.borrow_mut().push(position));
+
+        let peer = &mut client[peer_id];
+        // Not a valid position, so it is dropped as malformed.
+        let packet = Packet::new(vec![1], PacketMode::ReliableSequenced).unwrap();
+        peer.send_packet(packet, Position::ID).unwrap();
+        peer.send::<Chat>(&"hi".to_string()).unwrap();
+        peer.send::<Position>(&(3, 4)).unwrap();
+        pump_until(&mut [&mut server, &mut client], |index, _, event| {
+            if index == 0 {
+                if let Some(message) = event.message::<Chat>() {
+                    assert_eq!(message, "hi");
+                }
+                router.route(&event);
+            }
+            !chats.borrow().is_empty() && !positions.borrow().is_empty()
+        });
+
+        assert_eq!(*chats.borrow(), vec!["hi".to_string()]);
+        assert_eq!(*positions.borrow(), vec![(3, 4)]);
+        assert_eq!(router.malformed(), 1);
+    }
+
+    #[test]
+    fn test_router_service() {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12380);
+        let mut server = create_host(Some(&server_address), 1);
+        let mut client = create_host(None, 1);
+        let peer_id = client.connect(&server_address, 2, 0).unwrap().1;
+
+        let chats = Rc::new(RefCell::new(Vec::new()));
+        let mut router = ChannelRouter::new();
+        let routed_chats = chats.clone();
+        router.on::<Chat, _>(move |_, message| routed_chats.borrow_mut().push(message));
+
+        let timeout = Some(Duration::from_millis(2));
+        let mut unrouted = Vec::new();
+        for _ in 0..200 {
+            if let Some(event) = client.service(timeout).unwrap() {
+                if let EventKind::Connect = event.kind {
+                    let peer = &mut client[peer_id];
+                    for chat in &["a", "b", "c"] {
+                        peer.send::<Chat>(&chat.to_string()).unwrap();
+                    }
+                }
+            }
+            if let Some(event) = router.service(&mut server, timeout).unwrap() {
+                unrouted.push(event.kind);
+            }
+            if chats.borrow().len() == 3 {
+                break;
+            }
+        }
+
+        assert_eq!(*chats.borrow(), vec!["a", "b", "c"]);
+        assert!(matches!(unrouted[..], [EventKind::Connect]));
+    }
+
+    #[test]
+    fn test_channel_count() {
+        type GameChannels = Channels<2>;
+
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12370);
+        let create_host = |address: Option<&Address>| {
+            ENET.create_host::<()>(
+                address,
+                1,
+                GameChannels::new().into(),
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap()
+        };
+        let mut server = create_host(Some(&server_address));
+        let mut client = create_host(None);
+        let (peer, peer_id) = client
+            .connect_with_channels(&server_address, GameChannels::new(), 0)
+            .unwrap();
+        assert_eq!(peer.channel_count(), 2);
+
+        pump_until(&mut [&mut server, &mut client], |index, _, event| {
+            index == 1 && matches!(event.kind, EventKind::Connect)
+        });
+
+        let channels = GameChannels::new();
+        for id in channels.ids() {
+            let packet = Packet::new(vec![u8::from(id)], PacketMode::ReliableSequenced).unwrap();
+            client[peer_id].send_packet(packet, id.into()).unwrap();
+        }
+        assert_eq!(channels.get(1), Some(channels.id::<1>()));
+        assert_eq!(channels.get(2), None);
+    }
+
+    #[test]
+    fn test_channel_configs() {
+        let configs = [
+            ChannelConfig::new(PacketMode::ReliableSequenced),
+            ChannelConfig {
+                unreliable_fragment: true,
+                budget: Some(100),
+                ..ChannelConfig::new(PacketMode::UnreliableSequenced)
+            },
+        ];
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12412);
+        let mut server = create_host(Some(&server_address), 1);
+        server.set_channel_configs(&configs);
+        assert_eq!(server.channel_configs(), &configs[..]);
+        let mut client = create_host(None, 1);
+        let (peer, client_peer_id) = client
+            .connect_with_configs(&server_address, &configs[..1], 0)
+            .unwrap();
+        assert_eq!(peer.channel_count(), 1);
+        assert_eq!(peer.channel_config(1), None);
+
+        let mut server_peer_id = None;
+        pump_until(&mut [&mut server, &mut client], |index, _, event| {
+            if let (0, EventKind::Connect) = (index, &event.kind) {
+                server_peer_id = Some(event.peer_id);
+            }
+            server_peer_id.is_some()
+        });
+        let peer = &mut server[server_peer_id.unwrap()];
+        assert_eq!(peer.channel_config(1), Some(configs[1]));
+        assert!(peer.budget_usage(1).is_some());
+
+        // Only the first channel was allocated by the client.
+        match peer.send_on(1, vec![0; 10]) {
+            Err(SendError::InvalidChannel(1)) => (),
+            result => panic!("unexpected result {:?}", result),
+        }
+        match peer.send_on(2, vec![0; 10]) {
+            Err(SendError::Unconfigured(2)) => (),
+            result => panic!("unexpected result {:?}", result),
+        }
+        peer.send_on(0, b"chat".to_vec()).unwrap();
+
+        let mut received = None;
+        pump_until(&mut [&mut server, &mut client], |index, _, event| {
+            if let (1, EventKind::Receive { ref packet, .. }) = (index, &event.kind) {
+                received = Some((packet.data().to_vec(), packet.mode()));
+            }
+            received.is_some()
+        });
+        assert_eq!(
+            received,
+            Some((b"chat".to_vec(), PacketMode::ReliableSequenced))
+        );
+        assert_eq!(client[client_peer_id].channel_config(0), Some(configs[0]));
+    }
+}
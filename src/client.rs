@@ -0,0 +1,332 @@
+//! A single outgoing connection that reconnects by itself.
+//!
+//! A [Client](struct.Client.html) owns a `Host` with one peer. When its connection drops or an
+//! attempt times out, it reconnects with exponential backoff. A session hook decides whether the
+//! new connection resumes the previous session or starts a fresh one.
+
+use std::time::{Duration, Instant};
+
+use crate::{
+    Address, BandwidthLimit, ChannelLimit, Enet, Error, Event, EventKind, Host, Packet, Peer,
+    PeerID, PeerState, SendError,
+};
+
+/// The state of the connection of a `Client`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectionState {
+    /// The first connection attempt is in progress.
+    Connecting,
+    /// The client is connected to the server.
+    Connected,
+    /// The connection was lost, and the client is trying to reconnect.
+    Reconnecting {
+        /// The current reconnection attempt, starting at 1.
+        attempt: u32,
+    },
+    /// The client was disconnected with `Client::disconnect`, and stays disconnected.
+    Disconnected,
+}
+
+/// How the connection after a lost one starts, as decided by the session hook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Session {
+    /// Resume the previous session, connecting with the contained data instead of the default
+    /// connect data (e.g. a session token the server recognizes).
+    Resume(u32),
+    /// Start a fresh session, connecting with the default connect data.
+    Fresh,
+}
+
+type StateCallback = Box<dyn FnMut(ConnectionState)>;
+type SessionHook = Box<dyn FnMut() -> Session>;
+
+/// An outgoing connection to a server, which is re-established automatically when it drops.
+pub struct Client<T> {
+    host: Host<T>,
+    server: Address,
+    channel_count: usize,
+    connect_data: u32,
+    reconnect_data: u32,
+    peer_id: Option<PeerID>,
+    state: ConnectionState,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    backoff: Duration,
+    next_attempt: Option<Instant>,
+    on_state_change: Option<StateCallback>,
+    session_hook: Option<SessionHook>,
+}
+
+impl<T> Client<T> {
+    /// Creates a client and starts connecting to `server`.
+    ///
+    /// `channel_count` channels are allocated for the connection, and `connect_data` is sent with
+    /// every connection attempt of a fresh session.
+    ///
+    /// The client reconnects after 100ms at first, doubling the delay up to 10s, see `Client::set_backoff`.
+    pub fn new(
+        enet: &Enet,
+        server: Address,
+        channel_count: usize,
+        connect_data: u32,
+    ) -> Result<Client<T>, Error> {
+        let host = enet.create_host(
+            None,
+            1,
+            ChannelLimit::Limited(channel_count),
+            BandwidthLimit::Unlimited,
+            BandwidthLimit::Unlimited,
+        )?;
+
+        let initial_backoff = Duration::from_millis(100);
+        let mut client = Client {
+            host,
+            server,
+            channel_count,
+            connect_data,
+            reconnect_data: connect_data,
+            peer_id: None,
+            state: ConnectionState::Connecting,
+            initial_backoff,
+            max_backoff: Duration::from_secs(10),
+            backoff: initial_backoff,
+            next_attempt: None,
+            on_state_change: None,
+            session_hook: None,
+        };
+        client.attempt();
+
+        Ok(client)
+    }
+
+    /// Sets the delay before the first reconnection attempt, and the most it grows to.
+    ///
+    /// The delay doubles with each failed attempt, and is reset once connected.
+    pub fn set_backoff(&mut self, initial: Duration, max: Duration) {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self.backoff = initial;
+    }
+
+    /// Sets a callback, which is called whenever the connection state changes.
+    pub fn on_state_change<F: FnMut(ConnectionState) + 'static>(&mut self, callback: F) {
+        self.on_state_change = Some(Box::new(callback));
+    }
+
+    /// Sets the hook deciding how to reconnect, called whenever an established connection drops.
+    ///
+    /// Without a hook, every reconnection starts a fresh session.
+    pub fn on_session_lost<F: FnMut() -> Session + 'static>(&mut self, hook: F) {
+        self.session_hook = Some(Box::new(hook));
+    }
+
+    /// Returns the current state of the connection.
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    /// Returns the address of the server.
+    pub fn server(&self) -> &Address {
+        &self.server
+    }
+
+    /// Returns the underlying `Host`.
+    pub fn host(&self) -> &Host<T> {
+        &self.host
+    }
+
+    /// Returns the underlying `Host`, mutably.
+    pub fn host_mut(&mut self) -> &mut Host<T> {
+        &mut self.host
+    }
+
+    /// Returns the `PeerID` of the current connection, if one is established.
+    pub fn peer_id(&self) -> Option<PeerID> {
+        match self.state {
+            ConnectionState::Connected => self.peer_id,
+            _ => None,
+        }
+    }
+
+    /// Returns the peer of the current connection, if one is established.
+    pub fn peer_mut(&mut self) -> Option<&mut Peer<T>> {
+        let peer_id = self.peer_id()?;
+        self.host.peer_mut(peer_id)
+    }
+
+    /// Sends `packet` to the server on channel `channel_id`.
+    ///
+    /// Fails with `SendError::NotConnected` while the connection is not established.
+    pub fn send_packet(&mut self, packet: Packet, channel_id: u8) -> Result<(), SendError> {
+        match self.peer_mut() {
+            Some(peer) => peer.send_packet(packet, channel_id),
+            None => Err(SendError::NotConnected(PeerState::Disconnected)),
+        }
+    }
+
+    /// Disconnects from the server, without reconnecting afterwards.
+    pub fn disconnect(&mut self, data: u32) {
+        if let Some(peer) = self.peer_id.and_then(|peer_id| self.host.peer_mut(peer_id)) {
+            peer.disconnect(data);
+        }
+
+        self.next_attempt = None;
+        self.set_state(ConnectionState::Disconnected);
+    }
+
+    /// Services the host like `Host::service`, reconnecting whenever necessary.
+    ///
+    /// All events are returned, including those of the client's own connection.
+    pub fn service(&mut self, timeout: Option<Duration>) -> Result<Option<Event>, Error> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        loop {
+            if self.next_attempt.is_some_and(|at| at <= Instant::now()) {
+                self.attempt();
+            }
+
+            // Don't wait past the next attempt.
+            let now = Instant::now();
+            let mut wait = deadline.map(|deadline| deadline.saturating_duration_since(now));
+            if let Some(at) = self.next_attempt {
+                let until = at.saturating_duration_since(now);
+                wait = Some(wait.map_or(until, |wait| wait.min(until)));
+            }
+
+            if let Some(event) = self.host.service(wait)? {
+                self.handle_event(&event);
+                return Ok(Some(event));
+            }
+
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: &Event) {
+        if Some(event.peer_id) != self.peer_id {
+            return;
+        }
+
+        if event.kind.is_disconnect() {
+            self.peer_id = None;
+            match self.state {
+                ConnectionState::Connected => {
+                    self.reconnect_data = match self.session_hook.as_mut().map(|hook| hook()) {
+                        Some(Session::Resume(data)) => data,
+                        Some(Session::Fresh) | None => self.connect_data,
+                    };
+                    self.schedule(1);
+                }
+                ConnectionState::Connecting => self.schedule(1),
+                ConnectionState::Reconnecting { attempt } => self.schedule(attempt + 1),
+                ConnectionState::Disconnected => (),
+            }
+        } else if let EventKind::Connect = event.kind {
+            self.backoff = self.initial_backoff;
+            self.set_state(ConnectionState::Connected);
+        }
+    }
+
+    fn schedule(&mut self, attempt: u32) {
+        self.next_attempt = Some(Instant::now() + self.backoff);
+        self.backoff = (self.backoff * 2).min(self.max_backoff);
+        self.set_state(ConnectionState::Reconnecting { attempt });
+    }
+
+    fn attempt(&mut self) {
+        self.next_attempt = None;
+
+        let data = match self.state {
+            ConnectionState::Connecting => self.connect_data,
+            _ => self.reconnect_data,
+        };
+        match self.host.connect(&self.server, self.channel_count, data) {
+            Ok((_, peer_id)) => self.peer_id = Some(peer_id),
+            // Try again later, e.g. when the old connection has not been fully torn down yet.
+            Err(_) => {
+                let attempt = match self.state {
+                    ConnectionState::Reconnecting { attempt } => attempt + 1,
+                    _ => 1,
+                };
+                self.schedule(attempt);
+            }
+        }
+    }
+
+    fn set_state(&mut self, state: ConnectionState) {
+        if self.state == state {
+            return;
+        }
+
+        self.state = state;
+        if let Some(callback) = self.on_state_change.as_mut() {
+            callback(state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Client, ConnectionState, Session};
+    use crate::tests::ENET;
+    use crate::{Address, BandwidthLimit, ChannelLimit, EventKind};
+
+    use std::cell::RefCell;
+    use std::net::Ipv4Addr;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_reconnect() {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12359);
+        let mut server = ENET
+            .create_host::<()>(
+                Some(&server_address),
+                1,
+                ChannelLimit::Maximum,
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap();
+
+        let mut client = Client::<()>::new(&ENET, server_address, 1, 7).unwrap();
+        client.set_backoff(Duration::from_millis(10), Duration::from_millis(100));
+        let states = Rc::new(RefCell::new(Vec::new()));
+        let recorded = states.clone();
+        client.on_state_change(move |state| recorded.borrow_mut().push(state));
+        client.on_session_lost(|| Session::Resume(42));
+
+        let timeout = Some(Duration::from_millis(5));
+        let mut connects = 0;
+        for _ in 0..400 {
+            client.service(timeout).unwrap();
+            if let Some(event) = server.service(timeout).unwrap() {
+                if let EventKind::Connect = event.kind {
+                    connects += 1;
+                    if connects == 1 {
+                        server[event.peer_id].disconnect(0);
+                    }
+                }
+            }
+            if connects == 2 && client.state() == ConnectionState::Connected {
+                break;
+            }
+        }
+
+        assert_eq!(
+            *states.borrow(),
+            vec![
+                ConnectionState::Connected,
+                ConnectionState::Reconnecting { attempt: 1 },
+                ConnectionState::Connected,
+            ]
+        );
+        assert!(client.peer_mut().is_some());
+
+        client.disconnect(0);
+        assert_eq!(client.state(), ConnectionState::Disconnected);
+        assert!(client.peer_id().is_none());
+    }
+}
@@ -0,0 +1,436 @@
+//! Several hosts on their own threads, to spread a server over more than one core.
+//!
+//! A [Cluster](struct.Cluster.html) runs one `Host`, a shard, per worker thread, each bound to
+//! its own address. The matchmaker or lobby assigns every client a shard, e.g. the least loaded
+//! one, and the client connects to that shard's address. The cluster then works with the peers of
+//! all shards: it looks them up, sends to them, and broadcasts across shards, through the
+//! [event pump](../pump/index.html) of every worker.
+//!
+//! On Unix, the shards can instead share one address with `SO_REUSEPORT`, see
+//! [Cluster::spawn_shared](struct.Cluster.html#method.spawn_shared), and the kernel assigns the
+//! clients to them.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::pump::{self, PumpCommand, PumpEvent};
+use crate::{Address, BandwidthLimit, ChannelLimit, Enet, Error, PacketMode, PeerID};
+
+/// How long a worker waits for events before it executes new commands.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Identifies a peer of a `Cluster`: the shard it is connected to, and its id in that host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClusterPeerId {
+    /// The index of the shard.
+    pub shard: usize,
+    /// The id of the peer in the host of the shard.
+    pub peer_id: PeerID,
+}
+
+/// An event of one of the shards of a `Cluster`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClusterEvent {
+    /// The index of the shard.
+    pub shard: usize,
+    /// The event.
+    pub event: PumpEvent,
+}
+
+type Directory = Arc<Mutex<HashMap<ClusterPeerId, Address>>>;
+
+struct Shard {
+    address: Address,
+    commands: Sender<PumpCommand>,
+    thread: Option<JoinHandle<Result<(), Error>>>,
+}
+
+/// Runs a host per thread, and serves the peers of all of them.
+pub struct Cluster {
+    shards: Vec<Shard>,
+    events: Receiver<ClusterEvent>,
+    directory: Directory,
+    stop: Arc<AtomicBool>,
+}
+
+impl Cluster {
+    /// Starts a shard for every address in `addresses`, on its own thread.
+    ///
+    /// Each host has room for `peer_count` peers and allows `channel_limit` channels. Fails if
+    /// any of the hosts can't be created, e.g. because its address is in use.
+    pub fn spawn(
+        enet: &Enet,
+        addresses: &[Address],
+        peer_count: usize,
+        channel_limit: ChannelLimit,
+    ) -> Result<Cluster, Error> {
+        Cluster::start(enet, addresses, false, peer_count, channel_limit)
+    }
+
+    /// Starts `shard_count` shards that all receive on `address`, with `SO_REUSEPORT`.
+    ///
+    /// The kernel assigns every client one of the shards by its address, so clients connect to
+    /// the same address and need no matchmaker; `Cluster::least_loaded` has no say in where they
+    /// land. Linux balances the clients evenly, other systems may send all of them to one shard.
+    /// A port of 0 picks a free port for all shards. Fails like `Cluster::spawn`, e.g. if a
+    /// socket without `SO_REUSEPORT` is bound to `address`.
+    #[cfg(unix)]
+    pub fn spawn_shared(
+        enet: &Enet,
+        address: &Address,
+        shard_count: usize,
+        peer_count: usize,
+        channel_limit: ChannelLimit,
+    ) -> Result<Cluster, Error> {
+        let addresses = vec![address.clone(); shard_count];
+        Cluster::start(enet, &addresses, true, peer_count, channel_limit)
+    }
+
+    fn start(
+        enet: &Enet,
+        addresses: &[Address],
+        shared: bool,
+        peer_count: usize,
+        channel_limit: ChannelLimit,
+    ) -> Result<Cluster, Error> {
+        let (event_sender, events) = mpsc::channel();
+        let directory = Directory::default();
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut cluster = Cluster {
+            shards: Vec::new(),
+            events,
+            directory,
+            stop,
+        };
+
+        for (shard, address) in addresses.iter().enumerate() {
+            // Shared shards use the port the first one picked.
+            let address = match cluster.shards.first() {
+                Some(first) if shared => first.address.clone(),
+                _ => address.clone(),
+            };
+            let (started_sender, started) = mpsc::channel();
+            let (mut pump, commands, shard_events) = pump::channel();
+            let enet = enet.clone();
+            let host_address = address.clone();
+            let event_sender = event_sender.clone();
+            let directory = cluster.directory.clone();
+            let stop = cluster.stop.clone();
+
+            let thread = thread::spawn(move || {
+                // ENet binds its socket right away, so shared sockets replace it.
+                let host = enet.create_host::<()>(
+                    if shared { None } else { Some(&host_address) },
+                    peer_count,
+                    channel_limit,
+                    BandwidthLimit::Unlimited,
+                    BandwidthLimit::Unlimited,
+                );
+                #[cfg(unix)]
+                let host = host.and_then(|mut host| {
+                    if shared {
+                        host.rebind_shared(&host_address)?;
+                    }
+                    Ok(host)
+                });
+                let mut host = match host {
+                    Ok(host) => {
+                        let _ = started_sender.send(Ok(host.address()));
+                        host
+                    }
+                    Err(e) => {
+                        let _ = started_sender.send(Err(e.0));
+                        return Err(e);
+                    }
+                };
+
+                while !stop.load(Ordering::Relaxed) {
+                    pump.pump(&mut host, Some(POLL_INTERVAL))?;
+                    for event in shard_events.try_iter() {
+                        track(&directory, shard, &event);
+                        // Without a receiver, the cluster is being dropped.
+                        let _ = event_sender.send(ClusterEvent { shard, event });
+                    }
+                }
+                Ok(())
+            });
+
+            cluster.shards.push(Shard {
+                address,
+                commands,
+                thread: Some(thread),
+            });
+            match started.recv() {
+                Ok(Ok(bound)) => cluster.shards[shard].address = bound,
+                Ok(Err(error)) => return Err(Error(error)),
+                Err(_) => (),
+            }
+        }
+
+        Ok(cluster)
+    }
+
+    /// Returns the number of shards.
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Returns the address of `shard`.
+    pub fn shard_address(&self, shard: usize) -> Option<&Address> {
+        self.shards.get(shard).map(|shard| &shard.address)
+    }
+
+    /// Returns the shard with the fewest connected peers, to assign the next client to.
+    pub fn least_loaded(&self) -> usize {
+        let directory = self.directory.lock().unwrap_or_else(|e| e.into_inner());
+        let mut load = vec![0; self.shards.len()];
+        for peer in directory.keys() {
+            load[peer.shard] += 1;
+        }
+        (0..self.shards.len())
+            .min_by_key(|&shard| load[shard])
+            .unwrap_or(0)
+    }
+
+    /// Returns the connected peers of all shards, with their addresses.
+    pub fn peers(&self) -> Vec<(ClusterPeerId, Address)> {
+        let directory = self.directory.lock().unwrap_or_else(|e| e.into_inner());
+        directory
+            .iter()
+            .map(|(&peer, address)| (peer, address.clone()))
+            .collect()
+    }
+
+    /// Returns the peer connected from `address`, on any shard.
+    pub fn find_peer(&self, address: &Address) -> Option<ClusterPeerId> {
+        let directory = self.directory.lock().unwrap_or_else(|e| e.into_inner());
+        directory
+            .iter()
+            .find(|&(_, peer_address)| peer_address == address)
+            .map(|(&peer, _)| peer)
+    }
+
+    /// Waits at most `timeout` for the next event of any shard.
+    pub fn next_event(&self, timeout: Duration) -> Option<ClusterEvent> {
+        match self.events.recv_timeout(timeout) {
+            Ok(event) => Some(event),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
+
+    /// Sends `data` to `peer`. Returns `false` if its shard stopped.
+    pub fn send(
+        &self,
+        peer: ClusterPeerId,
+        channel_id: u8,
+        data: Vec<u8>,
+        mode: PacketMode,
+    ) -> bool {
+        self.command(
+            peer.shard,
+            PumpCommand::Send {
+                peer_id: peer.peer_id,
+                channel_id,
+                data,
+                mode,
+            },
+        )
+    }
+
+    /// Sends `data` to the connected peers of all shards.
+    pub fn broadcast(&self, channel_id: u8, data: &[u8], mode: PacketMode) {
+        for shard in 0..self.shards.len() {
+            self.command(
+                shard,
+                PumpCommand::Broadcast {
+                    channel_id,
+                    data: data.to_vec(),
+                    mode,
+                },
+            );
+        }
+    }
+
+    /// Disconnects from `peer`. Returns `false` if its shard stopped.
+    pub fn disconnect(&self, peer: ClusterPeerId, data: u32) -> bool {
+        self.command(
+            peer.shard,
+            PumpCommand::Disconnect {
+                peer_id: peer.peer_id,
+                data,
+            },
+        )
+    }
+
+    fn command(&self, shard: usize, command: PumpCommand) -> bool {
+        self.shards
+            .get(shard)
+            .is_some_and(|shard| shard.commands.send(command).is_ok())
+    }
+
+    /// Stops all shards, and returns the first error a shard stopped with.
+    ///
+    /// Their hosts are destroyed without disconnecting the peers.
+    pub fn shutdown(mut self) -> Result<(), Error> {
+        self.stop_shards()
+    }
+
+    fn stop_shards(&mut self) -> Result<(), Error> {
+        self.stop.store(true, Ordering::Relaxed);
+        let mut result = Ok(());
+        for shard in &mut self.shards {
+            if let Some(thread) = shard.thread.take() {
+                match thread.join() {
+                    Ok(Err(e)) if result.is_ok() => result = Err(e),
+                    Ok(_) => (),
+                    Err(panic) => std::panic::resume_unwind(panic),
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            let _ = self.stop_shards();
+        }
+    }
+}
+
+/// Keeps the directory of connected peers up to date with the events of `shard`.
+fn track(directory: &Directory, shard: usize, event: &PumpEvent) {
+    let mut directory = directory.lock().unwrap_or_else(|e| e.into_inner());
+    match *event {
+        PumpEvent::Connect {
+            peer_id,
+            ref address,
+        } => {
+            directory.insert(ClusterPeerId { shard, peer_id }, address.clone());
+        }
+        PumpEvent::Disconnect { peer_id, .. } | PumpEvent::ConnectTimeout { peer_id } => {
+            directory.remove(&ClusterPeerId { shard, peer_id });
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cluster;
+    use crate::pump::PumpEvent;
+    use crate::tests::ENET;
+    use crate::{Address, BandwidthLimit, ChannelLimit, EventKind, PacketMode};
+
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    #[test]
+    fn test_cluster() {
+        let addresses = [
+            Address::new(Ipv4Addr::LOCALHOST, 12383),
+            Address::new(Ipv4Addr::LOCALHOST, 12384),
+        ];
+        let cluster = Cluster::spawn(&ENET, &addresses, 4, ChannelLimit::Maximum).unwrap();
+        assert_eq!(cluster.shard_count(), 2);
+        assert!(Cluster::spawn(&ENET, &addresses[..1], 4, ChannelLimit::Maximum).is_err());
+
+        // Clients connect one after the other, so each shard gets one.
+        let mut clients = Vec::new();
+        let timeout = Some(Duration::from_millis(2));
+        for _ in 0..2 {
+            let mut client = ENET
+                .create_host::<()>(
+                    None,
+                    1,
+                    ChannelLimit::Maximum,
+                    BandwidthLimit::Unlimited,
+                    BandwidthLimit::Unlimited,
+                )
+                .unwrap();
+            let shard = cluster.least_loaded();
+            client
+                .connect(cluster.shard_address(shard).unwrap(), 1, 0)
+                .unwrap();
+
+            let mut connected = None;
+            for _ in 0..500 {
+                client.service(timeout).unwrap();
+                if let Some(event) = cluster.next_event(Duration::from_millis(1)) {
+                    if let PumpEvent::Connect { .. } = event.event {
+                        connected = Some(event.shard);
+                        break;
+                    }
+                }
+            }
+            assert_eq!(connected, Some(shard));
+            clients.push(client);
+        }
+
+        let peers = cluster.peers();
+        assert_eq!(peers.len(), 2);
+        assert_ne!(peers[0].0.shard, peers[1].0.shard);
+        assert_eq!(cluster.find_peer(&peers[1].1), Some(peers[1].0));
+
+        cluster.broadcast(0, b"everyone", PacketMode::ReliableSequenced);
+        let mut received = 0;
+        for _ in 0..500 {
+            for client in &mut clients {
+                if let Some(event) = client.service(timeout).unwrap() {
+                    if let EventKind::Receive { ref packet, .. } = event.kind {
+                        assert_eq!(packet.data(), b"everyone");
+                        received += 1;
+                    }
+                }
+            }
+            if received == 2 {
+                break;
+            }
+        }
+        assert_eq!(received, 2);
+        cluster.shutdown().unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_shared_address() {
+        let address = Address::new(Ipv4Addr::LOCALHOST, 12429);
+        let cluster = Cluster::spawn_shared(&ENET, &address, 2, 4, ChannelLimit::Maximum).unwrap();
+        assert_eq!(cluster.shard_address(0), Some(&address));
+        assert_eq!(cluster.shard_address(1), Some(&address));
+
+        let mut clients: Vec<_> = (0..4)
+            .map(|_| {
+                let mut client = ENET
+                    .create_host::<()>(
+                        None,
+                        1,
+                        ChannelLimit::Maximum,
+                        BandwidthLimit::Unlimited,
+                        BandwidthLimit::Unlimited,
+                    )
+                    .unwrap();
+                client.connect(&address, 1, 0).unwrap();
+                client
+            })
+            .collect();
+
+        let timeout = Some(Duration::from_millis(2));
+        for _ in 0..500 {
+            for client in &mut clients {
+                client.service(timeout).unwrap();
+            }
+            while cluster.next_event(Duration::ZERO).is_some() {}
+            if cluster.peers().len() == clients.len() {
+                break;
+            }
+        }
+        assert_eq!(cluster.peers().len(), clients.len());
+        cluster.shutdown().unwrap();
+    }
+}
@@ -0,0 +1,255 @@
+//! Batching of small messages into fewer packets.
+//!
+//! Every ENet packet carries a protocol header, and every datagram a UDP and IP header, so games
+//! that send many tiny messages spend much of their bandwidth on overhead. A
+//! [Coalescer](struct.Coalescer.html) collects the messages for each peer and channel, and sends
+//! them as one packet when `Coalescer::flush` is called, e.g. once per service interval, like
+//! Nagle's algorithm in TCP. The receiver splits the packets again with
+//! [messages](fn.messages.html).
+//!
+//! A batch is the magic prefix, followed by every message, prefixed with its length as a
+//! LEB128 varint.
+
+use std::collections::HashMap;
+
+use crate::{Event, Host, Packet, PacketMode, PeerID, PeerState, SendError};
+
+/// Marks batched packets.
+const MAGIC: &[u8] = b"\xffCOA";
+
+fn write_len(data: &mut Vec<u8>, mut len: usize) {
+    while len >= 0x80 {
+        data.push(len as u8 | 0x80);
+        len >>= 7;
+    }
+    data.push(len as u8);
+}
+
+fn read_len(data: &[u8]) -> Option<(usize, &[u8])> {
+    let mut len = 0usize;
+    for (i, &byte) in data.iter().enumerate().take(5) {
+        len |= usize::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((len, &data[i + 1..]));
+        }
+    }
+    None
+}
+
+/// Returns the messages batched into `data` by a `Coalescer`, in the order they were sent.
+///
+/// Returns `None` if `data` is not a batch, or if it is malformed.
+pub fn messages(data: &[u8]) -> Option<Vec<&[u8]>> {
+    let mut rest = data.strip_prefix(MAGIC)?;
+    let mut messages = Vec::new();
+    while !rest.is_empty() {
+        let (len, after) = read_len(rest)?;
+        if after.len() < len {
+            return None;
+        }
+        let (message, after) = after.split_at(len);
+        messages.push(message);
+        rest = after;
+    }
+    Some(messages)
+}
+
+/// Collects small messages per peer and channel, and sends each batch as one packet.
+///
+/// Batches are sent by `Coalescer::flush`, or as soon as they reach the maximum packet size.
+/// Messages that need to go out right away can be sent with `Coalescer::flush_now` after them.
+#[derive(Debug)]
+pub struct Coalescer {
+    max_packet_size: usize,
+    batches: HashMap<(PeerID, u8, PacketMode), Vec<u8>>,
+}
+
+impl Coalescer {
+    /// Creates a coalescer that sends a batch once it reaches `max_packet_size` bytes.
+    ///
+    /// Batches that fit the MTU of the connection, i.e. about 1200 bytes, are not fragmented by
+    /// ENet.
+    pub fn new(max_packet_size: usize) -> Coalescer {
+        Coalescer {
+            max_packet_size,
+            batches: HashMap::new(),
+        }
+    }
+
+    /// Adds `data` to the batch for `peer_id` on `channel_id` with `mode`.
+    ///
+    /// Fails right away like `Peer::send_packet` if the peer is not connected or the channel is
+    /// not allocated. Fails like it if a full batch was sent and ENet did not take it.
+    pub fn send<T>(
+        &mut self,
+        host: &mut Host<T>,
+        peer_id: PeerID,
+        channel_id: u8,
+        data: &[u8],
+        mode: PacketMode,
+    ) -> Result<(), SendError> {
+        let peer = match host.peer(peer_id) {
+            Some(peer) => peer,
+            None => return Err(SendError::NotConnected(PeerState::Disconnected)),
+        };
+        match peer.state() {
+            PeerState::Connected => (),
+            state => return Err(SendError::NotConnected(state)),
+        }
+        if usize::from(channel_id) >= peer.channel_count() {
+            return Err(SendError::InvalidChannel(channel_id));
+        }
+
+        let key = (peer_id, channel_id, mode);
+        let mut message = Vec::with_capacity(data.len() + 5);
+        write_len(&mut message, data.len());
+        message.extend_from_slice(data);
+
+        let batch_len = self.batches.get(&key).map_or(0, Vec::len);
+        if batch_len > 0 && batch_len + message.len() > self.max_packet_size {
+            self.send_batch(host, key)?;
+        }
+
+        let batch = self.batches.entry(key).or_insert_with(|| MAGIC.to_vec());
+        batch.extend_from_slice(&message);
+        if batch.len() >= self.max_packet_size {
+            self.send_batch(host, key)?;
+        }
+        Ok(())
+    }
+
+    fn send_batch<T>(
+        &mut self,
+        host: &mut Host<T>,
+        key: (PeerID, u8, PacketMode),
+    ) -> Result<(), SendError> {
+        let (peer_id, channel_id, mode) = key;
+        let data = match self.batches.remove(&key) {
+            Some(data) => data,
+            None => return Ok(()),
+        };
+        let packet = Packet::new(data, mode).map_err(|err| SendError::Error(err.0))?;
+        match host.peer_mut(peer_id) {
+            Some(peer) => peer.send_packet(packet, channel_id),
+            None => Ok(()),
+        }
+    }
+
+    /// Sends the batch for `peer_id` on `channel_id` with `mode` right away, e.g. after an urgent
+    /// message.
+    pub fn flush_now<T>(
+        &mut self,
+        host: &mut Host<T>,
+        peer_id: PeerID,
+        channel_id: u8,
+        mode: PacketMode,
+    ) -> Result<(), SendError> {
+        self.send_batch(host, (peer_id, channel_id, mode))
+    }
+
+    /// Sends all batches, call this once per service interval, e.g. before every `Host::service`.
+    ///
+    /// Returns the number of packets sent. Batches that can't be sent, e.g. because their peer
+    /// disconnected, are dropped.
+    pub fn flush<T>(&mut self, host: &mut Host<T>) -> usize {
+        let keys: Vec<_> = self.batches.keys().cloned().collect();
+        keys.into_iter()
+            .filter(|&key| self.send_batch(host, key).is_ok())
+            .count()
+    }
+
+    /// Returns the number of bytes waiting to be sent to `peer_id`, including the batch headers.
+    pub fn pending(&self, peer_id: PeerID) -> usize {
+        self.batches
+            .iter()
+            .filter(|((id, _, _), _)| *id == peer_id)
+            .map(|(_, batch)| batch.len())
+            .sum()
+    }
+
+    /// Processes an event returned by `Host::service`, dropping the batches of peers that
+    /// disconnect.
+    ///
+    /// Always returns `false`, the event still needs handling.
+    pub fn handle_event(&mut self, event: &Event) -> bool {
+        if event.kind.is_disconnect() {
+            self.batches
+                .retain(|&(peer_id, _, _), _| peer_id != event.peer_id);
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{messages, write_len, Coalescer, MAGIC};
+    use crate::tests::{connected_pair, pump_until};
+    use crate::{EventKind, PacketMode, PeerID, PeerState, SendError};
+
+    #[test]
+    fn test_messages() {
+        let mut data = MAGIC.to_vec();
+        write_len(&mut data, 2);
+        data.extend_from_slice(b"hi");
+        write_len(&mut data, 300);
+        data.extend_from_slice(&[7; 300]);
+        assert_eq!(data.len(), MAGIC.len() + 1 + 2 + 2 + 300);
+
+        assert_eq!(messages(&data), Some(vec![&b"hi"[..], &[7; 300][..]]));
+        assert_eq!(messages(MAGIC), Some(vec![]));
+        assert_eq!(messages(&data[..data.len() - 1]), None);
+        assert_eq!(messages(b"hi"), None);
+    }
+
+    #[test]
+    fn test_coalescer() {
+        let (mut server, mut client, _, peer_id) = connected_pair(12390, 1);
+
+        let mode = PacketMode::ReliableSequenced;
+        let mut coalescer = Coalescer::new(100);
+        for i in 0..30u8 {
+            coalescer
+                .send(&mut client, peer_id, 0, &[i; 5], mode)
+                .unwrap();
+        }
+        // Every batch holds 16 messages of 6 bytes after the header, so the first one was sent.
+        assert_eq!(coalescer.pending(peer_id), MAGIC.len() + 14 * 6);
+        assert_eq!(coalescer.flush(&mut client), 1);
+        assert_eq!(coalescer.pending(peer_id), 0);
+
+        let mut received = Vec::new();
+        let mut packets = 0;
+        pump_until(&mut [&mut client, &mut server], |_, _, event| {
+            if let EventKind::Receive { ref packet, .. } = event.kind {
+                packets += 1;
+                for message in messages(packet.data()).unwrap() {
+                    received.push(message[0]);
+                }
+            }
+            received.len() == 30
+        });
+        assert_eq!(packets, 2);
+        assert_eq!(received, (0..30).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_unknown_peer() {
+        let (_server, mut client, _, peer_id) = connected_pair(12428, 1);
+        let unknown = PeerID {
+            index: 1,
+            ..peer_id
+        };
+
+        let mut coalescer = Coalescer::new(1000);
+        assert!(matches!(
+            coalescer.send(
+                &mut client,
+                unknown,
+                0,
+                b"hi",
+                PacketMode::ReliableSequenced
+            ),
+            Err(SendError::NotConnected(PeerState::Disconnected))
+        ));
+    }
+}
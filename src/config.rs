@@ -0,0 +1,503 @@
+use std::fs;
+use std::io;
+use std::net::SocketAddrV4;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::{
+    Address, BandwidthLimit, ChannelConfig, ChannelLimit, ConnectionLimit, QueueLimit, QueuePolicy,
+    RateLimit,
+};
+
+/// When ENet gives up on a peer that doesn't acknowledge reliable packets, see `Peer::set_timeout`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PeerTimeout {
+    /// A factor of the round trip time, after which unacknowledged packets time out the peer.
+    pub limit: u32,
+    /// Unacknowledged packets never time out the peer before this.
+    pub minimum: Duration,
+    /// Unacknowledged packets always time out the peer after this.
+    pub maximum: Duration,
+}
+
+impl Default for PeerTimeout {
+    /// ENet's defaults.
+    fn default() -> PeerTimeout {
+        PeerTimeout {
+            limit: 32,
+            minimum: Duration::from_secs(5),
+            maximum: Duration::from_secs(30),
+        }
+    }
+}
+
+/// The settings of a `Host`, see `Enet::create_host_from_config`.
+///
+/// Dedicated servers can load them from a file with `HostConfig::load`, in a subset of TOML: one
+/// `key = value` per line, with `#` comments. Numbers are plain integers, the address is a quoted
+/// `"ip:port"` string. Bandwidths of 0 are unlimited, and `channels = "max"` is the maximum.
+///
+/// ```text
+/// address = "0.0.0.0:7777"
+/// peers = 64
+/// channels = 4
+/// outgoing_bandwidth = 1000000
+/// timeout_maximum_ms = 10000   # the other timeouts keep ENet's defaults
+/// connections_per_ip = 4
+/// queue_limit_bytes = 1048576  # rejects sends over the limit
+/// busy_poll_us = 200
+/// ```
+///
+/// The keys `incoming_bandwidth`, `timeout_limit`, `timeout_minimum_ms` and `compression`
+/// (`true` or `false`) are supported too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostConfig {
+    /// The address to listen on, `None` for clients.
+    pub address: Option<Address>,
+    /// The number of peers the host has room for.
+    pub peer_count: usize,
+    /// The channel limit of future connections.
+    pub channel_limit: ChannelLimit,
+    /// The downstream bandwidth of the host.
+    pub incoming_bandwidth: BandwidthLimit,
+    /// The upstream bandwidth of the host.
+    pub outgoing_bandwidth: BandwidthLimit,
+    /// The timeout of all peers, see `Host::set_peer_timeout`. `None` keeps ENet's defaults.
+    pub peer_timeout: Option<PeerTimeout>,
+    /// The connection limit, see `Host::set_connection_limit`.
+    pub connection_limit: Option<ConnectionLimit>,
+    /// The queue limit of all peers, see `Host::set_queue_limit`.
+    pub queue_limit: Option<QueueLimit>,
+    /// How long `Host::service` spins before sleeping, see `Host::set_busy_poll`.
+    pub busy_poll: Option<Duration>,
+    /// Whether packets are compressed, see `Host::set_compression`.
+    pub compression: bool,
+    /// The defaults of the channels, see `Host::set_channel_configs`. They can't be set from a
+    /// file, and are left unset if empty.
+    pub channel_configs: Vec<ChannelConfig>,
+}
+
+impl Default for HostConfig {
+    fn default() -> HostConfig {
+        HostConfig {
+            address: None,
+            peer_count: 32,
+            channel_limit: ChannelLimit::Maximum,
+            incoming_bandwidth: BandwidthLimit::Unlimited,
+            outgoing_bandwidth: BandwidthLimit::Unlimited,
+            peer_timeout: None,
+            connection_limit: None,
+            queue_limit: None,
+            busy_poll: None,
+            compression: false,
+            channel_configs: Vec::new(),
+        }
+    }
+}
+
+/// An error that can occur while loading a `HostConfig`.
+#[derive(Fail, Debug)]
+pub enum ConfigError {
+    /// The file could not be read.
+    #[fail(display = "could not read the host config: {}", _0)]
+    Io(#[cause] io::Error),
+    /// A line is not of the form `key = value`.
+    #[fail(display = "line {} of the host config is not `key = value`", line)]
+    Syntax {
+        /// The line, counting from 1.
+        line: usize,
+    },
+    /// A line sets a key that is not a setting.
+    #[fail(
+        display = "line {} of the host config sets the unknown key `{}`",
+        line, key
+    )]
+    UnknownKey {
+        /// The line, counting from 1.
+        line: usize,
+        /// The key.
+        key: String,
+    },
+    /// A line sets a key to a value it can't have.
+    #[fail(
+        display = "line {} of the host config has an invalid value for `{}`",
+        line, key
+    )]
+    InvalidValue {
+        /// The line, counting from 1.
+        line: usize,
+        /// The key.
+        key: String,
+    },
+}
+
+impl HostConfig {
+    /// The settings of a client with `channel_count` channels, connecting to a server created from
+    /// `HostConfig::server`.
+    ///
+    /// ```no_run
+    /// # use enet::*;
+    /// # use std::net::Ipv4Addr;
+    /// let enet = Enet::new().unwrap();
+    /// let mut host = enet.create_host_from_config::<()>(&HostConfig::client(2)).unwrap();
+    /// host.connect(&Address::new(Ipv4Addr::LOCALHOST, 7777), 2, 0).unwrap();
+    /// ```
+    pub fn client(channel_count: usize) -> HostConfig {
+        HostConfig {
+            peer_count: 1,
+            ..HostConfig::server(None, 1, channel_count)
+        }
+    }
+
+    /// The settings of a server listening on `address`, for up to `max_peers` clients with up to
+    /// `channel_count` channels each.
+    ///
+    /// Bandwidth is unlimited and packets are compressed, so the clients must be compressed too,
+    /// like the ones from `HostConfig::client`. Peers time out after at most 10 seconds without
+    /// acknowledgements, instead of ENet's 30.
+    pub fn server(
+        address: impl Into<Option<Address>>,
+        max_peers: usize,
+        channel_count: usize,
+    ) -> HostConfig {
+        HostConfig {
+            address: address.into(),
+            peer_count: max_peers,
+            channel_limit: ChannelLimit::Limited(channel_count),
+            peer_timeout: Some(PeerTimeout {
+                maximum: Duration::from_secs(10),
+                ..PeerTimeout::default()
+            }),
+            compression: true,
+            ..HostConfig::default()
+        }
+    }
+
+    /// Reads the settings from the file at `path`, see `HostConfig::parse`.
+    pub fn load(path: impl AsRef<Path>) -> Result<HostConfig, ConfigError> {
+        let text = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        HostConfig::parse(&text)
+    }
+
+    /// Parses the settings from `text`. Keys that are not set keep their defaults.
+    pub fn parse(text: &str) -> Result<HostConfig, ConfigError> {
+        let mut config = HostConfig::default();
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => return Err(ConfigError::Syntax { line: line_number }),
+            };
+            if config.set(key, value).is_none() {
+                let key = key.to_string();
+                return Err(if KEYS.contains(&key.as_str()) {
+                    ConfigError::InvalidValue {
+                        line: line_number,
+                        key,
+                    }
+                } else {
+                    ConfigError::UnknownKey {
+                        line: line_number,
+                        key,
+                    }
+                });
+            }
+        }
+        Ok(config)
+    }
+
+    /// Sets `key` to `value`, returning `None` if either is invalid.
+    fn set(&mut self, key: &str, value: &str) -> Option<()> {
+        let number = || value.parse::<u64>().ok();
+        let bandwidth = || match value.parse::<u32>().ok()? {
+            0 => Some(BandwidthLimit::Unlimited),
+            limit => Some(BandwidthLimit::Limited(limit)),
+        };
+        let millis = || number().map(Duration::from_millis);
+        match key {
+            "address" => {
+                let address = value.strip_prefix('"')?.strip_suffix('"')?;
+                self.address = Some(address.parse::<SocketAddrV4>().ok()?.into());
+            }
+            "peers" => self.peer_count = value.parse().ok()?,
+            "channels" => {
+                self.channel_limit = match value {
+                    "\"max\"" => ChannelLimit::Maximum,
+                    _ => ChannelLimit::Limited(value.parse().ok()?),
+                }
+            }
+            "incoming_bandwidth" => self.incoming_bandwidth = bandwidth()?,
+            "outgoing_bandwidth" => self.outgoing_bandwidth = bandwidth()?,
+            "timeout_limit" => self.timeout().limit = value.parse().ok()?,
+            "timeout_minimum_ms" => self.timeout().minimum = millis()?,
+            "timeout_maximum_ms" => self.timeout().maximum = millis()?,
+            "connections_per_ip" => {
+                let limit = self.connection_limit.get_or_insert(ConnectionLimit {
+                    per_ip: None,
+                    per_subnet: None,
+                    reject_data: 0,
+                });
+                limit.per_ip = Some(value.parse().ok()?);
+            }
+            "queue_limit_bytes" => {
+                self.queue_limit = Some(QueueLimit {
+                    max_bytes: value.parse().ok()?,
+                    policy: QueuePolicy::Reject,
+                })
+            }
+            "busy_poll_us" => self.busy_poll = Some(Duration::from_micros(number()?)),
+            "compression" => self.compression = value.parse().ok()?,
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn timeout(&mut self) -> &mut PeerTimeout {
+        self.peer_timeout.get_or_insert_with(PeerTimeout::default)
+    }
+}
+
+/// The keys `HostConfig::parse` understands.
+const KEYS: &[&str] = &[
+    "address",
+    "peers",
+    "channels",
+    "incoming_bandwidth",
+    "outgoing_bandwidth",
+    "timeout_limit",
+    "timeout_minimum_ms",
+    "timeout_maximum_ms",
+    "connections_per_ip",
+    "queue_limit_bytes",
+    "busy_poll_us",
+    "compression",
+];
+
+/// Cuts off a `#` comment, unless it is inside a quoted string.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..index],
+            _ => (),
+        }
+    }
+    line
+}
+
+/// Settings changed through a `HostConfigHandle`, not applied yet.
+#[derive(Debug, Default)]
+pub(crate) struct PendingConfig {
+    pub(crate) bandwidth_limits: Option<(BandwidthLimit, BandwidthLimit)>,
+    pub(crate) channel_limit: Option<ChannelLimit>,
+    pub(crate) rate_limits: Vec<(u8, Option<RateLimit>)>,
+    pub(crate) connection_limit: Option<Option<ConnectionLimit>>,
+    pub(crate) peer_timeout: Option<PeerTimeout>,
+}
+
+/// Changes the settings of a running `Host` from any thread, see `Host::config_handle`.
+///
+/// Changes are applied by the next `Host::service`, in the order they were made.
+#[derive(Debug, Clone)]
+pub struct HostConfigHandle {
+    pending: Arc<Mutex<PendingConfig>>,
+}
+
+impl HostConfigHandle {
+    pub(crate) fn new(pending: Arc<Mutex<PendingConfig>>) -> HostConfigHandle {
+        HostConfigHandle { pending }
+    }
+
+    fn change(&self, change: impl FnOnce(&mut PendingConfig)) {
+        change(&mut self.pending.lock().unwrap_or_else(|e| e.into_inner()));
+    }
+
+    /// Changes the bandwidth limits, see `Host::set_bandwith_limits`.
+    pub fn set_bandwith_limits(&self, incoming: BandwidthLimit, outgoing: BandwidthLimit) {
+        self.change(|pending| pending.bandwidth_limits = Some((incoming, outgoing)));
+    }
+
+    /// Changes the channel limit of future connections, see `Host::set_channel_limit`.
+    pub fn set_channel_limit(&self, limit: ChannelLimit) {
+        self.change(|pending| pending.channel_limit = Some(limit));
+    }
+
+    /// Changes the rate limit of `channel_id`, see `Host::set_rate_limit`.
+    pub fn set_rate_limit(&self, channel_id: u8, limit: Option<RateLimit>) {
+        self.change(|pending| pending.rate_limits.push((channel_id, limit)));
+    }
+
+    /// Changes the connection limit, see `Host::set_connection_limit`.
+    pub fn set_connection_limit(&self, limit: Option<ConnectionLimit>) {
+        self.change(|pending| pending.connection_limit = Some(limit));
+    }
+
+    /// Changes the timeout of all peers, see `Host::set_peer_timeout`.
+    pub fn set_peer_timeout(&self, timeout: PeerTimeout) {
+        self.change(|pending| pending.peer_timeout = Some(timeout));
+    }
+
+    pub(crate) fn take(pending: &Mutex<PendingConfig>) -> PendingConfig {
+        std::mem::take(&mut pending.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConfigError, HostConfig, PeerTimeout};
+    use crate::tests::ENET;
+    use crate::{Address, BandwidthLimit, ChannelLimit, EventKind, Packet, PacketMode};
+    use crate::{QueueLimit, QueuePolicy};
+    use crate::{RateLimit, RateLimitAction};
+
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    #[test]
+    fn test_config_handle() {
+        let mut host = ENET
+            .create_host::<()>(
+                None,
+                2,
+                ChannelLimit::Maximum,
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap();
+        let peer_id = host
+            .connect(&Address::new(Ipv4Addr::LOCALHOST, 12381), 1, 0)
+            .unwrap()
+            .1;
+
+        let handle = host.config_handle();
+        let timeout = PeerTimeout {
+            limit: 8,
+            minimum: Duration::from_secs(2),
+            maximum: Duration::from_secs(4),
+        };
+        std::thread::spawn(move || {
+            handle.set_bandwith_limits(BandwidthLimit::Limited(1000), BandwidthLimit::Unlimited);
+            handle.set_channel_limit(ChannelLimit::Limited(4));
+            handle.set_rate_limit(
+                1,
+                Some(RateLimit {
+                    messages_per_second: 10,
+                    bytes_per_second: 100,
+                    action: RateLimitAction::Drop,
+                }),
+            );
+            handle.set_peer_timeout(timeout);
+        })
+        .join()
+        .unwrap();
+
+        // Nothing changes until the host is serviced.
+        assert_eq!(host.incoming_bandwidth(), 0);
+        host.service(Some(Duration::ZERO)).unwrap();
+        assert_eq!(host.incoming_bandwidth(), 1000);
+        assert_eq!(host.channel_limit(), ChannelLimit::Limited(4));
+        assert_eq!(host[peer_id].timeout(), timeout);
+    }
+
+    #[test]
+    fn test_host_config() {
+        let text = "
+            # A dedicated server.
+            address = \"127.0.0.1:12400\" # the port of the test
+            peers = 4
+            channels = 3
+            outgoing_bandwidth = 1000
+            incoming_bandwidth = 0
+            timeout_maximum_ms = 10000
+            connections_per_ip = 2
+            queue_limit_bytes = 4096
+            compression = true
+        ";
+        let config = HostConfig::parse(text).unwrap();
+        assert_eq!(
+            config.address,
+            Some(Address::new(Ipv4Addr::LOCALHOST, 12400))
+        );
+        assert_eq!(config.peer_count, 4);
+        assert_eq!(config.outgoing_bandwidth, BandwidthLimit::Limited(1000));
+        assert_eq!(config.incoming_bandwidth, BandwidthLimit::Unlimited);
+        let timeout = PeerTimeout {
+            maximum: Duration::from_secs(10),
+            ..PeerTimeout::default()
+        };
+        assert_eq!(config.peer_timeout, Some(timeout));
+        assert_eq!(config.connection_limit.unwrap().per_ip, Some(2));
+        let queue_limit = QueueLimit {
+            max_bytes: 4096,
+            policy: QueuePolicy::Reject,
+        };
+        assert_eq!(config.queue_limit, Some(queue_limit));
+        assert!(config.compression);
+
+        let mut host = ENET.create_host_from_config::<()>(&config).unwrap();
+        assert_eq!(host.address(), Address::new(Ipv4Addr::LOCALHOST, 12400));
+        assert_eq!(host.peer_count(), 4);
+        assert_eq!(host.channel_limit(), ChannelLimit::Limited(3));
+        assert_eq!(host.outgoing_bandwidth(), 1000);
+        let peer_id = host
+            .connect(&Address::new(Ipv4Addr::LOCALHOST, 12401), 1, 0)
+            .unwrap()
+            .1;
+        assert_eq!(host[peer_id].timeout(), timeout);
+
+        match HostConfig::parse("peers = 4\nchannels = many") {
+            Err(ConfigError::InvalidValue { line: 2, ref key }) if key == "channels" => (),
+            result => panic!("unexpected result {:?}", result),
+        }
+        match HostConfig::parse("encryption = true") {
+            Err(ConfigError::UnknownKey { line: 1, .. }) => (),
+            result => panic!("unexpected result {:?}", result),
+        }
+        match HostConfig::parse("\n\npeers") {
+            Err(ConfigError::Syntax { line: 3 }) => (),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_presets() {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12405);
+        let server_config = HostConfig::server(server_address.clone(), 4, 2);
+        assert_eq!(server_config.channel_limit, ChannelLimit::Limited(2));
+        let mut server = ENET.create_host_from_config::<()>(&server_config).unwrap();
+        let mut client = ENET
+            .create_host_from_config::<()>(&HostConfig::client(2))
+            .unwrap();
+        assert_eq!(client.peer_count(), 1);
+        client.connect(&server_address, 2, 0).unwrap();
+
+        // Compressible data arrives intact.
+        let timeout = Some(Duration::from_millis(2));
+        let mut received = None;
+        for _ in 0..500 {
+            if let Some(event) = client.service(timeout).unwrap() {
+                if let EventKind::Connect = event.kind {
+                    let packet = Packet::new(vec![7; 1000], PacketMode::ReliableSequenced);
+                    client[event.peer_id]
+                        .send_packet(packet.unwrap(), 1)
+                        .unwrap();
+                }
+            }
+            if let Some(event) = server.service(timeout).unwrap() {
+                if let EventKind::Receive { packet, .. } = event.kind {
+                    received = Some(packet.data().to_vec());
+                    break;
+                }
+            }
+        }
+        assert!(received == Some(vec![7; 1000]));
+        // 1000 bytes of the same value compress to far fewer.
+        assert!(server.diagnostics().total_received_bytes < 500);
+    }
+}
@@ -0,0 +1,151 @@
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+
+use crate::intercept::is_connect;
+use crate::logging::{self, log_debug};
+use crate::{Datagram, Intercept, InterceptAction};
+
+/// A limit on the peers that connect to a `Host` from the same machine or network, see
+/// `Host::set_connection_limit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ConnectionLimit {
+    /// The number of peers one IP address may have at once.
+    pub per_ip: Option<usize>,
+    /// The prefix length of subnets, and the number of peers one subnet may have at once, e.g.
+    /// `Some((24, 16))` for 16 peers per `/24`.
+    pub per_subnet: Option<(u8, usize)>,
+    /// The data peers over the limit are disconnected with.
+    pub reject_data: u32,
+}
+
+impl ConnectionLimit {
+    /// Returns whether a new peer at `address` is within the limit, given the addresses of the
+    /// other peers.
+    pub(crate) fn admits(&self, address: Ipv4Addr, others: impl Iterator<Item = Ipv4Addr>) -> bool {
+        let mask = |prefix: u8| match prefix {
+            0 => 0,
+            prefix => u32::MAX << (32 - u32::from(prefix.min(32))),
+        };
+        let subnet = self
+            .per_subnet
+            .map(|(prefix, limit)| (mask(prefix), u32::from(address) & mask(prefix), limit));
+
+        let (mut same_ip, mut same_subnet) = (0, 0);
+        for other in others {
+            if other == address {
+                same_ip += 1;
+            }
+            if subnet.is_some_and(|(mask, network, _)| u32::from(other) & mask == network) {
+                same_subnet += 1;
+            }
+        }
+
+        self.per_ip.map_or(true, |limit| same_ip < limit)
+            && subnet.map_or(true, |(_, _, limit)| same_subnet < limit)
+    }
+}
+
+/// The connection limit of a `Host`, shared with its `ConnectionLimitIntercept`.
+pub(crate) type SharedConnectionLimit = Arc<Mutex<Option<ConnectionLimit>>>;
+
+/// Drops connects from addresses that are over the shared limit, see `Host::set_connection_limit`.
+pub(crate) struct ConnectionLimitIntercept(pub(crate) SharedConnectionLimit);
+
+impl Intercept for ConnectionLimitIntercept {
+    fn intercept(&mut self, datagram: &mut Datagram) -> InterceptAction {
+        if !is_connect(datagram.data()) {
+            return InterceptAction::Continue;
+        }
+        let limit = match *self.0.lock().unwrap() {
+            Some(limit) => limit,
+            None => return InterceptAction::Continue,
+        };
+
+        let address = *datagram.address().ip();
+        if limit.admits(address, datagram.peer_ips()) {
+            InterceptAction::Continue
+        } else {
+            log_debug!(
+                target: logging::DROP,
+                "dropped a connect from {}, it is over the connection limit",
+                address
+            );
+            InterceptAction::Drop
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConnectionLimit;
+    use crate::tests::{create_host, pump_until};
+    use crate::{Address, EventKind};
+
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    #[test]
+    fn test_admits() {
+        let limit = ConnectionLimit {
+            per_ip: Some(2),
+            per_subnet: Some((24, 3)),
+            reject_data: 0,
+        };
+        let address = Ipv4Addr::new(10, 0, 0, 1);
+        let neighbour = Ipv4Addr::new(10, 0, 0, 2);
+        let stranger = Ipv4Addr::new(10, 0, 1, 1);
+
+        assert!(limit.admits(address, vec![address, stranger, stranger].into_iter()));
+        assert!(!limit.admits(address, vec![address, address].into_iter()));
+        assert!(!limit.admits(address, vec![address, neighbour, neighbour].into_iter()));
+
+        let unlimited = ConnectionLimit {
+            per_ip: None,
+            per_subnet: Some((0, 5)),
+            reject_data: 0,
+        };
+        assert!(unlimited.admits(address, vec![address; 4].into_iter()));
+        assert!(!unlimited.admits(address, vec![stranger; 5].into_iter()));
+    }
+
+    #[test]
+    fn test_connection_limit() {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12379);
+        let mut server = create_host(Some(&server_address), 4);
+        server.set_connection_limit(Some(ConnectionLimit {
+            per_ip: Some(1),
+            per_subnet: None,
+            reject_data: 3,
+        }));
+
+        let mut first = create_host(None, 1);
+        first.connect(&server_address, 1, 0).unwrap();
+        let mut connects = 0;
+        pump_until(&mut [&mut server, &mut first], |_, _, event| {
+            connects += matches!(event.kind, EventKind::Connect) as usize;
+            connects == 2
+        });
+
+        // The connect of the second host is dropped, it never gets a response.
+        let mut second = create_host(None, 1);
+        second.connect(&server_address, 1, 0).unwrap();
+        let timeout = Some(Duration::from_millis(2));
+        let mut events = Vec::new();
+        for _ in 0..50 {
+            first.service(timeout).unwrap();
+            if let Some(event) = second.service(timeout).unwrap() {
+                events.push(event.kind);
+            }
+            if let Some(event) = server.service(timeout).unwrap() {
+                events.push(event.kind);
+            }
+        }
+        assert!(events.is_empty());
+
+        // Once the limit is lifted, a retransmission of the connect gets through.
+        server.set_connection_limit(None);
+        pump_until(&mut [&mut second, &mut server], |index, _, event| {
+            index == 0 && matches!(event.kind, EventKind::Connect)
+        });
+    }
+}
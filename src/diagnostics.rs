@@ -0,0 +1,486 @@
+use std::fmt::Write;
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use crate::{
+    Address, BandwidthLimit, ChannelLimit, ChannelTraffic, Enet, Error, EventKind, Host, Packet,
+    PacketMode, PeerID, PeerState,
+};
+
+/// The number of echoes timed by `Enet::benchmark`.
+const ROUND_TRIPS: u32 = 20;
+
+/// A snapshot of a `Host`'s state, see `Host::diagnostics`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostDiagnostics {
+    /// The address the host is bound to.
+    pub address: Address,
+    /// The number of peers the host has room for.
+    pub peer_slots: usize,
+    /// The number of connected peers.
+    pub connected_peers: usize,
+    /// The channel limit of the host.
+    pub channel_limit: ChannelLimit,
+    /// The downstream bandwidth of the host in bytes/second, 0 if unlimited.
+    pub incoming_bandwidth: u32,
+    /// The upstream bandwidth of the host in bytes/second, 0 if unlimited.
+    pub outgoing_bandwidth: u32,
+    /// The MTU of the host.
+    pub mtu: u32,
+    /// The bytes sent, as counted by ENet, wrapping.
+    pub total_sent_bytes: u32,
+    /// The datagrams sent, as counted by ENet, wrapping.
+    pub total_sent_packets: u32,
+    /// The bytes received, as counted by ENet, wrapping.
+    pub total_received_bytes: u32,
+    /// The datagrams received, as counted by ENet, wrapping.
+    pub total_received_packets: u32,
+    /// The packets sent and received on each channel, see `Host::channel_traffic`.
+    pub channel_traffic: Vec<ChannelTraffic>,
+    /// Every peer that is not disconnected.
+    pub peers: Vec<PeerDiagnostics>,
+}
+
+/// A snapshot of a `Peer`'s state, see `Host::diagnostics`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerDiagnostics {
+    /// The index of the peer among the host's peers.
+    pub index: usize,
+    /// The id of the peer's connection, see `Peer::connect_id`.
+    pub connect_id: u32,
+    /// The address of the peer.
+    pub address: Address,
+    /// The state of the peer.
+    pub state: PeerState,
+    /// The number of channels allocated for the peer.
+    pub channel_count: usize,
+    /// The mean round trip time, see `Peer::mean_rtt`.
+    pub mean_rtt: Duration,
+    /// The packet loss, see `Peer::packet_loss`.
+    pub packet_loss: f32,
+    /// The packet throttle, see `Peer::packet_throttle`.
+    pub packet_throttle: f32,
+    /// The estimated bandwidth, see `Peer::estimated_bandwidth`.
+    pub estimated_bandwidth: u32,
+    /// The reliable commands waiting to be sent, see `Peer::queued_reliable_commands`.
+    pub queued_reliable_commands: usize,
+    /// The reliable bytes not acknowledged yet, see `Peer::reliable_data_in_transit`.
+    pub reliable_data_in_transit: u32,
+    /// The time ENet last sent to the peer, see `Peer::last_send_time`.
+    pub last_send_time: u32,
+    /// The time ENet last received from the peer, see `Peer::last_receive_time`.
+    pub last_receive_time: u32,
+}
+
+impl HostDiagnostics {
+    pub(crate) fn new<T>(host: &Host<T>) -> HostDiagnostics {
+        let raw = unsafe { &*host.as_raw() };
+        let peers = host
+            .peer_ids()
+            .filter_map(|peer_id| {
+                let peer = &host[peer_id];
+                if peer.state() == PeerState::Disconnected {
+                    return None;
+                }
+
+                Some(PeerDiagnostics {
+                    index: peer_id.index,
+                    connect_id: peer.connect_id(),
+                    address: peer.address(),
+                    state: peer.state(),
+                    channel_count: peer.channel_count(),
+                    mean_rtt: peer.mean_rtt(),
+                    packet_loss: peer.packet_loss(),
+                    packet_throttle: peer.packet_throttle(),
+                    estimated_bandwidth: peer.estimated_bandwidth(),
+                    queued_reliable_commands: peer.queued_reliable_commands(),
+                    reliable_data_in_transit: peer.reliable_data_in_transit(),
+                    last_send_time: peer.last_send_time().as_millis(),
+                    last_receive_time: peer.last_receive_time().as_millis(),
+                })
+            })
+            .collect();
+
+        HostDiagnostics {
+            address: host.address(),
+            peer_slots: raw.peerCount,
+            connected_peers: raw.connectedPeers,
+            channel_limit: host.channel_limit(),
+            incoming_bandwidth: host.incoming_bandwidth(),
+            outgoing_bandwidth: host.outgoing_bandwidth(),
+            mtu: raw.mtu,
+            total_sent_bytes: raw.totalSentData,
+            total_sent_packets: raw.totalSentPackets,
+            total_received_bytes: raw.totalReceivedData,
+            total_received_packets: raw.totalReceivedPackets,
+            channel_traffic: host.channel_traffic(),
+            peers,
+        }
+    }
+
+    /// Returns the snapshot as JSON, e.g. to attach it to a bug report or serve it to an admin tool.
+    ///
+    /// Durations are in seconds, times in milliseconds of ENet's clock, and a channel limit of
+    /// `null` is the maximum.
+    pub fn to_json(&self) -> String {
+        let channel_limit = match self.channel_limit {
+            ChannelLimit::Maximum => "null".to_string(),
+            ChannelLimit::Limited(limit) => limit.to_string(),
+        };
+        let channel_traffic: Vec<_> = self
+            .channel_traffic
+            .iter()
+            .map(|traffic| {
+                format!(
+                    "{{\"sent_bytes\":{},\"sent_packets\":{},\"received_bytes\":{},\
+                     \"received_packets\":{}}}",
+                    traffic.sent_bytes,
+                    traffic.sent_packets,
+                    traffic.received_bytes,
+                    traffic.received_packets
+                )
+            })
+            .collect();
+        let peers: Vec<_> = self.peers.iter().map(PeerDiagnostics::to_json).collect();
+
+        let mut out = String::new();
+        let _ = write!(
+            out,
+            "{{\"address\":\"{}\",\"peer_slots\":{},\"connected_peers\":{},\"channel_limit\":{},\
+             \"incoming_bandwidth\":{},\"outgoing_bandwidth\":{},\"mtu\":{},\"total_sent_bytes\":{},\
+             \"total_sent_packets\":{},\"total_received_bytes\":{},\"total_received_packets\":{},\
+             \"channel_traffic\":[{}],\"peers\":[{}]}}",
+            self.address,
+            self.peer_slots,
+            self.connected_peers,
+            channel_limit,
+            self.incoming_bandwidth,
+            self.outgoing_bandwidth,
+            self.mtu,
+            self.total_sent_bytes,
+            self.total_sent_packets,
+            self.total_received_bytes,
+            self.total_received_packets,
+            channel_traffic.join(","),
+            peers.join(",")
+        );
+        out
+    }
+}
+
+impl PeerDiagnostics {
+    fn to_json(&self) -> String {
+        let mut out = String::new();
+        let _ = write!(
+            out,
+            "{{\"index\":{},\"connect_id\":{},\"address\":\"{}\",\"state\":\"{}\",\
+             \"channel_count\":{},\"mean_rtt\":{},\"packet_loss\":{},\"packet_throttle\":{},\
+             \"estimated_bandwidth\":{},\"queued_reliable_commands\":{},\
+             \"reliable_data_in_transit\":{},\"last_send_time\":{},\"last_receive_time\":{}}}",
+            self.index,
+            self.connect_id,
+            self.address,
+            self.state,
+            self.channel_count,
+            self.mean_rtt.as_secs_f64(),
+            self.packet_loss,
+            self.packet_throttle,
+            self.estimated_bandwidth,
+            self.queued_reliable_commands,
+            self.reliable_data_in_transit,
+            self.last_send_time,
+            self.last_receive_time
+        );
+        out
+    }
+}
+
+/// What `Enet::benchmark` sends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BenchmarkConfig {
+    /// The size of every packet in bytes.
+    pub packet_size: usize,
+    /// The number of packets sent to measure the throughput.
+    pub packet_count: usize,
+    /// The number of packets sent at once before the receiver is serviced.
+    pub burst: usize,
+    /// The mode of the packets.
+    pub mode: PacketMode,
+    /// How long the benchmark may take in total, after which it reports what it measured so far.
+    pub timeout: Duration,
+}
+
+impl Default for BenchmarkConfig {
+    fn default() -> BenchmarkConfig {
+        BenchmarkConfig {
+            packet_size: 64,
+            packet_count: 100_000,
+            burst: 100,
+            mode: PacketMode::UnreliableSequenced,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// What `Enet::benchmark` measured on this machine.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BenchmarkReport {
+    /// The packets sent.
+    pub packets_sent: usize,
+    /// The packets received. Unreliable packets are lost once the socket buffers overflow.
+    pub packets_received: usize,
+    /// How long sending and receiving the packets took.
+    pub elapsed: Duration,
+    /// The packets received per second.
+    pub packets_per_second: f64,
+    /// The bytes of packet data received per second.
+    pub bytes_per_second: f64,
+    /// The mean time of a `Host::service` call that returned a packet.
+    pub mean_service_time: Duration,
+    /// The longest `Host::service` call that returned a packet.
+    pub max_service_time: Duration,
+    /// The mean time for a packet to be echoed back, through both hosts, `None` if no echo
+    /// returned.
+    pub mean_round_trip: Option<Duration>,
+}
+
+impl BenchmarkReport {
+    /// Returns a one-line summary, e.g. to log it at startup.
+    pub fn summary(&self) -> String {
+        let round_trip = match self.mean_round_trip {
+            Some(rtt) => format!("{:.1} us", rtt.as_secs_f64() * 1e6),
+            None => "none".to_string(),
+        };
+        format!(
+            "{}/{} packets in {:.3} s, {:.0} packets/s, {:.1} MB/s, service {:.1} us mean, \
+             {:.1} us max, round trip {}",
+            self.packets_received,
+            self.packets_sent,
+            self.elapsed.as_secs_f64(),
+            self.packets_per_second,
+            self.bytes_per_second / 1e6,
+            self.mean_service_time.as_secs_f64() * 1e6,
+            self.max_service_time.as_secs_f64() * 1e6,
+            round_trip
+        )
+    }
+}
+
+/// An error that can occur in `Enet::benchmark`.
+#[derive(Fail, Debug)]
+pub enum BenchmarkError {
+    /// A loopback host could not be created, or servicing it failed.
+    #[fail(display = "loopback host failed: {}", _0)]
+    Host(#[cause] Error),
+    /// The loopback hosts did not connect to each other within the timeout.
+    #[fail(display = "the loopback hosts did not connect")]
+    NotConnected,
+}
+
+impl From<Error> for BenchmarkError {
+    fn from(error: Error) -> BenchmarkError {
+        BenchmarkError::Host(error)
+    }
+}
+
+pub(crate) fn benchmark(
+    enet: &Enet,
+    config: &BenchmarkConfig,
+) -> Result<BenchmarkReport, BenchmarkError> {
+    let deadline = Instant::now() + config.timeout;
+    let create_host = |address: Option<Address>| {
+        enet.create_host::<()>(
+            address.as_ref(),
+            1,
+            ChannelLimit::Limited(1),
+            BandwidthLimit::Unlimited,
+            BandwidthLimit::Unlimited,
+        )
+    };
+    let mut server = create_host(Some(Address::new(Ipv4Addr::LOCALHOST, 0)))?;
+    let mut client = create_host(None)?;
+    let (client_peer, server_peer) = connect(&mut client, &mut server, deadline)?;
+
+    let (mut sent, mut received) = (0, 0);
+    let (mut service_total, mut service_max) = (Duration::ZERO, Duration::ZERO);
+    let start = Instant::now();
+    while sent < config.packet_count && Instant::now() < deadline {
+        for _ in 0..config.burst.min(config.packet_count - sent).max(1) {
+            let packet = Packet::new(vec![0; config.packet_size], config.mode)?;
+            if client[client_peer].send_packet(packet, 0).is_ok() {
+                sent += 1;
+            }
+        }
+        client.flush();
+
+        // Drain what arrived, then briefly wait for the stragglers of the burst.
+        let mut idle = 0;
+        while idle < 2 {
+            let call = Instant::now();
+            let event = server.service(Some(Duration::ZERO))?;
+            let time = call.elapsed();
+            match event {
+                Some(event) => {
+                    if let EventKind::Receive { .. } = event.kind {
+                        received += 1;
+                        service_total += time;
+                        service_max = service_max.max(time);
+                    }
+                    idle = 0;
+                }
+                None => {
+                    idle += 1;
+                    client.service(Some(Duration::ZERO))?;
+                }
+            }
+        }
+    }
+    let elapsed = start.elapsed();
+
+    let mut round_trips = Duration::ZERO;
+    let mut echoes = 0;
+    for _ in 0..ROUND_TRIPS {
+        match echo(&mut client, &mut server, client_peer, server_peer, deadline)? {
+            Some(time) => {
+                round_trips += time;
+                echoes += 1;
+            }
+            None => break,
+        }
+    }
+
+    let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+    Ok(BenchmarkReport {
+        packets_sent: sent,
+        packets_received: received,
+        elapsed,
+        packets_per_second: received as f64 / seconds,
+        bytes_per_second: (received * config.packet_size) as f64 / seconds,
+        mean_service_time: service_total / received.max(1) as u32,
+        max_service_time: service_max,
+        mean_round_trip: if echoes > 0 {
+            Some(round_trips / echoes)
+        } else {
+            None
+        },
+    })
+}
+
+/// Connects `client` to `server`, returning the peer of each on the other.
+fn connect(
+    client: &mut Host<()>,
+    server: &mut Host<()>,
+    deadline: Instant,
+) -> Result<(PeerID, PeerID), BenchmarkError> {
+    client
+        .connect(&server.address(), 1, 0)
+        .map_err(|_| BenchmarkError::NotConnected)?;
+
+    let (mut client_peer, mut server_peer) = (None, None);
+    let timeout = Some(Duration::from_millis(1));
+    while Instant::now() < deadline {
+        if let Some(event) = client.service(timeout)? {
+            if let EventKind::Connect = event.kind {
+                client_peer = Some(event.peer_id);
+            }
+        }
+        if let Some(event) = server.service(timeout)? {
+            if let EventKind::Connect = event.kind {
+                server_peer = Some(event.peer_id);
+            }
+        }
+        if let (Some(client_peer), Some(server_peer)) = (client_peer, server_peer) {
+            return Ok((client_peer, server_peer));
+        }
+    }
+    Err(BenchmarkError::NotConnected)
+}
+
+/// Sends a packet from `client` that `server` echoes, and returns how long it took to come back.
+fn echo(
+    client: &mut Host<()>,
+    server: &mut Host<()>,
+    client_peer: PeerID,
+    server_peer: PeerID,
+    deadline: Instant,
+) -> Result<Option<Duration>, Error> {
+    let start = Instant::now();
+    let packet = Packet::new(vec![0; 1], PacketMode::ReliableSequenced)?;
+    if client[client_peer].send_packet(packet, 0).is_err() {
+        return Ok(None);
+    }
+    client.flush();
+
+    while Instant::now() < deadline {
+        if let Some(event) = server.service(Some(Duration::ZERO))? {
+            if let EventKind::Receive { packet, .. } = event.kind {
+                let _ = server[server_peer].send_packet(packet, 0);
+                server.flush();
+            }
+        }
+        if let Some(event) = client.service(Some(Duration::ZERO))? {
+            if let EventKind::Receive { .. } = event.kind {
+                return Ok(Some(start.elapsed()));
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::ENET;
+    use crate::{Address, BandwidthLimit, BenchmarkConfig, ChannelLimit, PacketMode, PeerState};
+
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    #[test]
+    fn test_diagnostics() {
+        let mut host = ENET
+            .create_host::<()>(
+                None,
+                4,
+                ChannelLimit::Limited(3),
+                BandwidthLimit::Limited(1000),
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap();
+        let server = Address::new(Ipv4Addr::LOCALHOST, 12372);
+        host.connect(&server, 2, 0).unwrap();
+
+        let diagnostics = host.diagnostics();
+        assert_eq!(diagnostics.peer_slots, 4);
+        assert_eq!(diagnostics.channel_limit, ChannelLimit::Limited(3));
+        assert_eq!(diagnostics.incoming_bandwidth, 1000);
+        assert_eq!(diagnostics.peers.len(), 1);
+        let peer = &diagnostics.peers[0];
+        assert_eq!(peer.state, PeerState::Connecting);
+        assert_eq!(peer.channel_count, 2);
+        assert_eq!(peer.address, server);
+
+        let json = diagnostics.to_json();
+        assert!(json.starts_with("{\"address\":\"0.0.0.0:"));
+        assert!(json.contains("\"channel_limit\":3,"));
+        assert!(json.contains("\"channel_traffic\":[],\"peers\":[{\"index\":0,"));
+        assert!(json.contains("\"address\":\"127.0.0.1:12372\",\"state\":\"Connecting\""));
+        assert!(json.ends_with("}]}"));
+    }
+
+    #[test]
+    fn test_benchmark() {
+        let config = BenchmarkConfig {
+            packet_count: 2000,
+            mode: PacketMode::ReliableSequenced,
+            timeout: Duration::from_secs(5),
+            ..BenchmarkConfig::default()
+        };
+        let report = ENET.benchmark(&config).unwrap();
+        assert_eq!(report.packets_sent, 2000);
+        assert_eq!(report.packets_received, 2000);
+        assert!(report.packets_per_second > 0.0);
+        assert!(report.max_service_time >= report.mean_service_time);
+        assert!(report.mean_round_trip.is_some());
+        assert!(report.summary().starts_with("2000/2000 packets in "));
+    }
+}
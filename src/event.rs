@@ -4,7 +4,7 @@ use enet_sys::{
     _ENetEventType_ENET_EVENT_TYPE_NONE, _ENetEventType_ENET_EVENT_TYPE_RECEIVE,
 };
 
-use crate::{Host, Packet, PeerID};
+use crate::{Channel, Host, Packet, PeerID};
 
 /// This struct represents an event that can occur when servicing an `Host`.
 #[derive(Debug)]
@@ -20,6 +20,15 @@ pub struct Event {
 pub enum EventKind {
     /// Peer has connected.
     Connect,
+    /// A connection attempt started with `Host::connect` has timed out.
+    ///
+    /// ENet reports a timeout as a disconnect with data 0 and keeps nothing that tells it apart, so
+    /// an attempt the remote host refused with `Peer::disconnect` and data 0 before it was
+    /// established is reported as a `ConnectTimeout` too. Reject connects with non-zero data to
+    /// have them delivered as `Disconnect` instead.
+    ///
+    /// Like for `Disconnect`, the data of the peer will be dropped on the next call to Host::service.
+    ConnectTimeout,
     /// Peer has disconnected.
     //
     /// The data of the peer will be dropped on the next call to Host::service or when the structure is dropped.
@@ -34,9 +43,39 @@ pub enum EventKind {
         /// The received packet.
         packet: Packet,
     },
+    /// A packet from the peer was dropped, because it exceeded the rate limit of its channel.
+    ///
+    /// Only delivered for channels limited with `RateLimitAction::Warn`, see `Host::set_rate_limit`.
+    RateLimited {
+        /// ID of the channel that the packet was received on.
+        channel_id: u8,
+    },
+}
+
+impl EventKind {
+    /// Returns whether this event ends the connection to its peer, i.e. is a `Disconnect` or `ConnectTimeout`.
+    pub fn is_disconnect(&self) -> bool {
+        match self {
+            EventKind::Disconnect { .. } | EventKind::ConnectTimeout => true,
+            EventKind::Connect | EventKind::Receive { .. } | EventKind::RateLimited { .. } => false,
+        }
+    }
 }
 
 impl Event {
+    /// Returns the message of a packet received on the channel `C`.
+    ///
+    /// Returns `None` for other events, packets on other channels, and packets that don't decode.
+    pub fn message<C: Channel>(&self) -> Option<C::Message> {
+        match self.kind {
+            EventKind::Receive {
+                channel_id,
+                ref packet,
+            } if channel_id == C::ID => C::decode(packet.data()),
+            _ => None,
+        }
+    }
+
     pub(crate) fn from_sys_event<T>(event_sys: ENetEvent, host: &Host<T>) -> Option<Event> {
         if event_sys.type_ == _ENetEventType_ENET_EVENT_TYPE_NONE {
             return None;
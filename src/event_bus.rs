@@ -0,0 +1,178 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::{Rc, Weak};
+use std::time::Duration;
+
+use crate::{Error, Event, HostLike};
+
+type Filter = Box<dyn Fn(&Event) -> bool>;
+
+struct Subscription {
+    queue: Weak<RefCell<VecDeque<Rc<Event>>>>,
+    filter: Option<Filter>,
+}
+
+/// Delivers the events of a host to several independent subscribers.
+///
+/// `Host::service` returns each event once, to a single caller. The bus services the host instead,
+/// and queues every event for each of its subscribers, e.g. the game logic, metrics, a replay
+/// recorder and anti-cheat. The events are shared between the queues behind an `Rc`, not copied.
+///
+/// A subscriber that stops reading its queue keeps every later event alive; drop it to
+/// unsubscribe.
+#[derive(Default)]
+pub struct EventBus {
+    subscriptions: Vec<Subscription>,
+}
+
+impl EventBus {
+    /// Creates a bus without subscribers.
+    pub fn new() -> EventBus {
+        EventBus::default()
+    }
+
+    /// Subscribes to all events published after this call.
+    pub fn subscribe(&mut self) -> EventSubscriber {
+        self.add(None)
+    }
+
+    /// Subscribes to the events published after this call for which `filter` returns `true`.
+    pub fn subscribe_filtered(
+        &mut self,
+        filter: impl Fn(&Event) -> bool + 'static,
+    ) -> EventSubscriber {
+        self.add(Some(Box::new(filter)))
+    }
+
+    fn add(&mut self, filter: Option<Filter>) -> EventSubscriber {
+        let queue = Rc::new(RefCell::new(VecDeque::new()));
+        self.subscriptions.push(Subscription {
+            queue: Rc::downgrade(&queue),
+            filter,
+        });
+        EventSubscriber { queue }
+    }
+
+    /// Returns the number of subscribers that were not dropped yet.
+    pub fn subscriber_count(&self) -> usize {
+        self.subscriptions
+            .iter()
+            .filter(|subscription| subscription.queue.strong_count() > 0)
+            .count()
+    }
+
+    /// Queues `event` for every subscriber that wants it.
+    pub fn publish(&mut self, event: Event) {
+        let event = Rc::new(event);
+        self.subscriptions.retain(|subscription| {
+            let queue = match subscription.queue.upgrade() {
+                Some(queue) => queue,
+                None => return false,
+            };
+            if subscription
+                .filter
+                .as_ref()
+                .map_or(true, |filter| filter(&event))
+            {
+                queue.borrow_mut().push_back(event.clone());
+            }
+            true
+        });
+    }
+
+    /// Services `host` and publishes its events.
+    ///
+    /// Waits at most `timeout` for the first event, like `Host::service`, then publishes the
+    /// events that are already available too. Returns the number of events published.
+    pub fn service<T, H: HostLike<T>>(
+        &mut self,
+        host: &mut H,
+        timeout: Option<Duration>,
+    ) -> Result<usize, Error> {
+        let mut published = 0;
+        let mut event = host.service(timeout)?;
+        while let Some(next) = event {
+            self.publish(next);
+            published += 1;
+            event = host.service(Some(Duration::ZERO))?;
+        }
+        Ok(published)
+    }
+}
+
+/// The queue of events of one subscriber of an `EventBus`, see `EventBus::subscribe`.
+pub struct EventSubscriber {
+    queue: Rc<RefCell<VecDeque<Rc<Event>>>>,
+}
+
+impl EventSubscriber {
+    /// Takes the oldest event out of the queue.
+    pub fn next_event(&self) -> Option<Rc<Event>> {
+        self.queue.borrow_mut().pop_front()
+    }
+
+    /// Takes all events out of the queue, oldest first.
+    pub fn drain(&self) -> Vec<Rc<Event>> {
+        self.queue.borrow_mut().drain(..).collect()
+    }
+
+    /// Returns the number of queued events.
+    pub fn len(&self) -> usize {
+        self.queue.borrow().len()
+    }
+
+    /// Returns whether no events are queued.
+    pub fn is_empty(&self) -> bool {
+        self.queue.borrow().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EventBus;
+    use crate::tests::create_host;
+    use crate::{Address, EventKind, Packet, PacketMode};
+
+    use std::net::Ipv4Addr;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_event_bus() {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12392);
+        let mut server = create_host(Some(&server_address), 1);
+        let mut client = create_host(None, 1);
+        client.connect(&server_address, 1, 0).unwrap();
+
+        let mut bus = EventBus::new();
+        let logic = bus.subscribe();
+        let receives =
+            bus.subscribe_filtered(|event| matches!(event.kind, EventKind::Receive { .. }));
+        let dropped = bus.subscribe();
+        drop(dropped);
+        assert_eq!(bus.subscriber_count(), 2);
+
+        let timeout = Some(Duration::from_millis(2));
+        for _ in 0..500 {
+            bus.service(&mut server, timeout).unwrap();
+            if let Some(event) = client.service(timeout).unwrap() {
+                if let EventKind::Connect = event.kind {
+                    let packet = Packet::new(b"hi".to_vec(), PacketMode::ReliableSequenced);
+                    client[event.peer_id]
+                        .send_packet(packet.unwrap(), 0)
+                        .unwrap();
+                }
+            }
+            if !receives.is_empty() {
+                break;
+            }
+        }
+
+        let events = logic.drain();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0].kind, EventKind::Connect));
+        let received = receives.next_event().unwrap();
+        assert!(Rc::ptr_eq(&received, &events[1]));
+        assert!(receives.next_event().is_none());
+    }
+}
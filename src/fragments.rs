@@ -0,0 +1,355 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use enet_sys::enet_protocol_command_size;
+
+use crate::{Datagram, Intercept, InterceptAction};
+
+// ENet's protocol, see `protocol.h`.
+const HEADER_FLAG_COMPRESSED: u16 = 1 << 14;
+const HEADER_FLAG_SENT_TIME: u16 = 1 << 15;
+const NO_PEER: u16 = 0x0fff;
+const COMMAND_MASK: u8 = 0x0f;
+const SEND_RELIABLE: u8 = 6;
+const SEND_UNRELIABLE: u8 = 7;
+const SEND_FRAGMENT: u8 = 8;
+const SEND_UNSEQUENCED: u8 = 9;
+const SEND_UNRELIABLE_FRAGMENT: u8 = 12;
+/// The protocol header and the command of a fragment, which ENet leaves room for in the MTU.
+const FRAGMENT_OVERHEAD: usize = 4 + 24;
+/// The packets of one peer that are reassembled at once; fragments of further packets are not
+/// counted until some of them completed or timed out.
+const MAX_REASSEMBLIES: usize = 256;
+
+/// How the packets exchanged with a peer were split into fragments, see `Host::fragment_stats`.
+///
+/// ENet splits packets larger than the MTU of a peer, and reassembles them on the other side.
+/// Lost fragments of reliable packets are resent, and hold back the rest of the channel meanwhile,
+/// so many fragments tend to show as latency spikes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct FragmentStats {
+    /// The packets sent that ENet split into fragments.
+    pub fragmented_packets: u64,
+    /// The fragments of the packets sent.
+    pub outgoing_fragments: u64,
+    /// The fragments received, without the ones resent after they arrived.
+    pub incoming_fragments: u64,
+    /// The packets received whose fragments all arrived.
+    pub reassembled_packets: u64,
+    /// The packets received whose fragments did not all arrive within the timeout set with
+    /// `Host::set_fragment_tracking`.
+    pub reassembly_timeouts: u64,
+    /// The packets received whose fragments did not all arrive yet.
+    pub reassembling: usize,
+}
+
+impl FragmentStats {
+    /// Counts a packet of `len` bytes sent with `mtu`, if ENet splits it. Datagrams with a
+    /// `checksum` leave 4 bytes less room.
+    pub(crate) fn record_sent(&mut self, len: usize, mtu: u32, checksum: bool) {
+        let overhead = FRAGMENT_OVERHEAD + if checksum { 4 } else { 0 };
+        let fragment_len = (mtu as usize).saturating_sub(overhead).max(1);
+        if len > fragment_len {
+            self.fragmented_packets += 1;
+            self.outgoing_fragments += ((len + fragment_len - 1) / fragment_len) as u64;
+        }
+    }
+}
+
+/// Identifies a packet whose fragments arrive: its channel, whether it is reliable, the reliable
+/// sequence number of unreliable packets, and the sequence number of the first fragment.
+type FragmentKey = (u8, bool, u16, u16);
+
+#[derive(Debug)]
+struct Reassembly {
+    fragment_count: u32,
+    received: HashSet<u32>,
+    started: Instant,
+}
+
+#[derive(Debug, Default)]
+struct PeerFragments {
+    stats: FragmentStats,
+    pending: HashMap<FragmentKey, Reassembly>,
+    /// Packets reassembled or given up on recently, so fragments resent after they arrived don't
+    /// start a new reassembly.
+    finished: HashMap<FragmentKey, Instant>,
+}
+
+/// Counts the fragments each peer sends, by the index of the peer.
+#[derive(Debug)]
+pub(crate) struct FragmentTracker {
+    timeout: Option<Duration>,
+    peers: HashMap<usize, PeerFragments>,
+}
+
+pub(crate) type SharedFragmentTracker = Arc<Mutex<FragmentTracker>>;
+
+impl FragmentTracker {
+    pub(crate) fn new(timeout: Duration) -> FragmentTracker {
+        FragmentTracker {
+            timeout: Some(timeout),
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Changes the reassembly timeout, or stops counting with `None`.
+    pub(crate) fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+        if timeout.is_none() {
+            self.peers.clear();
+        }
+    }
+
+    /// Returns the counts of incoming fragments of the peer with `index`.
+    pub(crate) fn stats(&self, index: usize) -> FragmentStats {
+        self.peers
+            .get(&index)
+            .map_or_else(FragmentStats::default, |peer| FragmentStats {
+                reassembling: peer.pending.len(),
+                ..peer.stats
+            })
+    }
+
+    pub(crate) fn remove_peer(&mut self, index: usize) {
+        self.peers.remove(&index);
+    }
+
+    fn fragment(&mut self, index: usize, key: FragmentKey, count: u32, number: u32, now: Instant) {
+        let peer = self.peers.entry(index).or_default();
+        if count <= 1 || number >= count || peer.finished.contains_key(&key) {
+            return;
+        }
+        if peer.pending.len() >= MAX_REASSEMBLIES && !peer.pending.contains_key(&key) {
+            return;
+        }
+
+        let reassembly = peer.pending.entry(key).or_insert_with(|| Reassembly {
+            fragment_count: count,
+            received: HashSet::new(),
+            started: now,
+        });
+        if reassembly.fragment_count != count || !reassembly.received.insert(number) {
+            return;
+        }
+        peer.stats.incoming_fragments += 1;
+
+        if reassembly.received.len() as u32 == count {
+            peer.pending.remove(&key);
+            peer.finished.insert(key, now);
+            peer.stats.reassembled_packets += 1;
+        }
+    }
+
+    /// Counts the reassemblies that took longer than the timeout, and forgets them.
+    pub(crate) fn expire(&mut self, now: Instant) {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return,
+        };
+
+        for peer in self.peers.values_mut() {
+            let expired: Vec<_> = peer
+                .pending
+                .iter()
+                .filter(|(_, reassembly)| now >= reassembly.started + timeout)
+                .map(|(&key, _)| key)
+                .collect();
+            for key in expired {
+                peer.pending.remove(&key);
+                peer.finished.insert(key, now);
+                peer.stats.reassembly_timeouts += 1;
+            }
+            // Sequence numbers wrap, so their packets are only remembered for a while.
+            peer.finished
+                .retain(|_, &mut finished| now < finished + timeout);
+        }
+    }
+}
+
+/// Calls `f` with the channel, command and data of every command in an ENet datagram.
+///
+/// Stops at the first command that doesn't fit, like ENet does.
+fn parse_commands(data: &[u8], has_checksum: bool, mut f: impl FnMut(u8, &[u8])) -> Option<usize> {
+    if data.len() < 2 {
+        return None;
+    }
+
+    let peer_id = u16::from_be_bytes([data[0], data[1]]);
+    if peer_id & HEADER_FLAG_COMPRESSED != 0 || peer_id & NO_PEER == NO_PEER {
+        return None;
+    }
+    let mut offset = if peer_id & HEADER_FLAG_SENT_TIME != 0 {
+        4
+    } else {
+        2
+    };
+    if has_checksum {
+        offset += 4;
+    }
+
+    while data.len() >= offset + 4 {
+        let command = data[offset] & COMMAND_MASK;
+        let size = unsafe { enet_protocol_command_size(command) };
+        if size == 0 || data.len() < offset + size {
+            break;
+        }
+
+        let command_data = &data[offset..offset + size];
+        let data_len = match command {
+            SEND_RELIABLE => u16::from_be_bytes([command_data[4], command_data[5]]),
+            SEND_UNRELIABLE | SEND_FRAGMENT | SEND_UNSEQUENCED | SEND_UNRELIABLE_FRAGMENT => {
+                u16::from_be_bytes([command_data[6], command_data[7]])
+            }
+            _ => 0,
+        };
+        f(command, command_data);
+        offset += size + usize::from(data_len);
+    }
+    Some(usize::from(peer_id & NO_PEER))
+}
+
+/// Counts the fragments in the datagrams a `Host` receives, see `Host::set_fragment_tracking`.
+pub(crate) struct FragmentIntercept(pub(crate) SharedFragmentTracker);
+
+impl Intercept for FragmentIntercept {
+    fn intercept(&mut self, datagram: &mut Datagram) -> InterceptAction {
+        let mut tracker = self.0.lock().unwrap();
+        if tracker.timeout.is_none() {
+            return InterceptAction::Continue;
+        }
+
+        let mut fragments = Vec::new();
+        let index = parse_commands(datagram.data(), datagram.has_checksum(), |command, data| {
+            if command != SEND_FRAGMENT && command != SEND_UNRELIABLE_FRAGMENT {
+                return;
+            }
+            let reliable = command == SEND_FRAGMENT;
+            let sequence = if reliable {
+                0
+            } else {
+                u16::from_be_bytes([data[2], data[3]])
+            };
+            let start = u16::from_be_bytes([data[4], data[5]]);
+            let count = u32::from_be_bytes([data[8], data[9], data[10], data[11]]);
+            let number = u32::from_be_bytes([data[12], data[13], data[14], data[15]]);
+            fragments.push(((data[1], reliable, sequence, start), count, number));
+        });
+
+        // Datagrams for peers that aren't connected, or from a different address, are dropped by
+        // ENet, so their fragments don't count.
+        let index = index.filter(|&index| datagram.is_from_connected_peer(index));
+        if let Some(index) = index {
+            let now = Instant::now();
+            for (key, count, number) in fragments {
+                tracker.fragment(index, key, count, number, now);
+            }
+        }
+        InterceptAction::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FragmentStats, FragmentTracker, MAX_REASSEMBLIES};
+    use crate::tests::{create_host, pump_until};
+    use crate::{Address, EventKind};
+
+    use std::net::{Ipv4Addr, UdpSocket};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_record_sent() {
+        let mut stats = FragmentStats::default();
+        stats.record_sent(1372, 1400, false);
+        stats.record_sent(1368, 1400, true);
+        assert_eq!(stats.fragmented_packets, 0);
+        stats.record_sent(1373, 1400, false);
+        stats.record_sent(4000, 1400, false);
+        stats.record_sent(1369, 1400, true);
+        assert_eq!(stats.fragmented_packets, 3);
+        assert_eq!(stats.outgoing_fragments, 2 + 3 + 2);
+    }
+
+    #[test]
+    fn test_reassembly_timeout() {
+        let timeout = Duration::from_millis(100);
+        let mut tracker = FragmentTracker::new(timeout);
+        let now = Instant::now();
+        let (complete, incomplete) = ((0, true, 0, 1), (1, false, 4, 7));
+
+        tracker.fragment(3, complete, 2, 0, now);
+        tracker.fragment(3, complete, 2, 0, now);
+        tracker.fragment(3, incomplete, 3, 2, now);
+        tracker.fragment(3, complete, 2, 1, now);
+        // Resent after the packet was reassembled.
+        tracker.fragment(3, complete, 2, 1, now);
+        assert_eq!(
+            tracker.stats(3),
+            FragmentStats {
+                incoming_fragments: 3,
+                reassembled_packets: 1,
+                reassembling: 1,
+                ..FragmentStats::default()
+            }
+        );
+
+        tracker.expire(now + timeout);
+        let stats = tracker.stats(3);
+        assert_eq!((stats.reassembly_timeouts, stats.reassembling), (1, 0));
+        assert_eq!(tracker.stats(4), FragmentStats::default());
+    }
+
+    #[test]
+    fn test_reassembly_limit() {
+        let mut tracker = FragmentTracker::new(Duration::from_secs(1));
+        let now = Instant::now();
+        for start in 0..=MAX_REASSEMBLIES as u16 {
+            tracker.fragment(0, (0, true, 0, start), 2, 0, now);
+        }
+        let stats = tracker.stats(0);
+        assert_eq!(stats.reassembling, MAX_REASSEMBLIES);
+        assert_eq!(stats.incoming_fragments, MAX_REASSEMBLIES as u64);
+
+        // Packets being reassembled still complete.
+        tracker.fragment(0, (0, true, 0, 0), 2, 1, now);
+        assert_eq!(tracker.stats(0).reassembled_packets, 1);
+    }
+
+    #[test]
+    fn test_fragments_of_strangers() {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12425);
+        let mut server = create_host(Some(&server_address), 2);
+        server.set_fragment_tracking(Some(Duration::from_secs(1)));
+        let mut client = create_host(None, 1);
+        client.connect(&server_address, 1, 0).unwrap();
+        let mut connects = 0;
+        pump_until(&mut [&mut server, &mut client], |_, _, event| {
+            connects += matches!(event.kind, EventKind::Connect) as usize;
+            connects == 2
+        });
+
+        // The first of two fragments of a packet on channel 0, for the peer with index `peer`: the
+        // connected one, at a different address, an unused slot and one past the last slot.
+        let fragment = |peer: u8| {
+            let mut data = vec![0, peer, 8, 0, 0, 1, 0, 1, 0, 0];
+            data.extend_from_slice(&[0, 0, 0, 2]);
+            data.extend_from_slice(&[0; 12]);
+            data
+        };
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        for peer in 0..3 {
+            socket
+                .send_to(&fragment(peer), (Ipv4Addr::LOCALHOST, 12425))
+                .unwrap();
+        }
+        for _ in 0..10 {
+            server.service(Some(Duration::from_millis(2))).unwrap();
+        }
+
+        let peer_id = server.peer_ids().next().unwrap();
+        assert_eq!(peer_id.index(), 0);
+        assert_eq!(server.fragment_stats(peer_id), FragmentStats::default());
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::PeerID;
+
+/// Identifies a group of a `Host`'s `PeerGroups`.
+#[derive(Clone, Copy, Hash, PartialEq, Eq, Debug)]
+pub struct GroupId(usize);
+
+/// Groups of peers of a `Host`, e.g. teams, rooms or interest areas, see `Host::groups_mut`.
+///
+/// Peers are removed from all groups when they disconnect. Groups stay, even when empty, until
+/// they are removed.
+#[derive(Debug, Default)]
+pub struct PeerGroups {
+    next_id: usize,
+    groups: HashMap<GroupId, HashSet<PeerID>>,
+}
+
+impl PeerGroups {
+    /// Creates a new, empty group.
+    pub fn create_group(&mut self) -> GroupId {
+        let id = GroupId(self.next_id);
+        self.next_id += 1;
+        self.groups.insert(id, HashSet::new());
+        id
+    }
+
+    /// Removes `group` and all of its memberships.
+    pub fn remove_group(&mut self, group: GroupId) {
+        self.groups.remove(&group);
+    }
+
+    /// Adds `peer_id` to `group`.
+    ///
+    /// Returns `false` if the group does not exist.
+    pub fn add(&mut self, group: GroupId, peer_id: PeerID) -> bool {
+        match self.groups.get_mut(&group) {
+            Some(members) => {
+                members.insert(peer_id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes `peer_id` from `group`.
+    pub fn remove(&mut self, group: GroupId, peer_id: PeerID) {
+        if let Some(members) = self.groups.get_mut(&group) {
+            members.remove(&peer_id);
+        }
+    }
+
+    /// Returns whether `peer_id` is a member of `group`.
+    pub fn contains(&self, group: GroupId, peer_id: PeerID) -> bool {
+        self.groups
+            .get(&group)
+            .is_some_and(|members| members.contains(&peer_id))
+    }
+
+    /// Returns the members of `group`.
+    pub fn members(&self, group: GroupId) -> impl Iterator<Item = PeerID> + '_ {
+        self.groups.get(&group).into_iter().flatten().cloned()
+    }
+
+    /// Returns all groups.
+    pub fn groups(&self) -> impl Iterator<Item = GroupId> + '_ {
+        self.groups.keys().cloned()
+    }
+
+    pub(crate) fn remove_peer(&mut self, peer_id: PeerID) {
+        for members in self.groups.values_mut() {
+            members.remove(&peer_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{connected_pair, create_host, pump_until};
+    use crate::{Address, EventKind, Packet, PacketMode};
+
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_broadcast_group() {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12361);
+        let mut server = create_host(Some(&server_address), 2);
+        let mut member = create_host(None, 1);
+        let mut outsider = create_host(None, 1);
+        let group = server.groups_mut().create_group();
+
+        let (_, member_id) = member.connect(&server_address, 1, 1).unwrap();
+        outsider.connect(&server_address, 1, 2).unwrap();
+        member.flush();
+        outsider.flush();
+
+        let (mut connected, mut member_received, mut outsider_received) = (0, 0, 0);
+        pump_until(
+            &mut [&mut server, &mut member, &mut outsider],
+            |index, host, event| {
+                match (index, event.kind) {
+                    (0, EventKind::Connect) => {
+                        // Only the member connects with data 1.
+                        if host[event.peer_id].event_data() == 1 {
+                            host.groups_mut().add(group, event.peer_id);
+                        }
+                        connected += 1;
+                        if connected == 2 {
+                            let packet =
+                                Packet::new(b"team".to_vec(), PacketMode::ReliableSequenced);
+                            assert_eq!(host.broadcast_group(group, 0, packet.unwrap()), 1);
+                        }
+                    }
+                    (0, _) => {
+                        return member_received == 1 && host.groups().members(group).count() == 0
+                    }
+                    (1, EventKind::Receive { .. }) => {
+                        member_received += 1;
+                        host[member_id].disconnect(0);
+                    }
+                    (2, EventKind::Receive { .. }) => outsider_received += 1,
+                    _ => (),
+                }
+                false
+            },
+        );
+
+        assert_eq!((member_received, outsider_received), (1, 0));
+        assert_eq!(server.groups().members(group).count(), 0);
+        assert_eq!(server.groups().groups().collect::<Vec<_>>(), vec![group]);
+    }
+    #[test]
+    fn test_reused_slot_leaves_groups() {
+        let (mut server, _, client_peer, _) = connected_pair(12423, 1);
+        let group = server.groups_mut().create_group();
+        server.groups_mut().add(group, client_peer);
+        server[client_peer].disconnect_now(0);
+
+        // The next connection gets the same slot, but not the group of the reset peer.
+        let mut next = create_host(None, 1);
+        next.connect(&Address::new(Ipv4Addr::LOCALHOST, 12423), 1, 0)
+            .unwrap();
+        pump_until(&mut [&mut server, &mut next], |index, _, event| {
+            index == 0 && matches!(event.kind, EventKind::Connect)
+        });
+        assert_eq!(server.groups().members(group).count(), 0);
+    }
+}
@@ -0,0 +1,752 @@
+//! An application handshake, run after ENet connected a peer and before the application uses it.
+//!
+//! Both sides exchange a [Hello](struct.Hello.html): the protocol version, build information, an
+//! optional token and a payload of the application, e.g. the locale, which doesn't fit the `u32`
+//! passed to `Host::connect`. The server checks the client's hello, and accepts or rejects it with
+//! a typed [Rejection](enum.Rejection.html). Only once accepted, the peer is reported as ready,
+//! along with its hello; until then, its packets are dropped, so game traffic can't arrive before
+//! the handshake.
+//!
+//! The server can additionally require the client to authenticate, with `Handshake::require_auth`:
+//! it answers the client's hello with a random nonce, and the client has to respond with the
+//! HMAC-SHA-256 of the nonce and its hello, keyed with a shared secret or a per-client ticket.
+//!
+//! All events are passed through `Handshake::handle_event`, which consumes those that belong to
+//! peers still in the handshake, and the outcome is retrieved with `Handshake::next_event`.
+
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::sha256::{constant_time_eq, hmac_sha256, sha256, DIGEST_LEN};
+use crate::{Event, EventKind, Host, Packet, PacketMode, PeerID};
+
+/// Marks handshake packets.
+const MAGIC: &[u8] = b"\xffHSK";
+const KIND_HELLO: u8 = 0;
+const KIND_ACCEPT: u8 = 1;
+const KIND_REJECT: u8 = 2;
+const KIND_CHALLENGE: u8 = 3;
+const KIND_RESPONSE: u8 = 4;
+
+const REASON_VERSION: u8 = 0;
+const REASON_DENIED: u8 = 1;
+const REASON_TIMED_OUT: u8 = 2;
+const REASON_MALFORMED: u8 = 3;
+const REASON_UNAUTHENTICATED: u8 = 4;
+
+const NONCE_LEN: usize = 32;
+
+/// What each side tells the other about itself.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Hello {
+    /// The version of the application protocol, which has to match on both sides.
+    pub protocol_version: u32,
+    /// Information about the build, e.g. its version or commit, at most 255 bytes.
+    pub build: String,
+    /// An opaque token, e.g. for authentication, at most 65535 bytes.
+    pub token: Vec<u8>,
+    /// Any other data the application wants to pass along, e.g. the locale or settings, encoded
+    /// with any format, at most 65535 bytes.
+    ///
+    /// Hellos without a payload are encoded like before it existed, so they are understood by
+    /// older versions of this crate.
+    pub payload: Vec<u8>,
+}
+
+/// Why a `Hello` can't be sent, see `Handshake::client` and `Handshake::server`.
+#[derive(Fail, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HelloError {
+    #[fail(
+        display = "the build information is {} bytes, at most 255 are allowed",
+        _0
+    )]
+    /// The build information is longer than 255 bytes.
+    BuildTooLong(usize),
+    #[fail(display = "the token is {} bytes, at most 65535 are allowed", _0)]
+    /// The token is longer than 65535 bytes.
+    TokenTooLong(usize),
+    #[fail(display = "the payload is {} bytes, at most 65535 are allowed", _0)]
+    /// The payload is longer than 65535 bytes.
+    PayloadTooLong(usize),
+}
+
+impl Hello {
+    /// Checks that the fields fit their length prefixes.
+    fn check(&self) -> Result<(), HelloError> {
+        if self.build.len() > usize::from(u8::MAX) {
+            return Err(HelloError::BuildTooLong(self.build.len()));
+        }
+        if self.token.len() > usize::from(u16::MAX) {
+            return Err(HelloError::TokenTooLong(self.token.len()));
+        }
+        if self.payload.len() > usize::from(u16::MAX) {
+            return Err(HelloError::PayloadTooLong(self.payload.len()));
+        }
+        Ok(())
+    }
+
+    /// Encodes a hello that passed `Hello::check`, as the hellos of a `Handshake` and decoded
+    /// ones do.
+    fn encode(&self, data: &mut Vec<u8>) {
+        data.extend_from_slice(&self.protocol_version.to_be_bytes());
+        data.push(self.build.len() as u8);
+        data.extend_from_slice(self.build.as_bytes());
+        data.extend_from_slice(&(self.token.len() as u16).to_be_bytes());
+        data.extend_from_slice(&self.token);
+        if !self.payload.is_empty() {
+            data.extend_from_slice(&(self.payload.len() as u16).to_be_bytes());
+            data.extend_from_slice(&self.payload);
+        }
+    }
+
+    fn decode(data: &[u8]) -> Option<Hello> {
+        let (version, rest) = split(data, 4)?;
+        let (&build_len, rest) = rest.split_first()?;
+        let (build, rest) = split(rest, usize::from(build_len))?;
+        let (token_len, rest) = split(rest, 2)?;
+        let token_len = u16::from_be_bytes([token_len[0], token_len[1]]);
+        let (token, rest) = split(rest, usize::from(token_len))?;
+        let payload = match rest {
+            [] => &[][..],
+            _ => {
+                let (payload_len, rest) = split(rest, 2)?;
+                let payload_len = u16::from_be_bytes([payload_len[0], payload_len[1]]);
+                let (payload, rest) = split(rest, usize::from(payload_len))?;
+                if payload.is_empty() || !rest.is_empty() {
+                    return None;
+                }
+                payload
+            }
+        };
+
+        Some(Hello {
+            protocol_version: u32::from_be_bytes([version[0], version[1], version[2], version[3]]),
+            build: String::from_utf8(build.to_vec()).ok()?,
+            token: token.to_vec(),
+            payload: payload.to_vec(),
+        })
+    }
+}
+
+fn split(data: &[u8], len: usize) -> Option<(&[u8], &[u8])> {
+    if data.len() < len {
+        None
+    } else {
+        Some(data.split_at(len))
+    }
+}
+
+/// Why a handshake failed.
+#[derive(Fail, Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    #[fail(
+        display = "protocol version {} is not supported, expected {}",
+        got, expected
+    )]
+    /// The protocol versions of both sides don't match.
+    VersionMismatch {
+        /// The version the server expects.
+        expected: u32,
+        /// The version the client has.
+        got: u32,
+    },
+    #[fail(display = "the handshake was denied: {}", _0)]
+    /// The server denied the handshake, with a reason.
+    Denied(String),
+    #[fail(display = "the handshake did not complete in time")]
+    /// The handshake did not complete within its timeout.
+    TimedOut,
+    #[fail(display = "the authentication failed")]
+    /// The client has no valid key, or failed to prove it has one.
+    Unauthenticated,
+    #[fail(display = "the handshake was malformed")]
+    /// The other side sent something that isn't a valid handshake.
+    Malformed,
+    #[fail(display = "the peer disconnected during the handshake")]
+    /// The peer disconnected before the handshake completed.
+    Disconnected,
+}
+
+impl Rejection {
+    fn encode(&self, data: &mut Vec<u8>) {
+        match *self {
+            Rejection::VersionMismatch { expected, got } => {
+                data.push(REASON_VERSION);
+                data.extend_from_slice(&expected.to_be_bytes());
+                data.extend_from_slice(&got.to_be_bytes());
+            }
+            Rejection::Denied(ref reason) => {
+                data.push(REASON_DENIED);
+                data.extend_from_slice(reason.as_bytes());
+            }
+            Rejection::TimedOut => data.push(REASON_TIMED_OUT),
+            Rejection::Unauthenticated => data.push(REASON_UNAUTHENTICATED),
+            Rejection::Malformed | Rejection::Disconnected => data.push(REASON_MALFORMED),
+        }
+    }
+
+    fn decode(data: &[u8]) -> Rejection {
+        let version = |data: &[u8]| u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        match data.split_first() {
+            Some((&REASON_VERSION, rest)) if rest.len() == 8 => Rejection::VersionMismatch {
+                expected: version(&rest[..4]),
+                got: version(&rest[4..]),
+            },
+            Some((&REASON_DENIED, rest)) => {
+                Rejection::Denied(String::from_utf8_lossy(rest).into_owned())
+            }
+            Some((&REASON_TIMED_OUT, [])) => Rejection::TimedOut,
+            Some((&REASON_UNAUTHENTICATED, [])) => Rejection::Unauthenticated,
+            _ => Rejection::Malformed,
+        }
+    }
+}
+
+/// The outcome of the handshake with a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandshakeEvent {
+    /// The handshake completed, and the peer can be used.
+    Ready {
+        /// The peer that is ready.
+        peer_id: PeerID,
+        /// What the peer told about itself.
+        hello: Hello,
+    },
+    /// The handshake failed, and the peer is being disconnected.
+    Rejected {
+        /// The peer that was rejected.
+        peer_id: PeerID,
+        /// Why the handshake failed.
+        reason: Rejection,
+    },
+}
+
+/// Decides whether to accept a client's hello, see `Handshake::server`.
+pub type Validator = Box<dyn FnMut(PeerID, &Hello) -> Result<(), String>>;
+
+/// Returns the authentication key for a client's hello, see `Handshake::require_auth`.
+pub type KeyLookup = Box<dyn FnMut(&Hello) -> Option<Vec<u8>>>;
+
+enum Role {
+    Client,
+    Server(Validator),
+}
+
+/// A challenge sent to a client, waiting for its response.
+struct Challenge {
+    nonce: [u8; NONCE_LEN],
+    key: Vec<u8>,
+    hello: Hello,
+}
+
+/// Returns a fresh nonce.
+///
+/// The standard library's `RandomState` is seeded from the operating system's random source, and
+/// hashing a counter with it is unpredictable without knowing that seed.
+fn nonce() -> [u8; NONCE_LEN] {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let time = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos());
+    let mut seed = Vec::new();
+    for _ in 0..4 {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        hasher.write_u128(time);
+        seed.extend_from_slice(&hasher.finish().to_be_bytes());
+    }
+
+    sha256(&[&seed])
+}
+
+/// The response to `nonce`, binding it to the client's `hello`.
+fn challenge_response(key: &[u8], nonce: &[u8], hello: &Hello) -> [u8; DIGEST_LEN] {
+    let mut hello_data = Vec::new();
+    hello.encode(&mut hello_data);
+    hmac_sha256(key, &[nonce, &hello_data])
+}
+
+fn encode(kind: u8) -> Vec<u8> {
+    let mut data = MAGIC.to_vec();
+    data.push(kind);
+    data
+}
+
+/// The handshake of one side, for all peers of a `Host`.
+pub struct Handshake {
+    channel_id: u8,
+    timeout: Duration,
+    hello: Hello,
+    role: Role,
+    secret: Option<Vec<u8>>,
+    keys: Option<KeyLookup>,
+    challenges: HashMap<PeerID, Challenge>,
+    pending: HashMap<PeerID, Instant>,
+    ready: HashSet<PeerID>,
+    events: VecDeque<HandshakeEvent>,
+}
+
+impl Handshake {
+    /// Creates the handshake of the connecting side, which sends `hello` once connected.
+    ///
+    /// Fails if a field of `hello` is longer than its documented limit.
+    pub fn client(channel_id: u8, hello: Hello) -> Result<Handshake, HelloError> {
+        Handshake::new(channel_id, hello, Role::Client)
+    }
+
+    /// Creates the handshake of the accepting side, which answers clients with `hello`.
+    ///
+    /// Clients with a different protocol version are rejected. The hellos of all others are
+    /// passed to `validator`, which accepts them, or denies them with a reason. Fails if a field
+    /// of `hello` is longer than its documented limit.
+    pub fn server<F>(channel_id: u8, hello: Hello, validator: F) -> Result<Handshake, HelloError>
+    where
+        F: FnMut(PeerID, &Hello) -> Result<(), String> + 'static,
+    {
+        Handshake::new(channel_id, hello, Role::Server(Box::new(validator)))
+    }
+
+    fn new(channel_id: u8, hello: Hello, role: Role) -> Result<Handshake, HelloError> {
+        hello.check()?;
+        Ok(Handshake {
+            channel_id,
+            timeout: Duration::from_secs(10),
+            hello,
+            role,
+            secret: None,
+            keys: None,
+            challenges: HashMap::new(),
+            pending: HashMap::new(),
+            ready: HashSet::new(),
+            events: VecDeque::new(),
+        })
+    }
+
+    /// Sets the key the client answers authentication challenges with.
+    ///
+    /// This is either a secret shared by all clients, or the key of a ticket identified by the
+    /// token of the client's hello.
+    pub fn set_secret(&mut self, secret: impl Into<Vec<u8>>) {
+        self.secret = Some(secret.into());
+    }
+
+    /// Makes the server authenticate clients before accepting them.
+    ///
+    /// `key_for` returns the key a client has to authenticate with, e.g. the shared secret, or the
+    /// key of the ticket named by the hello's token. Clients it returns `None` for, and clients that
+    /// fail the challenge, are rejected with `Rejection::Unauthenticated`. The validator only sees
+    /// authenticated clients.
+    pub fn require_auth<F>(&mut self, key_for: F)
+    where
+        F: FnMut(&Hello) -> Option<Vec<u8>> + 'static,
+    {
+        self.keys = Some(Box::new(key_for));
+    }
+
+    /// Sets how long the handshake may take after connecting, 10 seconds by default.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Returns whether the handshake with `peer_id` completed.
+    pub fn is_ready(&self, peer_id: PeerID) -> bool {
+        self.ready.contains(&peer_id)
+    }
+
+    /// Returns the next outcome of a handshake.
+    pub fn next_event(&mut self) -> Option<HandshakeEvent> {
+        self.events.pop_front()
+    }
+
+    /// Processes an event returned by `Host::service`.
+    ///
+    /// Returns `true` if the event belongs to a peer that is not ready yet, which needs no further
+    /// handling; this includes its `Connect` event. Events of ready peers are left to the caller,
+    /// except for packets on the handshake channel.
+    pub fn handle_event<T>(&mut self, host: &mut Host<T>, event: &Event) -> bool {
+        let peer_id = event.peer_id;
+        match event.kind {
+            EventKind::Connect => {
+                self.pending.insert(peer_id, Instant::now() + self.timeout);
+                if let Role::Client = self.role {
+                    let mut data = encode(KIND_HELLO);
+                    self.hello.encode(&mut data);
+                    send(host, peer_id, self.channel_id, data);
+                }
+                true
+            }
+            EventKind::Receive {
+                channel_id,
+                ref packet,
+            } => {
+                if self.ready.contains(&peer_id) {
+                    return channel_id == self.channel_id;
+                }
+
+                if channel_id == self.channel_id && self.pending.contains_key(&peer_id) {
+                    match packet
+                        .data()
+                        .strip_prefix(MAGIC)
+                        .and_then(|rest| rest.split_first())
+                    {
+                        Some((&kind, body)) => self.handle_packet(host, peer_id, kind, body),
+                        None => self.reject(host, peer_id, Rejection::Malformed),
+                    }
+                }
+                true
+            }
+            ref kind if kind.is_disconnect() => {
+                if self.ready.remove(&peer_id) {
+                    return false;
+                }
+
+                self.challenges.remove(&peer_id);
+                if self.pending.remove(&peer_id).is_some() {
+                    self.events.push_back(HandshakeEvent::Rejected {
+                        peer_id,
+                        reason: Rejection::Disconnected,
+                    });
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Rejects peers whose handshake did not complete within the timeout.
+    ///
+    /// Call this regularly, e.g. after every `Host::service`.
+    pub fn poll<T>(&mut self, host: &mut Host<T>) {
+        let now = Instant::now();
+        let expired: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(_, &deadline)| deadline <= now)
+            .map(|(&peer_id, _)| peer_id)
+            .collect();
+
+        for peer_id in expired {
+            self.reject(host, peer_id, Rejection::TimedOut);
+        }
+    }
+
+    fn handle_packet<T>(&mut self, host: &mut Host<T>, peer_id: PeerID, kind: u8, body: &[u8]) {
+        let is_server = matches!(self.role, Role::Server(_));
+        match (is_server, kind) {
+            (true, KIND_HELLO) => {
+                let hello = match Hello::decode(body) {
+                    Some(hello) => hello,
+                    None => return self.reject(host, peer_id, Rejection::Malformed),
+                };
+
+                let expected = self.hello.protocol_version;
+                if hello.protocol_version != expected {
+                    let reason = Rejection::VersionMismatch {
+                        expected,
+                        got: hello.protocol_version,
+                    };
+                    return self.reject(host, peer_id, reason);
+                }
+
+                let key = match self.keys.as_mut() {
+                    Some(key_for) => match key_for(&hello) {
+                        Some(key) => key,
+                        None => return self.reject(host, peer_id, Rejection::Unauthenticated),
+                    },
+                    None => return self.admit(host, peer_id, hello),
+                };
+
+                let nonce = nonce();
+                let mut data = encode(KIND_CHALLENGE);
+                data.extend_from_slice(&nonce);
+                send(host, peer_id, self.channel_id, data);
+                self.challenges
+                    .insert(peer_id, Challenge { nonce, key, hello });
+            }
+            (true, KIND_RESPONSE) => {
+                let challenge = match self.challenges.remove(&peer_id) {
+                    Some(challenge) => challenge,
+                    None => return self.reject(host, peer_id, Rejection::Malformed),
+                };
+
+                let expected =
+                    challenge_response(&challenge.key, &challenge.nonce, &challenge.hello);
+                if constant_time_eq(&expected, body) {
+                    self.admit(host, peer_id, challenge.hello);
+                } else {
+                    self.reject(host, peer_id, Rejection::Unauthenticated);
+                }
+            }
+            (false, KIND_CHALLENGE) if body.len() == NONCE_LEN => match self.secret {
+                Some(ref secret) => {
+                    let mut data = encode(KIND_RESPONSE);
+                    data.extend_from_slice(&challenge_response(secret, body, &self.hello));
+                    send(host, peer_id, self.channel_id, data);
+                }
+                None => self.fail(host, peer_id, Rejection::Unauthenticated),
+            },
+            (false, KIND_ACCEPT) => match Hello::decode(body) {
+                Some(hello) => self.complete(peer_id, hello),
+                None => self.reject(host, peer_id, Rejection::Malformed),
+            },
+            (false, KIND_REJECT) => {
+                self.fail(host, peer_id, Rejection::decode(body));
+            }
+            _ => self.reject(host, peer_id, Rejection::Malformed),
+        }
+    }
+
+    /// Runs the validator on a client's hello, and accepts or rejects the client.
+    fn admit<T>(&mut self, host: &mut Host<T>, peer_id: PeerID, hello: Hello) {
+        let result = match self.role {
+            Role::Server(ref mut validator) => validator(peer_id, &hello),
+            Role::Client => return,
+        };
+
+        match result {
+            Ok(()) => {
+                let mut data = encode(KIND_ACCEPT);
+                self.hello.encode(&mut data);
+                send(host, peer_id, self.channel_id, data);
+                self.complete(peer_id, hello);
+            }
+            Err(reason) => self.reject(host, peer_id, Rejection::Denied(reason)),
+        }
+    }
+
+    fn complete(&mut self, peer_id: PeerID, hello: Hello) {
+        self.pending.remove(&peer_id);
+        self.ready.insert(peer_id);
+        self.events
+            .push_back(HandshakeEvent::Ready { peer_id, hello });
+    }
+
+    /// Tells the peer why it was rejected, then fails the handshake.
+    fn reject<T>(&mut self, host: &mut Host<T>, peer_id: PeerID, reason: Rejection) {
+        if let Role::Server(_) = self.role {
+            let mut data = encode(KIND_REJECT);
+            reason.encode(&mut data);
+            send(host, peer_id, self.channel_id, data);
+        }
+
+        self.fail(host, peer_id, reason);
+    }
+
+    fn fail<T>(&mut self, host: &mut Host<T>, peer_id: PeerID, reason: Rejection) {
+        self.pending.remove(&peer_id);
+        self.challenges.remove(&peer_id);
+        if let Some(peer) = host.peer_mut(peer_id) {
+            // Sends the rejection before disconnecting.
+            peer.disconnect_later(0);
+        }
+
+        self.events
+            .push_back(HandshakeEvent::Rejected { peer_id, reason });
+    }
+}
+
+fn send<T>(host: &mut Host<T>, peer_id: PeerID, channel_id: u8, data: Vec<u8>) {
+    if let (Ok(packet), Some(peer)) = (
+        Packet::new(data, PacketMode::ReliableSequenced),
+        host.peer_mut(peer_id),
+    ) {
+        // A peer that can't be sent to is disconnecting, which fails the handshake anyway.
+        let _ = peer.send_packet(packet, channel_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Handshake, HandshakeEvent, Hello, HelloError, Rejection};
+    use crate::tests::{create_host, pump_until};
+    use crate::Address;
+
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_hello_roundtrip() {
+        let mut hello = Hello {
+            protocol_version: 3,
+            build: "1.2.0".to_string(),
+            token: vec![1, 2, 3],
+            payload: Vec::new(),
+        };
+        let mut data = Vec::new();
+        hello.encode(&mut data);
+        assert_eq!(Hello::decode(&data), Some(hello.clone()));
+        assert_eq!(Hello::decode(&data[..data.len() - 1]), None);
+
+        // The payload follows the token, only if there is one.
+        hello.payload = b"locale=de".to_vec();
+        let mut with_payload = Vec::new();
+        hello.encode(&mut with_payload);
+        assert_eq!(&with_payload[..data.len()], &data[..]);
+        assert_eq!(Hello::decode(&with_payload), Some(hello));
+        assert_eq!(Hello::decode(&with_payload[..with_payload.len() - 1]), None);
+
+        let reason = Rejection::VersionMismatch {
+            expected: 3,
+            got: 2,
+        };
+        let mut data = Vec::new();
+        reason.encode(&mut data);
+        assert_eq!(Rejection::decode(&data), reason);
+    }
+
+    #[test]
+    fn test_hello_limits() {
+        let too_long = |hello: Hello| Handshake::client(0, hello).err();
+        assert_eq!(
+            too_long(Hello {
+                build: "x".repeat(256),
+                ..Hello::default()
+            }),
+            Some(HelloError::BuildTooLong(256))
+        );
+        assert_eq!(
+            too_long(Hello {
+                token: vec![0; 65536],
+                ..Hello::default()
+            }),
+            Some(HelloError::TokenTooLong(65536))
+        );
+        assert_eq!(
+            too_long(Hello {
+                payload: vec![0; 65536],
+                ..Hello::default()
+            }),
+            Some(HelloError::PayloadTooLong(65536))
+        );
+        let longest = Hello {
+            build: "x".repeat(255),
+            token: vec![0; 65535],
+            payload: vec![0; 65535],
+            ..Hello::default()
+        };
+        assert!(Handshake::server(0, longest, |_, _| Ok(())).is_ok());
+    }
+
+    #[test]
+    fn test_handshake() {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12364);
+        let mut server = create_host(Some(&server_address), 2);
+        let [mut first, mut second, mut third] = [(); 3].map(|_| create_host(None, 1));
+        let hello = |protocol_version, token: &[u8]| Hello {
+            protocol_version,
+            build: "test".to_string(),
+            token: token.to_vec(),
+            payload: token.to_vec(),
+        };
+
+        let mut server_handshake = Handshake::server(0, hello(2, b""), |_, hello| {
+            if hello.token == b"secret" {
+                Ok(())
+            } else {
+                Err("wrong token".to_string())
+            }
+        })
+        .unwrap();
+        let mut handshakes = [
+            Handshake::client(0, hello(2, b"secret")).unwrap(),
+            Handshake::client(0, hello(2, b"guess")).unwrap(),
+            Handshake::client(0, hello(1, b"secret")).unwrap(),
+        ];
+        for client in [&mut first, &mut second, &mut third] {
+            client.connect(&server_address, 1, 0).unwrap();
+        }
+
+        let mut outcomes = vec![None, None, None];
+        let hosts = &mut [&mut server, &mut first, &mut second, &mut third];
+        pump_until(hosts, |index, host, event| {
+            if index == 0 {
+                server_handshake.handle_event(host, &event);
+                return false;
+            }
+            let i = index - 1;
+            handshakes[i].handle_event(host, &event);
+            if let Some(event) = handshakes[i].next_event() {
+                outcomes[i] = Some(match event {
+                    HandshakeEvent::Ready { hello, .. } => {
+                        assert_eq!(hello.payload, hello.token);
+                        Ok(hello.protocol_version)
+                    }
+                    HandshakeEvent::Rejected { reason, .. } => Err(reason),
+                });
+            }
+            outcomes.iter().all(Option::is_some)
+        });
+
+        assert_eq!(
+            outcomes,
+            vec![
+                Some(Ok(2)),
+                Some(Err(Rejection::Denied("wrong token".to_string()))),
+                Some(Err(Rejection::VersionMismatch {
+                    expected: 2,
+                    got: 1
+                })),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_authentication() {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12365);
+        let mut server = create_host(Some(&server_address), 2);
+        let [mut first, mut second, mut third] = [(); 3].map(|_| create_host(None, 1));
+        let hello = |token: &[u8]| Hello {
+            protocol_version: 1,
+            build: String::new(),
+            token: token.to_vec(),
+            payload: Vec::new(),
+        };
+
+        let mut server_handshake = Handshake::server(0, hello(b""), |_, _| Ok(())).unwrap();
+        server_handshake.require_auth(|hello| match &hello.token[..] {
+            b"ticket-1" => Some(b"key-1".to_vec()),
+            _ => None,
+        });
+        let mut handshakes = [
+            Handshake::client(0, hello(b"ticket-1")).unwrap(),
+            Handshake::client(0, hello(b"ticket-1")).unwrap(),
+            Handshake::client(0, hello(b"ticket-2")).unwrap(),
+        ];
+        handshakes[0].set_secret(&b"key-1"[..]);
+        handshakes[1].set_secret(&b"key-2"[..]);
+        handshakes[2].set_secret(&b"key-1"[..]);
+        for client in [&mut first, &mut second, &mut third] {
+            client.connect(&server_address, 1, 0).unwrap();
+        }
+
+        let mut outcomes = vec![None, None, None];
+        let hosts = &mut [&mut server, &mut first, &mut second, &mut third];
+        pump_until(hosts, |index, host, event| {
+            if index == 0 {
+                server_handshake.handle_event(host, &event);
+                return false;
+            }
+            let i = index - 1;
+            handshakes[i].handle_event(host, &event);
+            if let Some(event) = handshakes[i].next_event() {
+                outcomes[i] = Some(match event {
+                    HandshakeEvent::Ready { .. } => Ok(()),
+                    HandshakeEvent::Rejected { reason, .. } => Err(reason),
+                });
+            }
+            outcomes.iter().all(Option::is_some)
+        });
+
+        assert_eq!(
+            outcomes,
+            vec![
+                Some(Ok(())),
+                Some(Err(Rejection::Unauthenticated)),
+                Some(Err(Rejection::Unauthenticated)),
+            ]
+        );
+    }
+}
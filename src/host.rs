@@ -1,15 +1,40 @@
+use std::collections::HashMap;
 use std::marker::PhantomData;
 use std::mem::MaybeUninit;
-use std::ops::{Index, IndexMut};
-use std::sync::Arc;
-use std::time::Duration;
+use std::net::Ipv4Addr;
+use std::ops::{ControlFlow, Index, IndexMut};
+use std::os::raw::c_int;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::{Address, EnetKeepAlive, Error, Event, EventKind, Peer, PeerID};
+use crate::ban::{self, BanIntercept, BanList};
+use crate::config::PendingConfig;
+use crate::connection_limit::{ConnectionLimitIntercept, SharedConnectionLimit};
+use crate::fragments::{FragmentIntercept, FragmentTracker, SharedFragmentTracker};
+use crate::intercept::{self, AcceptIntercept, Intercepts};
+use crate::latency;
+use crate::logging::{self, log_debug, log_info};
+use crate::ticks::TickClock;
+use crate::traffic;
+use crate::transport::Bridge;
+use crate::{
+    Address, Ban, BanTarget, ChannelConfig, ChannelTraffic, Channels, ConnectError,
+    ConnectionLimit, DisconnectError, EnetKeepAlive, EnetTime, Error, Event, EventKind,
+    FragmentStats, GroupId, HostConfigHandle, HostDiagnostics, Intercept, Packet, PacketMode, Peer,
+    PeerDataEntry, PeerGroups, PeerID, PeerState, PeerTimeout, QueryResponder, QueueLimit,
+    RateLimit, RateLimitAction, ServerInfo, TickEvent, Transport, TransportBridge,
+};
 
 use enet_sys::{
-    enet_host_bandwidth_limit, enet_host_channel_limit, enet_host_check_events, enet_host_connect,
-    enet_host_destroy, enet_host_flush, enet_host_service, ENetEvent, ENetHost, ENetPeer,
-    ENET_PROTOCOL_MAXIMUM_CHANNEL_COUNT,
+    enet_host_bandwidth_limit, enet_host_channel_limit, enet_host_check_events, enet_host_compress,
+    enet_host_compress_with_range_coder, enet_host_connect, enet_host_destroy, enet_host_flush,
+    enet_host_service, enet_socket_bind, enet_socket_create, enet_socket_destroy,
+    enet_socket_get_address, enet_socket_set_option, ENetEvent, ENetHost, ENetPeer, ENetSocket,
+    ENET_HOST_RECEIVE_BUFFER_SIZE, ENET_HOST_SEND_BUFFER_SIZE, ENET_PROTOCOL_MAXIMUM_CHANNEL_COUNT,
+    ENET_SOCKET_NULL, _ENetSocketOption_ENET_SOCKOPT_BROADCAST,
+    _ENetSocketOption_ENET_SOCKOPT_NONBLOCK, _ENetSocketOption_ENET_SOCKOPT_RCVBUF,
+    _ENetSocketOption_ENET_SOCKOPT_SNDBUF, _ENetSocketType_ENET_SOCKET_TYPE_DATAGRAM,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -57,12 +82,55 @@ impl BandwidthLimit {
     }
 }
 
+/// The longest single wait inside `Host::service(None)`, before ENet is called again.
+const BLOCKING_SERVICE_SLICE: Duration = Duration::from_millis(100);
+
+/// Converts a timeout to ENet's millisecond representation, saturating instead of wrapping.
+fn timeout_to_millis(timeout: Duration) -> u32 {
+    timeout.as_millis().min(u128::from(u32::MAX)) as u32
+}
+
+/// Where `service_any` starts looking for events, so no host can starve the others.
+static SERVICE_ANY_START: AtomicUsize = AtomicUsize::new(0);
+
+/// Source of the ids that tie a `PeerID` to the `Host` that created it.
+static NEXT_HOST_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns a fresh id, for a `Host` or anything else that hands out `PeerID`s.
+pub(crate) fn next_host_id() -> usize {
+    NEXT_HOST_ID.fetch_add(1, Ordering::Relaxed)
+}
+
 /// A `Host` represents one endpoint of an ENet connection. Created through `Enet`.
 ///
 /// This type provides functionality such as connection establishment and packet transmission.
 pub struct Host<T> {
     inner: *mut ENetHost,
+    id: usize,
     disconnect_drop: Option<PeerID>,
+    pending_connects: HashMap<usize, u32>,
+    connect_ids: HashMap<u32, usize>,
+    #[cfg(feature = "log")]
+    logged_throttles: HashMap<usize, f32>,
+    bridge: Option<Box<dyn Bridge>>,
+    intercepts: Intercepts,
+    server_info: Option<Arc<Mutex<ServerInfo>>>,
+    bans: Option<BanList>,
+    accepting: Option<Arc<AtomicBool>>,
+    groups: PeerGroups,
+    latency_history: Duration,
+    next_latency_sample: Instant,
+    rate_limits: HashMap<u8, RateLimit>,
+    connection_limit: Option<SharedConnectionLimit>,
+    peer_timeout: Option<PeerTimeout>,
+    channel_budgets: HashMap<u8, u32>,
+    channel_configs: Option<Arc<[ChannelConfig]>>,
+    queue_limit: Option<QueueLimit>,
+    /// The traffic of the peers whose state was already cleared.
+    closed_traffic: Vec<ChannelTraffic>,
+    fragments: Option<SharedFragmentTracker>,
+    busy_poll: Option<Duration>,
+    config: Option<Arc<Mutex<PendingConfig>>>,
     _keep_alive: Arc<EnetKeepAlive>,
     _peer_data: PhantomData<*const T>,
 }
@@ -73,12 +141,250 @@ impl<T> Host<T> {
 
         Host {
             inner,
+            id: next_host_id(),
             disconnect_drop: None,
+            pending_connects: HashMap::new(),
+            connect_ids: HashMap::new(),
+            #[cfg(feature = "log")]
+            logged_throttles: HashMap::new(),
+            bridge: None,
+            intercepts: Vec::new(),
+            server_info: None,
+            bans: None,
+            accepting: None,
+            groups: PeerGroups::default(),
+            latency_history: Duration::from_secs(60),
+            next_latency_sample: Instant::now(),
+            rate_limits: HashMap::new(),
+            connection_limit: None,
+            peer_timeout: None,
+            channel_budgets: HashMap::new(),
+            channel_configs: None,
+            queue_limit: None,
+            closed_traffic: Vec::new(),
+            fragments: None,
+            busy_poll: None,
+            config: None,
             _keep_alive,
             _peer_data: PhantomData,
         }
     }
 
+    pub(crate) fn attach_bridge(&mut self, bridge: Box<dyn Bridge>) {
+        self.bridge = Some(bridge);
+    }
+
+    /// Returns the bridge to the `Transport` this `Host` was created with.
+    ///
+    /// Returns `None` if this `Host` uses its own UDP socket, or was created with a different kind of transport.
+    pub fn transport<Tr: Transport + 'static>(&self) -> Option<&TransportBridge<Tr>> {
+        self.bridge.as_ref()?.as_any().downcast_ref()
+    }
+
+    /// Returns the bridge to the `Transport` this `Host` was created with, see `Host::transport`.
+    pub fn transport_mut<Tr: Transport + 'static>(&mut self) -> Option<&mut TransportBridge<Tr>> {
+        self.bridge.as_mut()?.as_any_mut().downcast_mut()
+    }
+
+    /// Adds an `Intercept` that sees every datagram this `Host` receives, before ENet does.
+    ///
+    /// Intercepts run in the order they were added, until one of them drops the datagram.
+    /// They are only invoked from within `Host::service`.
+    pub fn add_intercept<I: Intercept + 'static>(&mut self, intercept: I) {
+        // Fragment tracking stays last, so it only counts the datagrams ENet gets to see.
+        let index = self.intercepts.len() - usize::from(self.fragments.is_some());
+        self.intercepts.insert(index, Box::new(intercept));
+
+        unsafe {
+            (*self.inner).intercept = Some(intercept::intercept_callback);
+        }
+    }
+
+    /// Removes all intercepts of this `Host`, including the one advertising its server info.
+    ///
+    /// The ban list, `Host::set_accepting` and the connection limit are still enforced, and
+    /// fragment tracking continues.
+    pub fn clear_intercepts(&mut self) {
+        self.intercepts.clear();
+        self.server_info = None;
+        if let Some(ref bans) = self.bans {
+            self.intercepts.push(Box::new(BanIntercept(bans.clone())));
+        }
+        if let Some(ref accepting) = self.accepting {
+            self.intercepts
+                .push(Box::new(AcceptIntercept(accepting.clone())));
+        }
+        if let Some(ref limit) = self.connection_limit {
+            self.intercepts
+                .push(Box::new(ConnectionLimitIntercept(limit.clone())));
+        }
+        if let Some(ref fragments) = self.fragments {
+            self.intercepts
+                .push(Box::new(FragmentIntercept(fragments.clone())));
+        }
+
+        if self.intercepts.is_empty() {
+            unsafe {
+                (*self.inner).intercept = None;
+            }
+        }
+    }
+
+    /// Sets whether this `Host` accepts new connections, e.g. to drain it for maintenance, or to
+    /// close the lobby once a match started.
+    ///
+    /// While not accepting, connects are dropped before ENet sees them, so the foreign hosts get a
+    /// `ConnectTimeout`. Connected peers, connections being set up, and connects made by this
+    /// `Host` itself are not affected. Hosts accept connections by default.
+    pub fn set_accepting(&mut self, accepting: bool) {
+        match self.accepting {
+            Some(ref flag) => flag.store(accepting, Ordering::Relaxed),
+            None if accepting => (),
+            None => {
+                let flag = Arc::new(AtomicBool::new(false));
+                self.intercepts
+                    .insert(0, Box::new(AcceptIntercept(flag.clone())));
+                unsafe {
+                    (*self.inner).intercept = Some(intercept::intercept_callback);
+                }
+                self.accepting = Some(flag);
+            }
+        }
+    }
+
+    /// Returns whether this `Host` accepts new connections, see `Host::set_accepting`.
+    pub fn is_accepting(&self) -> bool {
+        self.accepting
+            .as_ref()
+            .map_or(true, |flag| flag.load(Ordering::Relaxed))
+    }
+
+    /// Advertises `info` to server browsers and LAN discovery, replacing the info advertised so far.
+    ///
+    /// The first call adds a `QueryResponder`, later calls update what it reports, so queries and
+    /// discovery probes are always answered with the same info. Clients find the host with
+    /// [query_datagram](fn.query_datagram.html) or [discover_servers](fn.discover_servers.html).
+    pub fn set_server_info(&mut self, info: ServerInfo) {
+        match self.server_info {
+            Some(ref shared) => *shared.lock().unwrap_or_else(|e| e.into_inner()) = info,
+            None => {
+                let responder = QueryResponder::new(info);
+                self.server_info = Some(responder.info());
+                self.add_intercept(responder);
+            }
+        }
+    }
+
+    /// Returns the info set with `Host::set_server_info`.
+    pub fn server_info(&self) -> Option<ServerInfo> {
+        let shared = self.server_info.as_ref()?;
+        Some(shared.lock().unwrap_or_else(|e| e.into_inner()).clone())
+    }
+
+    /// Bans the address of `target` for `duration`, and disconnects its peers with `reason`.
+    ///
+    /// The peers are disconnected like with `Peer::disconnect_now`, so there are no `Disconnect`
+    /// events for them. Datagrams from banned addresses are dropped before any intercept or ENet sees them, and
+    /// bans expire on their own, except if `duration` reaches past what `SystemTime` can represent,
+    /// e.g. `Duration::MAX`. Returns the banned address, or `None` if `target` is a peer that does
+    /// not exist.
+    pub fn ban<B: Into<BanTarget>>(
+        &mut self,
+        target: B,
+        duration: Duration,
+        reason: u32,
+    ) -> Option<Ipv4Addr> {
+        let address = match target.into() {
+            BanTarget::Address(address) => address,
+            BanTarget::Peer(peer_id) => *self.peer(peer_id)?.address().ip(),
+        };
+        self.add_ban(Ban {
+            address,
+            until: SystemTime::now().checked_add(duration),
+            reason,
+        });
+        Some(address)
+    }
+
+    /// Adds `ban` to the ban list, e.g. to restore bans saved from `Host::bans`, and disconnects
+    /// the peers it matches like `Host::ban`.
+    ///
+    /// Replaces an existing ban of the same address.
+    pub fn add_ban(&mut self, ban: Ban) {
+        if self.bans.is_none() {
+            let bans = BanList::default();
+            self.intercepts
+                .insert(0, Box::new(BanIntercept(bans.clone())));
+            unsafe {
+                (*self.inner).intercept = Some(intercept::intercept_callback);
+            }
+            self.bans = Some(bans);
+        }
+
+        let matching: Vec<_> = self
+            .peer_ids()
+            .filter(|&peer_id| {
+                let peer = &self[peer_id];
+                peer.state() != PeerState::Disconnected && *peer.address().ip() == ban.address
+            })
+            .collect();
+        for peer_id in matching {
+            // The acknowledgement of a graceful disconnect would be dropped by the ban.
+            self.disconnect_now(peer_id, ban.reason);
+        }
+
+        if let Some(ref bans) = self.bans {
+            bans.lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(ban.address, ban);
+        }
+    }
+
+    /// Lifts the ban of `address`, returning it if there was one.
+    pub fn unban(&mut self, address: &Ipv4Addr) -> Option<Ban> {
+        let bans = self.bans.as_ref()?;
+        let ban = bans
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(address)?;
+        Some(ban).filter(|ban| ban.is_active(SystemTime::now()))
+    }
+
+    /// Returns the bans that have not expired yet.
+    pub fn bans(&self) -> Vec<Ban> {
+        self.bans.as_ref().map(ban::active_bans).unwrap_or_default()
+    }
+
+    /// Sends a raw datagram to `address` on the socket of this `Host`, bypassing the ENet protocol.
+    ///
+    /// The receiving side must be able to tell such datagrams apart from ENet's, e.g. with an
+    /// `Intercept` that looks for a prefix. Like any datagram, it may get lost.
+    pub fn send_datagram(&mut self, address: &Address, data: &[u8]) -> Result<(), Error> {
+        let result = unsafe { intercept::send_datagram(self.inner, address, data) };
+        self.pump_bridge();
+        result
+    }
+
+    fn pump_bridge(&mut self) {
+        if let Some(bridge) = self.bridge.as_mut() {
+            bridge.pump();
+        }
+    }
+
+    /// Returns the underlying `ENetHost`, for functionality this wrapper does not cover yet.
+    ///
+    /// The pointer is valid as long as this `Host` is. Dereferencing it is unsafe, and code using it must
+    /// uphold the wrapper's invariants:
+    ///
+    /// - the host must not be destroyed, and its peers must not be reallocated,
+    /// - the `data` field of its peers holds the associated data of type `T` and the latency history, and
+    ///   must not be changed,
+    /// - the `intercept` callback is managed by `Host::add_intercept` and must not be changed,
+    /// - events must not be taken out of ENet by other means than `Host::service` and `Host::check_events`.
+    pub fn as_raw(&self) -> *mut ENetHost {
+        self.inner
+    }
+
     /// Sends any queued packets on the host specified to its designated peers.
     ///
     /// This function need only be used in circumstances where one wishes to send queued packets earlier than in a call to `Host::service()`.
@@ -86,6 +392,9 @@ impl<T> Host<T> {
         unsafe {
             enet_host_flush(self.inner);
         }
+        self.forget_queued_bytes();
+
+        self.pump_bridge();
     }
 
     /// Sets the bandwith limits for this `Host`.
@@ -103,6 +412,22 @@ impl<T> Host<T> {
         }
     }
 
+    /// Turns compressing packets with ENet's range coder on or off.
+    ///
+    /// A compressed host can only talk to hosts that are compressed too, since packets are
+    /// compressed whenever that makes them smaller, and dropped by hosts that can't decompress
+    /// them.
+    pub fn set_compression(&mut self, enabled: bool) -> Result<(), Error> {
+        if !enabled {
+            unsafe { enet_host_compress(self.inner, std::ptr::null()) };
+            return Ok(());
+        }
+        match unsafe { enet_host_compress_with_range_coder(self.inner) } {
+            0 => Ok(()),
+            error => Err(Error(error)),
+        }
+    }
+
     /// Sets the maximum allowed channels of future connections.
     pub fn set_channel_limit(&mut self, max_channel_count: ChannelLimit) {
         unsafe {
@@ -130,6 +455,87 @@ impl<T> Host<T> {
         Address::from_enet_address(&unsafe { (*self.inner).address })
     }
 
+    /// Moves this `Host` to a new UDP socket bound to `address`, keeping its peers and settings.
+    ///
+    /// Use a port of 0 for any free port, `Host::address` returns the one that was picked. The old
+    /// socket is closed once the new one is ready; if binding fails, nothing changes. Foreign hosts
+    /// only accept packets from the address they connected to, so existing peers time out unless
+    /// disconnected before, and have to connect again. Fails for hosts created with a `Transport`.
+    pub fn rebind(&mut self, address: &Address) -> Result<(), Error> {
+        self.rebind_with(address, |_| 0)
+    }
+
+    /// Moves this `Host` to a socket bound to `address` with `SO_REUSEPORT`, like `Host::rebind`,
+    /// so it shares the address with the other sockets bound to it that way.
+    #[cfg(all(unix, feature = "cluster"))]
+    pub(crate) fn rebind_shared(&mut self, address: &Address) -> Result<(), Error> {
+        self.rebind_with(address, |socket| {
+            let enable: libc::c_int = 1;
+            unsafe {
+                libc::setsockopt(
+                    socket,
+                    libc::SOL_SOCKET,
+                    libc::SO_REUSEPORT,
+                    &enable as *const libc::c_int as *const libc::c_void,
+                    std::mem::size_of_val(&enable) as libc::socklen_t,
+                )
+            }
+        })
+    }
+
+    /// Rebinds like `Host::rebind`, calling `configure` on the new socket before it is bound.
+    fn rebind_with(
+        &mut self,
+        address: &Address,
+        configure: impl FnOnce(ENetSocket) -> c_int,
+    ) -> Result<(), Error> {
+        if self.bridge.is_some() {
+            return Err(Error(0));
+        }
+
+        let socket = unsafe { enet_socket_create(_ENetSocketType_ENET_SOCKET_TYPE_DATAGRAM) };
+        if socket == ENET_SOCKET_NULL {
+            return Err(Error(socket));
+        }
+        let result = configure(socket);
+        if result < 0 {
+            unsafe { enet_socket_destroy(socket) };
+            return Err(Error(result));
+        }
+        let result = unsafe { enet_socket_bind(socket, &address.to_enet_address()) };
+        if result < 0 {
+            unsafe { enet_socket_destroy(socket) };
+            return Err(Error(result));
+        }
+
+        // The same options as `enet_host_create` sets.
+        unsafe {
+            enet_socket_set_option(socket, _ENetSocketOption_ENET_SOCKOPT_NONBLOCK, 1);
+            enet_socket_set_option(socket, _ENetSocketOption_ENET_SOCKOPT_BROADCAST, 1);
+            let receive_buffer = ENET_HOST_RECEIVE_BUFFER_SIZE as c_int;
+            enet_socket_set_option(
+                socket,
+                _ENetSocketOption_ENET_SOCKOPT_RCVBUF,
+                receive_buffer,
+            );
+            let send_buffer = ENET_HOST_SEND_BUFFER_SIZE as c_int;
+            enet_socket_set_option(socket, _ENetSocketOption_ENET_SOCKOPT_SNDBUF, send_buffer);
+
+            let host = &mut *self.inner;
+            enet_socket_destroy(host.socket);
+            host.socket = socket;
+            if enet_socket_get_address(socket, &mut host.address) < 0 {
+                host.address = address.to_enet_address();
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the time at which this `Host` was last serviced, according to ENet's clock.
+    pub fn service_time(&self) -> EnetTime {
+        EnetTime::from_millis(unsafe { (*self.inner).serviceTime })
+    }
+
     /// Returns the number of peers allocated for this `Host`.
     pub fn peer_count(&self) -> usize {
         unsafe { (*self.inner).peerCount }
@@ -137,64 +543,643 @@ impl<T> Host<T> {
 
     /// Returns a mutable reference to a peer at the index, None if the index is invalid.
     pub fn peer_mut(&mut self, idx: PeerID) -> Option<&mut Peer<T>> {
-        if idx.0 >= self.peer_count() {
+        self.check_peer_id(idx);
+
+        if idx.index >= self.peer_count() {
             return None;
         }
 
         Some(Peer::new_mut(unsafe {
-            &mut *((*self.inner).peers.offset(idx.0 as isize))
+            &mut *((*self.inner).peers.offset(idx.index as isize))
         }))
     }
 
+    /// Returns the data associated with the peer at the index as an entry, see
+    /// `Peer::data_entry`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index is invalid, like indexing the `Host`.
+    pub fn peer_data_entry(&mut self, idx: PeerID) -> PeerDataEntry<'_, T> {
+        self[idx].data_entry()
+    }
+
     /// Returns a reference to a peer at the index, None if the index is invalid.
     pub fn peer(&self, idx: PeerID) -> Option<&Peer<T>> {
-        if idx.0 >= self.peer_count() {
+        self.check_peer_id(idx);
+
+        if idx.index >= self.peer_count() {
             return None;
         }
 
         Some(Peer::new(unsafe {
-            &*((*self.inner).peers.offset(idx.0 as isize))
+            &*((*self.inner).peers.offset(idx.index as isize))
         }))
     }
 
+    /// Returns the connected peer whose connection has the id `connect_id`, see
+    /// `Peer::connect_id`.
+    ///
+    /// The peers are looked up in a map kept up to date by `Host::service`, instead of scanning all
+    /// peers. Returns `None` once the peer disconnected, even before the disconnect event.
+    pub fn peer_by_connect_id(&self, connect_id: u32) -> Option<PeerID> {
+        let index = *self.connect_ids.get(&connect_id)?;
+        let peer_id = PeerID {
+            index,
+            host_id: self.id,
+        };
+        let peer = &self[peer_id];
+        if peer.connect_id() != connect_id || peer.state() == PeerState::Disconnected {
+            return None;
+        }
+        Some(peer_id)
+    }
+
     pub(crate) unsafe fn peer_id(&self, peer: *mut ENetPeer) -> PeerID {
-        PeerID((peer as usize - (*self.inner).peers as usize) / std::mem::size_of::<ENetPeer>())
+        PeerID {
+            index: (peer as usize - (*self.inner).peers as usize) / std::mem::size_of::<ENetPeer>(),
+            host_id: self.id,
+        }
+    }
+
+    fn socket(&self) -> ENetSocket {
+        unsafe { (*self.inner).socket }
+    }
+
+    fn check_peer_id(&self, idx: PeerID) {
+        debug_assert_eq!(
+            idx.host_id, self.id,
+            "{:?} was created by a different Host and is not valid for this one",
+            idx
+        );
+    }
+
+    /// Returns the ids of all peers of this `Host`, in any state.
+    pub(crate) fn peer_ids(&self) -> impl Iterator<Item = PeerID> {
+        let host_id = self.id;
+        (0..self.peer_count()).map(move |index| PeerID { index, host_id })
+    }
+
+    /// Returns a snapshot of the state of this `Host` and its peers, e.g. to dump on a crash.
+    pub fn diagnostics(&self) -> HostDiagnostics {
+        HostDiagnostics::new(self)
+    }
+
+    /// Returns all peers of this `Host`, mutably, see `Host::peers_slice`.
+    pub fn peers_slice_mut(&mut self) -> &mut [Peer<T>] {
+        // `Peer` is a transparent wrapper around `ENetPeer`.
+        unsafe {
+            std::slice::from_raw_parts_mut(
+                (*self.inner).peers as *mut Peer<T>,
+                (*self.inner).peerCount,
+            )
+        }
+    }
+
+    /// Returns all peers of this `Host`, including the disconnected ones, as a slice.
+    ///
+    /// The position of a peer is the index of its `PeerID`, so loops over thousands of peers can
+    /// index the slice directly.
+    pub fn peers_slice(&self) -> &[Peer<T>] {
+        // `Peer` is a transparent wrapper around `ENetPeer`.
+        unsafe {
+            std::slice::from_raw_parts(
+                (*self.inner).peers as *const Peer<T>,
+                (*self.inner).peerCount,
+            )
+        }
     }
 
     /// Returns an iterator over all peers connected to this `Host`.
     pub fn peers_mut(&mut self) -> impl Iterator<Item = &'_ mut Peer<T>> {
-        let peers =
-            unsafe { std::slice::from_raw_parts_mut((*self.inner).peers, (*self.inner).peerCount) };
-
-        peers.into_iter().map(|peer| Peer::new_mut(&mut *peer))
+        self.peers_slice_mut().iter_mut()
     }
 
     /// Returns an iterator over all peers connected to this `Host`.
     pub fn peers(&self) -> impl Iterator<Item = &'_ Peer<T>> {
-        let peers =
-            unsafe { std::slice::from_raw_parts((*self.inner).peers, (*self.inner).peerCount) };
+        self.peers_slice().iter()
+    }
+
+    /// Returns the peer groups of this `Host`.
+    pub fn groups(&self) -> &PeerGroups {
+        &self.groups
+    }
+
+    /// Returns the peer groups of this `Host`, mutably.
+    pub fn groups_mut(&mut self) -> &mut PeerGroups {
+        &mut self.groups
+    }
+
+    /// Queues `packet` for all members of `group` on channel `channel_id`, returning how many
+    /// peers it was queued for.
+    ///
+    /// The packet is shared between the peers, instead of copied for each one. Members that it
+    /// can't be sent to, e.g. because they are disconnecting, are skipped.
+    pub fn broadcast_group(&mut self, group: GroupId, channel_id: u8, packet: Packet) -> usize {
+        let members: Vec<_> = self.groups.members(group).collect();
+        self.broadcast_to(members, channel_id, packet)
+    }
+
+    /// Queues `packet` for all connected peers on channel `channel_id`, returning how many peers
+    /// it was queued for.
+    ///
+    /// Like for `Host::broadcast_group`, the packet is shared between the peers.
+    pub fn broadcast(&mut self, channel_id: u8, packet: Packet) -> usize {
+        let peer_ids: Vec<_> = self
+            .peer_ids()
+            .filter(|&peer_id| self[peer_id].state() == PeerState::Connected)
+            .collect();
+        self.broadcast_to(peer_ids, channel_id, packet)
+    }
+
+    /// Queues `data` for all connected peers on `channel_id` reliably and sequenced, like
+    /// `Host::broadcast`.
+    pub fn broadcast_reliable(&mut self, channel_id: u8, data: Vec<u8>) -> usize {
+        self.broadcast_data(channel_id, data, PacketMode::ReliableSequenced)
+    }
+
+    /// Queues `data` for all connected peers on `channel_id` unreliably but sequenced, like
+    /// `Host::broadcast`.
+    pub fn broadcast_unreliable(&mut self, channel_id: u8, data: Vec<u8>) -> usize {
+        self.broadcast_data(channel_id, data, PacketMode::UnreliableSequenced)
+    }
+
+    /// Queues `data` for all connected peers on `channel_id` unreliably and unsequenced, like
+    /// `Host::broadcast`.
+    pub fn broadcast_unsequenced(&mut self, channel_id: u8, data: Vec<u8>) -> usize {
+        self.broadcast_data(channel_id, data, PacketMode::UnreliableUnsequenced)
+    }
+
+    fn broadcast_data(&mut self, channel_id: u8, data: Vec<u8>, mode: PacketMode) -> usize {
+        match Packet::new(data, mode) {
+            Ok(packet) => self.broadcast(channel_id, packet),
+            Err(_) => 0,
+        }
+    }
+
+    fn broadcast_to(&mut self, peer_ids: Vec<PeerID>, channel_id: u8, packet: Packet) -> usize {
+        let packet = packet.into_inner();
+
+        let sent = peer_ids
+            .into_iter()
+            .filter(|&peer_id| {
+                self.peer_mut(peer_id)
+                    .is_some_and(|peer| unsafe { peer.send_raw(packet, channel_id) }.is_ok())
+            })
+            .count();
+
+        // Like `enet_host_broadcast`, free the packet if no peer took a reference to it.
+        if unsafe { (*packet).referenceCount } == 0 {
+            drop(Packet::from_sys_packet(packet));
+        }
+
+        sent
+    }
+
+    /// Sets for how long the round trip times of peers are kept, see `Peer::latency_stats`.
+    ///
+    /// Defaults to one minute.
+    pub fn set_latency_history(&mut self, history: Duration) {
+        self.latency_history = history;
+    }
+
+    /// Limits the peers that may connect from the same IP address or subnet at once, or lifts the
+    /// limit with `None`.
+    ///
+    /// Connects over the limit are dropped before ENet sees them, so the foreign hosts get a
+    /// `ConnectTimeout`, like with `Host::set_accepting`. Peers that only turn out to be over the
+    /// limit once connected, e.g. because an intercept added later changed their address, are
+    /// disconnected right away with its `reject_data`, and their `Connect` event is never returned
+    /// by `Host::service`. Connections this host initiated are not limited.
+    pub fn set_connection_limit(&mut self, limit: Option<ConnectionLimit>) {
+        match self.connection_limit {
+            Some(ref shared) => *shared.lock().unwrap() = limit,
+            None if limit.is_none() => (),
+            None => {
+                let shared = Arc::new(Mutex::new(limit));
+                // Like the intercepts added by the user, this runs before fragment tracking.
+                let index = self.intercepts.len() - usize::from(self.fragments.is_some());
+                self.intercepts
+                    .insert(index, Box::new(ConnectionLimitIntercept(shared.clone())));
+                unsafe {
+                    (*self.inner).intercept = Some(intercept::intercept_callback);
+                }
+                self.connection_limit = Some(shared);
+            }
+        }
+    }
+
+    /// Changes when ENet gives up on peers that don't acknowledge reliable packets, for all current
+    /// and future peers, see `Peer::set_timeout`.
+    pub fn set_peer_timeout(&mut self, timeout: PeerTimeout) {
+        self.peer_timeout = Some(timeout);
+        let peer_ids: Vec<_> = self.peer_ids().collect();
+        for peer_id in peer_ids {
+            self[peer_id].set_timeout(timeout);
+        }
+    }
+
+    /// Returns a handle to change the settings of this `Host` from other threads while it runs.
+    ///
+    /// All handles share the same pending changes, which the next `Host::service` applies.
+    pub fn config_handle(&mut self) -> HostConfigHandle {
+        HostConfigHandle::new(self.config.get_or_insert_with(Default::default).clone())
+    }
+
+    fn apply_config(&mut self) {
+        let pending = match self.config {
+            Some(ref pending) => HostConfigHandle::take(pending),
+            None => return,
+        };
+
+        if let Some((incoming, outgoing)) = pending.bandwidth_limits {
+            self.set_bandwith_limits(incoming, outgoing);
+        }
+        if let Some(limit) = pending.channel_limit {
+            self.set_channel_limit(limit);
+        }
+        for (channel_id, limit) in pending.rate_limits {
+            self.set_rate_limit(channel_id, limit);
+        }
+        if let Some(limit) = pending.connection_limit {
+            self.set_connection_limit(limit);
+        }
+        if let Some(timeout) = pending.peer_timeout {
+            self.set_peer_timeout(timeout);
+        }
+    }
+
+    /// Limits the packets `channel_id` receives from each peer, or lifts the limit with `None`.
+    ///
+    /// The limit is enforced while events are processed, so packets over the limit are never
+    /// returned by `Host::service`.
+    pub fn set_rate_limit(&mut self, channel_id: u8, limit: Option<RateLimit>) {
+        match limit {
+            Some(limit) => self.rate_limits.insert(channel_id, limit),
+            None => self.rate_limits.remove(&channel_id),
+        };
+    }
+
+    /// Limits the bytes sent to each current and future peer on `channel_id` per second, or lifts
+    /// the limit with `None`, see `Peer::set_channel_budget`.
+    ///
+    /// `Peer::budget_usage` reports how close each peer is to its budget.
+    pub fn set_channel_budget(&mut self, channel_id: u8, bytes_per_second: Option<u32>) {
+        match bytes_per_second {
+            Some(bytes_per_second) => self.channel_budgets.insert(channel_id, bytes_per_second),
+            None => self.channel_budgets.remove(&channel_id),
+        };
+        let peer_ids: Vec<_> = self.peer_ids().collect();
+        for peer_id in peer_ids {
+            self[peer_id].set_channel_budget(channel_id, bytes_per_second);
+        }
+    }
+
+    /// Sets the defaults of the channels of each current and future peer, `configs[i]` for channel
+    /// `i`, see `Peer::set_channel_configs`.
+    ///
+    /// Peers connected with `Host::connect_with_configs` keep their own configs.
+    pub fn set_channel_configs(&mut self, configs: &[ChannelConfig]) {
+        let configs: Arc<[ChannelConfig]> = configs.into();
+        self.channel_configs = Some(configs.clone());
+        for peer in self.peers_mut() {
+            if peer.state() == PeerState::Connected {
+                peer.set_shared_channel_configs(configs.clone());
+            }
+        }
+    }
+
+    /// Returns the configs set with `Host::set_channel_configs`.
+    pub fn channel_configs(&self) -> &[ChannelConfig] {
+        self.channel_configs.as_deref().unwrap_or(&[])
+    }
+
+    /// Returns the packets sent and received on each channel, indexed by channel, summed over all
+    /// peers of this `Host`, including the ones that disconnected.
+    ///
+    /// Attributes bandwidth to the channels, e.g. chat, voice and state updates, without counting
+    /// at every call site. See `Peer::channel_traffic` for a single peer.
+    pub fn channel_traffic(&self) -> Vec<ChannelTraffic> {
+        let mut totals = self.closed_traffic.clone();
+        for peer in self.peers_slice() {
+            traffic::add_traffic(&mut totals, peer.channel_traffic());
+        }
+        totals
+    }
+
+    /// Counts the fragments every peer sends to this `Host` and the packets reassembled from them,
+    /// or stops counting with `None`, see `Host::fragment_stats`.
+    ///
+    /// A packet counts as timed out if its fragments did not all arrive within `timeout`. ENet
+    /// itself keeps waiting for them, as long as the peer stays connected. Fragments in compressed
+    /// datagrams are not counted. Outgoing fragments are always counted.
+    pub fn set_fragment_tracking(&mut self, timeout: Option<Duration>) {
+        match (self.fragments.as_ref(), timeout) {
+            (Some(tracker), _) => tracker.lock().unwrap().set_timeout(timeout),
+            (None, None) => (),
+            (None, Some(timeout)) => {
+                let tracker = Arc::new(Mutex::new(FragmentTracker::new(timeout)));
+                self.intercepts
+                    .push(Box::new(FragmentIntercept(tracker.clone())));
+                unsafe {
+                    (*self.inner).intercept = Some(intercept::intercept_callback);
+                }
+                self.fragments = Some(tracker);
+            }
+        }
+    }
+
+    /// Returns how the packets sent to and received from `peer_id` were split into fragments.
+    ///
+    /// Packets larger than the MTU of the peer are split, see `Peer::mtu`, and a lost fragment of
+    /// a reliable packet holds back its whole channel until it is resent. Incoming fragments are
+    /// only counted while `Host::set_fragment_tracking` is on. The counts start over when the peer
+    /// disconnects.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `peer_id` does not belong to this `Host`.
+    pub fn fragment_stats(&self, peer_id: PeerID) -> FragmentStats {
+        let outgoing = self[peer_id].outgoing_fragments();
+        let incoming = match self.fragments {
+            Some(ref tracker) => tracker.lock().unwrap().stats(peer_id.index),
+            None => FragmentStats::default(),
+        };
+        FragmentStats {
+            fragmented_packets: outgoing.fragmented_packets,
+            outgoing_fragments: outgoing.outgoing_fragments,
+            ..incoming
+        }
+    }
+
+    /// Caps the bytes ENet queues for each current and future peer, or lifts the cap with `None`,
+    /// see `Peer::set_queue_limit`.
+    pub fn set_queue_limit(&mut self, limit: Option<QueueLimit>) {
+        self.queue_limit = limit;
+        let peer_ids: Vec<_> = self.peer_ids().collect();
+        for peer_id in peer_ids {
+            self[peer_id].set_queue_limit(limit);
+        }
+    }
 
-        peers.into_iter().map(|peer| Peer::new(&*peer))
+    /// Makes `Host::service` poll the socket without sleeping for up to `spin` before it waits for
+    /// events, or turns polling off with `None`.
+    ///
+    /// Waking a sleeping thread takes the scheduler some time, which adds to the latency of every
+    /// packet. Polling avoids that for packets that arrive within `spin`, at the cost of keeping a
+    /// core busy meanwhile. Timeouts shorter than `spin` are polled for entirely.
+    pub fn set_busy_poll(&mut self, spin: Option<Duration>) {
+        self.busy_poll = spin;
+    }
+
+    fn sample_latency(&mut self) {
+        let now = Instant::now();
+        if now < self.next_latency_sample {
+            return;
+        }
+        self.next_latency_sample = now + latency::SAMPLE_INTERVAL;
+
+        let history = self.latency_history;
+        for peer in self.peers_mut() {
+            if peer.state() == PeerState::Connected {
+                peer.record_latency(now, history);
+            }
+        }
+    }
+
+    /// Makes the peers count their queues for the queue limit again, after ENet sent from them.
+    fn forget_queued_bytes(&mut self) {
+        for peer in self.peers_mut() {
+            peer.forget_queued_bytes();
+        }
     }
 
     fn drop_disconnected(&mut self) {
         if let Some(idx) = self.disconnect_drop.take() {
-            self.peer_mut(idx)
-                .expect("Invalid PeerID in disconnect_drop in enet::Host")
-                .set_data(None);
+            let peer = self
+                .peer_mut(idx)
+                .expect("Invalid PeerID in disconnect_drop in enet::Host");
+            let traffic = peer.channel_traffic().to_vec();
+            peer.clear_state();
+            traffic::add_traffic(&mut self.closed_traffic, &traffic);
+        }
+    }
+
+    /// Disconnects `peer_id` immediately like `Peer::disconnect_now`, and removes it from the
+    /// bookkeeping of this host, e.g. its groups, like its `Disconnect` event would have.
+    ///
+    /// No `Disconnect` event will be created.
+    pub fn disconnect_now(&mut self, peer_id: PeerID, data: u32) {
+        self[peer_id].disconnect_now(data);
+        self.forget_reset_peer(peer_id);
+    }
+
+    /// Forgets the peers that were reset without an event, e.g. with `Peer::disconnect_now` or
+    /// `Peer::reset`, before ENet hands out their slots again.
+    fn forget_reset_peers(&mut self) {
+        let reset: Vec<_> = self
+            .peer_ids()
+            .filter(|&peer_id| Some(peer_id) != self.disconnect_drop)
+            .filter(|&peer_id| {
+                let peer = &self[peer_id];
+                peer.state() == PeerState::Disconnected && peer.has_state()
+            })
+            .collect();
+        for peer_id in reset {
+            self.forget_reset_peer(peer_id);
         }
     }
 
+    /// Forgets `peer_id` like its `Disconnect` event would have, and keeps its traffic in the
+    /// totals of this host.
+    fn forget_reset_peer(&mut self, peer_id: PeerID) {
+        self.forget_peer(peer_id);
+        let peer = &mut self[peer_id];
+        let traffic = peer.channel_traffic().to_vec();
+        peer.clear_state();
+        traffic::add_traffic(&mut self.closed_traffic, &traffic);
+    }
+
+    /// Removes `peer_id` from the bookkeeping of this host once it disconnected. Returns whether
+    /// the peer still was connecting to a foreign host.
+    fn forget_peer(&mut self, peer_id: PeerID) -> bool {
+        let connect_id = self[peer_id].connect_id();
+        let connecting = self.pending_connects.remove(&peer_id.index) == Some(connect_id);
+        // A reset peer has lost its connect id, so look for its slot instead.
+        self.connect_ids
+            .retain(|_, &mut index| index != peer_id.index);
+        #[cfg(feature = "log")]
+        self.logged_throttles.remove(&peer_id.index);
+
+        self.groups.remove_peer(peer_id);
+        if let Some(ref tracker) = self.fragments {
+            tracker.lock().unwrap().remove_peer(peer_id.index);
+        }
+        let address = self[peer_id].address();
+        if let Some(bridge) = self.bridge.as_mut() {
+            bridge.release(&address);
+        }
+        connecting
+    }
+
+    /// Returns the data to reject `peer_id` with, if it connected from an address that is over
+    /// the connection limit.
+    fn over_connection_limit(&self, peer_id: PeerID) -> Option<u32> {
+        let limit = (*self.connection_limit.as_ref()?.lock().unwrap())?;
+        let address = *self[peer_id].address().ip();
+        let others = self
+            .peer_ids()
+            .filter(|&other| other != peer_id)
+            .map(|other| &self[other])
+            .filter(|peer| peer.state() != PeerState::Disconnected)
+            .map(|peer| *peer.address().ip());
+        if limit.admits(address, others) {
+            return None;
+        }
+
+        log_info!(
+            target: logging::CONNECTION,
+            "{} rejected, {} is over the connection limit",
+            peer_id,
+            address
+        );
+        Some(limit.reject_data)
+    }
+
     fn process_event(&mut self, sys_event: ENetEvent) -> Option<Event> {
         self.drop_disconnected();
 
-        let event = Event::from_sys_event(sys_event, self);
-        if let Some(Event {
-            peer_id,
-            kind: EventKind::Disconnect { .. },
-        }) = event
-        {
-            self.disconnect_drop = Some(peer_id);
+        let mut event = Event::from_sys_event(sys_event, self);
+        let mut limited = None;
+        match event {
+            Some(Event {
+                peer_id,
+                kind: EventKind::Connect,
+            }) => {
+                let connect_id = self[peer_id].connect_id();
+                let outgoing = self.pending_connects.remove(&peer_id.index) == Some(connect_id);
+                let rejected = if outgoing {
+                    None
+                } else {
+                    self.over_connection_limit(peer_id)
+                };
+                if let Some(data) = rejected {
+                    self.disconnect_now(peer_id, data);
+                    limited = Some(None);
+                } else {
+                    // Peers reset with `Peer::disconnect_now` leave their entries behind, which
+                    // must not carry over to the new connection in their slot.
+                    self.connect_ids
+                        .retain(|_, &mut index| index != peer_id.index);
+                    self.connect_ids.insert(connect_id, peer_id.index);
+                    self.groups.remove_peer(peer_id);
+                    if let Some(ref tracker) = self.fragments {
+                        tracker.lock().unwrap().remove_peer(peer_id.index);
+                    }
+                    #[cfg(feature = "log")]
+                    self.logged_throttles.remove(&peer_id.index);
+                    let address = self[peer_id].address();
+                    if let Some(bridge) = self.bridge.as_mut() {
+                        bridge.establish(&address);
+                    }
+                    if let Some(timeout) = self.peer_timeout {
+                        self[peer_id].set_timeout(timeout);
+                    }
+                    let budgets: Vec<_> =
+                        self.channel_budgets.iter().map(|(&c, &b)| (c, b)).collect();
+                    for (channel_id, bytes_per_second) in budgets {
+                        self[peer_id].set_channel_budget(channel_id, Some(bytes_per_second));
+                    }
+                    let queue_limit = self.queue_limit;
+                    self[peer_id].set_queue_limit(queue_limit);
+                    if let Some(configs) = self.channel_configs.clone() {
+                        if !self[peer_id].has_channel_configs() {
+                            self[peer_id].set_shared_channel_configs(configs);
+                        }
+                    }
+                    log_info!(
+                        target: logging::CONNECTION,
+                        "{} connected {} {}",
+                        peer_id,
+                        if outgoing { "to" } else { "from" },
+                        self[peer_id].address()
+                    );
+                }
+            }
+            Some(Event {
+                peer_id,
+                ref mut kind,
+            }) if kind.is_disconnect() => {
+                let timed_out = self.forget_peer(peer_id);
+
+                // ENet reports a connection attempt that timed out as a `Disconnect` with data 0. A
+                // refusal with data 0 looks the same, see `EventKind::ConnectTimeout`.
+                if timed_out {
+                    if let EventKind::Disconnect { data: 0 } = kind {
+                        *kind = EventKind::ConnectTimeout;
+                    }
+                }
+                let address = Address::from_enet_address(&unsafe { (*sys_event.peer).address });
+                match kind {
+                    EventKind::Disconnect { data } => log_info!(
+                        target: logging::CONNECTION,
+                        "{} at {} disconnected with data {}",
+                        peer_id,
+                        address,
+                        data
+                    ),
+                    _ => log_info!(
+                        target: logging::CONNECTION,
+                        "{} timed out connecting to {}",
+                        peer_id,
+                        address
+                    ),
+                }
+                self.disconnect_drop = Some(peer_id);
+            }
+            Some(Event {
+                peer_id,
+                kind:
+                    EventKind::Receive {
+                        channel_id,
+                        ref packet,
+                    },
+            }) => {
+                let len = packet.data().len();
+                self[peer_id].record_received(channel_id, len);
+                if let Some(limit) = self.rate_limits.get(&channel_id).copied() {
+                    let peer = self
+                        .peer_mut(peer_id)
+                        .expect("Invalid PeerID in Receive event in enet::Host");
+
+                    if !peer.admit_rate(channel_id, &limit, Instant::now(), len) {
+                        log_debug!(
+                            target: logging::DROP,
+                            "dropped a packet of {} bytes from {} on channel {}, over the rate limit",
+                            len,
+                            peer_id,
+                            channel_id
+                        );
+                        limited = Some(match limit.action {
+                            RateLimitAction::Drop => None,
+                            RateLimitAction::Warn => Some(Event {
+                                peer_id,
+                                kind: EventKind::RateLimited { channel_id },
+                            }),
+                            RateLimitAction::Disconnect(data) => {
+                                peer.disconnect(data);
+                                None
+                            }
+                        });
+                    }
+                }
+            }
+            _ => (),
+        }
+
+        // Dropping the event frees the packet that exceeded the rate limit.
+        if let Some(replacement) = limited {
+            event = replacement;
         }
 
         event
@@ -204,56 +1189,233 @@ impl<T> Host<T> {
     ///
     /// This should be called regularly for ENet to work properly with good performance.
     ///
-    /// The function won't block for less than 1ms.
-    pub fn service(&mut self, timeout: Duration) -> Result<Option<Event>, Error> {
+    /// If `timeout` is `None`, this blocks until an event arrives.
+    /// Otherwise it waits at most `timeout` for an event. ENet only works with millisecond
+    /// precision, so any timeout below 1ms (including `Duration::from_secs(0)`) never blocks
+    /// and only handles what is immediately available.
+    ///
+    /// Must not be called from within an `Intercept`, debug builds panic if it is.
+    pub fn service(&mut self, timeout: Option<Duration>) -> Result<Option<Event>, Error> {
+        match timeout {
+            Some(timeout) => self.service_millis(timeout_to_millis(timeout)),
+            None => loop {
+                // Wait in bounded slices, so a "blocking" call still returns to us regularly.
+                if let Some(event) =
+                    self.service_millis(timeout_to_millis(BLOCKING_SERVICE_SLICE))?
+                {
+                    return Ok(Some(event));
+                }
+            },
+        }
+    }
+
+    /// Waits for events like `Host::service`, then appends every event that is available to
+    /// `events` in one call. Returns the number of events appended.
+    ///
+    /// After the wait, only events ENet already received are added, without sending or receiving
+    /// again, so the batch costs a single round of network I/O. The batch ends after a
+    /// `Disconnect` or `ConnectTimeout`, so the data of that peer is still available until the
+    /// next call, like for `Host::service`.
+    pub fn service_batch(
+        &mut self,
+        timeout: Option<Duration>,
+        events: &mut Vec<Event>,
+    ) -> Result<usize, Error> {
+        let mut count = 0;
+        let mut event = self.service(timeout)?;
+        while let Some(next) = event {
+            let disconnect = next.kind.is_disconnect();
+            events.push(next);
+            count += 1;
+            if disconnect {
+                break;
+            }
+            event = self.check_events()?;
+        }
+        Ok(count)
+    }
+
+    fn service_millis(&mut self, timeout_ms: u32) -> Result<Option<Event>, Error> {
+        self.apply_config();
+        if self.bridge.is_none() {
+            let timeout = Duration::from_millis(u64::from(timeout_ms));
+            let spin = match self.busy_poll {
+                Some(spin) if timeout_ms > 0 => spin.min(timeout),
+                _ => return self.service_enet(timeout_ms),
+            };
+
+            let start = Instant::now();
+            while start.elapsed() < spin {
+                if let Some(event) = self.service_enet(0)? {
+                    return Ok(Some(event));
+                }
+                std::hint::spin_loop();
+            }
+            // Rounds the time spun down, so the timeout is never cut short.
+            let spun = start.elapsed().as_millis() as u32;
+            return self.service_enet(timeout_ms.saturating_sub(spun));
+        }
+
+        // ENet can't wait on the transport, so wait in 1ms slices and relay traffic in between.
+        let deadline = Instant::now() + Duration::from_millis(u64::from(timeout_ms));
+        loop {
+            self.pump_bridge();
+            let slice = if Instant::now() < deadline { 1 } else { 0 };
+            let event = self.service_enet(slice)?;
+            self.pump_bridge();
+
+            if event.is_some() || Instant::now() >= deadline {
+                return Ok(event);
+            }
+        }
+    }
+
+    fn service_enet(&mut self, timeout_ms: u32) -> Result<Option<Event>, Error> {
         // ENetEvent is Copy (aka has no Drop impl), so we don't have to make sure we `mem::forget` it later on
         let mut sys_event = MaybeUninit::uninit();
 
-        let res = unsafe {
-            enet_host_service(
-                self.inner,
-                sys_event.as_mut_ptr(),
-                timeout.as_millis() as u32,
-            )
-        };
+        self.forget_reset_peers();
+        let inner = self.inner;
+        let res = intercept::with_active(&mut self.intercepts, || unsafe {
+            enet_host_service(inner, sys_event.as_mut_ptr(), timeout_ms)
+        });
+        self.forget_queued_bytes();
+        self.sample_latency();
+        if let Some(ref tracker) = self.fragments {
+            tracker.lock().unwrap().expire(Instant::now());
+        }
+        #[cfg(feature = "log")]
+        self.log_throttles();
 
         match res {
-            r if r > 0 => Ok(unsafe { self.process_event(sys_event.assume_init()) }),
+            r if r > 0 => match unsafe { self.process_event(sys_event.assume_init()) } {
+                Some(event) => Ok(Some(event)),
+                // The event was dropped by a rate limit, deliver the next one instead.
+                None => self.check_events(),
+            },
             0 => Ok(None),
-            r if r < 0 => Err(Error(r)),
+            r if r < 0 => {
+                log_info!(target: logging::ERROR, "servicing failed with {}", r);
+                Err(Error(r))
+            }
             _ => panic!("unreachable"),
         }
 
         // TODO: check `total*` fields on `inner`, these need to be reset from time to time.
     }
 
+    /// Logs the peers whose packet throttle changed since the last call.
+    #[cfg(feature = "log")]
+    fn log_throttles(&mut self) {
+        let throttles: Vec<_> = self
+            .peers_slice()
+            .iter()
+            .map(|peer| (peer.state() == PeerState::Connected).then(|| peer.packet_throttle()))
+            .collect();
+        for (index, throttle) in throttles.into_iter().enumerate() {
+            let throttle = match throttle {
+                Some(throttle) => throttle,
+                None => continue,
+            };
+            match self.logged_throttles.insert(index, throttle) {
+                Some(previous) if previous != throttle => log_debug!(
+                    target: logging::THROTTLE,
+                    "peer#{} throttle changed from {:.2} to {:.2}",
+                    index,
+                    previous,
+                    throttle
+                ),
+                _ => (),
+            }
+        }
+    }
+
+    /// Runs a fixed-timestep loop at `ticks_per_second`, servicing the host between the ticks.
+    ///
+    /// `handler` gets every event as `TickEvent::Event`, and every tick as `TickEvent::Tick`. Ticks
+    /// are scheduled from the start of the loop, so time spent in the handler doesn't make them
+    /// drift. Late ticks are caught up back to back, unless the loop falls more than a few ticks
+    /// behind, in which case it skips them and starts over from the current time.
+    ///
+    /// Runs until `handler` returns `ControlFlow::Break`, or servicing fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `ticks_per_second` is 0.
+    pub fn run_ticks<F>(&mut self, ticks_per_second: u32, mut handler: F) -> Result<(), Error>
+    where
+        F: FnMut(&mut Host<T>, TickEvent) -> ControlFlow<()>,
+    {
+        let mut clock = TickClock::new(ticks_per_second, Instant::now());
+        loop {
+            while let Some(tick) = clock.due(Instant::now()) {
+                if handler(self, TickEvent::Tick(tick)).is_break() {
+                    return Ok(());
+                }
+            }
+
+            if let Some(event) = self.service(Some(clock.timeout(Instant::now())))? {
+                if handler(self, TickEvent::Event(event)).is_break() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
     /// Checks for any queued events on this `Host` and dispatches one if available
     pub fn check_events(&mut self) -> Result<Option<Event>, Error> {
-        // ENetEvent is Copy (aka has no Drop impl), so we don't have to make sure we `mem::forget` it later on
-        let mut sys_event = MaybeUninit::uninit();
+        loop {
+            // ENetEvent is Copy (aka has no Drop impl), so we don't have to make sure we `mem::forget` it later on
+            let mut sys_event = MaybeUninit::uninit();
 
-        let res = unsafe { enet_host_check_events(self.inner, sys_event.as_mut_ptr()) };
+            let res = unsafe { enet_host_check_events(self.inner, sys_event.as_mut_ptr()) };
 
-        match res {
-            r if r > 0 => Ok(unsafe { self.process_event(sys_event.assume_init()) }),
-            0 => Ok(None),
-            r if r < 0 => Err(Error(r)),
-            _ => panic!("unreachable"),
+            match res {
+                // Events dropped by a rate limit are skipped.
+                r if r > 0 => {
+                    if let Some(event) = unsafe { self.process_event(sys_event.assume_init()) } {
+                        return Ok(Some(event));
+                    }
+                }
+                0 => return Ok(None),
+                r if r < 0 => {
+                    log_info!(target: logging::ERROR, "checking events failed with {}", r);
+                    return Err(Error(r));
+                }
+                _ => panic!("unreachable"),
+            }
         }
     }
 
     /// Initiates a connection to a foreign host.
     ///
     /// The connection will not be done until a `Event::Connected` for this peer was received.
+    /// If the attempt times out instead, an `EventKind::ConnectTimeout` is delivered for the returned `PeerID`.
     ///
-    /// `channel_count` specifies how many channels to allocate for this peer.
+    /// `channel_count` specifies how many channels to allocate for this peer, it may not exceed this `Host`'s channel limit.
     /// `data` is a user-specified value that can be chosen arbitrarily.
     pub fn connect(
         &mut self,
         address: &Address,
         channel_count: usize,
         data: u32,
-    ) -> Result<(&mut Peer<T>, PeerID), Error> {
+    ) -> Result<(&mut Peer<T>, PeerID), ConnectError> {
+        let limit = unsafe { (*self.inner).channelLimit };
+        if channel_count > limit {
+            return Err(ConnectError::ChannelCountExceedsLimit {
+                requested: channel_count,
+                limit,
+            });
+        }
+
+        self.forget_reset_peers();
+        if self
+            .peers()
+            .all(|peer| peer.state() != PeerState::Disconnected)
+        {
+            return Err(ConnectError::NoFreePeerSlots);
+        }
+
         let res: *mut ENetPeer = unsafe {
             enet_host_connect(
                 self.inner,
@@ -263,16 +1425,224 @@ impl<T> Host<T> {
             )
         };
 
+        // We checked for a free slot above, so ENet can only have failed to allocate the channels.
         if res.is_null() {
-            return Err(Error(0));
+            return Err(ConnectError::AllocationFailed);
+        }
+
+        // We can do pointer arithmetic here to determine the offset of our new Peer in the
+        // list of peers, which is it's PeerID.
+        let peer_id = unsafe { self.peer_id(res) };
+        self.pending_connects
+            .insert(peer_id.index, unsafe { (*res).connectID });
+
+        Ok((Peer::new_mut(unsafe { &mut *res }), peer_id))
+    }
+
+    /// Connects to a foreign host like `connect`, and services this `Host` until the connection is
+    /// established, returning its `Connect` event.
+    ///
+    /// Fails with `ConnectError::TimedOut` if the connection isn't established within `timeout`,
+    /// or ENet gives up before, and resets the peer. Fails with `ConnectError::Refused` if the
+    /// foreign host disconnects instead. Events of other peers that arrive meanwhile are appended
+    /// to `events`, the data of peers that disconnected is already freed by the time this returns.
+    pub fn connect_sync(
+        &mut self,
+        address: &Address,
+        channel_count: usize,
+        data: u32,
+        timeout: Duration,
+        events: &mut Vec<Event>,
+    ) -> Result<Event, ConnectError> {
+        let peer_id = self.connect(address, channel_count, data)?.1;
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::ZERO {
+                self.disconnect_now(peer_id, 0);
+                return Err(ConnectError::TimedOut);
+            }
+
+            // ENet resends the connect only between waits, so don't wait for long at once.
+            let event = match self.service(Some(remaining.min(BLOCKING_SERVICE_SLICE))) {
+                Ok(Some(event)) => event,
+                Ok(None) => continue,
+                Err(error) => return Err(ConnectError::Service(error)),
+            };
+            if event.peer_id != peer_id {
+                events.push(event);
+                continue;
+            }
+            match event.kind {
+                EventKind::Connect => {
+                    // Acknowledge the connection right away, so the foreign host completes it too.
+                    self.flush();
+                    return Ok(event);
+                }
+                EventKind::ConnectTimeout => return Err(ConnectError::TimedOut),
+                EventKind::Disconnect { data } => return Err(ConnectError::Refused(data)),
+                _ => events.push(event),
+            }
+        }
+    }
+
+    /// Disconnects from a peer like `Peer::disconnect`, and services this `Host` until the
+    /// disconnect is acknowledged, returning its `Disconnect` event.
+    ///
+    /// Once this returns successfully, the foreign host knows about the disconnect, e.g. before
+    /// shutting down. Fails with `DisconnectError::TimedOut` if no acknowledgement arrives within
+    /// `timeout`, and resets the peer. Events of other peers that arrive meanwhile are appended to
+    /// `events`, like in `connect_sync`.
+    pub fn disconnect_sync(
+        &mut self,
+        peer_id: PeerID,
+        data: u32,
+        timeout: Duration,
+        events: &mut Vec<Event>,
+    ) -> Result<Event, DisconnectError> {
+        if self[peer_id].state() == PeerState::Disconnected {
+            return Err(DisconnectError::NotConnected);
         }
+        self[peer_id].disconnect(data);
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::ZERO {
+                self.disconnect_now(peer_id, data);
+                return Err(DisconnectError::TimedOut);
+            }
 
-        Ok((
-            Peer::new_mut(unsafe { &mut *res }),
-            // We can do pointer arithmetic here to determine the offset of our new Peer in the
-            // list of peers, which is it's PeerID.
-            unsafe { self.peer_id(res) },
-        ))
+            let event = match self.service(Some(remaining.min(BLOCKING_SERVICE_SLICE))) {
+                Ok(Some(event)) => event,
+                Ok(None) => continue,
+                Err(error) => return Err(DisconnectError::Service(error)),
+            };
+            if event.peer_id == peer_id && event.kind.is_disconnect() {
+                return Ok(event);
+            }
+            events.push(event);
+        }
+    }
+
+    /// Disconnects all peers with `data`, waits at most `timeout` for them to acknowledge, and
+    /// destroys this `Host`, e.g. when a dedicated server stops.
+    ///
+    /// Events that arrive meanwhile are dropped. Peers that did not acknowledge in time are
+    /// disconnected like with `Peer::disconnect_now`. Returns the number of those.
+    pub fn shutdown(mut self, data: u32, timeout: Duration) -> Result<usize, Error> {
+        for peer in self.peers_mut() {
+            if peer.state() != PeerState::Disconnected {
+                peer.disconnect(data);
+            }
+        }
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            if self
+                .peers()
+                .all(|peer| peer.state() == PeerState::Disconnected)
+            {
+                return Ok(0);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::ZERO {
+                break;
+            }
+            self.service(Some(remaining.min(BLOCKING_SERVICE_SLICE)))?;
+        }
+
+        let unacknowledged: Vec<_> = self
+            .peer_ids()
+            .filter(|&peer_id| self[peer_id].state() != PeerState::Disconnected)
+            .collect();
+        for &peer_id in &unacknowledged {
+            self.disconnect_now(peer_id, data);
+        }
+        Ok(unacknowledged.len())
+    }
+
+    /// Initiates a connection like `connect`, allocating one channel per config, with the defaults
+    /// of `configs` for this peer only, see `Peer::set_channel_configs`.
+    pub fn connect_with_configs(
+        &mut self,
+        address: &Address,
+        configs: &[ChannelConfig],
+        data: u32,
+    ) -> Result<(&mut Peer<T>, PeerID), ConnectError> {
+        let (peer, peer_id) = self.connect(address, configs.len(), data)?;
+        peer.set_channel_configs(configs);
+        Ok((peer, peer_id))
+    }
+
+    /// Initiates a connection like `connect`, allocating the channels of `channels`.
+    pub fn connect_with_channels<const N: usize>(
+        &mut self,
+        address: &Address,
+        channels: Channels<N>,
+        data: u32,
+    ) -> Result<(&mut Peer<T>, PeerID), ConnectError> {
+        self.connect(address, channels.count(), data)
+    }
+}
+
+/// Services `hosts` until one of them has an event, see `Enet::service_any`.
+pub(crate) fn service_any<T>(
+    hosts: &mut [&mut Host<T>],
+    timeout: Option<Duration>,
+) -> Result<Option<(usize, Event)>, Error> {
+    if hosts.is_empty() {
+        return Ok(None);
+    }
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let start = SERVICE_ANY_START.fetch_add(1, Ordering::Relaxed);
+    // Sockets of hosts with a transport don't see its traffic, so their transport has to be polled.
+    let poll_transports = hosts.iter().any(|host| host.bridge.is_some());
+    let sockets: Vec<_> = hosts.iter().map(|host| host.socket()).collect();
+
+    loop {
+        for offset in 0..hosts.len() {
+            let index = (start + offset) % hosts.len();
+            if let Some(event) = hosts[index].service_millis(0)? {
+                return Ok(Some((index, event)));
+            }
+        }
+
+        let mut wait = match deadline {
+            Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+            None => BLOCKING_SERVICE_SLICE,
+        };
+        if wait == Duration::from_secs(0) {
+            return Ok(None);
+        }
+        if poll_transports {
+            wait = wait.min(Duration::from_millis(1));
+        }
+
+        crate::poll::wait_readable(&sockets, timeout_to_millis(wait).max(1))?;
+    }
+}
+
+/// Shows the settings and totals of the host. The peers are left out, there may be thousands of
+/// them, see `Host::diagnostics` for those.
+impl<T> std::fmt::Debug for Host<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let raw = unsafe { &*self.inner };
+        f.debug_struct("Host")
+            .field("address", &self.address())
+            .field("peer_slots", &raw.peerCount)
+            .field("connected_peers", &raw.connectedPeers)
+            .field("channel_limit", &self.channel_limit())
+            .field("incoming_bandwidth", &raw.incomingBandwidth)
+            .field("outgoing_bandwidth", &raw.outgoingBandwidth)
+            .field("mtu", &raw.mtu)
+            .field("total_sent_bytes", &raw.totalSentData)
+            .field("total_sent_packets", &raw.totalSentPackets)
+            .field("total_received_bytes", &raw.totalReceivedData)
+            .field("total_received_packets", &raw.totalReceivedPackets)
+            .finish()
     }
 }
 
@@ -290,11 +1660,50 @@ impl<T> IndexMut<PeerID> for Host<T> {
     }
 }
 
+/// The UDP socket of a `Host`, to wait for it in an existing event loop, e.g. one built on mio or
+/// epoll.
+///
+/// Register the socket for readability, and whenever it is readable, call
+/// `Host::service(Some(Duration::ZERO))` until it returns `None`. ENet also needs to be serviced
+/// without traffic to resend packets and send pings, so have the loop wake up no later than the
+/// next timer of a peer, see `Peer::next_timer` or `ServiceInterval::timeout`. Call `Host::flush`
+/// after sending outside of `Host::service`.
+///
+/// Hosts created with a `Transport` don't receive their traffic on this socket.
+///
+/// With mio, wrap the descriptor in `mio::unix::SourceFd` to register it.
+#[cfg(unix)]
+impl<T> std::os::unix::io::AsRawFd for Host<T> {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        self.socket()
+    }
+}
+
+/// The UDP socket of a `Host`, see the `AsRawFd` implementation.
+///
+/// Event loops that take ownership of their sources, e.g. calloop's `Generic`, can own the `Host`
+/// itself, and service it from their callback.
+#[cfg(unix)]
+impl<T> std::os::unix::io::AsFd for Host<T> {
+    fn as_fd(&self) -> std::os::unix::io::BorrowedFd<'_> {
+        // The socket stays open until the host is destroyed.
+        unsafe { std::os::unix::io::BorrowedFd::borrow_raw(self.socket()) }
+    }
+}
+
+/// The UDP socket of a `Host`, see the `AsRawFd` implementation on Unix.
+#[cfg(windows)]
+impl<T> std::os::windows::io::AsRawSocket for Host<T> {
+    fn as_raw_socket(&self) -> std::os::windows::io::RawSocket {
+        self.socket() as std::os::windows::io::RawSocket
+    }
+}
+
 impl<T> Drop for Host<T> {
     /// Call the corresponding ENet cleanup-function(s).
     fn drop(&mut self) {
         for peer in self.peers_mut() {
-            peer.set_data(None);
+            peer.clear_state();
         }
 
         unsafe {
@@ -302,3 +1711,43 @@ impl<T> Drop for Host<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::timeout_to_millis;
+    use crate::tests::{connected_pair, create_host};
+    use crate::{Address, ConnectError};
+
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    #[test]
+    fn test_timeout_to_millis() {
+        assert_eq!(timeout_to_millis(Duration::from_micros(999)), 0);
+        assert_eq!(timeout_to_millis(Duration::from_millis(1500)), 1500);
+        assert_eq!(timeout_to_millis(Duration::from_secs(u64::MAX)), u32::MAX);
+    }
+
+    #[test]
+    fn test_disconnect_now_forgets_peer() {
+        let (mut server, _client, client_peer, _) = connected_pair(12418, 1);
+        assert_eq!(server.connect_ids.len(), 1);
+        server.ban(client_peer, Duration::from_secs(60), 0).unwrap();
+        assert!(server.connect_ids.is_empty());
+        assert_eq!(
+            server.peer_by_connect_id(server[client_peer].connect_id()),
+            None
+        );
+
+        // Nothing listens on this port, so the attempt times out.
+        let mut client = create_host(None, 1);
+        let nowhere = Address::new(Ipv4Addr::LOCALHOST, 12419);
+        let mut events = Vec::new();
+        match client.connect_sync(&nowhere, 1, 0, Duration::from_millis(10), &mut events) {
+            Err(ConnectError::TimedOut) => (),
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert!(client.pending_connects.is_empty());
+        assert!(client.connect_ids.is_empty());
+    }
+}
@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use crate::{
+    Address, ConnectError, Error, Event, Host, Packet, PacketMode, PeerID, PeerState, SendError,
+};
+
+/// The part of a `Host`'s interface that application code typically uses.
+///
+/// Write application code against this trait to test it with a [MockHost](struct.MockHost.html)
+/// instead of a real `Host`.
+pub trait HostLike<T> {
+    /// Returns the next event, see `Host::service`.
+    fn service(&mut self, timeout: Option<Duration>) -> Result<Option<Event>, Error>;
+
+    /// Initiates a connection, see `Host::connect`.
+    fn connect(
+        &mut self,
+        address: &Address,
+        channel_count: usize,
+        data: u32,
+    ) -> Result<PeerID, ConnectError>;
+
+    /// Queues `packet` to be sent to `peer_id`, see `Peer::send_packet`.
+    fn send(&mut self, peer_id: PeerID, channel_id: u8, packet: Packet) -> Result<(), SendError>;
+
+    /// Sends `data` to all connected peers, returning how many peers it was sent to.
+    fn broadcast(&mut self, channel_id: u8, data: &[u8], mode: PacketMode) -> usize;
+
+    /// Disconnects from `peer_id`, see `Peer::disconnect`.
+    fn disconnect(&mut self, peer_id: PeerID, data: u32);
+
+    /// Returns the ids of all connected peers.
+    fn connected_peers(&self) -> Vec<PeerID>;
+
+    /// Returns the address of `peer_id`.
+    fn peer_address(&self, peer_id: PeerID) -> Option<Address>;
+
+    /// Returns the data associated with `peer_id`, see `Peer::data`.
+    fn peer_data(&self, peer_id: PeerID) -> Option<&T>;
+
+    /// Sets the data associated with `peer_id`, see `Peer::set_data`.
+    fn set_peer_data(&mut self, peer_id: PeerID, data: Option<T>);
+}
+
+impl<T> HostLike<T> for Host<T> {
+    fn service(&mut self, timeout: Option<Duration>) -> Result<Option<Event>, Error> {
+        Host::service(self, timeout)
+    }
+
+    fn connect(
+        &mut self,
+        address: &Address,
+        channel_count: usize,
+        data: u32,
+    ) -> Result<PeerID, ConnectError> {
+        Host::connect(self, address, channel_count, data).map(|(_, peer_id)| peer_id)
+    }
+
+    fn send(&mut self, peer_id: PeerID, channel_id: u8, packet: Packet) -> Result<(), SendError> {
+        match self.peer_mut(peer_id) {
+            Some(peer) => peer.send_packet(packet, channel_id),
+            None => Err(SendError::NotConnected(PeerState::Disconnected)),
+        }
+    }
+
+    fn broadcast(&mut self, channel_id: u8, data: &[u8], mode: PacketMode) -> usize {
+        self.connected_peers()
+            .into_iter()
+            .filter(|&peer_id| {
+                Packet::new(data.to_vec(), mode)
+                    .is_ok_and(|packet| self[peer_id].send_packet(packet, channel_id).is_ok())
+            })
+            .count()
+    }
+
+    fn disconnect(&mut self, peer_id: PeerID, data: u32) {
+        if let Some(peer) = self.peer_mut(peer_id) {
+            peer.disconnect(data);
+        }
+    }
+
+    fn connected_peers(&self) -> Vec<PeerID> {
+        self.peer_ids()
+            .filter(|&peer_id| self[peer_id].state() == PeerState::Connected)
+            .collect()
+    }
+
+    fn peer_address(&self, peer_id: PeerID) -> Option<Address> {
+        self.peer(peer_id).map(|peer| peer.address())
+    }
+
+    fn peer_data(&self, peer_id: PeerID) -> Option<&T> {
+        self.peer(peer_id)?.data()
+    }
+
+    fn set_peer_data(&mut self, peer_id: PeerID, data: Option<T>) {
+        if let Some(peer) = self.peer_mut(peer_id) {
+            peer.set_data(data);
+        }
+    }
+}
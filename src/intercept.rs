@@ -0,0 +1,318 @@
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::net::Ipv4Addr;
+use std::os::raw::c_int;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use enet_sys::{
+    enet_socket_send, ENetBuffer, ENetEvent, ENetHost, _ENetPeerState_ENET_PEER_STATE_CONNECTED,
+    _ENetPeerState_ENET_PEER_STATE_DISCONNECTED, _ENetPeerState_ENET_PEER_STATE_DISCONNECT_LATER,
+};
+
+use crate::logging::{self, log_debug};
+use crate::{Address, Error};
+
+/// What ENet should do with a datagram after an `Intercept` looked at it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InterceptAction {
+    /// Pass the (possibly modified) datagram on to the next `Intercept`, and then to ENet.
+    Continue,
+    /// Discard the datagram, ENet never sees it.
+    Drop,
+}
+
+/// A datagram received by a `Host`, before ENet processes it.
+pub struct Datagram<'a> {
+    host: &'a mut ENetHost,
+}
+
+impl<'a> Datagram<'a> {
+    /// Returns the address the datagram was received from.
+    pub fn address(&self) -> Address {
+        Address::from_enet_address(&self.host.receivedAddress)
+    }
+
+    /// Changes the address ENet attributes the datagram to.
+    pub fn set_address(&mut self, address: &Address) {
+        self.host.receivedAddress = address.to_enet_address();
+    }
+
+    /// Returns the contents of the datagram.
+    pub fn data(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.host.receivedData, self.host.receivedDataLength) }
+    }
+
+    /// Returns the contents of the datagram for modification in place.
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            std::slice::from_raw_parts_mut(self.host.receivedData, self.host.receivedDataLength)
+        }
+    }
+
+    /// Removes the first `count` bytes of the datagram, e.g. a header added by a proxy.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the datagram is shorter than `count` bytes.
+    pub fn strip_prefix(&mut self, count: usize) {
+        assert!(
+            count <= self.host.receivedDataLength,
+            "cannot strip {} bytes from a datagram of {} bytes",
+            count,
+            self.host.receivedDataLength
+        );
+
+        self.host.receivedData = unsafe { self.host.receivedData.add(count) };
+        self.host.receivedDataLength -= count;
+    }
+
+    /// Returns whether the datagrams of the `Host` carry a checksum after their header.
+    pub(crate) fn has_checksum(&self) -> bool {
+        self.host.checksum.is_some()
+    }
+
+    /// Returns the number of peers connected to the `Host`, and the number of peers it has room for.
+    pub(crate) fn peer_counts(&self) -> (usize, usize) {
+        (self.host.connectedPeers, self.host.peerCount)
+    }
+
+    /// Returns the IP addresses of the peers of the `Host` that are not disconnected.
+    pub(crate) fn peer_ips(&self) -> impl Iterator<Item = Ipv4Addr> + '_ {
+        let peers = unsafe { std::slice::from_raw_parts(self.host.peers, self.host.peerCount) };
+        peers
+            .iter()
+            .filter(|peer| peer.state != _ENetPeerState_ENET_PEER_STATE_DISCONNECTED)
+            .map(|peer| *Address::from_enet_address(&peer.address).ip())
+    }
+
+    /// Returns whether the peer with `index` exists, is connected and has the address of this
+    /// datagram, i.e. whether ENet would process the commands this datagram carries for it.
+    pub(crate) fn is_from_connected_peer(&self, index: usize) -> bool {
+        if index >= self.host.peerCount {
+            return false;
+        }
+
+        let peer = unsafe { &*self.host.peers.add(index) };
+        let connected = peer.state == _ENetPeerState_ENET_PEER_STATE_CONNECTED
+            || peer.state == _ENetPeerState_ENET_PEER_STATE_DISCONNECT_LATER;
+        connected
+            && peer.address.host == self.host.receivedAddress.host
+            && peer.address.port == self.host.receivedAddress.port
+    }
+
+    /// Sends a raw datagram back to the address of this datagram, on the socket of the `Host`.
+    ///
+    /// This is meant for traffic that is not ENet's, see `Host::send_datagram`.
+    pub fn reply(&mut self, data: &[u8]) -> Result<(), Error> {
+        let address = self.address();
+        unsafe { send_datagram(self.host, &address, data) }
+    }
+}
+
+/// Sends `data` as a single datagram on the socket of `host`, bypassing the ENet protocol.
+pub(crate) unsafe fn send_datagram(
+    host: *mut ENetHost,
+    address: &Address,
+    data: &[u8],
+) -> Result<(), Error> {
+    let buffer = ENetBuffer {
+        data: data.as_ptr() as *mut _,
+        dataLength: data.len(),
+    };
+
+    // A result of 0 means the socket would block; like any datagram, this one is then lost.
+    match enet_socket_send((*host).socket, &address.to_enet_address(), &buffer, 1) {
+        r if r < 0 => Err(Error(r)),
+        _ => Ok(()),
+    }
+}
+
+/// Inspects, modifies or drops the raw datagrams a `Host` receives, before ENet processes them.
+///
+/// Add an `Intercept` to a `Host` with [Host::add_intercept](struct.Host.html#method.add_intercept).
+/// Every closure taking a `&mut Datagram` and returning an `InterceptAction` is an `Intercept`.
+///
+/// Intercepts can also receive datagrams that are not meant for ENet at all, such as server
+/// browser queries, on the same socket. Such traffic needs a recognizable marker, e.g. a prefix
+/// that ENet never sends:
+///
+/// ```no_run
+/// # use enet::*;
+/// # fn add(host: &mut Host<()>) {
+/// host.add_intercept(|datagram: &mut Datagram| {
+///     if !datagram.data().starts_with(b"\xff\xff\xff\xffping") {
+///         return InterceptAction::Continue;
+///     }
+///
+///     let _ = datagram.reply(b"\xff\xff\xff\xffpong");
+///     InterceptAction::Drop
+/// });
+/// # }
+/// ```
+pub trait Intercept {
+    /// Called for every datagram received while the `Host` is serviced.
+    fn intercept(&mut self, datagram: &mut Datagram) -> InterceptAction;
+}
+
+impl<F> Intercept for F
+where
+    F: FnMut(&mut Datagram) -> InterceptAction,
+{
+    fn intercept(&mut self, datagram: &mut Datagram) -> InterceptAction {
+        self(datagram)
+    }
+}
+
+pub(crate) type Intercepts = Vec<Box<dyn Intercept>>;
+
+/// Returns whether `data` may be an ENet connect, i.e. is addressed to no peer and no session.
+pub(crate) fn is_connect(data: &[u8]) -> bool {
+    data.len() >= 2 && u16::from_be_bytes([data[0], data[1]]) & 0x3fff == 0x0fff
+}
+
+/// Drops connects while the shared flag is cleared, see `Host::set_accepting`.
+pub(crate) struct AcceptIntercept(pub(crate) Arc<AtomicBool>);
+
+impl Intercept for AcceptIntercept {
+    fn intercept(&mut self, datagram: &mut Datagram) -> InterceptAction {
+        if !self.0.load(Ordering::Relaxed) && is_connect(datagram.data()) {
+            log_debug!(
+                target: logging::DROP,
+                "dropped a connect from {}, the host is not accepting",
+                datagram.address()
+            );
+            InterceptAction::Drop
+        } else {
+            InterceptAction::Continue
+        }
+    }
+}
+
+thread_local! {
+    /// The intercepts of the `Host` that is currently inside `enet_host_service` on this thread.
+    static ACTIVE: Cell<*mut Intercepts> = Cell::new(std::ptr::null_mut());
+    /// A panic raised by an intercept, to be resumed once we are back from C.
+    static PANIC: RefCell<Option<Box<dyn Any + Send>>> = RefCell::new(None);
+}
+
+/// Runs `f` (a call into ENet), with `intercepts` receiving the datagrams ENet reads meanwhile.
+///
+/// Debug builds panic if this is reached from within an intercept, i.e. an intercept services a
+/// `Host` while ENet is still servicing the one it belongs to.
+pub(crate) fn with_active<R>(intercepts: &mut Intercepts, f: impl FnOnce() -> R) -> R {
+    debug_assert!(
+        ACTIVE.with(Cell::get).is_null(),
+        "a Host cannot be serviced from within an intercept"
+    );
+    let previous = ACTIVE.with(|active| active.replace(intercepts as *mut _));
+    let result = f();
+    ACTIVE.with(|active| active.set(previous));
+
+    if let Some(payload) = PANIC.with(|panic| panic.borrow_mut().take()) {
+        panic::resume_unwind(payload);
+    }
+
+    result
+}
+
+pub(crate) unsafe extern "C" fn intercept_callback(
+    host: *mut ENetHost,
+    _event: *mut ENetEvent,
+) -> c_int {
+    let intercepts = ACTIVE.with(Cell::get);
+    if intercepts.is_null() {
+        return 0;
+    }
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut datagram = Datagram { host: &mut *host };
+        (*intercepts)
+            .iter_mut()
+            .all(|intercept| intercept.intercept(&mut datagram) == InterceptAction::Continue)
+    }));
+
+    match result {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(payload) => {
+            PANIC.with(|panic| *panic.borrow_mut() = Some(payload));
+            // Make ENet return from `enet_host_service` right away.
+            -1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{create_host, ENET};
+    use crate::{Address, BandwidthLimit, ChannelLimit, Datagram, InterceptAction};
+
+    use std::net::{Ipv4Addr, UdpSocket};
+    use std::time::Duration;
+
+    #[test]
+    fn test_out_of_band_datagrams() {
+        let mut host = ENET
+            .create_host::<()>(
+                Some(&Address::new(Ipv4Addr::LOCALHOST, 12351)),
+                1,
+                ChannelLimit::Maximum,
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap();
+        host.add_intercept(|datagram: &mut Datagram| {
+            if datagram.data() != b"ping" {
+                return InterceptAction::Continue;
+            }
+
+            datagram.reply(b"pong").unwrap();
+            InterceptAction::Drop
+        });
+
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        socket
+            .send_to(b"ping", (Ipv4Addr::LOCALHOST, 12351))
+            .unwrap();
+
+        assert!(host
+            .service(Some(Duration::from_millis(100)))
+            .unwrap()
+            .is_none());
+
+        let mut buffer = [0; 16];
+        let (len, _) = socket.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"pong");
+
+        let address = match socket.local_addr().unwrap() {
+            std::net::SocketAddr::V4(address) => Address::from(address),
+            _ => unreachable!(),
+        };
+        host.send_datagram(&address, b"hello").unwrap();
+        let (len, _) = socket.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"hello");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "from within an intercept")]
+    fn test_service_from_intercept() {
+        let mut host = create_host(Some(&Address::new(Ipv4Addr::LOCALHOST, 12421)), 1);
+        let mut other = create_host(None, 1);
+        host.add_intercept(move |_: &mut Datagram| {
+            let _ = other.service(None);
+            InterceptAction::Continue
+        });
+
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        socket
+            .send_to(b"ping", (Ipv4Addr::LOCALHOST, 12421))
+            .unwrap();
+        let _ = host.service(Some(Duration::from_millis(100)));
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::{EnetTime, Event, EventKind, PeerID};
+
+/// The number of recent packets that the transit time of a stream is estimated from.
+const TRANSIT_WINDOW: usize = 64;
+
+/// What a `JitterBuffer` does with packets that arrive after their playout time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LatePolicy {
+    /// Late packets are dropped.
+    Drop,
+    /// Late packets are delivered right away.
+    Deliver,
+}
+
+#[derive(Debug, Default)]
+struct Stream {
+    /// The last sender timestamp, and the unwrapped value it corresponds to.
+    last_sent: Option<(u32, i64)>,
+    /// Recent differences between local arrival and sender timestamps, in milliseconds.
+    transits: VecDeque<i64>,
+    /// Packets waiting for their playout time, in order.
+    queue: VecDeque<(Instant, Vec<u8>)>,
+}
+
+impl Stream {
+    fn unwrap_timestamp(&mut self, sent: u32) -> i64 {
+        let unwrapped = match self.last_sent {
+            Some((last, last_unwrapped)) => {
+                last_unwrapped + i64::from(sent.wrapping_sub(last) as i32)
+            }
+            None => i64::from(sent),
+        };
+        self.last_sent = Some((sent, unwrapped));
+        unwrapped
+    }
+}
+
+/// Re-times packets of streams like voice or positions, so they are delivered at an even pace.
+///
+/// Packets on buffered channels are delivered `target_delay` after the fastest transit seen
+/// recently, following the pace they were sent at instead of the pace they arrived at. The sender
+/// stamps packets with `JitterBuffer::stamp`. Every peer and channel is a separate stream. Use
+/// this with unreliable sequenced packets, ENet then already drops packets that arrive out of order.
+#[derive(Debug)]
+pub struct JitterBuffer {
+    target_delay: Duration,
+    late_policy: LatePolicy,
+    channels: HashSet<u8>,
+    epoch: Instant,
+    streams: HashMap<(PeerID, u8), Stream>,
+    late: u64,
+}
+
+impl JitterBuffer {
+    /// Creates a buffer that delays packets by `target_delay` beyond their fastest transit.
+    pub fn new(target_delay: Duration, late_policy: LatePolicy) -> JitterBuffer {
+        JitterBuffer {
+            target_delay,
+            late_policy,
+            channels: HashSet::new(),
+            epoch: Instant::now(),
+            streams: HashMap::new(),
+            late: 0,
+        }
+    }
+
+    /// Prefixes `payload` with the current time, for sending on a buffered channel.
+    pub fn stamp(payload: &[u8]) -> Vec<u8> {
+        let mut data = EnetTime::now().as_millis().to_be_bytes().to_vec();
+        data.extend_from_slice(payload);
+        data
+    }
+
+    /// Buffers the packets received on `channel_id`.
+    pub fn buffer_channel(&mut self, channel_id: u8) {
+        self.channels.insert(channel_id);
+    }
+
+    /// Returns the number of packets that arrived after their playout time.
+    pub fn late(&self) -> u64 {
+        self.late
+    }
+
+    /// Processes an event returned by `Host::service`.
+    ///
+    /// Returns `true` if the event was a packet on a buffered channel, which is delivered by
+    /// `next_packet` instead. The streams of peers that disconnect are discarded.
+    pub fn handle_event(&mut self, event: &Event) -> bool {
+        self.handle_event_at(event, Instant::now())
+    }
+
+    /// Returns the next packet whose playout time has come, with its peer and channel.
+    ///
+    /// The packet is returned without its timestamp.
+    pub fn next_packet(&mut self) -> Option<(PeerID, u8, Vec<u8>)> {
+        self.next_packet_at(Instant::now())
+    }
+
+    /// Returns how long until the next packet is due, e.g. to use as the timeout of `Host::service`.
+    pub fn next_deadline(&self) -> Option<Duration> {
+        self.streams
+            .values()
+            .filter_map(|stream| stream.queue.front())
+            .map(|&(playout, _)| playout.saturating_duration_since(Instant::now()))
+            .min()
+    }
+
+    fn handle_event_at(&mut self, event: &Event, now: Instant) -> bool {
+        let (channel_id, data) = match event.kind {
+            EventKind::Receive {
+                channel_id,
+                ref packet,
+            } if self.channels.contains(&channel_id) => (channel_id, packet.data()),
+            ref kind if kind.is_disconnect() => {
+                self.streams
+                    .retain(|&(peer_id, _), _| peer_id != event.peer_id);
+                return false;
+            }
+            _ => return false,
+        };
+
+        if data.len() < 4 {
+            return true;
+        }
+
+        let stream = self.streams.entry((event.peer_id, channel_id)).or_default();
+        let sent =
+            stream.unwrap_timestamp(u32::from_be_bytes([data[0], data[1], data[2], data[3]]));
+        let arrived = now.duration_since(self.epoch).as_millis() as i64;
+
+        let transit = arrived - sent;
+        if stream.transits.len() == TRANSIT_WINDOW {
+            stream.transits.pop_front();
+        }
+        stream.transits.push_back(transit);
+        let fastest = stream.transits.iter().cloned().min().unwrap_or(transit);
+
+        // The packet is due when it would have arrived with the fastest transit, plus the delay.
+        let slower_by = Duration::from_millis((transit - fastest) as u64);
+        let playout = (now + self.target_delay).checked_sub(slower_by);
+
+        match playout {
+            Some(playout) if playout >= now || self.late_policy == LatePolicy::Deliver => {
+                if playout < now {
+                    self.late += 1;
+                }
+                // Keep the queue in order, in case the transit estimate moved.
+                let after = stream.queue.back().map_or(now, |&(last, _)| last.max(now));
+                stream
+                    .queue
+                    .push_back((playout.max(after), data[4..].to_vec()));
+            }
+            _ => self.late += 1,
+        }
+        true
+    }
+
+    fn next_packet_at(&mut self, now: Instant) -> Option<(PeerID, u8, Vec<u8>)> {
+        let (&(peer_id, channel_id), stream) = self
+            .streams
+            .iter_mut()
+            .filter(|(_, stream)| {
+                stream
+                    .queue
+                    .front()
+                    .is_some_and(|&(playout, _)| playout <= now)
+            })
+            .min_by_key(|(_, stream)| stream.queue.front().map(|&(playout, _)| playout))?;
+
+        let (_, data) = stream.queue.pop_front()?;
+        Some((peer_id, channel_id, data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{JitterBuffer, LatePolicy};
+    use crate::{Event, EventKind, Packet, PacketMode, PeerID};
+
+    use std::time::{Duration, Instant};
+
+    fn packet(peer_id: PeerID, sent: u32, payload: u8) -> Event {
+        let mut data = sent.to_be_bytes().to_vec();
+        data.push(payload);
+        Event {
+            peer_id,
+            kind: EventKind::Receive {
+                channel_id: 1,
+                packet: Packet::new(data, PacketMode::UnreliableSequenced).unwrap(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_jitter_buffer() {
+        let peer_id = PeerID {
+            index: 0,
+            host_id: usize::MAX,
+        };
+        let mut buffer = JitterBuffer::new(Duration::from_millis(50), LatePolicy::Drop);
+        buffer.buffer_channel(1);
+        let start = buffer.epoch + Duration::from_secs(1);
+        let at = |millis| start + Duration::from_millis(millis);
+
+        // Sent every 20ms, but arriving with jitter.
+        assert!(buffer.handle_event_at(&packet(peer_id, 0, 0), at(0)));
+        assert!(buffer.handle_event_at(&packet(peer_id, 20, 1), at(40)));
+        assert!(buffer.handle_event_at(&packet(peer_id, 40, 2), at(45)));
+        // 90ms slower than the fastest transit, more than the target delay.
+        assert!(buffer.handle_event_at(&packet(peer_id, 60, 3), at(150)));
+        assert_eq!(buffer.late(), 1);
+
+        // Delivered at the pace they were sent at, 50ms later.
+        assert_eq!(buffer.next_packet_at(at(49)), None);
+        assert_eq!(buffer.next_packet_at(at(50)), Some((peer_id, 1, vec![0])));
+        assert_eq!(buffer.next_packet_at(at(69)), None);
+        assert_eq!(buffer.next_packet_at(at(70)), Some((peer_id, 1, vec![1])));
+        assert_eq!(buffer.next_packet_at(at(89)), None);
+        assert_eq!(buffer.next_packet_at(at(90)), Some((peer_id, 1, vec![2])));
+        assert_eq!(buffer.next_packet_at(at(200)), None);
+
+        // Other channels pass through.
+        let other = Event {
+            peer_id,
+            kind: EventKind::Receive {
+                channel_id: 0,
+                packet: Packet::new(vec![0; 8], PacketMode::ReliableSequenced).unwrap(),
+            },
+        };
+        assert!(!buffer.handle_event_at(&other, Instant::now()));
+
+        let mut delivering = JitterBuffer::new(Duration::from_millis(50), LatePolicy::Deliver);
+        delivering.buffer_channel(1);
+        delivering.handle_event_at(&packet(peer_id, 0, 0), at(0));
+        delivering.handle_event_at(&packet(peer_id, 20, 1), at(150));
+        assert_eq!(delivering.late(), 1);
+        assert_eq!(
+            delivering.next_packet_at(at(150)),
+            Some((peer_id, 1, vec![0]))
+        );
+        assert_eq!(
+            delivering.next_packet_at(at(150)),
+            Some((peer_id, 1, vec![1]))
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How often the round trip time of each peer is sampled, matching ENet's ping interval.
+pub(crate) const SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Statistics over the round trip times sampled for a `Peer`, see `Peer::latency_stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LatencyStats {
+    /// The lowest sampled round trip time.
+    pub min: Duration,
+    /// The mean of the sampled round trip times.
+    pub avg: Duration,
+    /// The 95th percentile of the sampled round trip times.
+    pub p95: Duration,
+    /// The highest sampled round trip time.
+    pub max: Duration,
+    /// The number of samples the statistics are based on.
+    pub samples: usize,
+}
+
+/// The recent round trip times of a peer, in milliseconds.
+#[derive(Debug, Default)]
+pub(crate) struct LatencyHistory {
+    samples: VecDeque<(Instant, u32)>,
+}
+
+impl LatencyHistory {
+    /// Records `rtt_ms`, and forgets samples older than `history`.
+    pub(crate) fn record(&mut self, now: Instant, rtt_ms: u32, history: Duration) {
+        // Don't sample more often than the interval when serviced irregularly.
+        let due = self.samples.back().map_or(true, |&(time, _)| {
+            now.duration_since(time) >= SAMPLE_INTERVAL / 2
+        });
+        if due {
+            self.samples.push_back((now, rtt_ms));
+        }
+
+        while self
+            .samples
+            .front()
+            .is_some_and(|&(time, _)| now.duration_since(time) > history)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    pub(crate) fn stats(&self, window: Duration) -> Option<LatencyStats> {
+        let now = Instant::now();
+        let mut samples: Vec<u32> = self
+            .samples
+            .iter()
+            .filter(|&&(time, _)| now.duration_since(time) <= window)
+            .map(|&(_, rtt)| rtt)
+            .collect();
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_unstable();
+
+        let millis = |ms: u32| Duration::from_millis(u64::from(ms));
+        let sum: u64 = samples.iter().map(|&rtt| u64::from(rtt)).sum();
+        let p95 = (samples.len() * 95 + 99) / 100 - 1;
+        Some(LatencyStats {
+            min: millis(samples[0]),
+            avg: Duration::from_millis(sum / samples.len() as u64),
+            p95: millis(samples[p95]),
+            max: millis(samples[samples.len() - 1]),
+            samples: samples.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LatencyHistory;
+
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_stats() {
+        let mut history = LatencyHistory::default();
+        let start = Instant::now() - Duration::from_secs(100);
+        for i in 0..100u32 {
+            let time = start + Duration::from_secs(u64::from(i));
+            history.record(time, i + 1, Duration::from_secs(1000));
+        }
+
+        let stats = history.stats(Duration::from_secs(1000)).unwrap();
+        assert_eq!(stats.samples, 100);
+        assert_eq!(stats.min, Duration::from_millis(1));
+        assert_eq!(stats.avg, Duration::from_millis(50));
+        assert_eq!(stats.p95, Duration::from_millis(95));
+        assert_eq!(stats.max, Duration::from_millis(100));
+
+        // Only the 10 most recent samples are within the last 10.5 seconds.
+        let recent = history.stats(Duration::from_millis(10_500)).unwrap();
+        assert_eq!(
+            (recent.samples, recent.min),
+            (10, Duration::from_millis(91))
+        );
+
+        history.record(Instant::now(), 7, Duration::from_millis(5500));
+        assert_eq!(history.stats(Duration::from_secs(1000)).unwrap().samples, 6);
+    }
+}
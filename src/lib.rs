@@ -29,6 +29,15 @@
 //! So if the rust compilers allows you to send/sync an object between threads, it should be safe to do so.
 //!
 //! If you used no unsafe code and the library blows up in your face, that is considered a bug. Please report any bug you encounter via [github](https://github.com/futile/enet-rs).
+//!
+//! # Logging
+//! With the `log` feature, this crate emits records through the [log](https://crates.io/crates/log) crate.
+//! Their targets allow filtering them per subsystem:
+//!
+//! * `enet::connection`: connects, disconnects, connection attempts that timed out or were rejected (info)
+//! * `enet::error`: errors returned by ENet while servicing or sending (info)
+//! * `enet::throttle`: changes of the packet throttle of peers (debug)
+//! * `enet::drop`: packets and datagrams dropped by a rate or queue limit, a ban or `Host::set_accepting` (debug)
 
 #![warn(missing_docs)]
 
@@ -40,29 +49,145 @@ extern crate failure_derive;
 extern crate lazy_static;
 
 use std::{
+    net::Ipv4Addr,
     os::raw::c_int,
     sync::{
         atomic::{AtomicUsize, Ordering},
         Arc,
     },
+    time::Duration,
 };
 
-use enet_sys::{enet_deinitialize, enet_host_create, enet_initialize, enet_linked_version};
+use enet_sys::{
+    enet_deinitialize, enet_host_create, enet_initialize, enet_initialize_with_callbacks,
+};
 
 mod address;
+mod allocator;
+mod ban;
+mod broadcast;
+mod budget;
+mod capture;
+#[cfg(feature = "challenge")]
+pub mod challenge;
+mod channel;
+pub mod client;
+#[cfg(feature = "cluster")]
+pub mod cluster;
+#[cfg(feature = "coalesce")]
+pub mod coalesce;
+mod config;
+mod connection_limit;
+mod diagnostics;
 mod event;
+mod event_bus;
+mod fragments;
+mod groups;
+#[cfg(feature = "handshake")]
+pub mod handshake;
 mod host;
+mod host_like;
+mod intercept;
+mod jitter;
+mod latency;
+#[cfg(feature = "lockstep")]
+pub mod lockstep;
+mod logging;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+mod mock;
+#[cfg(feature = "mtu")]
+pub mod mtu;
 mod packet;
 mod peer;
+#[cfg(feature = "pipeline")]
+pub mod pipeline;
+mod poll;
+mod priority;
+mod proxy_protocol;
+#[cfg(feature = "pubsub")]
+pub mod pubsub;
+#[cfg(feature = "pump")]
+pub mod pump;
+mod query;
+mod queue_limit;
+mod rate_limit;
+mod relay;
+#[cfg(feature = "replication")]
+pub mod replication;
+#[cfg(feature = "resume")]
+pub mod resume;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+mod send_rate;
+pub mod server;
+mod service_interval;
+#[cfg(any(feature = "handshake", feature = "challenge"))]
+mod sha256;
+#[cfg(feature = "shutdown")]
+pub mod shutdown;
+mod socks5;
+mod stream;
+mod tcp;
+mod ticks;
+mod time;
+#[cfg(feature = "timesync")]
+pub mod timesync;
+mod traffic;
+#[cfg(feature = "transfer")]
+pub mod transfer;
+mod transport;
+mod version;
 
 pub use crate::address::Address;
+pub use crate::allocator::{Allocator, PoolAllocator, PoolStats};
+pub use crate::ban::{Ban, BanTarget};
+pub use crate::broadcast::{BroadcastProgress, BroadcastScheduler};
+pub use crate::budget::BudgetUsage;
+pub use crate::capture::{CaptureTransport, PcapWriter};
+pub use crate::channel::{Channel, ChannelConfig, ChannelId, ChannelRouter, Channels};
+pub use crate::config::{ConfigError, HostConfig, HostConfigHandle, PeerTimeout};
+pub use crate::connection_limit::ConnectionLimit;
+pub use crate::diagnostics::{
+    BenchmarkConfig, BenchmarkError, BenchmarkReport, HostDiagnostics, PeerDiagnostics,
+};
 pub use crate::event::{Event, EventKind};
+pub use crate::event_bus::{EventBus, EventSubscriber};
+pub use crate::fragments::FragmentStats;
+pub use crate::groups::{GroupId, PeerGroups};
 pub use crate::host::{BandwidthLimit, ChannelLimit, Host};
+pub use crate::host_like::HostLike;
+pub use crate::intercept::{Datagram, Intercept, InterceptAction};
+pub use crate::jitter::{JitterBuffer, LatePolicy};
+pub use crate::latency::LatencyStats;
+pub use crate::mock::{MockHost, SentPacket};
 pub use crate::packet::{Packet, PacketMode};
-pub use crate::peer::{Peer, PeerID, PeerState};
+pub use crate::peer::{Peer, PeerDataEntry, PeerID, PeerState};
+pub use crate::priority::{Priority, PrioritySender};
+pub use crate::proxy_protocol::ProxyProtocol;
+pub use crate::query::{
+    discover_servers, query_datagram, QueryResponder, QueryResponse, ServerInfo, QUERY_SIZE,
+};
+pub use crate::queue_limit::{QueueLimit, QueuePolicy};
+pub use crate::rate_limit::{RateLimit, RateLimitAction};
+pub use crate::relay::{Relay, RelayStats};
+pub use crate::send_rate::{SendRateConfig, SendRateDecision, SendRateReason, SendRateTuner};
+pub use crate::service_interval::{ServiceInterval, ServiceIntervalConfig};
+pub use crate::socks5::{Socks5Credentials, Socks5Transport};
+pub use crate::stream::ChannelStream;
+pub use crate::tcp::{FallbackTransport, TcpTransport};
+pub use crate::ticks::TickEvent;
+pub use crate::time::EnetTime;
+pub use crate::traffic::ChannelTraffic;
+pub use crate::transport::{Transport, TransportBridge};
+pub use crate::version::{linked_version, Capabilities, Version, ENET_VERSION};
 
 pub use enet_sys::ENetVersion as EnetVersion;
 
+/// The raw ENet bindings, for use with [Host::as_raw](struct.Host.html#method.as_raw) and
+/// [Peer::as_raw](struct.Peer.html#method.as_raw).
+pub use enet_sys;
+
 const ENET_UNINITIALIZED: usize = 1;
 const ENET_INITIALIZED: usize = 2;
 const ENET_DEINITIALIZED: usize = 3;
@@ -91,6 +216,75 @@ pub struct Enet {
 #[fail(display = "enet failure, returned '{}'", _0)]
 pub struct Error(pub c_int);
 
+/// An error that can occur when sending a packet to a `Peer`.
+#[derive(Fail, Debug)]
+pub enum SendError {
+    /// The peer is not connected, it is in the contained state instead.
+    #[fail(display = "cannot send to a peer in state {}", _0)]
+    NotConnected(PeerState),
+    /// The channel id is not one of the channels allocated for the peer.
+    #[fail(display = "channel {} is not allocated for this peer", _0)]
+    InvalidChannel(u8),
+    /// The channel has no `ChannelConfig`, so `Peer::send_on` doesn't know how to send on it.
+    #[fail(display = "channel {} has no config", _0)]
+    Unconfigured(u8),
+    /// The packet exceeds what is left of the budget of the channel, see `Peer::set_channel_budget`.
+    #[fail(display = "channel {} is over its budget", _0)]
+    OverBudget(u8),
+    /// The queue of the peer is full, see `Host::set_queue_limit`.
+    #[fail(display = "the queue of the peer is full")]
+    WouldBlock,
+    /// Internal ENet failure (`enet_peer_send` failed), containing the return code.
+    #[fail(display = "enet_peer_send failed (with '{}')", _0)]
+    Error(c_int),
+}
+
+/// An error that can occur when initiating a connection with `Host::connect`.
+#[derive(Fail, Debug)]
+pub enum ConnectError {
+    /// All peers of the host are in use, so there is none left for the new connection.
+    #[fail(display = "no free peer slots available")]
+    NoFreePeerSlots,
+    /// More channels were requested than the host allows.
+    #[fail(
+        display = "requested {} channels, but the channel limit is {}",
+        requested, limit
+    )]
+    ChannelCountExceedsLimit {
+        /// The requested channel count.
+        requested: usize,
+        /// The channel limit of the host.
+        limit: usize,
+    },
+    /// ENet failed to allocate memory for the connection.
+    #[fail(display = "failed to allocate memory for the connection")]
+    AllocationFailed,
+    /// The connection was not established in time, see `Host::connect_sync`.
+    #[fail(display = "the connection attempt timed out")]
+    TimedOut,
+    /// The foreign host disconnected during the attempt, with the contained data, see
+    /// `Host::connect_sync`.
+    #[fail(display = "the connection was refused with data {}", _0)]
+    Refused(u32),
+    /// Servicing the host failed during the attempt, see `Host::connect_sync`.
+    #[fail(display = "servicing the host failed: {}", _0)]
+    Service(#[cause] Error),
+}
+
+/// An error that can occur in `Host::disconnect_sync`.
+#[derive(Fail, Debug)]
+pub enum DisconnectError {
+    /// The peer was not connected, so there was nothing to disconnect.
+    #[fail(display = "the peer is not connected")]
+    NotConnected,
+    /// The foreign host did not acknowledge the disconnect in time, so the peer was reset.
+    #[fail(display = "the disconnect was not acknowledged in time")]
+    TimedOut,
+    /// Servicing the host failed while waiting.
+    #[fail(display = "servicing the host failed: {}", _0)]
+    Service(#[cause] Error),
+}
+
 /// An error that can occur when initializing ENet.
 #[derive(Fail, Debug)]
 pub enum InitializationError {
@@ -108,6 +302,70 @@ pub enum InitializationError {
 impl Enet {
     /// Initializes ENet and returns a handle to the top-level functionality, in the form of an `Enet`-instance.
     pub fn new() -> Result<Enet, InitializationError> {
+        Enet::initialize(|| unsafe { enet_initialize() })
+    }
+
+    /// Initializes ENet like `new()`, but makes ENet allocate all of its memory through `allocator`.
+    ///
+    /// # Examples
+    /// Counting the allocations made by ENet, using a header to remember each allocation's size:
+    ///
+    /// ```
+    /// use enet::{Allocator, BandwidthLimit, ChannelLimit, Enet};
+    /// use std::alloc::{self, Layout};
+    /// use std::os::raw::c_void;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    ///
+    /// const HEADER: usize = 16;
+    /// static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+    ///
+    /// struct Counting;
+    ///
+    /// impl Allocator for Counting {
+    ///     fn malloc(&self, size: usize) -> *mut c_void {
+    ///         ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    ///         let layout = Layout::from_size_align(size + HEADER, HEADER).unwrap();
+    ///         unsafe {
+    ///             let ptr = alloc::alloc(layout);
+    ///             if ptr.is_null() {
+    ///                 return ptr as *mut c_void;
+    ///             }
+    ///             (ptr as *mut usize).write(size);
+    ///             ptr.add(HEADER) as *mut c_void
+    ///         }
+    ///     }
+    ///
+    ///     unsafe fn free(&self, ptr: *mut c_void) {
+    ///         if ptr.is_null() {
+    ///             return;
+    ///         }
+    ///         let ptr = (ptr as *mut u8).sub(HEADER);
+    ///         let size = (ptr as *mut usize).read();
+    ///         alloc::dealloc(ptr, Layout::from_size_align(size + HEADER, HEADER).unwrap());
+    ///     }
+    /// }
+    ///
+    /// let enet = Enet::new_with_allocator(Counting).unwrap();
+    /// let host = enet
+    ///     .create_host::<()>(
+    ///         None,
+    ///         1,
+    ///         ChannelLimit::Maximum,
+    ///         BandwidthLimit::Unlimited,
+    ///         BandwidthLimit::Unlimited,
+    ///     )
+    ///     .unwrap();
+    ///
+    /// assert!(ALLOCATIONS.load(Ordering::Relaxed) > 0);
+    /// ```
+    pub fn new_with_allocator<A: Allocator>(allocator: A) -> Result<Enet, InitializationError> {
+        Enet::initialize(|| {
+            let callbacks = allocator::install(Box::new(allocator));
+            unsafe { enet_initialize_with_callbacks(ENET_VERSION, &callbacks as *const _) }
+        })
+    }
+
+    fn initialize(init: impl FnOnce() -> c_int) -> Result<Enet, InitializationError> {
         match ENET_STATUS.compare_and_swap(ENET_UNINITIALIZED, ENET_INITIALIZED, Ordering::SeqCst) {
             ENET_UNINITIALIZED => (),
             ENET_INITIALIZED => return Err(InitializationError::AlreadyInitialized),
@@ -118,7 +376,7 @@ impl Enet {
             ),
         };
 
-        let r = unsafe { enet_initialize() };
+        let r = init();
 
         if r != 0 {
             return Err(InitializationError::Error(r));
@@ -163,11 +421,89 @@ impl Enet {
 
         Ok(Host::new(self.keep_alive.clone(), inner))
     }
-}
 
-/// Returns the version of the linked ENet library.
-pub fn linked_version() -> EnetVersion {
-    unsafe { enet_linked_version() }
+    /// Creates a `Host` with the settings of `config`, e.g. loaded with `HostConfig::load`.
+    ///
+    /// Like `create_host`, and applies the timeout, connection limit, queue limit, busy polling and
+    /// compression of `config` to the new host.
+    pub fn create_host_from_config<T>(&self, config: &HostConfig) -> Result<Host<T>, Error> {
+        let mut host = self.create_host(
+            config.address.as_ref(),
+            config.peer_count,
+            config.channel_limit,
+            config.incoming_bandwidth,
+            config.outgoing_bandwidth,
+        )?;
+        if let Some(timeout) = config.peer_timeout {
+            host.set_peer_timeout(timeout);
+        }
+        host.set_connection_limit(config.connection_limit);
+        host.set_queue_limit(config.queue_limit);
+        host.set_busy_poll(config.busy_poll);
+        if config.compression {
+            host.set_compression(true)?;
+        }
+        if !config.channel_configs.is_empty() {
+            host.set_channel_configs(&config.channel_configs);
+        }
+        Ok(host)
+    }
+
+    /// Measures what ENet achieves on this machine, by sending packets between two hosts over the
+    /// loopback interface.
+    ///
+    /// Reports the packets and bytes per second received, the time of each `Host::service` call
+    /// that returned a packet, and the round trip time of echoed packets. This doesn't account for
+    /// a real network, but shows what the CPU and socket buffers allow, e.g. to size the number of
+    /// peers per process or to check a deployment. Both hosts are bound to ephemeral ports.
+    pub fn benchmark(&self, config: &BenchmarkConfig) -> Result<BenchmarkReport, BenchmarkError> {
+        crate::diagnostics::benchmark(self, config)
+    }
+
+    /// Services several hosts at once, until one of them delivers an event.
+    ///
+    /// This waits on the sockets of all `hosts` together, so processes running several hosts (e.g.
+    /// game and voice, or many shards) can do so on a single thread. Returns the index of the host
+    /// that produced the event, along with the event.
+    ///
+    /// `timeout` behaves as for `Host::service`. Successive calls start looking for events at
+    /// different hosts, so a busy host can't starve the others.
+    pub fn service_any<T>(
+        &self,
+        hosts: &mut [&mut Host<T>],
+        timeout: Option<Duration>,
+    ) -> Result<Option<(usize, Event)>, Error> {
+        host::service_any(hosts, timeout)
+    }
+
+    /// Creates a `Host` that exchanges its datagrams over `transport` instead of its own UDP socket.
+    ///
+    /// The `Host` receives from every endpoint of the transport, so it can act as client and server.
+    /// To connect to an endpoint, pass the address returned by `TransportBridge::address_of`
+    /// (see `Host::transport_mut`) to `Host::connect`.
+    ///
+    /// The transport is only driven by `Host::service` and `Host::flush`, so these have to be called
+    /// regularly, which ENet requires anyway.
+    pub fn create_host_with_transport<T, Tr: Transport + 'static>(
+        &self,
+        transport: Tr,
+        max_peer_count: usize,
+        max_channel_count: ChannelLimit,
+        incoming_bandwidth: BandwidthLimit,
+        outgoing_bandwidth: BandwidthLimit,
+    ) -> Result<Host<T>, Error> {
+        let mut host = self.create_host(
+            Some(&Address::new(Ipv4Addr::LOCALHOST, 0)),
+            max_peer_count,
+            max_channel_count,
+            incoming_bandwidth,
+            outgoing_bandwidth,
+        )?;
+
+        let bridge = TransportBridge::new(transport, &host.address(), max_peer_count);
+        host.attach_bridge(Box::new(bridge));
+        Ok(host)
+    }
 }
 
 impl Drop for EnetKeepAlive {
@@ -188,10 +524,76 @@ impl Drop for EnetKeepAlive {
 
 #[cfg(test)]
 mod tests {
-    use super::{BandwidthLimit, ChannelLimit, Enet};
+    use super::{Address, BandwidthLimit, ChannelLimit, Enet, Event, EventKind, Host, PeerID};
+
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
 
     lazy_static! {
-        static ref ENET: Enet = Enet::new().unwrap();
+        pub(crate) static ref ENET: Enet = Enet::new().unwrap();
+    }
+
+    /// Creates a host with room for `peer_count` peers, bound to `address` if given.
+    pub(crate) fn create_host(address: Option<&Address>, peer_count: usize) -> Host<()> {
+        ENET.create_host::<()>(
+            address,
+            peer_count,
+            ChannelLimit::Maximum,
+            BandwidthLimit::Unlimited,
+            BandwidthLimit::Unlimited,
+        )
+        .unwrap()
+    }
+
+    /// Services `hosts` in turn, and passes every event to `f` with the index of the host that
+    /// delivered it, until `f` returns `true`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `f` doesn't return `true` within 500 rounds.
+    pub(crate) fn pump_until<T>(
+        hosts: &mut [&mut Host<T>],
+        mut f: impl FnMut(usize, &mut Host<T>, Event) -> bool,
+    ) {
+        let timeout = Some(Duration::from_millis(2));
+        for _ in 0..500 {
+            for (index, host) in hosts.iter_mut().enumerate() {
+                if let Some(event) = host.service(timeout).unwrap() {
+                    if f(index, host, event) {
+                        return;
+                    }
+                }
+            }
+        }
+        panic!("pump_until gave up after 500 rounds");
+    }
+
+    /// Creates a server on `port` and a client connected to it with `channel_count` channels, and
+    /// services both until the connection is established on both sides.
+    ///
+    /// Returns the server, the client, the client's peer on the server and the server's peer on
+    /// the client.
+    pub(crate) fn connected_pair(
+        port: u16,
+        channel_count: usize,
+    ) -> (Host<()>, Host<()>, PeerID, PeerID) {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, port);
+        let mut server = create_host(Some(&server_address), 1);
+        let mut client = create_host(None, 1);
+        let server_peer = client.connect(&server_address, channel_count, 0).unwrap().1;
+
+        let mut client_peer = None;
+        let mut client_connected = false;
+        pump_until(&mut [&mut server, &mut client], |index, _, event| {
+            if let EventKind::Connect = event.kind {
+                match index {
+                    0 => client_peer = Some(event.peer_id),
+                    _ => client_connected = true,
+                }
+            }
+            client_peer.is_some() && client_connected
+        });
+        (server, client, client_peer.unwrap(), server_peer)
     }
 
     #[test]
@@ -215,4 +617,1111 @@ mod tests {
         )
         .unwrap();
     }
+
+    #[test]
+    fn test_send_to_unconnected_peer() {
+        use crate::{Address, Packet, PacketMode, PeerState, SendError};
+        use std::net::Ipv4Addr;
+
+        let mut host = ENET
+            .create_host::<()>(
+                None,
+                1,
+                ChannelLimit::Maximum,
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap();
+
+        let (peer, _) = host
+            .connect(&Address::new(Ipv4Addr::LOCALHOST, 12346), 1, 0)
+            .unwrap();
+
+        let packet = Packet::new(vec![1, 2, 3], PacketMode::ReliableSequenced).unwrap();
+        match peer.send_packet(packet, 0) {
+            Err(SendError::NotConnected(PeerState::Connecting)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "different Host")]
+    fn test_peer_id_from_other_host() {
+        let create = || {
+            ENET.create_host::<()>(
+                None,
+                1,
+                ChannelLimit::Maximum,
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap()
+        };
+
+        let mut first = create();
+        let second = create();
+
+        let (_, peer_id) = first
+            .connect(
+                &crate::Address::new(std::net::Ipv4Addr::LOCALHOST, 12347),
+                1,
+                0,
+            )
+            .unwrap();
+        let _ = second.peer(peer_id);
+    }
+
+    #[test]
+    fn test_connect_errors() {
+        use crate::{Address, ConnectError};
+        use std::net::Ipv4Addr;
+
+        let mut host = ENET
+            .create_host::<()>(
+                None,
+                1,
+                ChannelLimit::Limited(2),
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap();
+        let address = Address::new(Ipv4Addr::LOCALHOST, 12348);
+
+        match host.connect(&address, 3, 0) {
+            Err(ConnectError::ChannelCountExceedsLimit {
+                requested: 3,
+                limit: 2,
+            }) => (),
+            other => panic!("unexpected result: {:?}", other.map(|(_, id)| id)),
+        }
+
+        host.connect(&address, 2, 0).unwrap();
+        match host.connect(&address, 2, 0) {
+            Err(ConnectError::NoFreePeerSlots) => (),
+            other => panic!("unexpected result: {:?}", other.map(|(_, id)| id)),
+        }
+    }
+
+    #[test]
+    fn test_host_over_transport() {
+        use crate::{EventKind, Packet, PacketMode, Transport};
+        use std::collections::VecDeque;
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        type Queue = Arc<Mutex<VecDeque<Vec<u8>>>>;
+
+        /// One end of an in-process datagram pipe, with a single remote endpoint `()`.
+        struct MemoryTransport {
+            incoming: Queue,
+            outgoing: Queue,
+        }
+
+        impl Transport for MemoryTransport {
+            type Endpoint = ();
+            type Error = ();
+
+            fn send_to(&mut self, data: &[u8], _: &()) -> Result<(), ()> {
+                self.outgoing.lock().unwrap().push_back(data.to_vec());
+                Ok(())
+            }
+
+            fn recv_from(&mut self, buffer: &mut [u8]) -> Result<Option<(usize, ())>, ()> {
+                Ok(self.incoming.lock().unwrap().pop_front().map(|datagram| {
+                    buffer[..datagram.len()].copy_from_slice(&datagram);
+                    (datagram.len(), ())
+                }))
+            }
+        }
+
+        let (a, b) = (Queue::default(), Queue::default());
+        let create_host = |incoming: &Queue, outgoing: &Queue| {
+            let transport = MemoryTransport {
+                incoming: incoming.clone(),
+                outgoing: outgoing.clone(),
+            };
+            ENET.create_host_with_transport::<(), _>(
+                transport,
+                1,
+                ChannelLimit::Maximum,
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap()
+        };
+        let mut client = create_host(&a, &b);
+        let mut server = create_host(&b, &a);
+
+        let address = client
+            .transport_mut::<MemoryTransport>()
+            .unwrap()
+            .address_of(&())
+            .unwrap();
+        let (_, peer_id) = client.connect(&address, 1, 0).unwrap();
+
+        let timeout = Some(Duration::from_millis(10));
+        let mut received = None;
+        for _ in 0..200 {
+            if let Some(event) = client.service(timeout).unwrap() {
+                if let EventKind::Connect = event.kind {
+                    client[peer_id]
+                        .send_packet(
+                            Packet::new(b"over transport".to_vec(), PacketMode::ReliableSequenced)
+                                .unwrap(),
+                            0,
+                        )
+                        .unwrap();
+                }
+            }
+            if let Some(event) = server.service(timeout).unwrap() {
+                if let EventKind::Receive { ref packet, .. } = event.kind {
+                    received = Some(packet.data().to_vec());
+                    break;
+                }
+            }
+        }
+
+        assert_eq!(received.as_deref(), Some(&b"over transport"[..]));
+        assert!(server.transport::<std::net::UdpSocket>().is_none());
+    }
+
+    #[test]
+    fn test_transport_link_limit() {
+        use std::net::{Ipv4Addr, UdpSocket};
+        use std::time::Duration;
+
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 12422)).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let mut host = ENET
+            .create_host_with_transport::<(), _>(
+                socket,
+                1,
+                ChannelLimit::Maximum,
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap();
+
+        // Every sender would get a link of its own, but there is only room for one.
+        let senders: Vec<_> = (0..3)
+            .map(|_| UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap())
+            .collect();
+        for sender in &senders {
+            sender
+                .send_to(b"junk", (Ipv4Addr::LOCALHOST, 12422))
+                .unwrap();
+        }
+        for _ in 0..5 {
+            host.service(Some(Duration::from_millis(2))).unwrap();
+        }
+
+        let bridge = host.transport_mut::<UdpSocket>().unwrap();
+        assert_eq!(bridge.link_count(), 1);
+        assert!(bridge.address_of(&"127.0.0.1:1".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_fallback_to_tcp() {
+        use crate::{EventKind, FallbackTransport, TcpTransport};
+        use std::time::Duration;
+
+        let server_address = ([127, 0, 0, 1], 12349).into();
+        let mut server = ENET
+            .create_host_with_transport::<(), _>(
+                TcpTransport::listen(server_address).unwrap(),
+                1,
+                ChannelLimit::Maximum,
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap();
+        let mut client = ENET
+            .create_host_with_transport::<(), _>(
+                FallbackTransport::new(server_address, Duration::from_millis(100)).unwrap(),
+                1,
+                ChannelLimit::Maximum,
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap();
+
+        let address = client
+            .transport_mut::<FallbackTransport>()
+            .unwrap()
+            .address_of(&server_address)
+            .unwrap();
+        client.connect(&address, 1, 0).unwrap();
+
+        let timeout = Some(Duration::from_millis(10));
+        let mut connected = false;
+        for _ in 0..500 {
+            let _ = client.service(timeout).unwrap();
+            if let Some(event) = server.service(timeout).unwrap() {
+                if let EventKind::Connect = event.kind {
+                    connected = true;
+                    break;
+                }
+            }
+        }
+
+        assert!(connected);
+        assert!(client
+            .transport::<FallbackTransport>()
+            .unwrap()
+            .transport()
+            .uses_tcp());
+    }
+
+    #[test]
+    fn test_service_any() {
+        use crate::{Address, EventKind};
+        use std::net::Ipv4Addr;
+        use std::time::Duration;
+
+        let create_host = |port| {
+            ENET.create_host::<()>(
+                Some(&Address::new(Ipv4Addr::LOCALHOST, port)),
+                1,
+                ChannelLimit::Maximum,
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap()
+        };
+        let (mut game, mut voice) = (create_host(12353), create_host(12354));
+        let mut client = create_host(0);
+        client
+            .connect(&Address::new(Ipv4Addr::LOCALHOST, 12354), 1, 0)
+            .unwrap();
+
+        let mut connected = None;
+        for _ in 0..100 {
+            client.service(Some(Duration::from_millis(0))).unwrap();
+            if let Some((index, event)) = ENET
+                .service_any(
+                    &mut [&mut game, &mut voice],
+                    Some(Duration::from_millis(10)),
+                )
+                .unwrap()
+            {
+                if let EventKind::Connect = event.kind {
+                    connected = Some(index);
+                    break;
+                }
+            }
+        }
+
+        assert_eq!(connected, Some(1));
+    }
+
+    #[test]
+    fn test_service_batch() {
+        use crate::{Address, EventKind, Packet, PacketMode};
+        use std::net::Ipv4Addr;
+        use std::time::Duration;
+
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12393);
+        let mut server = create_host(Some(&server_address), 1);
+        let mut client = create_host(None, 1);
+        client.connect(&server_address, 1, 0).unwrap();
+
+        let timeout = Some(Duration::from_millis(2));
+        let (mut events, mut largest_batch) = (Vec::new(), 0);
+        for _ in 0..500 {
+            let batch = server.service_batch(timeout, &mut events).unwrap();
+            largest_batch = batch.max(largest_batch);
+            if let Some(event) = client.service(timeout).unwrap() {
+                if let EventKind::Connect = event.kind {
+                    for i in 0..10 {
+                        let packet = Packet::new(vec![i], PacketMode::ReliableSequenced).unwrap();
+                        client[event.peer_id].send_packet(packet, 0).unwrap();
+                    }
+                    client.flush();
+                }
+            }
+            if events.len() == 11 {
+                break;
+            }
+        }
+
+        assert_eq!(events.len(), 11);
+        assert!(matches!(events[0].kind, EventKind::Connect));
+        let received: Vec<_> = events[1..]
+            .iter()
+            .map(|event| match event.kind {
+                EventKind::Receive { ref packet, .. } => packet.data()[0],
+                ref kind => panic!("unexpected event {:?}", kind),
+            })
+            .collect();
+        assert_eq!(received, (0..10).collect::<Vec<_>>());
+        // The packets were flushed in one datagram, so they arrive in one batch.
+        assert!(largest_batch >= 10);
+    }
+
+    #[test]
+    fn test_busy_poll() {
+        use crate::{Address, EventKind};
+        use std::net::Ipv4Addr;
+        use std::time::{Duration, Instant};
+
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12394);
+        let mut server = create_host(Some(&server_address), 1);
+        server.set_busy_poll(Some(Duration::from_millis(5)));
+
+        // Without events, the whole timeout still passes.
+        let start = Instant::now();
+        assert!(server
+            .service(Some(Duration::from_millis(20)))
+            .unwrap()
+            .is_none());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        let mut client = create_host(None, 1);
+        client.connect(&server_address, 1, 0).unwrap();
+        let mut connected = false;
+        for _ in 0..100 {
+            client.service(Some(Duration::ZERO)).unwrap();
+            if let Some(event) = server.service(Some(Duration::from_millis(10))).unwrap() {
+                connected = matches!(event.kind, EventKind::Connect);
+                break;
+            }
+        }
+        assert!(connected);
+    }
+
+    #[test]
+    fn test_estimated_bandwidth() {
+        use crate::{Address, EventKind};
+        use std::net::Ipv4Addr;
+
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12363);
+        let mut server = ENET
+            .create_host::<()>(
+                Some(&server_address),
+                1,
+                ChannelLimit::Maximum,
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap();
+        let mut client = ENET
+            .create_host::<()>(
+                None,
+                1,
+                ChannelLimit::Maximum,
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Limited(10_000),
+            )
+            .unwrap();
+
+        let (_, peer_id) = client.connect(&server_address, 1, 0).unwrap();
+        pump_until(&mut [&mut server, &mut client], |index, _, event| {
+            index == 1 && matches!(event.kind, EventKind::Connect)
+        });
+
+        let peer = &client[peer_id];
+        let estimate = peer.estimated_bandwidth();
+        assert!(estimate > 0 && estimate <= 10_000, "estimate {}", estimate);
+        assert_eq!(peer.packet_loss(), 0.0);
+        assert!(peer.packet_throttle() > 0.0);
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        use crate::{Address, EventKind, Packet, PacketMode, RateLimit, RateLimitAction};
+        use std::net::Ipv4Addr;
+
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12366);
+        let mut server = create_host(Some(&server_address), 1);
+        let mut client = create_host(None, 1);
+        server.set_rate_limit(
+            0,
+            Some(RateLimit {
+                messages_per_second: 3,
+                bytes_per_second: 1000,
+                action: RateLimitAction::Warn,
+            }),
+        );
+
+        let (_, peer_id) = client.connect(&server_address, 1, 0).unwrap();
+        let (mut received, mut limited) = (0, 0);
+        pump_until(&mut [&mut client, &mut server], |index, host, event| {
+            match (index, event.kind) {
+                (0, EventKind::Connect) => {
+                    for _ in 0..10 {
+                        let packet = Packet::new(b"spam".to_vec(), PacketMode::ReliableSequenced);
+                        host[peer_id].send_packet(packet.unwrap(), 0).unwrap();
+                    }
+                }
+                (1, EventKind::Receive { .. }) => received += 1,
+                (1, EventKind::RateLimited { channel_id: 0 }) => limited += 1,
+                _ => (),
+            }
+            received + limited == 10
+        });
+
+        assert_eq!((received, limited), (3, 7));
+    }
+
+    #[test]
+    fn test_peers_slice() {
+        use crate::{Address, PeerState};
+        use std::net::Ipv4Addr;
+
+        let mut host = ENET
+            .create_host::<u32>(
+                None,
+                4,
+                ChannelLimit::Maximum,
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap();
+        let (_, peer_id) = host
+            .connect(&Address::new(Ipv4Addr::LOCALHOST, 12395), 1, 0)
+            .unwrap();
+        host[peer_id].set_data(Some(7));
+
+        assert_eq!(host.peers_slice().len(), 4);
+        let peer = &host.peers_slice()[peer_id.index()];
+        assert_eq!(peer.state(), PeerState::Connecting);
+        assert_eq!(peer.data(), Some(&7));
+
+        for peer in host.peers_slice_mut() {
+            peer.set_data(Some(1));
+        }
+        assert_eq!(
+            host.peers().filter(|peer| peer.data() == Some(&1)).count(),
+            4
+        );
+    }
+
+    #[test]
+    fn test_peer_by_connect_id() {
+        use crate::{Address, EventKind};
+        use std::net::Ipv4Addr;
+
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12396);
+        let mut server = create_host(Some(&server_address), 4);
+        let mut client = create_host(None, 1);
+        let (_, client_peer) = client.connect(&server_address, 1, 0).unwrap();
+
+        let mut server_peer = None;
+        pump_until(&mut [&mut client, &mut server], |index, _, event| {
+            if let (1, EventKind::Connect) = (index, &event.kind) {
+                server_peer = Some(event.peer_id);
+            }
+            server_peer.is_some()
+        });
+
+        let server_peer = server_peer.unwrap();
+        let connect_id = client[client_peer].connect_id();
+        assert_eq!(server[server_peer].connect_id(), connect_id);
+        assert_eq!(server.peer_by_connect_id(connect_id), Some(server_peer));
+        assert_eq!(server.peer_by_connect_id(connect_id.wrapping_add(1)), None);
+
+        server[server_peer].disconnect_now(0);
+        assert_eq!(server.peer_by_connect_id(connect_id), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_as_raw_fd() {
+        use crate::{Address, EventKind};
+        use std::net::Ipv4Addr;
+        use std::os::unix::io::{AsFd, AsRawFd};
+        use std::time::Duration;
+
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12399);
+        let mut server = create_host(Some(&server_address), 1);
+        let mut client = create_host(None, 1);
+        assert_ne!(server.as_raw_fd(), client.as_raw_fd());
+        assert_eq!(server.as_fd().as_raw_fd(), server.as_raw_fd());
+        client.connect(&server_address, 1, 0).unwrap();
+
+        // Drive both hosts from a loop that waits on their sockets, like an event loop would.
+        let mut connected = false;
+        for _ in 0..100 {
+            crate::poll::wait_readable(&[server.as_raw_fd(), client.as_raw_fd()], 10).unwrap();
+            while let Some(event) = server.service(Some(Duration::ZERO)).unwrap() {
+                connected |= matches!(event.kind, EventKind::Connect);
+            }
+            while client.service(Some(Duration::ZERO)).unwrap().is_some() {}
+            if connected {
+                break;
+            }
+        }
+        assert!(connected);
+    }
+
+    #[test]
+    fn test_debug() {
+        use crate::Address;
+        use std::net::Ipv4Addr;
+
+        let mut host = ENET
+            .create_host::<u32>(
+                None,
+                2,
+                ChannelLimit::Limited(3),
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Limited(1000),
+            )
+            .unwrap();
+        let (_, peer_id) = host
+            .connect(&Address::new(Ipv4Addr::LOCALHOST, 12395), 2, 0)
+            .unwrap();
+        host[peer_id].set_data(Some(7));
+
+        let debug = format!("{:?}", host);
+        assert!(debug.starts_with("Host { address: "), "{}", debug);
+        assert!(debug.contains("peer_slots: 2, "), "{}", debug);
+        assert!(debug.contains("channel_limit: Limited(3), "), "{}", debug);
+        assert!(debug.contains("outgoing_bandwidth: 1000, "), "{}", debug);
+
+        let debug = format!("{:?}", host[peer_id]);
+        assert!(debug.contains("state: Connecting, "), "{}", debug);
+        assert!(debug.contains("channel_count: 2, "), "{}", debug);
+        assert!(debug.ends_with("data: Some(7) }"), "{}", debug);
+    }
+
+    #[test]
+    fn test_display() {
+        use crate::{PeerID, PeerState, SendError};
+
+        let peer_id = PeerID {
+            index: 12,
+            host_id: 0,
+        };
+        assert_eq!(peer_id.to_string(), "peer#12");
+        assert_eq!(PeerState::Connected.to_string(), "Connected");
+        assert_eq!(
+            SendError::NotConnected(PeerState::Zombie).to_string(),
+            "cannot send to a peer in state Zombie"
+        );
+    }
+
+    #[test]
+    fn test_peer_data_entry() {
+        use crate::Address;
+        use std::net::Ipv4Addr;
+
+        let mut host = ENET
+            .create_host::<Vec<u32>>(
+                None,
+                1,
+                ChannelLimit::Maximum,
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap();
+        let (_, peer_id) = host
+            .connect(&Address::new(Ipv4Addr::LOCALHOST, 12395), 1, 0)
+            .unwrap();
+
+        assert_eq!(host.peer_data_entry(peer_id).get(), None);
+        host.peer_data_entry(peer_id)
+            .and_modify(|data| data.push(0))
+            .or_default()
+            .push(1);
+        host.peer_data_entry(peer_id)
+            .and_modify(|data| data.push(2))
+            .or_insert_with(|| Vec::with_capacity(4))
+            .push(3);
+        assert_eq!(host[peer_id].data(), Some(&vec![1, 2, 3]));
+
+        assert_eq!(host.peer_data_entry(peer_id).remove(), Some(vec![1, 2, 3]));
+        assert_eq!(host.peer_data_entry(peer_id).or_insert(vec![4]), &vec![4]);
+    }
+
+    #[test]
+    fn test_connect_sync() {
+        use crate::{Address, ConnectError, EventKind, PeerState};
+        use std::net::Ipv4Addr;
+        use std::thread;
+        use std::time::{Duration, Instant};
+
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12402);
+        let server = thread::spawn(move || {
+            let mut server = create_host(Some(&server_address), 1);
+            for _ in 0..2500 {
+                if let Some(event) = server.service(Some(Duration::from_millis(2))).unwrap() {
+                    if let EventKind::Connect = event.kind {
+                        break;
+                    }
+                }
+            }
+        });
+
+        let mut client = create_host(None, 1);
+        let mut events = Vec::new();
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12402);
+        let event = client
+            .connect_sync(&server_address, 1, 0, Duration::from_secs(5), &mut events)
+            .unwrap();
+        assert!(matches!(event.kind, EventKind::Connect));
+        assert_eq!(client[event.peer_id].state(), PeerState::Connected);
+        assert!(events.is_empty());
+        server.join().unwrap();
+
+        // Nothing listens on this port, so the attempt times out.
+        let mut client = create_host(None, 1);
+        let start = Instant::now();
+        let nowhere = Address::new(Ipv4Addr::LOCALHOST, 12403);
+        match client.connect_sync(&nowhere, 1, 0, Duration::from_millis(50), &mut events) {
+            Err(ConnectError::TimedOut) => (),
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(client
+            .peers()
+            .all(|peer| peer.state() == PeerState::Disconnected));
+    }
+
+    #[test]
+    fn test_disconnect_sync() {
+        use crate::{Address, DisconnectError, EventKind, PeerState};
+        use std::net::Ipv4Addr;
+        use std::thread;
+        use std::time::Duration;
+
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12404);
+        let server = thread::spawn(move || {
+            let mut server = create_host(Some(&server_address), 1);
+            for _ in 0..2500 {
+                if let Some(event) = server.service(Some(Duration::from_millis(2))).unwrap() {
+                    if let EventKind::Disconnect { data } = event.kind {
+                        return Some(data);
+                    }
+                }
+            }
+            None
+        });
+
+        let mut client = create_host(None, 1);
+        let mut events = Vec::new();
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12404);
+        let timeout = Duration::from_secs(5);
+        let peer_id = client
+            .connect_sync(&server_address, 1, 0, timeout, &mut events)
+            .unwrap()
+            .peer_id;
+
+        let event = client
+            .disconnect_sync(peer_id, 42, timeout, &mut events)
+            .unwrap();
+        assert!(matches!(event.kind, EventKind::Disconnect { .. }));
+        assert_eq!(client[peer_id].state(), PeerState::Disconnected);
+        // The foreign host was notified before `disconnect_sync` returned.
+        assert_eq!(server.join().unwrap(), Some(42));
+
+        match client.disconnect_sync(peer_id, 0, timeout, &mut events) {
+            Err(DisconnectError::NotConnected) => (),
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn test_rebind() {
+        use crate::{Address, EventKind};
+        use std::net::Ipv4Addr;
+
+        let old_address = Address::new(Ipv4Addr::LOCALHOST, 12406);
+        let new_address = Address::new(Ipv4Addr::LOCALHOST, 12407);
+        let mut server = create_host(Some(&old_address), 1);
+        server.rebind(&new_address).unwrap();
+        assert_eq!(server.address(), new_address);
+
+        // The old port is free again, and the new one is taken.
+        let mut other = create_host(Some(&old_address), 1);
+        assert!(other.rebind(&new_address).is_err());
+
+        let mut client = create_host(None, 1);
+        client.connect(&new_address, 1, 0).unwrap();
+        pump_until(&mut [&mut client, &mut server], |index, _, event| {
+            index == 1 && matches!(event.kind, EventKind::Connect)
+        });
+    }
+
+    #[test]
+    fn test_set_accepting() {
+        use crate::{Address, EventKind, Packet, PacketMode};
+        use std::net::Ipv4Addr;
+        use std::time::Duration;
+
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12408);
+        let mut server = create_host(Some(&server_address), 2);
+        let mut first = create_host(None, 1);
+        let first_id = first.connect(&server_address, 1, 0).unwrap().1;
+        let mut connects = 0;
+        pump_until(&mut [&mut server, &mut first], |_, _, event| {
+            connects += matches!(event.kind, EventKind::Connect) as usize;
+            connects == 2
+        });
+
+        assert!(server.is_accepting());
+        server.set_accepting(false);
+        server.clear_intercepts();
+        assert!(!server.is_accepting());
+
+        let mut second = create_host(None, 1);
+        second.connect(&server_address, 1, 0).unwrap();
+        let packet = Packet::new(b"hi".to_vec(), PacketMode::ReliableSequenced).unwrap();
+        first[first_id].send_packet(packet, 0).unwrap();
+        let timeout = Some(Duration::from_millis(2));
+        let mut events = Vec::new();
+        for _ in 0..50 {
+            first.service(timeout).unwrap();
+            second.service(timeout).unwrap();
+            if let Some(event) = server.service(timeout).unwrap() {
+                events.push(event.kind);
+            }
+        }
+        assert_eq!(events.len(), 1);
+        assert!(matches!(events[0], EventKind::Receive { .. }));
+
+        // The second host connects once a retransmission of its connect gets through.
+        server.set_accepting(true);
+        pump_until(&mut [&mut second, &mut server], |index, _, event| {
+            index == 1 && matches!(event.kind, EventKind::Connect)
+        });
+    }
+
+    #[test]
+    fn test_retransmit_timeout() {
+        use crate::{Address, EventKind, Packet, PacketMode};
+        use std::net::Ipv4Addr;
+        use std::time::Duration;
+
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12409);
+        let mut server = create_host(Some(&server_address), 1);
+        let mut client = create_host(None, 1);
+        let peer_id = client.connect(&server_address, 1, 0).unwrap().1;
+        pump_until(&mut [&mut server, &mut client], |index, _, event| {
+            index == 1 && matches!(event.kind, EventKind::Connect)
+        });
+        assert_eq!(client[peer_id].retransmit_timeout(), None);
+        assert_eq!(client[peer_id].oldest_unacknowledged(), None);
+
+        // Without the server, the packet is never acknowledged.
+        drop(server);
+        let packet = Packet::new(b"hi".to_vec(), PacketMode::ReliableSequenced).unwrap();
+        client[peer_id].send_packet(packet, 0).unwrap();
+        client.flush();
+        let initial = client[peer_id].retransmit_timeout().unwrap();
+        assert!(client[peer_id].oldest_unacknowledged().is_some());
+        assert_eq!(client[peer_id].timeout_started(), None);
+
+        let timeout = Some(Duration::from_millis(2));
+        for _ in 0..1000 {
+            client.service(timeout).unwrap();
+            if client[peer_id].timeout_started().is_some() {
+                break;
+            }
+        }
+        assert!(client[peer_id].timeout_started().is_some());
+        assert_eq!(client[peer_id].retransmit_timeout(), Some(initial * 2));
+    }
+
+    #[test]
+    fn test_send_shortcuts() {
+        use crate::{Address, EventKind, PacketMode, SendError};
+        use std::net::Ipv4Addr;
+
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12413);
+        let mut server = create_host(Some(&server_address), 2);
+        let (mut first, mut second) = (create_host(None, 1), create_host(None, 1));
+        let peer_id = first.connect(&server_address, 1, 0).unwrap().1;
+        second.connect(&server_address, 1, 0).unwrap();
+
+        let mut connects = 0;
+        pump_until(
+            &mut [&mut server, &mut first, &mut second],
+            |_, _, event| {
+                connects += matches!(event.kind, EventKind::Connect) as usize;
+                connects == 4
+            },
+        );
+
+        assert_eq!(server.broadcast_reliable(0, b"all".to_vec()), 2);
+        let peer = &mut first[peer_id];
+        peer.send_unsequenced(0, b"fast".to_vec()).unwrap();
+        match peer.send_reliable(1, b"chat".to_vec()) {
+            Err(SendError::InvalidChannel(1)) => (),
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        let (mut broadcasts, mut received) = (0, None);
+        pump_until(
+            &mut [&mut server, &mut first, &mut second],
+            |index, _, event| {
+                if let EventKind::Receive { ref packet, .. } = event.kind {
+                    if index == 0 {
+                        received = Some((packet.data().to_vec(), packet.mode()));
+                    } else {
+                        assert_eq!(packet.data(), b"all");
+                        assert_eq!(packet.mode(), PacketMode::ReliableSequenced);
+                        broadcasts += 1;
+                    }
+                }
+                broadcasts == 2 && received.is_some()
+            },
+        );
+        assert_eq!(
+            received,
+            Some((b"fast".to_vec(), PacketMode::UnreliableUnsequenced))
+        );
+    }
+
+    #[test]
+    fn test_clear_outgoing() {
+        use crate::{EventKind, Packet, PacketMode, Priority, PrioritySender};
+
+        let (mut server, mut client, _, peer_id) = connected_pair(12414, 2);
+
+        let peer = &mut client[peer_id];
+        peer.send_unreliable(0, b"old snapshot".to_vec()).unwrap();
+        peer.send_unsequenced(0, b"old input".to_vec()).unwrap();
+        peer.send_reliable(0, b"chat".to_vec()).unwrap();
+        peer.send_unreliable(1, b"other channel".to_vec()).unwrap();
+        assert_eq!(
+            peer.clear_outgoing(0, Some(PacketMode::ReliableSequenced)),
+            0
+        );
+        assert_eq!(
+            peer.clear_outgoing(0, Some(PacketMode::UnreliableSequenced)),
+            1
+        );
+        assert_eq!(peer.clear_outgoing(0, None), 1);
+        assert_eq!(peer.queued_unreliable_commands(), 1);
+        peer.send_unreliable(0, b"new snapshot".to_vec()).unwrap();
+
+        let mut received = Vec::new();
+        pump_until(&mut [&mut client, &mut server], |_, _, event| {
+            if let EventKind::Receive { channel_id, packet } = event.kind {
+                received.push((channel_id, packet.data().to_vec()));
+            }
+            received.len() == 3
+        });
+        received.sort();
+        assert_eq!(
+            received,
+            vec![
+                (0, b"chat".to_vec()),
+                (0, b"new snapshot".to_vec()),
+                (1, b"other channel".to_vec())
+            ]
+        );
+
+        // Nothing goes straight to ENet without room in its queue.
+        let mut sender = PrioritySender::new(0);
+        for mode in [
+            PacketMode::ReliableSequenced,
+            PacketMode::UnreliableSequenced,
+        ] {
+            let packet = Packet::new(b"bulk".to_vec(), mode).unwrap();
+            sender
+                .send(&mut client, peer_id, 0, packet, Priority::Low)
+                .unwrap();
+        }
+        assert_eq!(sender.clear(peer_id, 1, None), 0);
+        assert_eq!(sender.clear(peer_id, 0, None), 2);
+        assert_eq!(sender.queued(peer_id), 0);
+    }
+
+    #[test]
+    fn test_channel_traffic() {
+        use crate::{ChannelTraffic, EventKind};
+        use std::time::Duration;
+
+        let (mut server, mut client, _, peer_id) = connected_pair(12415, 2);
+        client[peer_id].send_reliable(0, b"chat".to_vec()).unwrap();
+        client[peer_id]
+            .send_reliable(0, b"more chat".to_vec())
+            .unwrap();
+        client[peer_id]
+            .send_unreliable(1, b"voice".to_vec())
+            .unwrap();
+        let sent = [
+            ChannelTraffic {
+                sent_bytes: 13,
+                sent_packets: 2,
+                ..ChannelTraffic::default()
+            },
+            ChannelTraffic {
+                sent_bytes: 5,
+                sent_packets: 1,
+                ..ChannelTraffic::default()
+            },
+        ];
+        assert_eq!(client[peer_id].channel_traffic(), sent);
+        assert_eq!(client.channel_traffic(), sent);
+
+        let mut received = 0;
+        pump_until(&mut [&mut client, &mut server], |_, _, event| {
+            received += matches!(event.kind, EventKind::Receive { .. }) as usize;
+            received == 3
+        });
+        let expected: Vec<_> = sent
+            .iter()
+            .map(|sent| ChannelTraffic {
+                received_bytes: sent.sent_bytes,
+                received_packets: sent.sent_packets,
+                ..ChannelTraffic::default()
+            })
+            .collect();
+        assert_eq!(server.channel_traffic(), expected);
+
+        // The traffic of peers that disconnected stays counted.
+        client[peer_id].disconnect(0);
+        pump_until(&mut [&mut client, &mut server], |index, _, event| {
+            index == 1 && event.kind.is_disconnect()
+        });
+        server.service(Some(Duration::from_millis(2))).unwrap();
+        assert_eq!(server.channel_traffic(), expected);
+        assert_eq!(server.diagnostics().channel_traffic, expected);
+    }
+
+    #[test]
+    fn test_banned_peer_traffic() {
+        use crate::ChannelTraffic;
+        use std::time::Duration;
+
+        let (mut server, mut client, client_peer, peer_id) = connected_pair(12420, 1);
+        client[peer_id].send_reliable(0, b"chat".to_vec()).unwrap();
+        pump_until(&mut [&mut client, &mut server], |index, _, event| {
+            index == 1 && matches!(event.kind, EventKind::Receive { .. })
+        });
+
+        let received = vec![ChannelTraffic {
+            received_bytes: 4,
+            received_packets: 1,
+            ..ChannelTraffic::default()
+        }];
+        assert_eq!(server.channel_traffic(), received);
+        server.ban(client_peer, Duration::from_secs(60), 0).unwrap();
+        assert_eq!(server.channel_traffic(), received);
+    }
+
+    #[test]
+    fn test_reset_peer_traffic() {
+        use crate::ChannelTraffic;
+        use std::time::Duration;
+
+        let (mut server, mut client, client_peer, peer_id) = connected_pair(12424, 1);
+        client[peer_id].send_reliable(0, b"chat".to_vec()).unwrap();
+        pump_until(&mut [&mut client, &mut server], |index, _, event| {
+            index == 1 && matches!(event.kind, EventKind::Receive { .. })
+        });
+
+        let received = vec![ChannelTraffic {
+            received_bytes: 4,
+            received_packets: 1,
+            ..ChannelTraffic::default()
+        }];
+        server[client_peer].disconnect_now(0);
+        server.service(Some(Duration::ZERO)).unwrap();
+        // The traffic stays in the totals, but the slot starts over for the next connection.
+        assert_eq!(server.channel_traffic(), received);
+        assert!(server[client_peer].channel_traffic().is_empty());
+    }
+
+    #[test]
+    fn test_fragment_stats() {
+        use crate::{
+            Address, Datagram, EventKind, FragmentStats, InterceptAction, Packet, PacketMode,
+        };
+        use std::net::Ipv4Addr;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+        use std::time::{Duration, Instant};
+
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12417);
+        let mut server = create_host(Some(&server_address), 1);
+        let mut client = create_host(None, 1);
+        let peer_id = client.connect(&server_address, 1, 0).unwrap().1;
+
+        server.set_fragment_tracking(Some(Duration::from_millis(100)));
+        // Drops the next datagram that carries a fragment, once armed.
+        let drop_next = Arc::new(AtomicBool::new(false));
+        let armed = drop_next.clone();
+        server.add_intercept(move |datagram: &mut Datagram| {
+            if datagram.data().len() > 1000 && armed.swap(false, Ordering::Relaxed) {
+                InterceptAction::Drop
+            } else {
+                InterceptAction::Continue
+            }
+        });
+
+        let mut server_peer_id = None;
+        pump_until(&mut [&mut client, &mut server], |index, _, event| {
+            if let (1, EventKind::Connect) = (index, &event.kind) {
+                server_peer_id = Some(event.peer_id);
+            }
+            server_peer_id.is_some()
+        });
+        let server_peer_id = server_peer_id.unwrap();
+        let timeout = Some(Duration::from_millis(2));
+        for _ in 0..10 {
+            client.service(timeout).unwrap();
+        }
+
+        client[peer_id].send_reliable(0, vec![1; 5000]).unwrap();
+        client[peer_id].send_reliable(0, vec![2; 100]).unwrap();
+        let sent = FragmentStats {
+            fragmented_packets: 1,
+            outgoing_fragments: 4,
+            ..FragmentStats::default()
+        };
+        assert_eq!(client.fragment_stats(peer_id), sent);
+
+        let mut received = 0;
+        pump_until(&mut [&mut client, &mut server], |_, _, event| {
+            received += matches!(event.kind, EventKind::Receive { .. }) as usize;
+            received == 2
+        });
+        let reassembled = FragmentStats {
+            incoming_fragments: 4,
+            reassembled_packets: 1,
+            ..FragmentStats::default()
+        };
+        assert_eq!(server.fragment_stats(server_peer_id), reassembled);
+
+        // An unreliable packet with a lost fragment is never reassembled.
+        drop_next.store(true, Ordering::Relaxed);
+        let packet =
+            Packet::new_unreliable_fragment(vec![3; 5000], PacketMode::UnreliableSequenced)
+                .unwrap();
+        client[peer_id].send_packet(packet, 0).unwrap();
+        let deadline = Instant::now() + Duration::from_millis(300);
+        while Instant::now() < deadline {
+            client.service(timeout).unwrap();
+            server.service(timeout).unwrap();
+        }
+        assert!(!drop_next.load(Ordering::Relaxed));
+        assert_eq!(
+            server.fragment_stats(server_peer_id),
+            FragmentStats {
+                incoming_fragments: 7,
+                reassembly_timeouts: 1,
+                ..reassembled
+            }
+        );
+        assert_eq!(
+            client.fragment_stats(peer_id),
+            FragmentStats {
+                fragmented_packets: 2,
+                outgoing_fragments: 8,
+                ..FragmentStats::default()
+            }
+        );
+
+        server.set_fragment_tracking(None);
+        assert_eq!(
+            server.fragment_stats(server_peer_id),
+            FragmentStats::default()
+        );
+    }
 }
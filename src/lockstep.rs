@@ -0,0 +1,351 @@
+//! Deterministic lockstep, for games that run the same simulation on every machine.
+//!
+//! Every player submits one input per tick. The inputs are exchanged on a reliable channel, and a
+//! [Lockstep](struct.Lockstep.html) releases the frame of a tick once it has the inputs of all
+//! players for it. Frames are released strictly in order, so every machine feeds the simulation
+//! the same inputs.
+//!
+//! Players are numbered by the application, and the numbering must be the same on all machines,
+//! e.g. assigned by the server. Inputs are sent directly to the peer of every other player, so the
+//! peers must form a mesh. On a star topology, run the lockstep on the server only, and send the
+//! released frames on to the clients.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+
+use crate::{Event, EventKind, Host, Packet, PacketMode, PeerID};
+
+/// Marks lockstep packets.
+const MAGIC: &[u8] = b"\xffLKS";
+
+/// Identifies a tick of the simulation. The first tick is 0.
+pub type Tick = u32;
+
+/// How many ticks past the next frame remote inputs may be for. Inputs further ahead are dropped,
+/// so peers can't make a `Lockstep` hold on to inputs without bounds.
+pub const MAX_TICKS_AHEAD: Tick = 256;
+
+/// What a `Lockstep` does while it waits for the inputs of a tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StallPolicy {
+    /// Waits for the inputs indefinitely.
+    Wait,
+    /// Once the inputs took this long, releases the frame without them.
+    ///
+    /// Inputs that arrive after their frame was released are dropped.
+    SkipAfter(Duration),
+    /// Once the inputs took this long, removes the players that are late, and releases the frame
+    /// without them.
+    RemoveAfter(Duration),
+}
+
+/// The inputs of all players for one tick.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// The tick of the frame.
+    pub tick: Tick,
+    /// The input of every player, by player number. The input is `None` for players that were
+    /// skipped or removed.
+    pub inputs: BTreeMap<u8, Option<Vec<u8>>>,
+    /// The players that were removed because of this frame, see `StallPolicy::RemoveAfter`.
+    pub removed: Vec<u8>,
+}
+
+/// Collects the inputs of all players per tick, and releases complete frames in order.
+#[derive(Debug)]
+pub struct Lockstep {
+    channel_id: u8,
+    local: u8,
+    policy: StallPolicy,
+    players: BTreeMap<u8, PeerID>,
+    inputs: BTreeMap<Tick, BTreeMap<u8, Vec<u8>>>,
+    next_tick: Tick,
+    next_local_tick: Tick,
+    waiting_since: Option<Instant>,
+    removed: BTreeSet<u8>,
+}
+
+impl Lockstep {
+    /// Creates a lockstep for the `local` player, using `channel_id` exclusively.
+    pub fn new(channel_id: u8, local: u8, policy: StallPolicy) -> Lockstep {
+        Lockstep {
+            channel_id,
+            local,
+            policy,
+            players: BTreeMap::new(),
+            inputs: BTreeMap::new(),
+            next_tick: 0,
+            next_local_tick: 0,
+            waiting_since: None,
+            removed: BTreeSet::new(),
+        }
+    }
+
+    /// Adds the remote `player`, whose inputs come from `peer_id`.
+    pub fn add_player(&mut self, player: u8, peer_id: PeerID) {
+        self.players.insert(player, peer_id);
+    }
+
+    /// Removes the remote `player`, so frames don't wait for its inputs anymore.
+    ///
+    /// The next released frame lists the player as removed.
+    pub fn remove_player(&mut self, player: u8) {
+        if self.players.remove(&player).is_some() {
+            self.removed.insert(player);
+        }
+    }
+
+    /// Returns the numbers of the remote players.
+    pub fn players(&self) -> impl Iterator<Item = u8> + '_ {
+        self.players.keys().cloned()
+    }
+
+    /// Returns the tick of the next frame to be released.
+    pub fn next_tick(&self) -> Tick {
+        self.next_tick
+    }
+
+    /// Returns the tick the next local input is submitted for.
+    pub fn next_local_tick(&self) -> Tick {
+        self.next_local_tick
+    }
+
+    /// Returns the remote players whose input for the next frame is missing.
+    pub fn missing(&self) -> Vec<u8> {
+        let inputs = self.inputs.get(&self.next_tick);
+        self.players()
+            .filter(|player| !inputs.is_some_and(|inputs| inputs.contains_key(player)))
+            .collect()
+    }
+
+    /// Submits the local input for the next local tick, and sends it to all remote players.
+    ///
+    /// Submitting ahead of the released frames gives the inputs time to arrive, i.e. adds input
+    /// delay. Returns the tick the input was submitted for.
+    pub fn submit<T>(&mut self, host: &mut Host<T>, input: Vec<u8>) -> Tick {
+        let tick = self.next_local_tick;
+        self.next_local_tick += 1;
+
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&tick.to_be_bytes());
+        data.extend_from_slice(&input);
+        for &peer_id in self.players.values() {
+            if let (Ok(packet), Some(peer)) = (
+                Packet::new(data.clone(), PacketMode::ReliableSequenced),
+                host.peer_mut(peer_id),
+            ) {
+                // Players that can't be sent to are disconnecting, and will be removed.
+                let _ = peer.send_packet(packet, self.channel_id);
+            }
+        }
+
+        self.inputs
+            .entry(tick)
+            .or_default()
+            .insert(self.local, input);
+        tick
+    }
+
+    /// Processes an event returned by `Host::service`.
+    ///
+    /// Returns `true` if the event was a lockstep packet, which needs no further handling. The
+    /// players of peers that disconnect are removed, but the disconnect is left to the caller.
+    /// Inputs more than `MAX_TICKS_AHEAD` ticks past the next frame are dropped.
+    pub fn handle_event(&mut self, event: &Event) -> bool {
+        let player = self
+            .players
+            .iter()
+            .find(|(_, &peer_id)| peer_id == event.peer_id)
+            .map(|(&player, _)| player);
+
+        match event.kind {
+            EventKind::Receive {
+                channel_id,
+                ref packet,
+            } if channel_id == self.channel_id => {
+                let data = match packet.data().strip_prefix(MAGIC) {
+                    Some(data) if data.len() >= 4 => data,
+                    _ => return true,
+                };
+                let tick = Tick::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                if let Some(player) = player {
+                    // Inputs for released frames were skipped already.
+                    let ahead = tick.checked_sub(self.next_tick);
+                    if ahead.is_some_and(|ahead| ahead <= MAX_TICKS_AHEAD) {
+                        self.inputs
+                            .entry(tick)
+                            .or_default()
+                            .insert(player, data[4..].to_vec());
+                    }
+                }
+                true
+            }
+            ref kind if kind.is_disconnect() => {
+                if let Some(player) = player {
+                    self.remove_player(player);
+                }
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the next frame, if it is complete or the stall policy releases it.
+    ///
+    /// Frames always wait for the local input. Call this until it returns `None` every time the
+    /// host was serviced.
+    pub fn next_frame(&mut self) -> Option<Frame> {
+        if !self
+            .inputs
+            .get(&self.next_tick)
+            .is_some_and(|inputs| inputs.contains_key(&self.local))
+        {
+            return None;
+        }
+
+        let missing = self.missing();
+        if !missing.is_empty() {
+            let waiting_since = *self.waiting_since.get_or_insert_with(Instant::now);
+            match self.policy {
+                StallPolicy::SkipAfter(timeout) if waiting_since.elapsed() >= timeout => (),
+                StallPolicy::RemoveAfter(timeout) if waiting_since.elapsed() >= timeout => {
+                    for player in missing {
+                        self.remove_player(player);
+                    }
+                }
+                _ => return None,
+            }
+        }
+
+        let tick = self.next_tick;
+        let mut received = self.inputs.remove(&tick).unwrap_or_default();
+        let mut inputs: BTreeMap<_, _> = self
+            .players()
+            .chain(Some(self.local))
+            .map(|player| (player, received.remove(&player)))
+            .collect();
+        let removed: Vec<_> = std::mem::take(&mut self.removed).into_iter().collect();
+        for &player in &removed {
+            inputs.insert(player, None);
+        }
+
+        self.next_tick += 1;
+        self.waiting_since = None;
+        Some(Frame {
+            tick,
+            inputs,
+            removed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Lockstep, StallPolicy, MAGIC, MAX_TICKS_AHEAD};
+    use crate::tests::{create_host, pump_until};
+    use crate::{Address, Event, EventKind, Packet, PacketMode, PeerID};
+
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    #[test]
+    fn test_lockstep() {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12373);
+        let mut server = create_host(Some(&server_address), 2);
+        let mut client = create_host(None, 1);
+        let server_peer = client.connect(&server_address, 1, 0).unwrap().1;
+
+        let mut server_lockstep = Lockstep::new(0, 0, StallPolicy::Wait);
+        let mut client_lockstep = Lockstep::new(0, 1, StallPolicy::Wait);
+        client_lockstep.add_player(0, server_peer);
+
+        let (mut server_frames, mut client_frames) = (Vec::new(), Vec::new());
+        pump_until(&mut [&mut server, &mut client], |index, host, event| {
+            let (lockstep, frames, tag) = match index {
+                0 => (&mut server_lockstep, &mut server_frames, b's'),
+                _ => (&mut client_lockstep, &mut client_frames, b'c'),
+            };
+            if let EventKind::Connect = event.kind {
+                if index == 0 {
+                    lockstep.add_player(1, event.peer_id);
+                }
+                for tick in 0..3u8 {
+                    lockstep.submit(host, vec![tag, tick]);
+                }
+            }
+            lockstep.handle_event(&event);
+            frames.extend(std::iter::from_fn(|| lockstep.next_frame()));
+            server_frames.len() == 3 && client_frames.len() == 3
+        });
+
+        assert_eq!(server_frames, client_frames);
+        assert_eq!(server_frames[2].tick, 2);
+        assert_eq!(server_frames[2].inputs[&0], Some(vec![b's', 2]));
+        assert_eq!(server_frames[2].inputs[&1], Some(vec![b'c', 2]));
+        assert_eq!(client_lockstep.next_frame(), None);
+    }
+
+    #[test]
+    fn test_stall_policies() {
+        let mut host = create_host(None, 1);
+        let silent = host
+            .connect(&Address::new(Ipv4Addr::LOCALHOST, 12374), 1, 0)
+            .unwrap()
+            .1;
+
+        let mut waiting = Lockstep::new(0, 0, StallPolicy::Wait);
+        waiting.add_player(1, silent);
+        waiting.submit(&mut host, vec![1]);
+        assert_eq!(waiting.next_frame(), None);
+        assert_eq!(waiting.missing(), vec![1]);
+
+        let mut skipping = Lockstep::new(0, 0, StallPolicy::SkipAfter(Duration::from_millis(10)));
+        skipping.add_player(1, silent);
+        skipping.submit(&mut host, vec![1]);
+        assert_eq!(skipping.next_frame(), None);
+        std::thread::sleep(Duration::from_millis(15));
+        let frame = skipping.next_frame().unwrap();
+        assert_eq!(frame.inputs[&1], None);
+        assert!(frame.removed.is_empty());
+        assert_eq!(skipping.players().count(), 1);
+
+        let mut removing = Lockstep::new(0, 0, StallPolicy::RemoveAfter(Duration::ZERO));
+        removing.add_player(1, silent);
+        removing.submit(&mut host, vec![1]);
+        let frame = removing.next_frame().unwrap();
+        assert_eq!(frame.removed, vec![1]);
+        assert_eq!(frame.inputs[&1], None);
+        assert_eq!(removing.players().count(), 0);
+        removing.submit(&mut host, vec![2]);
+        assert_eq!(removing.next_frame().unwrap().inputs.len(), 1);
+    }
+
+    #[test]
+    fn test_inputs_far_ahead() {
+        let peer_id = PeerID {
+            index: 0,
+            host_id: 0,
+        };
+        let input = |tick: u32| {
+            let mut data = MAGIC.to_vec();
+            data.extend_from_slice(&tick.to_be_bytes());
+            Event {
+                peer_id,
+                kind: EventKind::Receive {
+                    channel_id: 0,
+                    packet: Packet::new(data, PacketMode::ReliableSequenced).unwrap(),
+                },
+            }
+        };
+
+        let mut lockstep = Lockstep::new(0, 0, StallPolicy::Wait);
+        lockstep.add_player(1, peer_id);
+        for tick in &[MAX_TICKS_AHEAD, MAX_TICKS_AHEAD + 1, u32::MAX] {
+            assert!(lockstep.handle_event(&input(*tick)));
+        }
+        assert_eq!(
+            lockstep.inputs.keys().collect::<Vec<_>>(),
+            vec![&MAX_TICKS_AHEAD]
+        );
+    }
+}
@@ -0,0 +1,91 @@
+//! Records for the `log` crate, emitted with the `log` feature.
+//!
+//! Without the feature, the macros compile to nothing, but still check their arguments.
+
+/// Connects, disconnects and connection attempts that timed out or were rejected.
+pub(crate) const CONNECTION: &str = "enet::connection";
+/// Changes of the packet throttle of peers, see `Peer::packet_throttle`.
+#[cfg(feature = "log")]
+pub(crate) const THROTTLE: &str = "enet::throttle";
+/// Packets and datagrams dropped by a limit, a ban or `Host::set_accepting`.
+pub(crate) const DROP: &str = "enet::drop";
+/// Errors returned by ENet.
+pub(crate) const ERROR: &str = "enet::error";
+
+macro_rules! log_info {
+    (target: $target:expr, $($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        log::info!(target: $target, $($arg)+);
+        #[cfg(not(feature = "log"))]
+        if false {
+            let _ = ($target, format_args!($($arg)+));
+        }
+    }};
+}
+
+macro_rules! log_debug {
+    (target: $target:expr, $($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        log::debug!(target: $target, $($arg)+);
+        #[cfg(not(feature = "log"))]
+        if false {
+            let _ = ($target, format_args!($($arg)+));
+        }
+    }};
+}
+
+pub(crate) use log_debug;
+pub(crate) use log_info;
+
+#[cfg(all(test, feature = "log"))]
+mod tests {
+    use crate::tests::{connected_pair, pump_until};
+
+    use std::sync::Mutex;
+
+    /// Keeps the target and message of every record.
+    struct Recorder(Mutex<Vec<(String, String)>>);
+
+    impl log::Log for Recorder {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            let entry = (record.target().to_string(), record.args().to_string());
+            self.0.lock().unwrap().push(entry);
+        }
+
+        fn flush(&self) {}
+    }
+
+    static RECORDER: Recorder = Recorder(Mutex::new(Vec::new()));
+
+    #[test]
+    fn test_log_records() {
+        log::set_logger(&RECORDER).unwrap();
+        log::set_max_level(log::LevelFilter::Debug);
+
+        let (mut server, mut client, client_peer, peer_id) = connected_pair(12411, 1);
+        let client_address = server[client_peer].address();
+
+        client[peer_id].disconnect(5);
+        pump_until(&mut [&mut client, &mut server], |index, _, event| {
+            index == 1 && event.kind.is_disconnect()
+        });
+
+        // Other tests log concurrently, so only look for the records of this one.
+        let records = RECORDER.0.lock().unwrap();
+        let client_address = client_address.to_string();
+        let connection: Vec<_> = records
+            .iter()
+            .filter(|(target, message)| {
+                target == super::CONNECTION && message.contains(&client_address)
+            })
+            .map(|(_, message)| message.as_str())
+            .collect();
+        assert_eq!(connection.len(), 2, "{:?}", connection);
+        assert!(connection[0].contains("connected from"));
+        assert!(connection[1].contains("disconnected with data 5"));
+    }
+}
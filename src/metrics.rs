@@ -0,0 +1,420 @@
+//! Host and peer metrics in the Prometheus text format.
+//!
+//! A [MetricsExporter](struct.MetricsExporter.html) renders the metrics of a `Host`, and either
+//! serves them over HTTP for Prometheus to scrape, or writes them to a file for the textfile
+//! collector of the node exporter. Both happen on the thread that services the host, so call
+//! `MetricsExporter::serve` or `MetricsExporter::write_textfile` regularly, e.g. after every
+//! `Host::service`.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::{ChannelTraffic, FragmentStats, Host, Peer, PeerState};
+
+/// How long a scrape may take to send its request and read the response, in total.
+const REQUEST_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The name, help and value of a per-peer gauge.
+type Gauge<T> = (&'static str, &'static str, fn(&Peer<T>) -> f64);
+
+/// The name, help and value of a per-channel counter.
+type ChannelCounter = (&'static str, &'static str, fn(&ChannelTraffic) -> u64);
+
+/// The name, help and value of a per-peer fragmentation counter.
+type FragmentCounter = (&'static str, &'static str, fn(&FragmentStats) -> u64);
+
+/// Keeps 64 bit totals of one of ENet's 32 bit traffic counters, which wrap.
+#[derive(Debug, Default)]
+struct Counter {
+    last: u32,
+    total: u64,
+}
+
+impl Counter {
+    fn update(&mut self, value: u32) -> u64 {
+        self.total += u64::from(value.wrapping_sub(self.last));
+        self.last = value;
+        self.total
+    }
+}
+
+/// Renders and publishes the metrics of a `Host`.
+#[derive(Debug, Default)]
+pub struct MetricsExporter {
+    listener: Option<TcpListener>,
+    sent_bytes: Counter,
+    sent_packets: Counter,
+    received_bytes: Counter,
+    received_packets: Counter,
+}
+
+impl MetricsExporter {
+    /// Creates an exporter that only renders metrics, e.g. for `write_textfile`.
+    pub fn new() -> MetricsExporter {
+        MetricsExporter::default()
+    }
+
+    /// Creates an exporter that serves metrics over HTTP on `address`, at `/metrics`.
+    pub fn listen(address: SocketAddr) -> io::Result<MetricsExporter> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(MetricsExporter {
+            listener: Some(listener),
+            ..MetricsExporter::default()
+        })
+    }
+
+    /// Returns the address the exporter serves metrics on, if it listens.
+    pub fn local_addr(&self) -> Option<io::Result<SocketAddr>> {
+        self.listener.as_ref().map(TcpListener::local_addr)
+    }
+
+    /// Renders the current metrics of `host`.
+    ///
+    /// Traffic totals are counted from the first call, so render regularly, at least once per
+    /// 4GiB of traffic.
+    pub fn render<T>(&mut self, host: &Host<T>) -> String {
+        let raw = unsafe { &*host.as_raw() };
+        let mut out = String::new();
+
+        metric(
+            &mut out,
+            "enet_peer_slots",
+            "gauge",
+            "Peers the host has room for.",
+        );
+        let _ = writeln!(out, "enet_peer_slots {}", raw.peerCount);
+        metric(
+            &mut out,
+            "enet_peers_connected",
+            "gauge",
+            "Connected peers.",
+        );
+        let _ = writeln!(out, "enet_peers_connected {}", raw.connectedPeers);
+
+        let counters = [
+            (
+                "enet_sent_bytes_total",
+                "Bytes sent.",
+                self.sent_bytes.update(raw.totalSentData),
+            ),
+            (
+                "enet_sent_packets_total",
+                "Datagrams sent.",
+                self.sent_packets.update(raw.totalSentPackets),
+            ),
+            (
+                "enet_received_bytes_total",
+                "Bytes received.",
+                self.received_bytes.update(raw.totalReceivedData),
+            ),
+            (
+                "enet_received_packets_total",
+                "Datagrams received.",
+                self.received_packets.update(raw.totalReceivedPackets),
+            ),
+        ];
+        for &(name, help, value) in &counters {
+            metric(&mut out, name, "counter", help);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+
+        let traffic = host.channel_traffic();
+        let channel_counters: [ChannelCounter; 4] = [
+            (
+                "enet_channel_sent_bytes_total",
+                "Packet data sent on the channel, in bytes.",
+                |traffic| traffic.sent_bytes,
+            ),
+            (
+                "enet_channel_sent_packets_total",
+                "Packets sent on the channel.",
+                |traffic| traffic.sent_packets,
+            ),
+            (
+                "enet_channel_received_bytes_total",
+                "Packet data received on the channel, in bytes.",
+                |traffic| traffic.received_bytes,
+            ),
+            (
+                "enet_channel_received_packets_total",
+                "Packets received on the channel.",
+                |traffic| traffic.received_packets,
+            ),
+        ];
+        for &(name, help, value) in &channel_counters {
+            metric(&mut out, name, "counter", help);
+            for (channel_id, traffic) in traffic.iter().enumerate() {
+                let _ = writeln!(
+                    out,
+                    "{}{{channel=\"{}\"}} {}",
+                    name,
+                    channel_id,
+                    value(traffic)
+                );
+            }
+        }
+
+        let peers: Vec<_> = host
+            .peer_ids()
+            .filter(|&peer_id| host[peer_id].state() == PeerState::Connected)
+            .collect();
+        let gauges: [Gauge<T>; 5] = [
+            ("enet_peer_rtt_seconds", "Mean round trip time.", |peer| {
+                peer.mean_rtt().as_secs_f64()
+            }),
+            (
+                "enet_peer_packet_loss_ratio",
+                "Estimated packet loss.",
+                |peer| f64::from(peer.packet_loss()),
+            ),
+            (
+                "enet_peer_estimated_bandwidth_bytes",
+                "Estimated bandwidth, in bytes per second.",
+                |peer| f64::from(peer.estimated_bandwidth()),
+            ),
+            (
+                "enet_peer_queued_reliable_commands",
+                "Reliable commands waiting to be sent.",
+                |peer| peer.queued_reliable_commands() as f64,
+            ),
+            (
+                "enet_peer_reliable_bytes_in_transit",
+                "Reliable bytes sent, but not acknowledged yet.",
+                |peer| f64::from(peer.reliable_data_in_transit()),
+            ),
+        ];
+        for &(name, help, value) in &gauges {
+            metric(&mut out, name, "gauge", help);
+            for &peer_id in &peers {
+                let peer = &host[peer_id];
+                let _ = writeln!(
+                    out,
+                    "{}{{peer=\"{}\",address=\"{}\"}} {}",
+                    name,
+                    peer_id.index,
+                    peer.address(),
+                    value(peer)
+                );
+            }
+        }
+
+        let fragment_counters: [FragmentCounter; 5] = [
+            (
+                "enet_peer_fragmented_packets_total",
+                "Packets sent that were split into fragments.",
+                |stats| stats.fragmented_packets,
+            ),
+            (
+                "enet_peer_outgoing_fragments_total",
+                "Fragments sent.",
+                |stats| stats.outgoing_fragments,
+            ),
+            (
+                "enet_peer_incoming_fragments_total",
+                "Fragments received, if fragment tracking is on.",
+                |stats| stats.incoming_fragments,
+            ),
+            (
+                "enet_peer_reassembled_packets_total",
+                "Packets received that were reassembled from fragments.",
+                |stats| stats.reassembled_packets,
+            ),
+            (
+                "enet_peer_reassembly_timeouts_total",
+                "Packets received whose fragments did not all arrive in time.",
+                |stats| stats.reassembly_timeouts,
+            ),
+        ];
+        let fragments: Vec<_> = peers
+            .iter()
+            .map(|&peer_id| host.fragment_stats(peer_id))
+            .collect();
+        for &(name, help, value) in &fragment_counters {
+            metric(&mut out, name, "counter", help);
+            for (&peer_id, stats) in peers.iter().zip(&fragments) {
+                let _ = writeln!(
+                    out,
+                    "{}{{peer=\"{}\",address=\"{}\"}} {}",
+                    name,
+                    peer_id.index,
+                    host[peer_id].address(),
+                    value(stats)
+                );
+            }
+        }
+
+        out
+    }
+
+    /// Answers the pending HTTP requests for metrics with the current metrics of `host`.
+    ///
+    /// Returns the number of requests answered. Does nothing if the exporter doesn't listen.
+    pub fn serve<T>(&mut self, host: &Host<T>) -> io::Result<usize> {
+        let mut streams = Vec::new();
+        if let Some(ref listener) = self.listener {
+            loop {
+                match listener.accept() {
+                    Ok((stream, _)) => streams.push(stream),
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        if streams.is_empty() {
+            return Ok(0);
+        }
+
+        let metrics = self.render(host);
+        let count = streams.len();
+        for stream in streams {
+            // A scraper that misbehaves only fails its own scrape.
+            let _ = respond(stream, &metrics);
+        }
+        Ok(count)
+    }
+
+    /// Writes the current metrics of `host` to `path`, for the node exporter's textfile collector.
+    ///
+    /// The file is replaced atomically, so the collector never reads a partial file.
+    pub fn write_textfile<T>(&mut self, host: &Host<T>, path: &Path) -> io::Result<()> {
+        let metrics = self.render(host);
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        fs::write(&temporary, metrics)?;
+        fs::rename(&temporary, path)
+    }
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Returns the time left until `deadline`, failing with `TimedOut` once it passed.
+fn remaining(deadline: Instant) -> io::Result<Duration> {
+    match deadline.saturating_duration_since(Instant::now()) {
+        Duration::ZERO => Err(io::ErrorKind::TimedOut.into()),
+        remaining => Ok(remaining),
+    }
+}
+
+fn respond(mut stream: TcpStream, metrics: &str) -> io::Result<()> {
+    // The whole exchange shares one deadline, so a slow scraper can't hold up the host for long.
+    let deadline = Instant::now() + REQUEST_TIMEOUT;
+    stream.set_nonblocking(false)?;
+
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") && request.len() < 8192 {
+        stream.set_read_timeout(Some(remaining(deadline)?))?;
+        match stream.read(&mut buffer)? {
+            0 => break,
+            len => request.extend_from_slice(&buffer[..len]),
+        }
+    }
+
+    let (status, body) = if request.starts_with(b"GET /metrics ") {
+        ("200 OK", metrics)
+    } else {
+        ("404 Not Found", "not found\n")
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let mut response = response.as_bytes();
+    while !response.is_empty() {
+        stream.set_write_timeout(Some(remaining(deadline)?))?;
+        match stream.write(response)? {
+            0 => return Err(io::ErrorKind::WriteZero.into()),
+            len => response = &response[len..],
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Counter, MetricsExporter};
+    use crate::tests::{create_host, pump_until};
+    use crate::{Address, EventKind};
+
+    use std::io::{Read, Write};
+    use std::net::{Ipv4Addr, TcpStream};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_counter_wraps() {
+        let mut counter = Counter::default();
+        assert_eq!(counter.update(u32::MAX - 1), u64::from(u32::MAX - 1));
+        assert_eq!(counter.update(3), u64::from(u32::MAX) + 4);
+    }
+
+    #[test]
+    fn test_serve_metrics() {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12371);
+        let mut server = create_host(Some(&server_address), 2);
+        let mut client = create_host(None, 1);
+        client.connect(&server_address, 1, 0).unwrap();
+
+        pump_until(&mut [&mut client, &mut server], |index, _, event| {
+            index == 1 && matches!(event.kind, EventKind::Connect)
+        });
+
+        let mut exporter = MetricsExporter::listen("127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut scrape = TcpStream::connect(exporter.local_addr().unwrap().unwrap()).unwrap();
+        scrape
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+
+        let mut answered = 0;
+        for _ in 0..100 {
+            answered += exporter.serve(&server).unwrap();
+            if answered > 0 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(answered, 1);
+
+        let mut response = String::new();
+        scrape.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\nenet_peer_slots 2\n"));
+        assert!(response.contains("\nenet_peers_connected 1\n"));
+        assert!(response.contains("# TYPE enet_sent_bytes_total counter\n"));
+        assert!(response.contains("# TYPE enet_channel_sent_bytes_total counter\n"));
+        assert!(response.contains("\nenet_peer_rtt_seconds{peer=\"0\",address=\"127.0.0.1:"));
+        assert!(response.contains("\nenet_peer_reassembly_timeouts_total{peer=\"0\","));
+    }
+    #[test]
+    fn test_slow_scrape_times_out() {
+        let host = create_host(None, 1);
+        let mut exporter = MetricsExporter::listen("127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut scrape = TcpStream::connect(exporter.local_addr().unwrap().unwrap()).unwrap();
+
+        // Trickles in the request slower than the deadline allows, a byte at a time.
+        let trickle = std::thread::spawn(move || {
+            for &byte in b"GET /metrics HTTP/1.1\r\n\r\n" {
+                if scrape.write_all(&[byte]).is_err() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(20));
+            }
+        });
+
+        std::thread::sleep(Duration::from_millis(10));
+        let start = Instant::now();
+        exporter.serve(&host).unwrap();
+        assert!(start.elapsed() < Duration::from_millis(300));
+        trickle.join().unwrap();
+    }
+}
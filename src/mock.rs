@@ -0,0 +1,359 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::host::next_host_id;
+use crate::{
+    Address, ConnectError, Error, Event, EventKind, HostLike, Packet, PacketMode, PeerID,
+    PeerState, SendError,
+};
+
+/// A packet a `MockHost` was asked to send.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SentPacket {
+    /// The peer the packet was sent to.
+    pub peer_id: PeerID,
+    /// The channel the packet was sent on.
+    pub channel_id: u8,
+    /// The contents of the packet.
+    pub data: Vec<u8>,
+    /// The mode the packet was sent with.
+    pub mode: PacketMode,
+}
+
+#[derive(Debug)]
+struct MockPeer<T> {
+    address: Address,
+    channel_count: usize,
+    state: PeerState,
+    data: Option<T>,
+}
+
+/// An in-memory `HostLike`, for testing application code without sockets or timers.
+///
+/// Nothing happens on its own: tests script what the remote side does with `add_peer`,
+/// `accept`, `receive` and `drop_peer`, and inspect what the application sent with `sent`.
+/// `service` returns the scripted events in order, and never waits. Like for a `Host`, the data
+/// of a peer is dropped on the `service` call after its disconnect.
+///
+/// Packets are still allocated by ENet, but an `Enet` instance is not needed.
+#[derive(Debug)]
+pub struct MockHost<T> {
+    id: usize,
+    channel_limit: usize,
+    peers: Vec<MockPeer<T>>,
+    events: VecDeque<Event>,
+    disconnect_drop: Option<PeerID>,
+    sent: Vec<SentPacket>,
+}
+
+impl<T> Default for MockHost<T> {
+    fn default() -> MockHost<T> {
+        MockHost::new()
+    }
+}
+
+impl<T> MockHost<T> {
+    /// Creates a mock host without any peers.
+    pub fn new() -> MockHost<T> {
+        MockHost {
+            id: next_host_id(),
+            channel_limit: enet_sys::ENET_PROTOCOL_MAXIMUM_CHANNEL_COUNT as usize,
+            peers: Vec::new(),
+            events: VecDeque::new(),
+            disconnect_drop: None,
+            sent: Vec::new(),
+        }
+    }
+
+    /// Adds a peer that connects from `address`, queueing its `Connect` event.
+    pub fn add_peer(&mut self, address: Address, channel_count: usize) -> PeerID {
+        let peer_id = self.push_peer(address, channel_count, PeerState::Connected);
+        self.push_event(peer_id, EventKind::Connect);
+        peer_id
+    }
+
+    /// Completes a connection initiated with `connect`, queueing its `Connect` event.
+    pub fn accept(&mut self, peer_id: PeerID) {
+        if self.set_state(peer_id, PeerState::Connecting, PeerState::Connected) {
+            self.push_event(peer_id, EventKind::Connect);
+        }
+    }
+
+    /// Fails a connection initiated with `connect`, queueing its `ConnectTimeout` event.
+    pub fn time_out(&mut self, peer_id: PeerID) {
+        if self.set_state(peer_id, PeerState::Connecting, PeerState::Zombie) {
+            self.push_event(peer_id, EventKind::ConnectTimeout);
+        }
+    }
+
+    /// Queues a packet received from `peer_id`.
+    pub fn receive(
+        &mut self,
+        peer_id: PeerID,
+        channel_id: u8,
+        data: &[u8],
+        mode: PacketMode,
+    ) -> Result<(), Error> {
+        let packet = Packet::new(data.to_vec(), mode)?;
+        self.push_event(peer_id, EventKind::Receive { channel_id, packet });
+        Ok(())
+    }
+
+    /// Disconnects `peer_id` from the remote side, queueing its `Disconnect` event.
+    pub fn drop_peer(&mut self, peer_id: PeerID, data: u32) {
+        if self.set_state(peer_id, PeerState::Connected, PeerState::Zombie) {
+            self.push_event(peer_id, EventKind::Disconnect { data });
+        }
+    }
+
+    /// Returns the state of `peer_id`.
+    pub fn peer_state(&self, peer_id: PeerID) -> Option<PeerState> {
+        self.peer(peer_id).map(|peer| peer.state)
+    }
+
+    /// Returns the packets sent so far.
+    pub fn sent(&self) -> &[SentPacket] {
+        &self.sent
+    }
+
+    /// Returns the packets sent so far, and forgets them.
+    pub fn take_sent(&mut self) -> Vec<SentPacket> {
+        std::mem::take(&mut self.sent)
+    }
+
+    fn peer(&self, peer_id: PeerID) -> Option<&MockPeer<T>> {
+        self.check_peer_id(peer_id);
+        self.peers.get(peer_id.index)
+    }
+
+    fn peer_mut(&mut self, peer_id: PeerID) -> Option<&mut MockPeer<T>> {
+        self.check_peer_id(peer_id);
+        self.peers.get_mut(peer_id.index)
+    }
+
+    fn check_peer_id(&self, peer_id: PeerID) {
+        debug_assert_eq!(
+            peer_id.host_id, self.id,
+            "{:?} was created by a different Host and is not valid for this one",
+            peer_id
+        );
+    }
+
+    fn push_peer(&mut self, address: Address, channel_count: usize, state: PeerState) -> PeerID {
+        let peer = MockPeer {
+            address,
+            channel_count,
+            state,
+            data: None,
+        };
+
+        // Reuse the slot of a peer that is gone, like ENet does.
+        let index = match self
+            .peers
+            .iter()
+            .position(|peer| peer.state == PeerState::Disconnected)
+        {
+            Some(index) => {
+                self.peers[index] = peer;
+                index
+            }
+            None => {
+                self.peers.push(peer);
+                self.peers.len() - 1
+            }
+        };
+
+        PeerID {
+            index,
+            host_id: self.id,
+        }
+    }
+
+    fn set_state(&mut self, peer_id: PeerID, from: PeerState, to: PeerState) -> bool {
+        match self.peer_mut(peer_id) {
+            Some(peer) if peer.state == from => {
+                peer.state = to;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn push_event(&mut self, peer_id: PeerID, kind: EventKind) {
+        self.events.push_back(Event { peer_id, kind });
+    }
+}
+
+impl<T> HostLike<T> for MockHost<T> {
+    fn service(&mut self, _timeout: Option<Duration>) -> Result<Option<Event>, Error> {
+        if let Some(peer_id) = self.disconnect_drop.take() {
+            if let Some(peer) = self.peer_mut(peer_id) {
+                peer.state = PeerState::Disconnected;
+                peer.data = None;
+            }
+        }
+
+        let event = self.events.pop_front();
+        if let Some(ref event) = event {
+            if event.kind.is_disconnect() {
+                self.disconnect_drop = Some(event.peer_id);
+            }
+        }
+        Ok(event)
+    }
+
+    fn connect(
+        &mut self,
+        address: &Address,
+        channel_count: usize,
+        _data: u32,
+    ) -> Result<PeerID, ConnectError> {
+        if channel_count > self.channel_limit {
+            return Err(ConnectError::ChannelCountExceedsLimit {
+                requested: channel_count,
+                limit: self.channel_limit,
+            });
+        }
+
+        Ok(self.push_peer(address.clone(), channel_count, PeerState::Connecting))
+    }
+
+    fn send(&mut self, peer_id: PeerID, channel_id: u8, packet: Packet) -> Result<(), SendError> {
+        let peer = match self.peer(peer_id) {
+            Some(peer) => peer,
+            None => return Err(SendError::NotConnected(PeerState::Disconnected)),
+        };
+        if peer.state != PeerState::Connected {
+            return Err(SendError::NotConnected(peer.state));
+        }
+        if usize::from(channel_id) >= peer.channel_count {
+            return Err(SendError::InvalidChannel(channel_id));
+        }
+
+        self.sent.push(SentPacket {
+            peer_id,
+            channel_id,
+            data: packet.data().to_vec(),
+            mode: packet.mode(),
+        });
+        Ok(())
+    }
+
+    fn broadcast(&mut self, channel_id: u8, data: &[u8], mode: PacketMode) -> usize {
+        self.connected_peers()
+            .into_iter()
+            .filter(|&peer_id| {
+                Packet::new(data.to_vec(), mode)
+                    .is_ok_and(|packet| self.send(peer_id, channel_id, packet).is_ok())
+            })
+            .count()
+    }
+
+    fn disconnect(&mut self, peer_id: PeerID, data: u32) {
+        let connected = self.set_state(peer_id, PeerState::Connected, PeerState::Disconnecting)
+            || self.set_state(peer_id, PeerState::Connecting, PeerState::Disconnecting);
+        if connected {
+            self.push_event(peer_id, EventKind::Disconnect { data });
+        }
+    }
+
+    fn connected_peers(&self) -> Vec<PeerID> {
+        (0..self.peers.len())
+            .filter(|&index| self.peers[index].state == PeerState::Connected)
+            .map(|index| PeerID {
+                index,
+                host_id: self.id,
+            })
+            .collect()
+    }
+
+    fn peer_address(&self, peer_id: PeerID) -> Option<Address> {
+        self.peer(peer_id).map(|peer| peer.address.clone())
+    }
+
+    fn peer_data(&self, peer_id: PeerID) -> Option<&T> {
+        self.peer(peer_id)?.data.as_ref()
+    }
+
+    fn set_peer_data(&mut self, peer_id: PeerID, data: Option<T>) {
+        if let Some(peer) = self.peer_mut(peer_id) {
+            peer.data = data;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MockHost, SentPacket};
+    use crate::{Address, EventKind, HostLike, PacketMode, PeerState};
+
+    use std::net::Ipv4Addr;
+
+    /// Application code under test: greets peers, and echoes their packets to everyone.
+    fn serve<H: HostLike<u32>>(host: &mut H) -> usize {
+        let mut events = 0;
+        while let Some(event) = host.service(None).unwrap() {
+            events += 1;
+            match event.kind {
+                EventKind::Connect => {
+                    host.set_peer_data(event.peer_id, Some(0));
+                    let greeting =
+                        crate::Packet::new(b"hi".to_vec(), PacketMode::ReliableSequenced);
+                    host.send(event.peer_id, 0, greeting.unwrap()).unwrap();
+                }
+                EventKind::Receive {
+                    channel_id,
+                    ref packet,
+                } => {
+                    let count = host.peer_data(event.peer_id).unwrap() + 1;
+                    host.set_peer_data(event.peer_id, Some(count));
+                    host.broadcast(channel_id, packet.data(), packet.mode());
+                }
+                _ => (),
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn test_mock_host() {
+        let mut host = MockHost::new();
+        let a = host.add_peer(Address::new(Ipv4Addr::LOCALHOST, 1), 2);
+        let b = host.add_peer(Address::new(Ipv4Addr::LOCALHOST, 2), 2);
+        host.receive(a, 1, b"move", PacketMode::UnreliableSequenced)
+            .unwrap();
+        assert_eq!(serve(&mut host), 3);
+
+        let sent = |peer_id, channel_id, data: &[u8], mode| SentPacket {
+            peer_id,
+            channel_id,
+            data: data.to_vec(),
+            mode,
+        };
+        assert_eq!(
+            host.take_sent(),
+            vec![
+                sent(a, 0, b"hi", PacketMode::ReliableSequenced),
+                sent(b, 0, b"hi", PacketMode::ReliableSequenced),
+                sent(a, 1, b"move", PacketMode::UnreliableSequenced),
+                sent(b, 1, b"move", PacketMode::UnreliableSequenced),
+            ]
+        );
+        assert_eq!(host.peer_data(a), Some(&1));
+
+        host.drop_peer(a, 7);
+        assert_eq!(serve(&mut host), 1);
+        assert_eq!(host.connected_peers(), vec![b]);
+        assert_eq!(host.peer_data(a), None);
+        assert_eq!(host.peer_state(a), Some(PeerState::Disconnected));
+
+        // The freed slot is reused for the next connection.
+        let c = host
+            .connect(&Address::new(Ipv4Addr::LOCALHOST, 3), 1, 0)
+            .unwrap();
+        assert_eq!(c, a);
+        host.accept(c);
+        assert_eq!(serve(&mut host), 1);
+        assert_eq!(host.connected_peers().len(), 2);
+    }
+}
@@ -0,0 +1,382 @@
+//! Path MTU discovery for peers.
+//!
+//! ENet sends datagrams of up to 1400 bytes, or the smaller MTU of both hosts. On paths that
+//! carry more, large packets are split into more fragments than necessary. On paths that carry
+//! less, e.g. through a tunnel, full datagrams are lost. An [MtuProber](struct.MtuProber.html)
+//! sends padded probes of different sizes to each peer on a reserved channel, searches for the
+//! largest one that arrives, and then sets the MTU of the peer to it with `Peer::set_mtu`.
+//!
+//! Both sides of a connection need to run an `MtuProber`, since it answers the probes of the other
+//! side. Each side only changes the MTU it sends with, so paths that differ by direction are fine.
+//!
+//! Probes only fail if the path drops them. Where the operating system fragments datagrams larger
+//! than the path itself, e.g. because the don't-fragment flag is not set on `Host::socket`, every
+//! probe arrives and the prober settles on `MtuProbeConfig::max`.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use enet_sys::{ENET_PROTOCOL_MAXIMUM_MTU, ENET_PROTOCOL_MINIMUM_MTU};
+
+use crate::{Event, EventKind, Host, Packet, PacketMode, PeerID, PeerState};
+
+/// Marks MTU probe packets.
+const MAGIC: &[u8] = b"\xffMTU";
+const KIND_PROBE: u8 = 0;
+const KIND_ACK: u8 = 1;
+/// Magic, kind and the probed size.
+const ACK_LEN: usize = 4 + 1 + 2;
+/// ENet's protocol header without the optional sent time, and the command of an unsequenced
+/// packet, which precede the data of a probe in its datagram. A datagram is never smaller than the
+/// size its probe claims.
+const HEADERS: u32 = 2 + 8;
+/// ENet splits packets that don't fit the MTU with the larger command of a fragment.
+const FRAGMENT_HEADERS: u32 = 4 + 24;
+
+/// How an `MtuProber` searches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MtuProbeConfig {
+    /// The MTU assumed to work without probing, and the result if no probe arrives.
+    pub min: u32,
+    /// The largest MTU probed, at most 4078 bytes, since ENet's buffers hold 4096 bytes and a probe
+    /// is sent with the larger headers of a fragment.
+    pub max: u32,
+    /// The search ends once the largest size that arrived and the smallest that did not are at
+    /// most this many bytes apart.
+    pub precision: u32,
+    /// How long to wait for the answer to a probe.
+    pub timeout: Duration,
+    /// How often a size is probed before it counts as too large, since probes may also be lost
+    /// for other reasons.
+    pub attempts: u32,
+}
+
+impl Default for MtuProbeConfig {
+    /// Searches between ENet's minimum MTU and the 1472 bytes of UDP data that fit into an
+    /// Ethernet frame.
+    fn default() -> MtuProbeConfig {
+        MtuProbeConfig {
+            min: ENET_PROTOCOL_MINIMUM_MTU,
+            max: 1472,
+            precision: 16,
+            timeout: Duration::from_millis(500),
+            attempts: 3,
+        }
+    }
+}
+
+/// The state of the search for the MTU of one peer.
+#[derive(Debug)]
+struct Search {
+    /// The largest size that arrived, or `MtuProbeConfig::min`.
+    low: u32,
+    /// The smallest size that did not arrive, or one more than `MtuProbeConfig::max`.
+    high: u32,
+    /// The size being probed, when it was last sent, and how often.
+    probe: Option<(u32, Instant, u32)>,
+    /// The MTU found, once the search ended.
+    found: Option<u32>,
+}
+
+fn encode_ack(size: u32) -> Vec<u8> {
+    let mut data = Vec::with_capacity(ACK_LEN);
+    data.extend_from_slice(MAGIC);
+    data.push(KIND_ACK);
+    data.extend_from_slice(&(size as u16).to_be_bytes());
+    data
+}
+
+fn encode_probe(size: u32) -> Vec<u8> {
+    let mut data = encode_ack(size);
+    data[MAGIC.len()] = KIND_PROBE;
+
+    // Padding that doesn't compress, in case the host has a compressor.
+    let mut state = 0x2545_f491_u32 ^ size;
+    while data.len() < (size - HEADERS) as usize {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        data.push(state as u8);
+    }
+    data
+}
+
+fn decode(data: &[u8]) -> Option<(u8, u32)> {
+    if data.len() < ACK_LEN || !data.starts_with(MAGIC) {
+        return None;
+    }
+
+    let size = u32::from(u16::from_be_bytes([data[5], data[6]]));
+    match data[4] {
+        // A probe that doesn't have the size it claims is of no use.
+        KIND_PROBE if data.len() as u32 + HEADERS == size => Some((KIND_PROBE, size)),
+        KIND_ACK if data.len() == ACK_LEN => Some((KIND_ACK, size)),
+        _ => None,
+    }
+}
+
+/// Discovers the path MTU to each peer of a `Host`, and adjusts the MTU of the peer to it.
+#[derive(Debug)]
+pub struct MtuProber {
+    channel_id: u8,
+    config: MtuProbeConfig,
+    peers: HashMap<PeerID, Search>,
+}
+
+impl MtuProber {
+    /// Creates a prober that probes on `channel_id`, which it uses exclusively.
+    pub fn new(channel_id: u8, config: MtuProbeConfig) -> MtuProber {
+        let max = config
+            .max
+            .min(ENET_PROTOCOL_MAXIMUM_MTU + HEADERS - FRAGMENT_HEADERS);
+        let min = config.min.clamp(ENET_PROTOCOL_MINIMUM_MTU, max);
+        MtuProber {
+            channel_id,
+            config: MtuProbeConfig {
+                min,
+                max,
+                precision: config.precision.max(1),
+                ..config
+            },
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Returns the MTU found for `peer_id`, once the search for it ended.
+    pub fn mtu(&self, peer_id: PeerID) -> Option<u32> {
+        self.peers.get(&peer_id)?.found
+    }
+
+    /// Returns whether the MTU of `peer_id` is still being searched for.
+    pub fn is_probing(&self, peer_id: PeerID) -> bool {
+        self.peers
+            .get(&peer_id)
+            .is_some_and(|search| search.found.is_none())
+    }
+
+    /// Searches for the MTU of `peer_id` again, e.g. after its address changed.
+    pub fn restart(&mut self, peer_id: PeerID) {
+        self.peers.insert(peer_id, self.search());
+    }
+
+    fn search(&self) -> Search {
+        Search {
+            low: self.config.min,
+            high: self.config.max + 1,
+            probe: None,
+            found: None,
+        }
+    }
+
+    /// Processes an event returned by `Host::service`.
+    ///
+    /// Returns `true` if the event was a probe or the answer to one, which needs no further
+    /// handling. The MTU of connected peers is searched for from then on, and disconnected peers
+    /// are forgotten.
+    pub fn handle_event<T>(&mut self, host: &mut Host<T>, event: &Event) -> bool {
+        match event.kind {
+            EventKind::Connect => {
+                self.restart(event.peer_id);
+                false
+            }
+            EventKind::Receive {
+                channel_id,
+                ref packet,
+            } if channel_id == self.channel_id => {
+                match decode(packet.data()) {
+                    Some((KIND_PROBE, size)) => {
+                        let ack = Packet::new(encode_ack(size), PacketMode::UnreliableUnsequenced);
+                        if let (Ok(ack), Some(peer)) = (ack, host.peer_mut(event.peer_id)) {
+                            // A lost answer only costs the other side another attempt.
+                            let _ = peer.send_packet(ack, self.channel_id);
+                        }
+                    }
+                    Some((KIND_ACK, size)) => self.arrived(event.peer_id, size),
+                    _ => (),
+                }
+                true
+            }
+            ref kind if kind.is_disconnect() => {
+                self.peers.remove(&event.peer_id);
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn arrived(&mut self, peer_id: PeerID, size: u32) {
+        let search = match self.peers.get_mut(&peer_id) {
+            Some(search) if search.found.is_none() => search,
+            _ => return,
+        };
+
+        // Answers can arrive after their probe was given up on, they still count.
+        search.low = search.low.max(size);
+        search.high = search.high.max(search.low + 1);
+        if search
+            .probe
+            .is_some_and(|(probe, _, _)| probe <= search.low)
+        {
+            search.probe = None;
+        }
+    }
+
+    /// Sends the probes that are due, and sets the MTU of the peers whose search ended.
+    ///
+    /// Call this regularly, e.g. after every `Host::service`. Flushes `host` before and after
+    /// sending probes, since ENet only sends datagrams as large as a probe while it is queued.
+    pub fn poll<T>(&mut self, host: &mut Host<T>) {
+        let now = Instant::now();
+        let config = self.config;
+        let mut due = Vec::new();
+        for (&peer_id, search) in &mut self.peers {
+            if search.found.is_some() {
+                continue;
+            }
+
+            let attempt = match search.probe {
+                Some((_, sent, _)) if now < sent + config.timeout => continue,
+                Some((size, _, attempts)) if attempts < config.attempts => (size, attempts + 1),
+                Some((size, _, _)) => {
+                    search.high = size;
+                    search.probe = None;
+                    continue;
+                }
+                None if search.high - search.low <= config.precision => {
+                    search.found = Some(search.low);
+                    if let Some(peer) = host.peer_mut(peer_id) {
+                        peer.set_mtu(search.low);
+                    }
+                    continue;
+                }
+                // The largest size is tried first, it is the one that most often works.
+                None if search.high > config.max => (config.max, 1),
+                None => (search.low + (search.high - search.low) / 2, 1),
+            };
+            search.probe = Some((attempt.0, now, attempt.1));
+            due.push((peer_id, attempt.0));
+        }
+
+        if due.is_empty() {
+            return;
+        }
+
+        // Everything else is sent with the MTU of its peer first.
+        host.flush();
+        let mut restore = Vec::new();
+        for (peer_id, size) in due {
+            let peer = match host.peer_mut(peer_id) {
+                Some(peer) if peer.state() == PeerState::Connected => peer,
+                _ => continue,
+            };
+            let packet = match Packet::new(encode_probe(size), PacketMode::UnreliableUnsequenced) {
+                Ok(packet) => packet,
+                Err(_) => continue,
+            };
+
+            // ENet splits packets when they are queued, and only fills datagrams up to the MTU of
+            // the peer when they are sent. The probe needs to pass both in one piece.
+            let mtu = peer.mtu();
+            unsafe {
+                (*peer.as_raw_mut()).mtu =
+                    (size - HEADERS + FRAGMENT_HEADERS).min(ENET_PROTOCOL_MAXIMUM_MTU);
+            }
+            // A probe that can't be queued is retried like a lost one.
+            let _ = peer.send_packet(packet, self.channel_id);
+            restore.push((peer_id, mtu));
+        }
+        host.flush();
+        for (peer_id, mtu) in restore {
+            if let Some(peer) = host.peer_mut(peer_id) {
+                unsafe {
+                    (*peer.as_raw_mut()).mtu = mtu;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode_probe, MtuProbeConfig, MtuProber, HEADERS, KIND_PROBE};
+    use crate::tests::create_host;
+    use crate::{Address, Datagram, EventKind, InterceptAction};
+
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    #[test]
+    fn test_probe_encoding() {
+        let probe = encode_probe(1200);
+        assert_eq!(probe.len() as u32 + HEADERS, 1200);
+        assert_eq!(decode(&probe), Some((KIND_PROBE, 1200)));
+        assert_eq!(decode(&probe[..1000]), None);
+        assert_eq!(decode(b"\xffMT"), None);
+    }
+
+    #[test]
+    fn test_max_fits_enet_buffers() {
+        let config = MtuProbeConfig {
+            max: 4096,
+            ..MtuProbeConfig::default()
+        };
+        assert_eq!(MtuProber::new(0, config).config.max, 4078);
+    }
+
+    #[test]
+    fn test_discover_mtu() {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12416);
+        let mut server = create_host(Some(&server_address), 1);
+        let mut client = create_host(None, 1);
+
+        // The path from the client to the server carries at most 1300 bytes.
+        server.add_intercept(|datagram: &mut Datagram| {
+            if datagram.data().len() > 1300 {
+                InterceptAction::Drop
+            } else {
+                InterceptAction::Continue
+            }
+        });
+
+        let config = MtuProbeConfig {
+            max: 2000,
+            timeout: Duration::from_millis(50),
+            attempts: 2,
+            ..MtuProbeConfig::default()
+        };
+        let mut client_prober = MtuProber::new(1, config);
+        let mut server_prober = MtuProber::new(1, config);
+
+        let (_, peer_id) = client.connect(&server_address, 2, 0).unwrap();
+        let mut server_peer_id = None;
+        let timeout = Some(Duration::from_millis(2));
+        for _ in 0..2000 {
+            if let Some(event) = client.service(timeout).unwrap() {
+                client_prober.handle_event(&mut client, &event);
+            }
+            if let Some(event) = server.service(timeout).unwrap() {
+                if let EventKind::Connect = event.kind {
+                    server_peer_id = Some(event.peer_id);
+                }
+                server_prober.handle_event(&mut server, &event);
+            }
+            client_prober.poll(&mut client);
+            server_prober.poll(&mut server);
+
+            let server_done = server_peer_id.is_some_and(|id| server_prober.mtu(id).is_some());
+            if client_prober.mtu(peer_id).is_some() && server_done {
+                break;
+            }
+        }
+
+        let mtu = client_prober.mtu(peer_id).unwrap();
+        assert!(mtu <= 1300 && mtu > 1300 - 16, "found {}", mtu);
+        assert_eq!(client[peer_id].mtu(), mtu);
+        assert!(!client_prober.is_probing(peer_id));
+
+        // The other direction carries every size.
+        let server_peer_id = server_peer_id.unwrap();
+        assert_eq!(server_prober.mtu(server_peer_id), Some(2000));
+        assert_eq!(server[server_peer_id].mtu(), 2000);
+    }
+}
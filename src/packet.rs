@@ -1,6 +1,7 @@
 use enet_sys::{
     enet_packet_create, enet_packet_destroy, ENetPacket,
     _ENetPacketFlag_ENET_PACKET_FLAG_NO_ALLOCATE, _ENetPacketFlag_ENET_PACKET_FLAG_RELIABLE,
+    _ENetPacketFlag_ENET_PACKET_FLAG_UNRELIABLE_FRAGMENT,
     _ENetPacketFlag_ENET_PACKET_FLAG_UNSEQUENCED,
 };
 
@@ -60,11 +61,28 @@ impl Packet {
     ///
     /// The data is consumed and moved into the enet package without copy.
     pub fn new(data: Vec<u8>, mode: PacketMode) -> Result<Packet, Error> {
+        Packet::with_flags(data, mode.to_sys_flags())
+    }
+
+    /// Creates a packet like `new`, that is sent in unreliable fragments if it is larger than the
+    /// MTU and `mode` is unreliable.
+    pub(crate) fn new_unreliable_fragment(
+        data: Vec<u8>,
+        mode: PacketMode,
+    ) -> Result<Packet, Error> {
+        let flags = match mode {
+            PacketMode::ReliableSequenced => mode.to_sys_flags(),
+            _ => mode.to_sys_flags() | _ENetPacketFlag_ENET_PACKET_FLAG_UNRELIABLE_FRAGMENT as u32,
+        };
+        Packet::with_flags(data, flags)
+    }
+
+    fn with_flags(data: Vec<u8>, flags: u32) -> Result<Packet, Error> {
         let res = unsafe {
             enet_packet_create(
                 data.as_ptr() as *const _,
                 data.len(),
-                mode.to_sys_flags() | _ENetPacketFlag_ENET_PACKET_FLAG_NO_ALLOCATE,
+                flags | _ENetPacketFlag_ENET_PACKET_FLAG_NO_ALLOCATE,
             )
         };
 
@@ -95,6 +113,18 @@ impl Packet {
         res
     }
 
+    /// Returns the mode this packet was created or received with.
+    pub fn mode(&self) -> PacketMode {
+        let flags = unsafe { (*self.inner).flags };
+        if flags & _ENetPacketFlag_ENET_PACKET_FLAG_RELIABLE as u32 != 0 {
+            PacketMode::ReliableSequenced
+        } else if flags & _ENetPacketFlag_ENET_PACKET_FLAG_UNSEQUENCED as u32 != 0 {
+            PacketMode::UnreliableUnsequenced
+        } else {
+            PacketMode::UnreliableSequenced
+        }
+    }
+
     /// Returns a reference to the bytes inside this packet.
     pub fn data<'a>(&'a self) -> &'a [u8] {
         unsafe { std::slice::from_raw_parts((*self.inner).data, (*self.inner).dataLength) }
@@ -1,11 +1,17 @@
-use std::fmt::{self, Debug, Formatter};
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Display, Formatter};
 use std::marker::PhantomData;
-use std::time::Duration;
+use std::mem::ManuallyDrop;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use enet_sys::{
-    enet_peer_disconnect, enet_peer_disconnect_later, enet_peer_disconnect_now, enet_peer_receive,
-    enet_peer_reset, enet_peer_send, ENetPeer, _ENetPeerState,
-    _ENetPeerState_ENET_PEER_STATE_ACKNOWLEDGING_CONNECT,
+    enet_free, enet_list_remove, enet_list_size, enet_packet_destroy, enet_peer_disconnect,
+    enet_peer_disconnect_later, enet_peer_disconnect_now, enet_peer_receive, enet_peer_reset,
+    enet_peer_send, enet_peer_throttle_configure, enet_peer_timeout, ENetListNode,
+    ENetOutgoingCommand, ENetPacket, ENetPeer, ENET_PEER_PACKET_LOSS_SCALE,
+    ENET_PEER_PACKET_THROTTLE_SCALE, ENET_PROTOCOL_MAXIMUM_MTU, ENET_PROTOCOL_MINIMUM_MTU,
+    _ENetPeerState, _ENetPeerState_ENET_PEER_STATE_ACKNOWLEDGING_CONNECT,
     _ENetPeerState_ENET_PEER_STATE_ACKNOWLEDGING_DISCONNECT,
     _ENetPeerState_ENET_PEER_STATE_CONNECTED, _ENetPeerState_ENET_PEER_STATE_CONNECTING,
     _ENetPeerState_ENET_PEER_STATE_CONNECTION_PENDING,
@@ -14,7 +20,45 @@ use enet_sys::{
     _ENetPeerState_ENET_PEER_STATE_DISCONNECT_LATER, _ENetPeerState_ENET_PEER_STATE_ZOMBIE,
 };
 
-use crate::{Address, Error, Packet};
+use crate::budget::Budget;
+use crate::latency::LatencyHistory;
+use crate::logging::{self, log_debug, log_info};
+use crate::rate_limit::Bucket;
+use crate::{
+    Address, BudgetUsage, Channel, ChannelConfig, ChannelTraffic, EnetTime, FragmentStats,
+    LatencyStats, Packet, PacketMode, PeerTimeout, QueueLimit, QueuePolicy, RateLimit, SendError,
+};
+
+/// What this crate keeps for each peer, behind the `data` field of its `ENetPeer`.
+struct PeerData<T> {
+    data: Option<T>,
+    latency: LatencyHistory,
+    rates: HashMap<u8, Bucket>,
+    budgets: HashMap<u8, Budget>,
+    queue_limit: Option<QueueLimit>,
+    /// The bytes ENet queues, counted once and then kept up to date by `send_raw` until the host
+    /// sends again, so the queue limit doesn't walk ENet's queues for every packet.
+    queued_bytes: Option<usize>,
+    channel_configs: Option<Arc<[ChannelConfig]>>,
+    traffic: Vec<ChannelTraffic>,
+    fragments: FragmentStats,
+}
+
+impl<T> Default for PeerData<T> {
+    fn default() -> PeerData<T> {
+        PeerData {
+            data: None,
+            latency: LatencyHistory::default(),
+            rates: HashMap::new(),
+            budgets: HashMap::new(),
+            queue_limit: None,
+            queued_bytes: None,
+            channel_configs: None,
+            traffic: Vec::new(),
+            fragments: FragmentStats::default(),
+        }
+    }
+}
 
 /// This struct represents an endpoint in an ENet-connection.
 ///
@@ -53,6 +97,19 @@ where
         unsafe { &mut *(inner as *mut _ as *mut Peer<T>) }
     }
 
+    /// Returns the underlying `ENetPeer`, for functionality this wrapper does not cover yet.
+    ///
+    /// The pointer is valid as long as this `Peer` is borrowed. The `data` field holds the
+    /// associated data of type `T` and the latency history, it must not be changed through the pointer.
+    pub fn as_raw(&self) -> *const ENetPeer {
+        &self.inner
+    }
+
+    /// Returns the underlying `ENetPeer` for modification, see `Peer::as_raw`.
+    pub fn as_raw_mut(&mut self) -> *mut ENetPeer {
+        &mut self.inner
+    }
+
     /// Returns the address of this `Peer`.
     pub fn address(&self) -> Address {
         Address::from_enet_address(&self.inner.address)
@@ -63,49 +120,169 @@ where
         self.inner.channelCount
     }
 
-    /// Returns a reference to the data associated with this `Peer`, if set.
-    pub fn data(&self) -> Option<&T> {
-        unsafe {
-            let raw_data = self.inner.data as *const T;
+    fn state_ref(&self) -> Option<&PeerData<T>> {
+        unsafe { (self.inner.data as *const PeerData<T>).as_ref() }
+    }
 
-            if raw_data.is_null() {
-                None
-            } else {
-                Some(&(*raw_data))
-            }
+    fn state_mut(&mut self) -> &mut PeerData<T> {
+        if self.inner.data.is_null() {
+            let state: Box<PeerData<T>> = Box::default();
+            self.inner.data = Box::into_raw(state) as *mut _;
+        }
+
+        unsafe { &mut *(self.inner.data as *mut PeerData<T>) }
+    }
+
+    /// Frees the associated data and the latency history, once the connection is over.
+    /// Returns whether anything is stored for this `Peer`, e.g. its data or traffic.
+    pub(crate) fn has_state(&self) -> bool {
+        !self.inner.data.is_null()
+    }
+
+    pub(crate) fn clear_state(&mut self) {
+        let raw_state = self.inner.data as *mut PeerData<T>;
+        if !raw_state.is_null() {
+            let _: Box<PeerData<T>> = unsafe { Box::from_raw(raw_state) };
+            self.inner.data = std::ptr::null_mut();
         }
     }
 
+    /// Returns a reference to the data associated with this `Peer`, if set.
+    pub fn data(&self) -> Option<&T> {
+        self.state_ref().and_then(|state| state.data.as_ref())
+    }
+
     /// Returns a mutable reference to the data associated with this `Peer`, if set.
     pub fn data_mut(&mut self) -> Option<&mut T> {
-        unsafe {
-            let raw_data = self.inner.data as *mut T;
-
-            if raw_data.is_null() {
-                None
-            } else {
-                Some(&mut (*raw_data))
-            }
+        if self.inner.data.is_null() {
+            return None;
         }
+
+        self.state_mut().data.as_mut()
     }
 
     /// Sets or clears the data associated with this `Peer`, replacing existing data.
     pub fn set_data(&mut self, data: Option<T>) {
-        unsafe {
-            let raw_data = self.inner.data as *mut T;
+        if data.is_none() && self.inner.data.is_null() {
+            return;
+        }
+
+        self.state_mut().data = data;
+    }
+
+    /// Takes the data associated with this `Peer` out, leaving it unset.
+    pub fn take_data(&mut self) -> Option<T> {
+        if self.inner.data.is_null() {
+            return None;
+        }
+
+        self.state_mut().data.take()
+    }
+
+    /// Returns the data associated with this `Peer` as an entry, e.g. to set it up on first use,
+    /// see `Host::peer_data_entry`.
+    pub fn data_entry(&mut self) -> PeerDataEntry<'_, T> {
+        PeerDataEntry { peer: self }
+    }
+
+    /// Returns statistics over the round trip times sampled within the last `window`.
+    ///
+    /// The round trip time is sampled every 500ms while the `Host` is serviced, and kept for as
+    /// long as configured with `Host::set_latency_history`. Returns `None` if there are no samples
+    /// within `window` yet.
+    pub fn latency_stats(&self, window: Duration) -> Option<LatencyStats> {
+        self.state_ref()?.latency.stats(window)
+    }
 
-            if !raw_data.is_null() {
-                // free old data
-                let _: Box<T> = Box::from_raw(raw_data);
+    /// Limits the bytes sent to this `Peer` on `channel_id` per second, or lifts the limit with
+    /// `None`.
+    ///
+    /// Each second starts with the full budget. Sends that exceed what is left of it fail with
+    /// `SendError::OverBudget`, so bulk data on one channel can't crowd out the others. Changing
+    /// the budget keeps what was used of it in the current second.
+    pub fn set_channel_budget(&mut self, channel_id: u8, bytes_per_second: Option<u32>) {
+        let budgets = &mut self.state_mut().budgets;
+        match bytes_per_second {
+            Some(bytes_per_second) => budgets
+                .entry(channel_id)
+                .or_insert_with(|| Budget::new(bytes_per_second, Instant::now()))
+                .set_bytes_per_second(bytes_per_second),
+            None => {
+                budgets.remove(&channel_id);
             }
+        }
+    }
 
-            let new_data = match data {
-                Some(data) => Box::into_raw(Box::new(data)) as *mut _,
-                None => std::ptr::null_mut(),
-            };
+    /// Returns how much of the budget of `channel_id` is used, and how much pressure it is under.
+    ///
+    /// Returns `None` if the channel has no budget.
+    pub fn budget_usage(&mut self, channel_id: u8) -> Option<BudgetUsage> {
+        if self.inner.data.is_null() {
+            return None;
+        }
+
+        let budget = self.state_mut().budgets.get_mut(&channel_id)?;
+        Some(budget.usage(Instant::now()))
+    }
+
+    /// Returns whether a packet of `len` bytes on `channel_id` is within `limit`.
+    pub(crate) fn admit_rate(
+        &mut self,
+        channel_id: u8,
+        limit: &RateLimit,
+        now: Instant,
+        len: usize,
+    ) -> bool {
+        self.state_mut()
+            .rates
+            .entry(channel_id)
+            .or_insert_with(|| Bucket::new(limit, now))
+            .admit(limit, now, len)
+    }
+
+    /// Returns the packets sent to and received from this `Peer` on each channel, indexed by
+    /// channel, see `Host::channel_traffic`.
+    ///
+    /// Channels that carried nothing yet may be missing at the end.
+    pub fn channel_traffic(&self) -> &[ChannelTraffic] {
+        self.state_ref().map_or(&[], |state| &state.traffic)
+    }
 
-            self.inner.data = new_data;
+    fn traffic_mut(&mut self, channel_id: u8) -> &mut ChannelTraffic {
+        let traffic = &mut self.state_mut().traffic;
+        let index = usize::from(channel_id);
+        if traffic.len() <= index {
+            traffic.resize(index + 1, ChannelTraffic::default());
         }
+        &mut traffic[index]
+    }
+
+    pub(crate) fn record_received(&mut self, channel_id: u8, bytes: usize) {
+        self.traffic_mut(channel_id).record_received(bytes);
+    }
+
+    /// Returns how the packets sent to this `Peer` were split into fragments, see
+    /// `Host::fragment_stats` for the incoming ones.
+    pub(crate) fn outgoing_fragments(&self) -> FragmentStats {
+        self.state_ref()
+            .map_or_else(FragmentStats::default, |state| state.fragments)
+    }
+
+    pub(crate) fn record_latency(&mut self, now: Instant, history: Duration) {
+        let rtt = self.inner.roundTripTime;
+        self.state_mut().latency.record(now, rtt, history);
+    }
+
+    /// Returns the id ENet chose for the current connection of this `Peer`.
+    ///
+    /// Unlike the `PeerID`, which is reused for later connections, this identifies a single connection.
+    pub fn connect_id(&self) -> u32 {
+        self.inner.connectID
+    }
+
+    /// Returns the data of the last event of this `Peer`, e.g. the data a remote host connected with.
+    pub(crate) fn event_data(&self) -> u32 {
+        self.inner.eventData
     }
 
     /// Returns the downstream bandwidth of this `Peer` in bytes/second.
@@ -123,9 +300,283 @@ where
         Duration::from_millis(self.inner.roundTripTime as u64)
     }
 
+    /// Returns when ENet gives up on this `Peer`, if it doesn't acknowledge reliable packets.
+    pub fn timeout(&self) -> PeerTimeout {
+        PeerTimeout {
+            limit: self.inner.timeoutLimit,
+            minimum: Duration::from_millis(u64::from(self.inner.timeoutMinimum)),
+            maximum: Duration::from_millis(u64::from(self.inner.timeoutMaximum)),
+        }
+    }
+
+    /// Changes when ENet gives up on this `Peer`, if it doesn't acknowledge reliable packets.
+    ///
+    /// ENet's defaults are a limit of 32, a minimum of 5 seconds and a maximum of 30 seconds.
+    pub fn set_timeout(&mut self, timeout: PeerTimeout) {
+        let millis = |duration: Duration| duration.as_millis().min(u128::from(u32::MAX)) as u32;
+        unsafe {
+            enet_peer_timeout(
+                &mut self.inner as *mut _,
+                timeout.limit,
+                millis(timeout.minimum),
+                millis(timeout.maximum),
+            );
+        }
+    }
+
+    /// Returns the time at which ENet last sent something to this `Peer`.
+    pub fn last_send_time(&self) -> EnetTime {
+        EnetTime::from_millis(self.inner.lastSendTime)
+    }
+
+    /// Returns the time at which ENet last received something from this `Peer`.
+    pub fn last_receive_time(&self) -> EnetTime {
+        EnetTime::from_millis(self.inner.lastReceiveTime)
+    }
+
+    /// Returns the number of reliable commands queued for this `Peer` that were not sent yet.
+    ///
+    /// Packets larger than the MTU are split into several commands. A growing queue means packets
+    /// are sent faster than the connection can carry them.
+    pub fn queued_reliable_commands(&self) -> usize {
+        unsafe { enet_list_size(&self.inner.outgoingReliableCommands as *const _ as *mut _) }
+    }
+
+    /// Returns the number of unreliable commands queued for this `Peer` that were not sent yet.
+    pub fn queued_unreliable_commands(&self) -> usize {
+        unsafe { enet_list_size(&self.inner.outgoingUnreliableCommands as *const _ as *mut _) }
+    }
+
+    /// Returns the number of bytes queued for this `Peer` that were not sent yet.
+    ///
+    /// This walks ENet's queues, so it takes longer the more is queued.
+    pub fn queued_bytes(&self) -> usize {
+        let queues = [
+            &self.inner.outgoingReliableCommands,
+            &self.inner.outgoingUnreliableCommands,
+        ];
+        let mut bytes = 0;
+        for queue in queues {
+            let sentinel = &queue.sentinel as *const ENetListNode;
+            let mut node = queue.sentinel.next as *const ENetListNode;
+            // Every command starts with its node in the list.
+            while node != sentinel {
+                let command = unsafe { &*(node as *const ENetOutgoingCommand) };
+                bytes += usize::from(command.fragmentLength);
+                node = command.outgoingCommandList.next;
+            }
+        }
+        bytes
+    }
+
+    /// Forgets the bytes counted for the queue limit, once ENet sent or dropped some of them.
+    pub(crate) fn forget_queued_bytes(&mut self) {
+        if let Some(state) = unsafe { (self.inner.data as *mut PeerData<T>).as_mut() } {
+            state.queued_bytes = None;
+        }
+    }
+
+    /// Removes the unreliable packets queued for this `Peer` on `channel_id` that were not sent
+    /// yet, e.g. older snapshots that a newer one superseded. Only removes packets with `mode`,
+    /// or with any unreliable mode if `mode` is `None`.
+    ///
+    /// Reliable packets stay queued, ENet already numbered them and the peer would wait for the
+    /// missing ones forever. Packets held back by a `PrioritySender` are removed by
+    /// `PrioritySender::clear`. Returns the number of commands removed, see
+    /// `queued_unreliable_commands`.
+    pub fn clear_outgoing(&mut self, channel_id: u8, mode: Option<PacketMode>) -> usize {
+        if mode.is_some_and(|mode| mode.is_reliable()) {
+            return 0;
+        }
+
+        let sentinel = &mut self.inner.outgoingUnreliableCommands.sentinel as *mut ENetListNode;
+        let mut node = unsafe { (*sentinel).next };
+        let mut removed = 0;
+        while node != sentinel {
+            let command = node as *mut ENetOutgoingCommand;
+            let next = unsafe { (*node).next };
+            // Commands without a packet, such as a disconnect on channel 0xFF, are left alone.
+            let (channel, packet) =
+                unsafe { ((*command).command.header.channelID, (*command).packet) };
+            if channel != channel_id || packet.is_null() {
+                node = next;
+                continue;
+            }
+            let packet_mode = ManuallyDrop::new(Packet::from_sys_packet(packet)).mode();
+            if mode.map_or(true, |mode| mode == packet_mode) {
+                unsafe {
+                    enet_list_remove(node);
+                    (*packet).referenceCount -= 1;
+                    if (*packet).referenceCount == 0 {
+                        enet_packet_destroy(packet);
+                    }
+                    enet_free(command as *mut _);
+                }
+                removed += 1;
+            }
+            node = next;
+        }
+        if removed > 0 {
+            self.forget_queued_bytes();
+        }
+        removed
+    }
+
+    /// Returns when ENet next has to service this `Peer` on its own, to resend an unacknowledged
+    /// reliable packet or to send a ping. `None` if the peer is disconnected.
+    pub fn next_timer(&self) -> Option<EnetTime> {
+        if self.state() == PeerState::Disconnected {
+            return None;
+        }
+        let ping = EnetTime::from_millis(
+            self.inner
+                .lastReceiveTime
+                .wrapping_add(self.inner.pingInterval),
+        );
+        let sent = &self.inner.sentReliableCommands;
+        if std::ptr::eq(sent.sentinel.next, &sent.sentinel) {
+            return Some(ping);
+        }
+        let resend = EnetTime::from_millis(self.inner.nextTimeout);
+        Some(if resend.is_before(ping) { resend } else { ping })
+    }
+
+    fn oldest_sent_reliable(&self) -> Option<&ENetOutgoingCommand> {
+        let sent = &self.inner.sentReliableCommands;
+        if std::ptr::eq(sent.sentinel.next, &sent.sentinel) {
+            return None;
+        }
+        // Commands are appended as they are sent, and every command starts with its node.
+        Some(unsafe { &*(sent.sentinel.next as *const ENetOutgoingCommand) })
+    }
+
+    /// Returns how long ENet waits for the acknowledgement of the oldest reliable command in
+    /// transit to this `Peer`, before resending it. `None` if nothing is in transit.
+    ///
+    /// The timeout starts at the round trip time plus four times its variance, and doubles with
+    /// every resend, so a growing value means the peer is stalling.
+    pub fn retransmit_timeout(&self) -> Option<Duration> {
+        let command = self.oldest_sent_reliable()?;
+        Some(Duration::from_millis(u64::from(command.roundTripTimeout)))
+    }
+
+    /// Returns when the oldest reliable command in transit to this `Peer` was last sent. `None`
+    /// if nothing is in transit.
+    pub fn oldest_unacknowledged(&self) -> Option<EnetTime> {
+        let command = self.oldest_sent_reliable()?;
+        Some(EnetTime::from_millis(command.sentTime))
+    }
+
+    /// Returns when ENet first had to resend a reliable command since this `Peer` last
+    /// acknowledged one, or `None` if it didn't have to.
+    ///
+    /// ENet disconnects the peer once the maximum of its `PeerTimeout` passed since then, or once
+    /// the minimum passed and `Peer::retransmit_timeout` grew to `limit` times its initial value.
+    pub fn timeout_started(&self) -> Option<EnetTime> {
+        match self.inner.earliestTimeout {
+            0 => None,
+            time => Some(EnetTime::from_millis(time)),
+        }
+    }
+
+    /// Caps the bytes ENet queues for this `Peer`, or lifts the cap with `None`.
+    ///
+    /// Sends that would exceed the cap are handled by its `QueuePolicy`.
+    pub fn set_queue_limit(&mut self, limit: Option<QueueLimit>) {
+        if limit.is_none() && self.inner.data.is_null() {
+            return;
+        }
+
+        self.state_mut().queue_limit = limit;
+    }
+
+    /// Returns the number of bytes sent reliably to this `Peer` that were not acknowledged yet.
+    pub fn reliable_data_in_transit(&self) -> u32 {
+        self.inner.reliableDataInTransit
+    }
+
+    /// Returns the share of reliable packets to this `Peer` that ENet had to resend recently, from 0 to 1.
+    pub fn packet_loss(&self) -> f32 {
+        self.inner.packetLoss as f32 / ENET_PEER_PACKET_LOSS_SCALE as f32
+    }
+
+    /// Returns ENet's throttle for this `Peer`, the share of unreliable packets it lets through, from 0 to 1.
+    ///
+    /// ENet lowers the throttle when round trip times rise above their mean, i.e. when the link is congested.
+    pub fn packet_throttle(&self) -> f32 {
+        self.inner.packetThrottle as f32 / ENET_PEER_PACKET_THROTTLE_SCALE as f32
+    }
+
+    /// Changes how ENet adapts the packet throttle of this `Peer`, see `Peer::packet_throttle`.
+    ///
+    /// Every `interval`, ENet raises the throttle by `acceleration` if round trip times were low,
+    /// and lowers it by `deceleration` if they were high, both out of 32. ENet's defaults are an
+    /// interval of 5 seconds, and an acceleration and deceleration of 2.
+    pub fn configure_throttle(&mut self, interval: Duration, acceleration: u32, deceleration: u32) {
+        let interval = interval.as_millis().min(u128::from(u32::MAX)) as u32;
+        unsafe {
+            enet_peer_throttle_configure(
+                &mut self.inner as *mut _,
+                interval,
+                acceleration,
+                deceleration,
+            );
+        }
+    }
+
+    /// Returns the MTU of the connection to this `Peer`, the size of the largest datagram ENet
+    /// sends to it.
+    pub fn mtu(&self) -> u32 {
+        self.inner.mtu
+    }
+
+    /// Changes the MTU of the connection to this `Peer`, clamped to the 576 to 4096 bytes ENet
+    /// supports.
+    ///
+    /// ENet agrees on the smaller MTU of both hosts when connecting, which may be too small or too
+    /// large for the path, see the `mtu` module. Only packets sent afterwards are split to fit it.
+    pub fn set_mtu(&mut self, mtu: u32) {
+        self.inner.mtu = mtu.clamp(ENET_PROTOCOL_MINIMUM_MTU, ENET_PROTOCOL_MAXIMUM_MTU);
+    }
+
+    /// Estimates the throughput to this `Peer` the link can sustain, in bytes/second.
+    ///
+    /// This combines ENet's view of the connection: reliable data in transit is limited to the
+    /// throttled window per round trip, packet loss limits the throughput (like for TCP), and
+    /// bandwidth limits set with `Host::set_bandwith_limits` or by the foreign host apply too. The
+    /// estimate follows the link as ENet adapts to it, so systems like voice bitrate or snapshot
+    /// frequency can scale their send rate to it. Compare with `Peer::reliable_data_in_transit` to
+    /// see how much of the window is in use.
+    pub fn estimated_bandwidth(&self) -> u32 {
+        let rtt = u64::from(self.inner.roundTripTime.max(1));
+        let mtu = u64::from(self.inner.mtu);
+
+        let window = u64::from(self.inner.packetThrottle) * u64::from(self.inner.windowSize)
+            / u64::from(ENET_PEER_PACKET_THROTTLE_SCALE);
+        let mut estimate = window.max(mtu) * 1000 / rtt;
+
+        // The Mathis et al. model of loss-limited throughput: MSS / RTT * 1.22 / sqrt(loss).
+        let loss = f64::from(self.packet_loss());
+        if loss > 0.0 {
+            let limit = (mtu * 1000) as f64 / rtt as f64 * 1.22 / loss.sqrt();
+            estimate = estimate.min(limit as u64);
+        }
+
+        let host_limit = unsafe { (*self.inner.host).outgoingBandwidth };
+        for limit in [self.inner.incomingBandwidth, host_limit] {
+            if limit != 0 {
+                estimate = estimate.min(u64::from(limit));
+            }
+        }
+
+        estimate.min(u64::from(u32::MAX)) as u32
+    }
+
     /// Forcefully disconnects this `Peer`.
     ///
     /// The foreign host represented by the peer is not notified of the disconnection and will timeout on its connection to the local host.
+    /// The data of this `Peer` is dropped on the next call to `Host::service` or `Host::connect`,
+    /// which also adds its traffic to `Host::channel_traffic`.
     pub fn reset(&mut self) {
         unsafe {
             enet_peer_reset(&mut self.inner as *mut _);
@@ -140,14 +591,185 @@ where
     /// Queues a packet to be sent.
     ///
     /// Actual sending will happen during `Host::service`.
-    pub fn send_packet(&mut self, packet: Packet, channel_id: u8) -> Result<(), Error> {
-        let res =
-            unsafe { enet_peer_send(&mut self.inner as *mut _, channel_id, packet.into_inner()) };
+    ///
+    /// Fails if this `Peer` is not connected (e.g. it is a `Zombie` that is about to be reported as
+    /// disconnected), or if `channel_id` is not one of its allocated channels. Also fails if the
+    /// packet exceeds the budget of its channel or the queue limit of this `Peer`.
+    pub fn send_packet(&mut self, packet: Packet, channel_id: u8) -> Result<(), SendError> {
+        let packet = packet.into_inner();
+        let res = unsafe { self.send_raw(packet, channel_id) };
+
+        // ENet did not take a reference to the packet on failure, or if a `QueuePolicy` dropped it,
+        // so it is still ours to free.
+        if unsafe { (*packet).referenceCount } == 0 {
+            drop(Packet::from_sys_packet(packet));
+        }
+
+        res
+    }
+
+    /// Queues `data` to be sent on `channel_id` reliably and sequenced, like `send_packet`.
+    pub fn send_reliable(&mut self, channel_id: u8, data: Vec<u8>) -> Result<(), SendError> {
+        self.send_data(channel_id, data, PacketMode::ReliableSequenced)
+    }
+
+    /// Queues `data` to be sent on `channel_id` unreliably but sequenced, like `send_packet`.
+    pub fn send_unreliable(&mut self, channel_id: u8, data: Vec<u8>) -> Result<(), SendError> {
+        self.send_data(channel_id, data, PacketMode::UnreliableSequenced)
+    }
+
+    /// Queues `data` to be sent on `channel_id` unreliably and unsequenced, like `send_packet`.
+    pub fn send_unsequenced(&mut self, channel_id: u8, data: Vec<u8>) -> Result<(), SendError> {
+        self.send_data(channel_id, data, PacketMode::UnreliableUnsequenced)
+    }
+
+    fn send_data(
+        &mut self,
+        channel_id: u8,
+        data: Vec<u8>,
+        mode: PacketMode,
+    ) -> Result<(), SendError> {
+        let packet = Packet::new(data, mode).map_err(|err| SendError::Error(err.0))?;
+        self.send_packet(packet, channel_id)
+    }
 
-        match res {
+    /// Queues `message` to be sent on the channel `C`, with its mode.
+    ///
+    /// Fails like `send_packet`.
+    pub fn send<C: Channel>(&mut self, message: &C::Message) -> Result<(), SendError> {
+        let packet =
+            Packet::new(C::encode(message), C::MODE).map_err(|err| SendError::Error(err.0))?;
+        self.send_packet(packet, C::ID)
+    }
+
+    /// Sets the defaults of this `Peer`'s channels, `configs[i]` for channel `i`, and applies their
+    /// budgets, see `Peer::send_on`.
+    ///
+    /// Replaces the configs of `Host::set_channel_configs` for this peer.
+    pub fn set_channel_configs(&mut self, configs: &[ChannelConfig]) {
+        self.set_shared_channel_configs(configs.into());
+    }
+
+    pub(crate) fn set_shared_channel_configs(&mut self, configs: Arc<[ChannelConfig]>) {
+        for (channel_id, config) in configs.iter().enumerate() {
+            if let Some(budget) = config.budget {
+                self.set_channel_budget(channel_id as u8, Some(budget));
+            }
+        }
+        self.state_mut().channel_configs = Some(configs);
+    }
+
+    pub(crate) fn has_channel_configs(&self) -> bool {
+        self.state_ref()
+            .is_some_and(|state| state.channel_configs.is_some())
+    }
+
+    /// Returns the defaults of `channel_id`, if its channel has a config.
+    pub fn channel_config(&self, channel_id: u8) -> Option<ChannelConfig> {
+        let configs = self.state_ref()?.channel_configs.as_ref()?;
+        configs.get(usize::from(channel_id)).copied()
+    }
+
+    /// Queues `data` to be sent on `channel_id` with the defaults of its `ChannelConfig`.
+    ///
+    /// Fails with `SendError::Unconfigured` if the channel has no config, and like `send_packet`
+    /// otherwise.
+    pub fn send_on(&mut self, channel_id: u8, data: Vec<u8>) -> Result<(), SendError> {
+        let config = self
+            .channel_config(channel_id)
+            .ok_or(SendError::Unconfigured(channel_id))?;
+        let packet = if config.unreliable_fragment {
+            Packet::new_unreliable_fragment(data, config.mode)
+        } else {
+            Packet::new(data, config.mode)
+        };
+        self.send_packet(packet.map_err(|err| SendError::Error(err.0))?, channel_id)
+    }
+
+    /// Queues `packet` like `send_packet`, but leaves it to the caller to free it if no peer took a
+    /// reference to it, so the same packet can be queued for several peers.
+    ///
+    /// `packet` must be a valid packet.
+    pub(crate) unsafe fn send_raw(
+        &mut self,
+        packet: *mut ENetPacket,
+        channel_id: u8,
+    ) -> Result<(), SendError> {
+        match self.state() {
+            PeerState::Connected => (),
+            state => return Err(SendError::NotConnected(state)),
+        }
+
+        if usize::from(channel_id) >= self.channel_count() {
+            return Err(SendError::InvalidChannel(channel_id));
+        }
+
+        let len = (*packet).dataLength;
+        let limit = self.state_ref().and_then(|state| state.queue_limit);
+        if let Some(limit) = limit {
+            let queued = match self.state_mut().queued_bytes {
+                Some(queued) => queued,
+                None => {
+                    let queued = self.queued_bytes();
+                    self.state_mut().queued_bytes = Some(queued);
+                    queued
+                }
+            };
+            if queued + len > limit.max_bytes {
+                let packet = ManuallyDrop::new(Packet::from_sys_packet(packet));
+                let unreliable = packet.mode() != PacketMode::ReliableSequenced;
+                match limit.policy {
+                    QueuePolicy::Reject => (),
+                    QueuePolicy::DropUnreliable if unreliable => {
+                        log_debug!(
+                            target: logging::DROP,
+                            "dropped a packet of {} bytes to {}, over the queue limit",
+                            len,
+                            self.address()
+                        );
+                        // Nothing took a reference to the packet, so the caller frees it.
+                        return Ok(());
+                    }
+                    QueuePolicy::DropUnreliable => (),
+                    QueuePolicy::Disconnect(data) => {
+                        log_info!(
+                            target: logging::CONNECTION,
+                            "disconnecting {}, over the queue limit",
+                            self.address()
+                        );
+                        self.disconnect(data);
+                    }
+                }
+                return Err(SendError::WouldBlock);
+            }
+        }
+
+        let admitted = match self.state_mut().budgets.get_mut(&channel_id) {
+            Some(budget) => budget.admit(Instant::now(), len),
+            None => true,
+        };
+        if !admitted {
+            return Err(SendError::OverBudget(channel_id));
+        }
+
+        match enet_peer_send(&mut self.inner as *mut _, channel_id, packet) {
             r if r > 0 => panic!("unexpected res: {}", r),
-            0 => Ok(()),
-            r if r < 0 => Err(Error(r)),
+            0 => {
+                if let Some(queued) = self.state_mut().queued_bytes.as_mut() {
+                    *queued += len;
+                }
+                self.traffic_mut(channel_id).record_sent(len);
+                let (mtu, checksum) = (self.inner.mtu, (*self.inner.host).checksum.is_some());
+                self.state_mut().fragments.record_sent(len, mtu, checksum);
+                Ok(())
+            }
+            r if r < 0 => {
+                if let Some(budget) = self.state_mut().budgets.get_mut(&channel_id) {
+                    budget.refund(len);
+                }
+                log_info!(target: logging::ERROR, "sending to {} failed with {}", self.address(), r);
+                Err(SendError::Error(r))
+            }
             _ => panic!("unreachable"),
         }
     }
@@ -164,8 +786,10 @@ where
     /// Disconnects from this peer immediately.
     ///
     /// No `Disconnect` event will be created. No disconnect notification for the foreign peer is guaranteed, and this `Peer` is immediately reset on return from this method.
+    /// The data of this `Peer` is dropped right away, its traffic is added to
+    /// `Host::channel_traffic` on the next call to `Host::service` or `Host::connect`.
     pub fn disconnect_now(&mut self, data: u32) {
-        self.set_data(None);
+        drop(self.take_data());
 
         unsafe {
             enet_peer_disconnect_now(&mut self.inner as *mut _, data);
@@ -191,6 +815,7 @@ where
         if res.is_null() {
             None
         } else {
+            self.record_received(channel_id, unsafe { (*res).dataLength });
             Some(PeerPacket {
                 packet: Packet::from_sys_packet(res),
                 channel_id,
@@ -204,7 +829,68 @@ where
     T: Debug,
 {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        f.debug_struct("Peer").field("data", &self.data()).finish()
+        f.debug_struct("Peer")
+            .field("address", &self.address())
+            .field("state", &self.state())
+            .field("connect_id", &self.connect_id())
+            .field("channel_count", &self.channel_count())
+            .field("mean_rtt", &self.mean_rtt())
+            .field("packet_loss", &self.packet_loss())
+            .field("queued_reliable_commands", &self.queued_reliable_commands())
+            .field(
+                "queued_unreliable_commands",
+                &self.queued_unreliable_commands(),
+            )
+            .field("reliable_data_in_transit", &self.reliable_data_in_transit())
+            .field("data", &self.data())
+            .finish()
+    }
+}
+
+/// The data associated with a `Peer`, which may be unset, like an entry of a `HashMap`.
+pub struct PeerDataEntry<'a, T> {
+    peer: &'a mut Peer<T>,
+}
+
+impl<'a, T> PeerDataEntry<'a, T> {
+    /// Returns the data, setting it to `data` if it is unset.
+    pub fn or_insert(self, data: T) -> &'a mut T {
+        self.or_insert_with(|| data)
+    }
+
+    /// Returns the data, setting it to the result of `default` if it is unset.
+    pub fn or_insert_with(self, default: impl FnOnce() -> T) -> &'a mut T {
+        if self.peer.data().is_none() {
+            self.peer.set_data(Some(default()));
+        }
+        self.peer
+            .data_mut()
+            .expect("enet-rs internal error; peer data unset after setting it")
+    }
+
+    /// Modifies the data with `modify` if it is set.
+    pub fn and_modify(self, modify: impl FnOnce(&mut T)) -> PeerDataEntry<'a, T> {
+        if let Some(data) = self.peer.data_mut() {
+            modify(data);
+        }
+        self
+    }
+
+    /// Returns the data, if it is set.
+    pub fn get(&self) -> Option<&T> {
+        self.peer.data()
+    }
+
+    /// Takes the data out, leaving it unset.
+    pub fn remove(self) -> Option<T> {
+        self.peer.take_data()
+    }
+}
+
+impl<'a, T: Default> PeerDataEntry<'a, T> {
+    /// Returns the data, setting it to its default if it is unset.
+    pub fn or_default(self) -> &'a mut T {
+        self.or_insert_with(T::default)
     }
 }
 
@@ -215,8 +901,27 @@ where
 /// primary way of storing owned references to Peers.
 ///
 /// When connecting to a host, both a reference to the host, and it's ID are returned.
+///
+/// A `PeerID` is only valid for the `Host` that created it. Debug builds check this on every use.
 #[derive(Clone, Copy, Hash, PartialEq, Eq, Debug)]
-pub struct PeerID(pub(crate) usize);
+pub struct PeerID {
+    pub(crate) index: usize,
+    pub(crate) host_id: usize,
+}
+
+impl PeerID {
+    /// Returns the position of the peer in `Host::peers_slice`.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+/// Formats the ID as `peer#` followed by its index, e.g. `peer#12`.
+impl Display for PeerID {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "peer#{}", self.index)
+    }
+}
 
 /// Describes the state a `Peer` is in.
 ///
@@ -236,6 +941,13 @@ pub enum PeerState {
     Zombie,
 }
 
+/// Formats the state as its name, e.g. `Connected`.
+impl Display for PeerState {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
 impl PeerState {
     fn from_sys_state(enet_sys_state: _ENetPeerState) -> PeerState {
         #[allow(non_upper_case_globals)]
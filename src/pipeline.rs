@@ -0,0 +1,322 @@
+//! Encoding messages on worker threads.
+//!
+//! Encoding, compressing or encrypting messages for many peers can take up much of the thread that
+//! services a `Host`. A [Pipeline](struct.Pipeline.html) runs a function that turns messages into
+//! packet data on a pool of worker threads. The finished packets are handed to the host by
+//! `Pipeline::drain` on the host thread, in the order the messages were submitted, no matter
+//! which worker finished first.
+//!
+//! For broadcasts that encode a different view for every peer within one tick,
+//! [broadcast_parallel](fn.broadcast_parallel.html) encodes the packets on scoped threads and
+//! sends them before returning.
+//!
+//! ```no_run
+//! # use enet::*;
+//! # use enet::pipeline::Pipeline;
+//! # fn run(mut host: Host<()>, peer_id: PeerID) -> Result<(), Error> {
+//! let mut pipeline = Pipeline::new(4, |message: String| message.into_bytes());
+//! pipeline.submit(peer_id, 0, PacketMode::ReliableSequenced, "hello".to_string());
+//! loop {
+//!     pipeline.drain(&mut host);
+//!     host.service(None)?;
+//! }
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::{Host, Packet, PacketMode, PeerID};
+
+struct Job<M> {
+    sequence: u64,
+    peer_id: PeerID,
+    channel_id: u8,
+    mode: PacketMode,
+    message: M,
+}
+
+struct Finished {
+    peer_id: PeerID,
+    channel_id: u8,
+    mode: PacketMode,
+    /// The encoded message, `None` if encoding it panicked.
+    data: Option<Vec<u8>>,
+}
+
+/// Encodes messages on worker threads, and sends them from the host thread in submission order.
+pub struct Pipeline<M> {
+    jobs: Option<Sender<Job<M>>>,
+    results: Receiver<(u64, Finished)>,
+    workers: Vec<JoinHandle<()>>,
+    next_submitted: u64,
+    next_sent: u64,
+    finished: BTreeMap<u64, Finished>,
+    failed: u64,
+}
+
+impl<M: Send + 'static> Pipeline<M> {
+    /// Starts `workers` threads that encode messages with `encode`.
+    pub fn new<F>(workers: usize, encode: F) -> Pipeline<M>
+    where
+        F: Fn(M) -> Vec<u8> + Send + Sync + 'static,
+    {
+        let (jobs, job_receiver) = mpsc::channel::<Job<M>>();
+        let (result_sender, results) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let encode = Arc::new(encode);
+
+        let workers = (0..workers.max(1))
+            .map(|_| {
+                let job_receiver = job_receiver.clone();
+                let result_sender = result_sender.clone();
+                let encode = encode.clone();
+                thread::spawn(move || loop {
+                    let job = job_receiver
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .recv();
+                    // The pipeline is gone once its sender is.
+                    let job = match job {
+                        Ok(job) => job,
+                        Err(_) => break,
+                    };
+
+                    let message = job.message;
+                    let data = panic::catch_unwind(AssertUnwindSafe(|| encode(message))).ok();
+                    let finished = Finished {
+                        peer_id: job.peer_id,
+                        channel_id: job.channel_id,
+                        mode: job.mode,
+                        data,
+                    };
+                    if result_sender.send((job.sequence, finished)).is_err() {
+                        break;
+                    }
+                })
+            })
+            .collect();
+
+        Pipeline {
+            jobs: Some(jobs),
+            results,
+            workers,
+            next_submitted: 0,
+            next_sent: 0,
+            finished: BTreeMap::new(),
+            failed: 0,
+        }
+    }
+
+    /// Submits `message` to be encoded and sent to `peer_id` on `channel_id` with `mode`.
+    pub fn submit(&mut self, peer_id: PeerID, channel_id: u8, mode: PacketMode, message: M) {
+        let job = Job {
+            sequence: self.next_submitted,
+            peer_id,
+            channel_id,
+            mode,
+            message,
+        };
+        self.next_submitted += 1;
+        if let Some(ref jobs) = self.jobs {
+            // The workers only stop once the sender is dropped.
+            let _ = jobs.send(job);
+        }
+    }
+
+    /// Returns the number of messages submitted but not sent yet.
+    pub fn pending(&self) -> usize {
+        (self.next_submitted - self.next_sent) as usize
+    }
+
+    /// Returns the number of messages that could not be sent, because encoding them panicked or
+    /// because `Peer::send_packet` failed, e.g. after their peer disconnected.
+    pub fn failed(&self) -> u64 {
+        self.failed
+    }
+
+    /// Sends the messages that finished encoding on `host`, in the order they were submitted. Call
+    /// this before every `Host::service`.
+    ///
+    /// Doesn't wait for the workers: a message that is still being encoded holds back the ones
+    /// submitted after it until the next call. Returns the number of packets sent.
+    pub fn drain<T>(&mut self, host: &mut Host<T>) -> usize {
+        for (sequence, finished) in self.results.try_iter() {
+            self.finished.insert(sequence, finished);
+        }
+
+        let mut sent = 0;
+        while let Some(finished) = self.finished.remove(&self.next_sent) {
+            self.next_sent += 1;
+            let Finished {
+                peer_id,
+                channel_id,
+                mode,
+                data,
+            } = finished;
+            let packet = data.and_then(|data| Packet::new(data, mode).ok());
+            let succeeded = packet.is_some_and(|packet| {
+                host.peer_mut(peer_id)
+                    .is_some_and(|peer| peer.send_packet(packet, channel_id).is_ok())
+            });
+            if succeeded {
+                sent += 1;
+            } else {
+                self.failed += 1;
+            }
+        }
+        sent
+    }
+}
+
+impl<M> Drop for Pipeline<M> {
+    fn drop(&mut self) {
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Encodes a packet for every peer in `peer_ids` on up to `threads` threads, then sends them on
+/// `channel_id` with `mode` from the calling thread.
+///
+/// `encode` returns the data for a peer, e.g. its view of the world, or `None` to skip it. The
+/// peers are split into one contiguous range per thread, so `encode` should take about as long for
+/// each of them. Blocks until every packet is encoded, and panics if `encode` does. Returns the
+/// number of packets sent.
+pub fn broadcast_parallel<T, F>(
+    host: &mut Host<T>,
+    peer_ids: &[PeerID],
+    channel_id: u8,
+    mode: PacketMode,
+    threads: usize,
+    encode: F,
+) -> usize
+where
+    F: Fn(PeerID) -> Option<Vec<u8>> + Sync,
+{
+    if peer_ids.is_empty() {
+        return 0;
+    }
+    let threads = threads.max(1);
+    let chunk_size = (peer_ids.len() + threads - 1) / threads;
+    let encode = &encode;
+    let encoded: Vec<Vec<Option<Vec<u8>>>> = thread::scope(|scope| {
+        let workers: Vec<_> = peer_ids
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(|&id| encode(id)).collect()))
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+            .collect()
+    });
+
+    let mut sent = 0;
+    for (&peer_id, data) in peer_ids.iter().zip(encoded.into_iter().flatten()) {
+        let packet = match data.map(|data| Packet::new(data, mode)) {
+            Some(Ok(packet)) => packet,
+            _ => continue,
+        };
+        if host
+            .peer_mut(peer_id)
+            .is_some_and(|peer| peer.send_packet(packet, channel_id).is_ok())
+        {
+            sent += 1;
+        }
+    }
+    sent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{broadcast_parallel, Pipeline};
+    use crate::tests::{connected_pair, create_host, pump_until};
+    use crate::{Address, EventKind, PacketMode};
+
+    use std::net::Ipv4Addr;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_pipeline() {
+        let (mut server, mut client, _, peer_id) = connected_pair(12391, 1);
+        let timeout = Some(Duration::from_millis(2));
+
+        // Earlier messages take longer, so the workers finish them out of order.
+        let mut pipeline = Pipeline::new(4, |message: u8| {
+            thread::sleep(Duration::from_millis(u64::from(8 - message) * 5));
+            assert_ne!(message, 3, "cannot encode 3");
+            vec![message]
+        });
+        for message in 0..8 {
+            pipeline.submit(peer_id, 0, PacketMode::ReliableSequenced, message);
+        }
+        assert_eq!(pipeline.pending(), 8);
+
+        let mut received = Vec::new();
+        for _ in 0..500 {
+            pipeline.drain(&mut client);
+            client.service(timeout).unwrap();
+            if let Some(event) = server.service(timeout).unwrap() {
+                if let EventKind::Receive { ref packet, .. } = event.kind {
+                    received.push(packet.data()[0]);
+                }
+            }
+            if received.len() == 7 {
+                break;
+            }
+        }
+
+        assert_eq!(received, vec![0, 1, 2, 4, 5, 6, 7]);
+        assert_eq!(pipeline.pending(), 0);
+        assert_eq!(pipeline.failed(), 1);
+    }
+
+    #[test]
+    fn test_broadcast_parallel() {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12398);
+        let mut server = create_host(Some(&server_address), 2);
+        let (mut first, mut second) = (create_host(None, 1), create_host(None, 1));
+        first.connect(&server_address, 1, 0).unwrap();
+        second.connect(&server_address, 1, 0).unwrap();
+
+        let mut peer_ids = Vec::new();
+        pump_until(
+            &mut [&mut server, &mut first, &mut second],
+            |index, _, event| {
+                if let (0, EventKind::Connect) = (index, &event.kind) {
+                    peer_ids.push(event.peer_id);
+                }
+                peer_ids.len() == 2
+            },
+        );
+
+        // Every peer gets its own data, the second one is skipped once it is encoded.
+        let mode = PacketMode::ReliableSequenced;
+        let sent = broadcast_parallel(&mut server, &peer_ids, 0, mode, 4, |peer_id| {
+            (peer_id == peer_ids[0]).then(|| vec![7])
+        });
+        assert_eq!(sent, 1);
+        assert_eq!(
+            broadcast_parallel(&mut server, &[], 0, mode, 4, |_| None),
+            0
+        );
+
+        let mut received = Vec::new();
+        pump_until(
+            &mut [&mut server, &mut first, &mut second],
+            |index, _, event| {
+                if let (1 | 2, EventKind::Receive { ref packet, .. }) = (index, event.kind) {
+                    received.push(packet.data().to_vec());
+                }
+                !received.is_empty()
+            },
+        );
+        assert_eq!(received, vec![vec![7]]);
+    }
+}
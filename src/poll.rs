@@ -0,0 +1,64 @@
+use std::io;
+use std::os::raw::{c_int, c_short};
+
+use enet_sys::ENetSocket;
+
+use crate::Error;
+
+#[repr(C)]
+struct PollFd {
+    fd: ENetSocket,
+    events: c_short,
+    revents: c_short,
+}
+
+#[cfg(unix)]
+const POLLIN: c_short = 0x0001;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+type NFds = std::os::raw::c_ulong;
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+type NFds = std::os::raw::c_uint;
+
+#[cfg(unix)]
+extern "C" {
+    fn poll(fds: *mut PollFd, nfds: NFds, timeout: c_int) -> c_int;
+}
+
+/// `POLLRDNORM | POLLRDBAND`, which `WSAPoll` requires instead of `POLLIN`.
+#[cfg(windows)]
+const POLLIN: c_short = 0x0100 | 0x0200;
+
+#[cfg(windows)]
+type NFds = std::os::raw::c_ulong;
+
+#[cfg(windows)]
+#[link(name = "ws2_32")]
+extern "system" {
+    #[link_name = "WSAPoll"]
+    fn poll(fds: *mut PollFd, nfds: NFds, timeout: c_int) -> c_int;
+}
+
+/// Waits until one of `sockets` has data to receive, or `timeout_ms` has passed.
+///
+/// ENet only waits on a single socket, this does the same for several at once.
+pub(crate) fn wait_readable(sockets: &[ENetSocket], timeout_ms: u32) -> Result<(), Error> {
+    let mut fds: Vec<_> = sockets
+        .iter()
+        .map(|&fd| PollFd {
+            fd,
+            events: POLLIN,
+            revents: 0,
+        })
+        .collect();
+
+    let timeout = timeout_ms.min(c_int::MAX as u32) as c_int;
+    let res = unsafe { poll(fds.as_mut_ptr(), fds.len() as NFds, timeout) };
+
+    // Being interrupted by a signal is as good as a timeout, the caller checks again anyway.
+    if res < 0 && io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+        return Err(Error(res));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,243 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::{Event, Host, Packet, PacketMode, Peer, PeerID, PeerState, SendError};
+
+/// How urgent a packet sent through a `PrioritySender` is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Bulk data, e.g. file transfers, sent once nothing else waits.
+    Low,
+    /// Regular traffic, e.g. snapshots.
+    Normal,
+    /// Urgent packets, e.g. input acknowledgements or disconnect notices.
+    High,
+}
+
+const PRIORITIES: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+
+#[derive(Debug, Default)]
+struct PeerQueues {
+    /// The queued packets and their channels, indexed like `PRIORITIES`.
+    queues: [VecDeque<(Packet, u8)>; 3],
+}
+
+impl PeerQueues {
+    fn len(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+
+    fn index(priority: Priority) -> usize {
+        PRIORITIES.iter().position(|&p| p == priority).unwrap()
+    }
+
+    /// Returns whether packets of `priority` or higher are waiting.
+    fn has_queued(&self, priority: Priority) -> bool {
+        self.queues[..=PeerQueues::index(priority)]
+            .iter()
+            .any(|queue| !queue.is_empty())
+    }
+}
+
+/// Sends packets to peers by priority, instead of in the order they were sent.
+///
+/// ENet sends queued packets first come, first served, so an urgent packet waits behind all the
+/// bulk data queued before it. While ENet's queue for a peer holds fewer than `max_queued`
+/// commands, packets go straight to ENet. Beyond that, the sender holds them back, and
+/// `PrioritySender::flush` hands them over highest priority first as ENet's queue drains. Packets
+/// of the same priority keep their order.
+#[derive(Debug)]
+pub struct PrioritySender {
+    max_queued: usize,
+    peers: HashMap<PeerID, PeerQueues>,
+}
+
+impl PrioritySender {
+    /// Creates a sender that lets ENet queue at most `max_queued` commands per peer.
+    pub fn new(max_queued: usize) -> PrioritySender {
+        PrioritySender {
+            max_queued,
+            peers: HashMap::new(),
+        }
+    }
+
+    fn backed_up<T>(&self, peer: &Peer<T>) -> bool {
+        peer.queued_reliable_commands() + peer.queued_unreliable_commands() >= self.max_queued
+    }
+
+    /// Sends `packet` to `peer_id` on `channel_id`, or queues it if ENet's queue for the peer is
+    /// full or packets of the same or a higher priority are waiting.
+    ///
+    /// Fails right away like `Peer::send_packet` if the peer is not connected or the channel is
+    /// not allocated.
+    pub fn send<T>(
+        &mut self,
+        host: &mut Host<T>,
+        peer_id: PeerID,
+        channel_id: u8,
+        packet: Packet,
+        priority: Priority,
+    ) -> Result<(), SendError> {
+        let peer = match host.peer_mut(peer_id) {
+            Some(peer) => peer,
+            None => return Err(SendError::NotConnected(PeerState::Disconnected)),
+        };
+        match peer.state() {
+            PeerState::Connected => (),
+            state => return Err(SendError::NotConnected(state)),
+        }
+        if usize::from(channel_id) >= peer.channel_count() {
+            return Err(SendError::InvalidChannel(channel_id));
+        }
+
+        let waiting = self
+            .peers
+            .get(&peer_id)
+            .is_some_and(|queues| queues.has_queued(priority));
+        if !waiting && !self.backed_up(peer) {
+            return peer.send_packet(packet, channel_id);
+        }
+
+        self.peers.entry(peer_id).or_default().queues[PeerQueues::index(priority)]
+            .push_back((packet, channel_id));
+        Ok(())
+    }
+
+    /// Returns the number of packets waiting for `peer_id`.
+    pub fn queued(&self, peer_id: PeerID) -> usize {
+        self.peers.get(&peer_id).map_or(0, PeerQueues::len)
+    }
+
+    /// Returns the number of packets waiting for any peer.
+    pub fn pending(&self) -> usize {
+        self.peers.values().map(PeerQueues::len).sum()
+    }
+
+    /// Drops the packets waiting for `peer_id` on `channel_id`, e.g. a large transfer that was
+    /// aborted. Only drops packets with `mode`, or with any mode if `mode` is `None`.
+    ///
+    /// Unlike `Peer::clear_outgoing`, this drops reliable packets too, ENet never saw them. Returns
+    /// the number of packets dropped.
+    pub fn clear(&mut self, peer_id: PeerID, channel_id: u8, mode: Option<PacketMode>) -> usize {
+        let queues = match self.peers.get_mut(&peer_id) {
+            Some(queues) => queues,
+            None => return 0,
+        };
+        let before = queues.len();
+        for queue in &mut queues.queues {
+            queue.retain(|(packet, channel)| {
+                *channel != channel_id || mode.is_some_and(|mode| mode != packet.mode())
+            });
+        }
+        before - queues.len()
+    }
+
+    /// Hands the waiting packets to ENet, highest priority first, as long as its queues have room.
+    /// Call this before every `Host::service`.
+    ///
+    /// Returns the number of packets handed to ENet. Packets that can't be sent, e.g. because
+    /// their peer disconnected, are dropped.
+    pub fn flush<T>(&mut self, host: &mut Host<T>) -> usize {
+        let peer_ids: Vec<_> = self.peers.keys().cloned().collect();
+        let mut sent = 0;
+        for peer_id in peer_ids {
+            let peer = match host.peer_mut(peer_id) {
+                Some(peer) => peer,
+                None => {
+                    self.peers.remove(&peer_id);
+                    continue;
+                }
+            };
+            while !self.backed_up(peer) {
+                let queues = self.peers.get_mut(&peer_id).unwrap();
+                let next = queues.queues.iter_mut().find_map(VecDeque::pop_front);
+                let (packet, channel_id) = match next {
+                    Some(next) => next,
+                    None => break,
+                };
+                if peer.send_packet(packet, channel_id).is_ok() {
+                    sent += 1;
+                }
+            }
+        }
+        self.peers.retain(|_, queues| queues.len() > 0);
+        sent
+    }
+
+    /// Processes an event returned by `Host::service`, dropping the packets of peers that
+    /// disconnect.
+    ///
+    /// Always returns `false`, the event still needs handling.
+    pub fn handle_event(&mut self, event: &Event) -> bool {
+        if event.kind.is_disconnect() {
+            self.peers.remove(&event.peer_id);
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Priority, PrioritySender};
+    use crate::tests::connected_pair;
+    use crate::{EventKind, Packet, PacketMode, PeerID, PeerState, SendError};
+
+    use std::time::Duration;
+
+    #[test]
+    fn test_priority_sender() {
+        let (mut server, mut client, _, peer_id) = connected_pair(12388, 1);
+        let timeout = Some(Duration::from_millis(2));
+
+        let mut sender = PrioritySender::new(1);
+        let mut send = |client: &mut _, data: &[u8], priority| {
+            let packet = Packet::new(data.to_vec(), PacketMode::ReliableSequenced).unwrap();
+            sender.send(client, peer_id, 0, packet, priority).unwrap();
+        };
+        send(&mut client, b"bulk 1", Priority::Low);
+        send(&mut client, b"bulk 2", Priority::Low);
+        send(&mut client, b"bulk 3", Priority::Low);
+        send(&mut client, b"ack", Priority::High);
+        assert_eq!(sender.queued(peer_id), 3);
+
+        let mut received = Vec::new();
+        for _ in 0..500 {
+            sender.flush(&mut client);
+            client.service(timeout).unwrap();
+            if let Some(event) = server.service(timeout).unwrap() {
+                if let EventKind::Receive { ref packet, .. } = event.kind {
+                    received.push(packet.data().to_vec());
+                }
+            }
+            if received.len() == 4 {
+                break;
+            }
+        }
+
+        assert_eq!(
+            received,
+            vec![
+                b"bulk 1".to_vec(),
+                b"ack".to_vec(),
+                b"bulk 2".to_vec(),
+                b"bulk 3".to_vec()
+            ]
+        );
+        assert_eq!(sender.pending(), 0);
+    }
+
+    #[test]
+    fn test_unknown_peer() {
+        let (_server, mut client, _, peer_id) = connected_pair(12426, 1);
+        let unknown = PeerID {
+            index: 1,
+            ..peer_id
+        };
+
+        let mut sender = PrioritySender::new(1);
+        let packet = Packet::new(b"hi".to_vec(), PacketMode::ReliableSequenced).unwrap();
+        assert!(matches!(
+            sender.send(&mut client, unknown, 0, packet, Priority::Low),
+            Err(SendError::NotConnected(PeerState::Disconnected))
+        ));
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::{Address, Datagram, Intercept, InterceptAction};
+
+/// Every PROXY protocol v2 header starts with this signature.
+const SIGNATURE: [u8; 12] = [
+    0x0d, 0x0a, 0x0d, 0x0a, 0x00, 0x0d, 0x0a, 0x51, 0x55, 0x49, 0x54, 0x0a,
+];
+
+/// Signature, version/command, family and length.
+const FIXED_HEADER_LEN: usize = 16;
+
+const VERSION_2: u8 = 0x20;
+const COMMAND_LOCAL: u8 = 0x00;
+const COMMAND_PROXY: u8 = 0x01;
+const FAMILY_INET: u8 = 0x10;
+const FAMILY_INET6: u8 = 0x20;
+
+/// The result of parsing a PROXY protocol v2 header.
+#[derive(Debug, PartialEq, Eq)]
+enum Header {
+    /// The header announces the original source address.
+    Proxied { source: Address, len: usize },
+    /// The balancer sent the datagram itself (e.g. a health check), or the source can't be represented.
+    Local { len: usize },
+}
+
+fn parse_header(data: &[u8]) -> Option<Header> {
+    if data.len() < FIXED_HEADER_LEN || data[..12] != SIGNATURE {
+        return None;
+    }
+
+    let version_command = data[12];
+    if version_command & 0xf0 != VERSION_2 {
+        return None;
+    }
+
+    let len = FIXED_HEADER_LEN + usize::from(u16::from_be_bytes([data[14], data[15]]));
+    if data.len() < len {
+        return None;
+    }
+
+    let addresses = &data[FIXED_HEADER_LEN..len];
+    match version_command & 0x0f {
+        COMMAND_LOCAL => Some(Header::Local { len }),
+        COMMAND_PROXY => {
+            let ip = match data[13] & 0xf0 {
+                FAMILY_INET if addresses.len() >= 12 => Some((
+                    Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]),
+                    8,
+                )),
+                // ENet only speaks IPv4, so only IPv4-mapped IPv6 sources can be represented.
+                FAMILY_INET6 if addresses.len() >= 36 => {
+                    let mut octets = [0; 16];
+                    octets.copy_from_slice(&addresses[..16]);
+                    Ipv6Addr::from(octets).to_ipv4_mapped().map(|ip| (ip, 32))
+                }
+                _ => None,
+            };
+
+            match ip {
+                Some((ip, port_offset)) => {
+                    let port =
+                        u16::from_be_bytes([addresses[port_offset], addresses[port_offset + 1]]);
+                    Some(Header::Proxied {
+                        source: Address::new(ip, port),
+                        len,
+                    })
+                }
+                None => Some(Header::Local { len }),
+            }
+        }
+        _ => None,
+    }
+}
+
+/// An `Intercept` that strips PROXY protocol v2 headers, added by UDP proxies and load balancers.
+///
+/// Datagrams from the given balancers have their header removed, and are attributed to the
+/// original client address it announces, so e.g. `Peer::address` reflects the real client.
+/// Datagrams from balancers without a valid header are dropped, while datagrams from any other
+/// address are passed on unchanged; headers are only trusted when they come from a balancer.
+///
+/// ENet sends its replies to the rewritten address, i.e. directly to the client. This matches
+/// balancers in direct server return mode.
+///
+/// ```no_run
+/// # use enet::*;
+/// # use std::net::Ipv4Addr;
+/// # fn add(host: &mut Host<()>) {
+/// host.add_intercept(ProxyProtocol::new(vec![Ipv4Addr::new(10, 0, 0, 1)]));
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ProxyProtocol {
+    balancers: HashSet<Ipv4Addr>,
+}
+
+impl ProxyProtocol {
+    /// Creates an intercept that trusts the headers sent from the given balancer addresses.
+    pub fn new(balancers: impl IntoIterator<Item = Ipv4Addr>) -> ProxyProtocol {
+        ProxyProtocol {
+            balancers: balancers.into_iter().collect(),
+        }
+    }
+}
+
+impl Intercept for ProxyProtocol {
+    fn intercept(&mut self, datagram: &mut Datagram) -> InterceptAction {
+        if !self.balancers.contains(datagram.address().ip()) {
+            return InterceptAction::Continue;
+        }
+
+        match parse_header(datagram.data()) {
+            Some(Header::Proxied { source, len }) => {
+                datagram.strip_prefix(len);
+                datagram.set_address(&source);
+                InterceptAction::Continue
+            }
+            Some(Header::Local { len }) => {
+                datagram.strip_prefix(len);
+                InterceptAction::Continue
+            }
+            None => InterceptAction::Drop,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_header, Header, ProxyProtocol, SIGNATURE};
+    use crate::tests::ENET;
+    use crate::{Address, BandwidthLimit, ChannelLimit, EventKind, Transport};
+
+    use std::io;
+    use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+    use std::time::Duration;
+
+    fn header(source: &Address) -> Vec<u8> {
+        let mut header = SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x21, 0x12, 0, 12]);
+        header.extend_from_slice(&source.ip().octets());
+        header.extend_from_slice(&[10, 0, 0, 2]);
+        header.extend_from_slice(&source.port().to_be_bytes());
+        header.extend_from_slice(&7777u16.to_be_bytes());
+        header
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let source = Address::new(Ipv4Addr::new(203, 0, 113, 9), 5000);
+        let mut datagram = header(&source);
+        datagram.extend_from_slice(b"enet");
+
+        assert_eq!(
+            parse_header(&datagram),
+            Some(Header::Proxied { source, len: 28 })
+        );
+        assert_eq!(parse_header(b"enet"), None);
+
+        // A LOCAL header (command 0) carries no usable address.
+        datagram[12] = 0x20;
+        assert_eq!(parse_header(&datagram), Some(Header::Local { len: 28 }));
+    }
+
+    #[test]
+    fn test_proxied_peer_address() {
+        /// Sends everything to the server with a header claiming to come from `source`.
+        struct Balancer {
+            socket: UdpSocket,
+            source: Address,
+        }
+
+        impl Transport for Balancer {
+            type Endpoint = SocketAddr;
+            type Error = io::Error;
+
+            fn send_to(&mut self, data: &[u8], endpoint: &SocketAddr) -> io::Result<()> {
+                let mut datagram = header(&self.source);
+                datagram.extend_from_slice(data);
+                Transport::send_to(&mut self.socket, &datagram, endpoint)
+            }
+
+            fn recv_from(&mut self, buffer: &mut [u8]) -> io::Result<Option<(usize, SocketAddr)>> {
+                Transport::recv_from(&mut self.socket, buffer)
+            }
+        }
+
+        let server_address = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 12350);
+        let mut server = ENET
+            .create_host::<()>(
+                Some(&Address::from(server_address)),
+                1,
+                ChannelLimit::Maximum,
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap();
+        server.add_intercept(ProxyProtocol::new(vec![Ipv4Addr::LOCALHOST]));
+
+        // The socket receives what the server sends to the announced source, 127.0.0.2.
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let source = Address::new(
+            Ipv4Addr::new(127, 0, 0, 2),
+            socket.local_addr().unwrap().port(),
+        );
+        let mut client = ENET
+            .create_host_with_transport::<(), _>(
+                Balancer {
+                    socket,
+                    source: source.clone(),
+                },
+                1,
+                ChannelLimit::Maximum,
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap();
+
+        let address = client
+            .transport_mut::<Balancer>()
+            .unwrap()
+            .address_of(&SocketAddr::V4(server_address))
+            .unwrap();
+        client.connect(&address, 1, 0).unwrap();
+
+        let timeout = Some(Duration::from_millis(10));
+        let mut peer_address = None;
+        for _ in 0..200 {
+            let _ = client.service(timeout).unwrap();
+            if let Some(event) = server.service(timeout).unwrap() {
+                if let EventKind::Connect = event.kind {
+                    peer_address = Some(server[event.peer_id].address());
+                    break;
+                }
+            }
+        }
+
+        assert_eq!(peer_address, Some(source));
+    }
+}
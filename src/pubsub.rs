@@ -0,0 +1,319 @@
+//! Named topics that peers subscribe to, and a host publishes to.
+//!
+//! Peers join and leave topics with [subscribe](fn.subscribe.html) and [unsubscribe](fn.unsubscribe.html).
+//! The publishing side passes its events through `Topics::handle_event`, which keeps track of the
+//! members of each topic, and sends to all members of a topic with `Topics::publish`. Subscribers
+//! recognize publications with [parse_publication](fn.parse_publication.html).
+//!
+//! This suits chat rooms, area-of-interest updates or spectators.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{Event, EventKind, Host, Packet, PacketMode, Peer, PeerID, SendError};
+
+/// Marks topic packets, so they can share a channel with other traffic.
+const MAGIC: &[u8] = b"\xffPUB";
+const KIND_SUBSCRIBE: u8 = 0;
+const KIND_UNSUBSCRIBE: u8 = 1;
+const KIND_PUBLISH: u8 = 2;
+
+/// The longest topic name, in bytes.
+pub const MAX_TOPIC_LEN: usize = 255;
+
+/// The topics a peer may subscribe to, unless changed with `Topics::set_subscription_limit`.
+pub const DEFAULT_SUBSCRIPTION_LIMIT: usize = 64;
+
+fn encode(kind: u8, topic: &str, payload: &[u8]) -> Vec<u8> {
+    assert!(
+        topic.len() <= MAX_TOPIC_LEN,
+        "topic names may be at most {} bytes",
+        MAX_TOPIC_LEN
+    );
+
+    let mut data = Vec::with_capacity(MAGIC.len() + 2 + topic.len() + payload.len());
+    data.extend_from_slice(MAGIC);
+    data.push(kind);
+    data.push(topic.len() as u8);
+    data.extend_from_slice(topic.as_bytes());
+    data.extend_from_slice(payload);
+    data
+}
+
+fn decode(data: &[u8]) -> Option<(u8, &str, &[u8])> {
+    let rest = data.strip_prefix(MAGIC)?;
+    let (&kind, rest) = rest.split_first()?;
+    let (&len, rest) = rest.split_first()?;
+    let len = usize::from(len);
+    if rest.len() < len {
+        return None;
+    }
+
+    let topic = std::str::from_utf8(&rest[..len]).ok()?;
+    Some((kind, topic, &rest[len..]))
+}
+
+fn send_control<T>(
+    peer: &mut Peer<T>,
+    channel_id: u8,
+    kind: u8,
+    topic: &str,
+) -> Result<(), SendError> {
+    let packet = Packet::new(encode(kind, topic, &[]), PacketMode::ReliableSequenced)
+        .map_err(|e| SendError::Error(e.0))?;
+    peer.send_packet(packet, channel_id)
+}
+
+/// Asks the publishing `peer` to add us to `topic`.
+///
+/// # Panics
+///
+/// Panics if `topic` is longer than `MAX_TOPIC_LEN` bytes.
+pub fn subscribe<T>(peer: &mut Peer<T>, channel_id: u8, topic: &str) -> Result<(), SendError> {
+    send_control(peer, channel_id, KIND_SUBSCRIBE, topic)
+}
+
+/// Asks the publishing `peer` to remove us from `topic`.
+///
+/// # Panics
+///
+/// Panics if `topic` is longer than `MAX_TOPIC_LEN` bytes.
+pub fn unsubscribe<T>(peer: &mut Peer<T>, channel_id: u8, topic: &str) -> Result<(), SendError> {
+    send_control(peer, channel_id, KIND_UNSUBSCRIBE, topic)
+}
+
+/// Splits a received packet into the topic it was published to, and the published data.
+///
+/// Returns `None` if the packet is not a publication.
+pub fn parse_publication(packet: &Packet) -> Option<(&str, &[u8])> {
+    match decode(packet.data())? {
+        (KIND_PUBLISH, topic, payload) => Some((topic, payload)),
+        _ => None,
+    }
+}
+
+/// The topics of a publishing `Host`, and the peers subscribed to each of them.
+#[derive(Debug)]
+pub struct Topics {
+    members: HashMap<String, HashSet<PeerID>>,
+    /// The number of topics each peer is subscribed to.
+    subscriptions: HashMap<PeerID, usize>,
+    subscription_limit: usize,
+}
+
+impl Default for Topics {
+    fn default() -> Topics {
+        Topics {
+            members: HashMap::new(),
+            subscriptions: HashMap::new(),
+            subscription_limit: DEFAULT_SUBSCRIPTION_LIMIT,
+        }
+    }
+}
+
+impl Topics {
+    /// Creates an empty set of topics.
+    pub fn new() -> Topics {
+        Topics::default()
+    }
+
+    /// Limits the topics a peer may subscribe itself to, so peers can't make this `Topics` grow
+    /// without bounds. Requests beyond the limit are ignored.
+    ///
+    /// Subscriptions made with `Topics::subscribe` are counted, but never refused. Lowering the
+    /// limit doesn't remove existing subscriptions. Defaults to `DEFAULT_SUBSCRIPTION_LIMIT`.
+    pub fn set_subscription_limit(&mut self, limit: usize) {
+        self.subscription_limit = limit;
+    }
+
+    /// Processes an event returned by `Host::service`.
+    ///
+    /// Returns `true` if the event was a subscription request, which needs no further handling.
+    /// Peers that disconnect are removed from all topics, and requests beyond the subscription
+    /// limit are ignored, see `Topics::set_subscription_limit`.
+    pub fn handle_event(&mut self, event: &Event) -> bool {
+        match event.kind {
+            EventKind::Receive { ref packet, .. } => match decode(packet.data()) {
+                Some((KIND_SUBSCRIBE, topic, _)) => {
+                    let count = self.subscriptions.get(&event.peer_id).copied();
+                    if count.unwrap_or(0) < self.subscription_limit {
+                        self.subscribe(event.peer_id, topic);
+                    }
+                    true
+                }
+                Some((KIND_UNSUBSCRIBE, topic, _)) => {
+                    self.unsubscribe(event.peer_id, topic);
+                    true
+                }
+                _ => false,
+            },
+            ref kind if kind.is_disconnect() => {
+                self.remove_peer(event.peer_id);
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Adds `peer_id` to `topic`, as if it had subscribed itself.
+    pub fn subscribe(&mut self, peer_id: PeerID, topic: &str) {
+        let added = self
+            .members
+            .entry(topic.to_string())
+            .or_default()
+            .insert(peer_id);
+        if added {
+            *self.subscriptions.entry(peer_id).or_default() += 1;
+        }
+    }
+
+    /// Removes `peer_id` from `topic`.
+    pub fn unsubscribe(&mut self, peer_id: PeerID, topic: &str) {
+        let members = match self.members.get_mut(topic) {
+            Some(members) => members,
+            None => return,
+        };
+        if !members.remove(&peer_id) {
+            return;
+        }
+        if members.is_empty() {
+            self.members.remove(topic);
+        }
+
+        if let Some(count) = self.subscriptions.get_mut(&peer_id) {
+            *count -= 1;
+            if *count == 0 {
+                self.subscriptions.remove(&peer_id);
+            }
+        }
+    }
+
+    /// Removes `peer_id` from all topics.
+    pub fn remove_peer(&mut self, peer_id: PeerID) {
+        if self.subscriptions.remove(&peer_id).is_none() {
+            return;
+        }
+        self.members.retain(|_, members| {
+            members.remove(&peer_id);
+            !members.is_empty()
+        });
+    }
+
+    /// Returns the peers subscribed to `topic`.
+    pub fn subscribers<'a>(&'a self, topic: &str) -> impl Iterator<Item = PeerID> + 'a {
+        self.members.get(topic).into_iter().flatten().cloned()
+    }
+
+    /// Returns the names of all topics with at least one subscriber.
+    pub fn topics(&self) -> impl Iterator<Item = &str> {
+        self.members.keys().map(String::as_str)
+    }
+
+    /// Sends `data` to all subscribers of `topic` on `channel_id`, returning how many peers it was sent to.
+    ///
+    /// Sending to an individual subscriber can fail, e.g. because it is disconnecting; such
+    /// subscribers are skipped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `topic` is longer than `MAX_TOPIC_LEN` bytes.
+    pub fn publish<T>(
+        &self,
+        host: &mut Host<T>,
+        topic: &str,
+        channel_id: u8,
+        data: &[u8],
+        mode: PacketMode,
+    ) -> usize {
+        let members = match self.members.get(topic) {
+            Some(members) => members,
+            None => return 0,
+        };
+
+        let data = encode(KIND_PUBLISH, topic, data);
+        members
+            .iter()
+            .filter(|&&peer_id| {
+                let packet = match Packet::new(data.clone(), mode) {
+                    Ok(packet) => packet,
+                    Err(_) => return false,
+                };
+
+                host.peer_mut(peer_id)
+                    .is_some_and(|peer| peer.send_packet(packet, channel_id).is_ok())
+            })
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, parse_publication, subscribe, unsubscribe, Topics, KIND_SUBSCRIBE};
+    use crate::tests::{connected_pair, pump_until};
+    use crate::{Event, EventKind, Packet, PacketMode, PeerID};
+
+    #[test]
+    fn test_publish_to_subscribers() {
+        let (mut server, mut client, _, peer_id) = connected_pair(12356, 1);
+        let mut topics = Topics::new();
+
+        subscribe(&mut client[peer_id], 0, "lobby").unwrap();
+        subscribe(&mut client[peer_id], 0, "spectators").unwrap();
+        unsubscribe(&mut client[peer_id], 0, "spectators").unwrap();
+        let mut received = None;
+        pump_until(&mut [&mut server, &mut client], |index, host, event| {
+            match event.kind {
+                EventKind::Receive { ref packet, .. } if index == 1 => {
+                    let (topic, data) = parse_publication(packet).unwrap();
+                    received = Some((topic.to_string(), data.to_vec()));
+                    return true;
+                }
+                _ => (),
+            }
+            if topics.handle_event(&event) && topics.subscribers("lobby").count() == 1 {
+                topics.publish(host, "lobby", 0, b"hi", PacketMode::ReliableSequenced);
+            }
+            false
+        });
+
+        assert_eq!(received, Some(("lobby".to_string(), b"hi".to_vec())));
+        assert_eq!(topics.topics().collect::<Vec<_>>(), vec!["lobby"]);
+
+        let subscriber = topics.subscribers("lobby").next().unwrap();
+        topics.remove_peer(subscriber);
+        assert_eq!(topics.topics().count(), 0);
+    }
+
+    #[test]
+    fn test_subscription_limit() {
+        let mut topics = Topics::new();
+        topics.set_subscription_limit(2);
+        let peer_id = PeerID {
+            index: 0,
+            host_id: 0,
+        };
+        let request = |topic: &str| Event {
+            peer_id,
+            kind: EventKind::Receive {
+                channel_id: 0,
+                packet: Packet::new(
+                    encode(KIND_SUBSCRIBE, topic, &[]),
+                    PacketMode::ReliableSequenced,
+                )
+                .unwrap(),
+            },
+        };
+
+        for topic in ["a", "b", "a", "c"] {
+            assert!(topics.handle_event(&request(topic)));
+        }
+        let mut subscribed: Vec<_> = topics.topics().collect();
+        subscribed.sort_unstable();
+        assert_eq!(subscribed, vec!["a", "b"]);
+
+        topics.unsubscribe(peer_id, "a");
+        topics.handle_event(&request("c"));
+        assert_eq!(topics.subscribers("c").count(), 1);
+        topics.subscribe(peer_id, "d");
+        assert_eq!(topics.subscribers("d").count(), 1);
+    }
+}
@@ -0,0 +1,304 @@
+//! Message passing between a host and the rest of an application.
+//!
+//! A `Host` must stay on the thread that services it. An [EventPump](struct.EventPump.html) runs
+//! on that thread and moves every event into a channel as an owned [PumpEvent](enum.PumpEvent.html),
+//! which other threads receive. They control the host by sending
+//! [PumpCommand](enum.PumpCommand.html)s, which the pump executes before servicing the host.
+//!
+//! The channels are `std::sync::mpsc` channels: commands can come from many senders, events go to
+//! one receiver. Sending doesn't take a lock, so many threads can send commands without contending
+//! with each other or with the pump, which drains them before every service. To share the events
+//! between several threads, wrap the receiver in a `Mutex`.
+//!
+//! ```no_run
+//! # use enet::*;
+//! # use enet::pump::{self, PumpCommand, PumpEvent};
+//! # use std::time::Duration;
+//! # fn run(mut host: Host<()>) -> Result<(), Error> {
+//! let (mut pump, commands, events) = pump::channel();
+//! std::thread::spawn(move || {
+//!     for event in events {
+//!         if let PumpEvent::Receive { peer_id, channel_id, data } = event {
+//!             // Echo every packet.
+//!             let mode = PacketMode::ReliableSequenced;
+//!             let command = PumpCommand::Send { peer_id, channel_id, data, mode };
+//!             if commands.send(command).is_err() {
+//!                 break;
+//!             }
+//!         }
+//!     }
+//! });
+//!
+//! while pump.pump(&mut host, Some(Duration::from_millis(10)))? {}
+//! # Ok(())
+//! # }
+//! ```
+
+use std::net::Ipv4Addr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+
+use crate::{Address, Error, Event, EventKind, HostLike, Packet, PacketMode, PeerID};
+
+/// An event of the host, with the packet data copied out of ENet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PumpEvent {
+    /// A peer connected, see `EventKind::Connect`.
+    Connect {
+        /// The peer that connected.
+        peer_id: PeerID,
+        /// The address of the peer.
+        address: Address,
+    },
+    /// A connection attempt timed out, see `EventKind::ConnectTimeout`.
+    ConnectTimeout {
+        /// The peer that didn't connect.
+        peer_id: PeerID,
+    },
+    /// A peer disconnected, see `EventKind::Disconnect`.
+    Disconnect {
+        /// The peer that disconnected.
+        peer_id: PeerID,
+        /// The data sent with the disconnect.
+        data: u32,
+    },
+    /// A packet was received, see `EventKind::Receive`.
+    Receive {
+        /// The peer that sent the packet.
+        peer_id: PeerID,
+        /// The channel the packet was received on.
+        channel_id: u8,
+        /// The contents of the packet.
+        data: Vec<u8>,
+    },
+    /// A packet was dropped by a rate limit, see `EventKind::RateLimited`.
+    RateLimited {
+        /// The peer that sent the packet.
+        peer_id: PeerID,
+        /// The channel of the packet.
+        channel_id: u8,
+    },
+}
+
+impl PumpEvent {
+    fn new<T>(host: &impl HostLike<T>, event: Event) -> PumpEvent {
+        let peer_id = event.peer_id;
+        match event.kind {
+            EventKind::Connect => PumpEvent::Connect {
+                peer_id,
+                address: host
+                    .peer_address(peer_id)
+                    .unwrap_or_else(|| Address::new(Ipv4Addr::UNSPECIFIED, 0)),
+            },
+            EventKind::ConnectTimeout => PumpEvent::ConnectTimeout { peer_id },
+            EventKind::Disconnect { data } => PumpEvent::Disconnect { peer_id, data },
+            EventKind::Receive { channel_id, packet } => PumpEvent::Receive {
+                peer_id,
+                channel_id,
+                data: packet.data().to_vec(),
+            },
+            EventKind::RateLimited { channel_id } => PumpEvent::RateLimited {
+                peer_id,
+                channel_id,
+            },
+        }
+    }
+}
+
+/// A command for the host, sent to an `EventPump`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PumpCommand {
+    /// Connects to `address`, see `Host::connect`. The peer is reported by its `Connect` event.
+    Connect {
+        /// The address to connect to.
+        address: Address,
+        /// The number of channels to allocate.
+        channel_count: usize,
+        /// The data sent with the connect.
+        data: u32,
+    },
+    /// Sends `data` to `peer_id`, see `Peer::send_packet`.
+    Send {
+        /// The peer to send to.
+        peer_id: PeerID,
+        /// The channel to send on.
+        channel_id: u8,
+        /// The contents of the packet.
+        data: Vec<u8>,
+        /// The mode to send the packet with.
+        mode: PacketMode,
+    },
+    /// Sends `data` to all connected peers.
+    Broadcast {
+        /// The channel to send on.
+        channel_id: u8,
+        /// The contents of the packet.
+        data: Vec<u8>,
+        /// The mode to send the packet with.
+        mode: PacketMode,
+    },
+    /// Disconnects from `peer_id`, see `Peer::disconnect`.
+    Disconnect {
+        /// The peer to disconnect from.
+        peer_id: PeerID,
+        /// The data sent with the disconnect.
+        data: u32,
+    },
+}
+
+/// Creates an `EventPump`, the sender of its commands and the receiver of its events.
+pub fn channel() -> (EventPump, Sender<PumpCommand>, Receiver<PumpEvent>) {
+    let (command_sender, commands) = mpsc::channel();
+    let (events, event_receiver) = mpsc::channel();
+    let pump = EventPump {
+        events,
+        commands,
+        failed_commands: 0,
+    };
+    (pump, command_sender, event_receiver)
+}
+
+/// Moves the events of a host into a channel, and executes the commands sent to it.
+#[derive(Debug)]
+pub struct EventPump {
+    events: Sender<PumpEvent>,
+    commands: Receiver<PumpCommand>,
+    failed_commands: u64,
+}
+
+impl EventPump {
+    /// Returns the number of commands that failed, e.g. sends to peers that are gone.
+    pub fn failed_commands(&self) -> u64 {
+        self.failed_commands
+    }
+
+    /// Executes the pending commands, then services `host` and sends its events on.
+    ///
+    /// Waits at most `timeout` for the first event, like `Host::service`, then passes on the
+    /// events that are already available. Returns `false` once the receiver of the events is
+    /// gone, i.e. there is no one left to pump for.
+    pub fn pump<T, H: HostLike<T>>(
+        &mut self,
+        host: &mut H,
+        timeout: Option<Duration>,
+    ) -> Result<bool, Error> {
+        // Commands stop once all senders are gone, but the events keep flowing.
+        while let Ok(command) = self.commands.try_recv() {
+            self.execute(host, command);
+        }
+
+        let mut event = host.service(timeout)?;
+        while let Some(next) = event {
+            if self.events.send(PumpEvent::new(host, next)).is_err() {
+                return Ok(false);
+            }
+            event = host.service(Some(Duration::ZERO))?;
+        }
+        Ok(true)
+    }
+
+    fn execute<T, H: HostLike<T>>(&mut self, host: &mut H, command: PumpCommand) {
+        let succeeded = match command {
+            PumpCommand::Connect {
+                address,
+                channel_count,
+                data,
+            } => host.connect(&address, channel_count, data).is_ok(),
+            PumpCommand::Send {
+                peer_id,
+                channel_id,
+                data,
+                mode,
+            } => Packet::new(data, mode)
+                .is_ok_and(|packet| host.send(peer_id, channel_id, packet).is_ok()),
+            PumpCommand::Broadcast {
+                channel_id,
+                data,
+                mode,
+            } => {
+                host.broadcast(channel_id, &data, mode);
+                true
+            }
+            PumpCommand::Disconnect { peer_id, data } => {
+                host.disconnect(peer_id, data);
+                true
+            }
+        };
+
+        if !succeeded {
+            self.failed_commands += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PumpCommand, PumpEvent};
+    use crate::tests::create_host;
+    use crate::{Address, EventKind, Packet, PacketMode};
+
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    #[test]
+    fn test_event_pump() {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12382);
+        let mut server = create_host(Some(&server_address), 1);
+        let mut client = create_host(None, 1);
+        client.connect(&server_address, 1, 0).unwrap();
+
+        let (mut pump, commands, events) = super::channel();
+        let consumer = std::thread::spawn(move || {
+            let mut received = Vec::new();
+            for event in events {
+                match event {
+                    PumpEvent::Connect { peer_id, address } => {
+                        assert_eq!(*address.ip(), Ipv4Addr::LOCALHOST);
+                        let command = PumpCommand::Send {
+                            peer_id,
+                            channel_id: 0,
+                            data: b"welcome".to_vec(),
+                            mode: PacketMode::ReliableSequenced,
+                        };
+                        commands.send(command).unwrap();
+                    }
+                    PumpEvent::Receive { data, .. } => {
+                        received.push(data);
+                        break;
+                    }
+                    _ => (),
+                }
+            }
+            received
+        });
+
+        let timeout = Some(Duration::from_millis(2));
+        let mut welcomed = false;
+        for _ in 0..500 {
+            // Keeps servicing the server after the consumer is done, to send the welcome.
+            pump.pump(&mut server, timeout).unwrap();
+            if let Some(event) = client.service(timeout).unwrap() {
+                match event.kind {
+                    EventKind::Connect => {
+                        let packet = Packet::new(b"hi".to_vec(), PacketMode::ReliableSequenced);
+                        client[event.peer_id]
+                            .send_packet(packet.unwrap(), 0)
+                            .unwrap();
+                    }
+                    EventKind::Receive { ref packet, .. } => {
+                        assert_eq!(packet.data(), b"welcome");
+                        welcomed = true;
+                    }
+                    _ => (),
+                }
+            }
+            if welcomed && consumer.is_finished() {
+                break;
+            }
+        }
+
+        assert!(welcomed);
+        assert_eq!(consumer.join().unwrap(), vec![b"hi".to_vec()]);
+        assert_eq!(pump.failed_commands(), 0);
+    }
+}
@@ -0,0 +1,326 @@
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::{Datagram, Intercept, InterceptAction};
+
+/// Marks both query and response datagrams. ENet never sends datagrams starting like this.
+const MAGIC: &[u8] = b"\xff\xff\xff\xffENETINFO";
+const KIND_QUERY: u8 = b'?';
+const KIND_RESPONSE: u8 = b'!';
+
+/// The size of query datagrams.
+///
+/// Responses are never larger than queries, so a responder can't be abused to amplify traffic
+/// towards a spoofed address.
+pub const QUERY_SIZE: usize = 1024;
+
+/// The longest string a response can contain, in bytes.
+const MAX_STRING_LEN: usize = 255;
+
+/// The information a `QueryResponder` reports about a server.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerInfo {
+    /// The name of the server.
+    pub name: String,
+    /// The map, level or mode currently played.
+    pub map: String,
+    /// The version of the game.
+    pub version: String,
+}
+
+/// A parsed answer to a server info query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryResponse {
+    /// The server's information.
+    pub info: ServerInfo,
+    /// The number of currently connected peers.
+    pub players: u32,
+    /// The maximum number of peers.
+    pub max_players: u32,
+}
+
+impl QueryResponse {
+    /// Parses a datagram received in reply to a [query_datagram](fn.query_datagram.html).
+    ///
+    /// Returns `None` if the datagram is not a server info response.
+    pub fn parse(datagram: &[u8]) -> Option<QueryResponse> {
+        let rest = datagram.strip_prefix(MAGIC)?;
+        let (&kind, rest) = rest.split_first()?;
+        if kind != KIND_RESPONSE || rest.len() < 8 {
+            return None;
+        }
+
+        let players = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]);
+        let max_players = u32::from_be_bytes([rest[4], rest[5], rest[6], rest[7]]);
+
+        let mut rest = &rest[8..];
+        let mut read_string = || {
+            let (&len, tail) = rest.split_first()?;
+            let len = usize::from(len);
+            if tail.len() < len {
+                return None;
+            }
+
+            let string = String::from_utf8_lossy(&tail[..len]).into_owned();
+            rest = &tail[len..];
+            Some(string)
+        };
+
+        Some(QueryResponse {
+            info: ServerInfo {
+                name: read_string()?,
+                map: read_string()?,
+                version: read_string()?,
+            },
+            players,
+            max_players,
+        })
+    }
+}
+
+/// Returns a server info query, to be sent to the address of a `Host` with a `QueryResponder`.
+pub fn query_datagram() -> Vec<u8> {
+    let mut datagram = MAGIC.to_vec();
+    datagram.push(KIND_QUERY);
+    datagram.resize(QUERY_SIZE, 0);
+    datagram
+}
+
+/// Finds the servers listening at `address` by sending it a query, and collects the responses
+/// that arrive within `timeout`.
+///
+/// For LAN discovery, `address` is the broadcast address with the port of the servers, e.g.
+/// `(Ipv4Addr::BROADCAST, port)`. The servers must answer queries, see `Host::set_server_info`.
+pub fn discover_servers(
+    address: SocketAddr,
+    timeout: Duration,
+) -> io::Result<Vec<(SocketAddr, QueryResponse)>> {
+    let local: SocketAddr = match address {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.set_broadcast(true)?;
+    socket.send_to(&query_datagram(), address)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut servers: Vec<(SocketAddr, QueryResponse)> = Vec::new();
+    let mut buffer = [0; QUERY_SIZE];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::ZERO {
+            break;
+        }
+        socket.set_read_timeout(Some(remaining))?;
+
+        match socket.recv_from(&mut buffer) {
+            Ok((len, from)) => {
+                if let Some(response) = QueryResponse::parse(&buffer[..len]) {
+                    // A server reachable on several interfaces may answer more than once.
+                    if !servers.iter().any(|&(server, _)| server == from) {
+                        servers.push((from, response));
+                    }
+                }
+            }
+            Err(ref e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                break
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(servers)
+}
+
+fn write_string(out: &mut Vec<u8>, string: &str) {
+    let mut len = string.len().min(MAX_STRING_LEN);
+    while !string.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    out.push(len as u8);
+    out.extend_from_slice(&string.as_bytes()[..len]);
+}
+
+/// An `Intercept` that answers server info queries, without allocating a peer for the querying side.
+///
+/// `Host::set_server_info` manages a responder for the host, which is usually simpler than adding
+/// one directly.
+///
+/// Server browsers send a [query_datagram](fn.query_datagram.html) to the address of a `Host`
+/// and receive the `ServerInfo` along with its current player count, which they can read with
+/// [QueryResponse::parse](struct.QueryResponse.html#method.parse).
+///
+/// ```no_run
+/// # use enet::*;
+/// # fn add(host: &mut Host<()>) {
+/// let responder = QueryResponder::new(ServerInfo {
+///     name: "My server".into(),
+///     map: "de_dust".into(),
+///     version: "1.0".into(),
+/// });
+/// let info = responder.info();
+/// host.add_intercept(responder);
+///
+/// // Later on, e.g. after a map change:
+/// info.lock().unwrap().map = "cs_office".into();
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct QueryResponder {
+    info: Arc<Mutex<ServerInfo>>,
+}
+
+impl QueryResponder {
+    /// Creates a responder that reports `info`.
+    pub fn new(info: ServerInfo) -> QueryResponder {
+        QueryResponder {
+            info: Arc::new(Mutex::new(info)),
+        }
+    }
+
+    /// Returns the shared `ServerInfo`, to update it while the responder is in use.
+    pub fn info(&self) -> Arc<Mutex<ServerInfo>> {
+        self.info.clone()
+    }
+
+    fn response(&self, players: usize, max_players: usize) -> Vec<u8> {
+        let info = self.info.lock().unwrap_or_else(|e| e.into_inner());
+
+        let mut response = MAGIC.to_vec();
+        response.push(KIND_RESPONSE);
+        response.extend_from_slice(&(players as u32).to_be_bytes());
+        response.extend_from_slice(&(max_players as u32).to_be_bytes());
+        write_string(&mut response, &info.name);
+        write_string(&mut response, &info.map);
+        write_string(&mut response, &info.version);
+        response
+    }
+}
+
+impl Intercept for QueryResponder {
+    fn intercept(&mut self, datagram: &mut Datagram) -> InterceptAction {
+        let data = datagram.data();
+        if !data.starts_with(MAGIC) {
+            return InterceptAction::Continue;
+        }
+
+        // Undersized queries could be used for amplification, and responses shouldn't trigger responses.
+        if data.len() >= QUERY_SIZE && data[MAGIC.len()] == KIND_QUERY {
+            let (players, max_players) = datagram.peer_counts();
+            let response = self.response(players, max_players);
+            let _ = datagram.reply(&response);
+        }
+
+        InterceptAction::Drop
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        discover_servers, query_datagram, QueryResponder, QueryResponse, ServerInfo, QUERY_SIZE,
+    };
+    use crate::tests::ENET;
+    use crate::{Address, BandwidthLimit, ChannelLimit};
+
+    use std::net::{Ipv4Addr, UdpSocket};
+    use std::time::Duration;
+
+    #[test]
+    fn test_response_roundtrip() {
+        let info = ServerInfo {
+            name: "ä".repeat(200),
+            map: "map".into(),
+            version: String::new(),
+        };
+
+        let response = QueryResponder::new(info).response(3, 8);
+        assert!(response.len() <= QUERY_SIZE);
+
+        let parsed = QueryResponse::parse(&response).unwrap();
+        assert_eq!(parsed.info.name, "ä".repeat(127));
+        assert_eq!(parsed.info.map, "map");
+        assert_eq!((parsed.players, parsed.max_players), (3, 8));
+
+        assert_eq!(QueryResponse::parse(&query_datagram()), None);
+    }
+
+    #[test]
+    fn test_query_host() {
+        let mut host = ENET
+            .create_host::<()>(
+                Some(&Address::new(Ipv4Addr::LOCALHOST, 12352)),
+                4,
+                ChannelLimit::Maximum,
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap();
+        let responder = QueryResponder::new(ServerInfo::default());
+        responder.info().lock().unwrap().name = "test server".into();
+        host.add_intercept(responder);
+
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        socket
+            .send_to(&query_datagram(), (Ipv4Addr::LOCALHOST, 12352))
+            .unwrap();
+        host.service(Some(Duration::from_millis(100))).unwrap();
+
+        let mut buffer = [0; QUERY_SIZE];
+        let (len, _) = socket.recv_from(&mut buffer).unwrap();
+        let response = QueryResponse::parse(&buffer[..len]).unwrap();
+        assert_eq!(response.info.name, "test server");
+        assert_eq!((response.players, response.max_players), (0, 4));
+    }
+
+    #[test]
+    fn test_set_server_info() {
+        let mut host = ENET
+            .create_host::<()>(
+                Some(&Address::new(Ipv4Addr::LOCALHOST, 12376)),
+                2,
+                ChannelLimit::Maximum,
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap();
+        assert_eq!(host.server_info(), None);
+        host.set_server_info(ServerInfo {
+            name: "old name".into(),
+            ..ServerInfo::default()
+        });
+        let info = ServerInfo {
+            name: "lan server".into(),
+            map: "map".into(),
+            version: "2".into(),
+        };
+        host.set_server_info(info.clone());
+        assert_eq!(host.server_info(), Some(info.clone()));
+
+        let discovery = std::thread::spawn(|| {
+            discover_servers(
+                (Ipv4Addr::LOCALHOST, 12376).into(),
+                Duration::from_millis(300),
+            )
+        });
+        for _ in 0..30 {
+            host.service(Some(Duration::from_millis(10))).unwrap();
+        }
+
+        let servers = discovery.join().unwrap().unwrap();
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].0, (Ipv4Addr::LOCALHOST, 12376).into());
+        assert_eq!(servers[0].1.info, info);
+        assert_eq!(servers[0].1.max_players, 2);
+
+        host.clear_intercepts();
+        assert_eq!(host.server_info(), None);
+    }
+}
@@ -0,0 +1,77 @@
+/// What happens to a packet sent while the queue of its peer is over its `QueueLimit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QueuePolicy {
+    /// The send fails with `SendError::WouldBlock`, so the caller can retry later.
+    Reject,
+    /// Unreliable packets are dropped silently, as if the network lost them. Reliable packets
+    /// are rejected like with `Reject`.
+    DropUnreliable,
+    /// The peer is disconnected with the contained data, dropping its queue, and the send fails
+    /// with `SendError::WouldBlock`.
+    Disconnect(u32),
+}
+
+/// A cap on the bytes ENet queues for a peer, see `Host::set_queue_limit`.
+///
+/// Without a cap, packets sent faster than a slow peer can receive them pile up in ENet's queues
+/// until the host runs out of memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct QueueLimit {
+    /// The number of bytes that may wait to be sent to a peer, see `Peer::queued_bytes`.
+    pub max_bytes: usize,
+    /// What happens to packets that would exceed `max_bytes`.
+    pub policy: QueuePolicy,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QueueLimit, QueuePolicy};
+    use crate::tests::connected_pair;
+    use crate::{Packet, PacketMode, PeerState, SendError};
+
+    use std::time::Duration;
+
+    #[test]
+    fn test_queue_limit() {
+        let (_server, mut client, _, peer_id) = connected_pair(12389, 1);
+        let timeout = Some(Duration::from_millis(2));
+
+        client.set_queue_limit(Some(QueueLimit {
+            max_bytes: 2500,
+            policy: QueuePolicy::DropUnreliable,
+        }));
+
+        let peer = &mut client[peer_id];
+        let packet = |mode| Packet::new(vec![0; 1000], mode).unwrap();
+        peer.send_packet(packet(PacketMode::ReliableSequenced), 0)
+            .unwrap();
+        peer.send_packet(packet(PacketMode::UnreliableSequenced), 0)
+            .unwrap();
+        assert_eq!(peer.queued_bytes(), 2000);
+
+        peer.send_packet(packet(PacketMode::UnreliableSequenced), 0)
+            .unwrap();
+        match peer.send_packet(packet(PacketMode::ReliableSequenced), 0) {
+            Err(SendError::WouldBlock) => (),
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert_eq!(peer.queued_bytes(), 2000);
+
+        // Servicing the host sends the queue, which makes room again.
+        client.service(timeout).unwrap();
+        let peer = &mut client[peer_id];
+        assert_eq!(peer.queued_bytes(), 0);
+        peer.send_packet(packet(PacketMode::ReliableSequenced), 0)
+            .unwrap();
+
+        peer.set_queue_limit(Some(QueueLimit {
+            max_bytes: 0,
+            policy: QueuePolicy::Disconnect(7),
+        }));
+        match peer.send_packet(packet(PacketMode::ReliableSequenced), 0) {
+            Err(SendError::WouldBlock) => (),
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert_eq!(peer.state(), PeerState::Disconnecting);
+    }
+}
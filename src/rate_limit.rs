@@ -0,0 +1,96 @@
+use std::time::Instant;
+
+/// What happens to a packet that exceeds the rate limit of its channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateLimitAction {
+    /// The packet is dropped silently.
+    Drop,
+    /// The packet is dropped, and an `EventKind::RateLimited` is delivered in its place.
+    Warn,
+    /// The packet is dropped, and the peer is disconnected with the contained data.
+    Disconnect(u32),
+}
+
+/// A limit on the packets a channel receives from each peer, see `Host::set_rate_limit`.
+///
+/// Peers may send bursts of up to a second's worth of packets, as long as they stay within the
+/// limit on average.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RateLimit {
+    /// The number of packets per second a peer may send.
+    pub messages_per_second: u32,
+    /// The number of bytes per second a peer may send.
+    pub bytes_per_second: u32,
+    /// What happens to packets over the limit.
+    pub action: RateLimitAction,
+}
+
+/// Token buckets for the packets and bytes of one peer on one channel.
+#[derive(Debug)]
+pub(crate) struct Bucket {
+    messages: f64,
+    bytes: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    pub(crate) fn new(limit: &RateLimit, now: Instant) -> Bucket {
+        Bucket {
+            messages: f64::from(limit.messages_per_second),
+            bytes: f64::from(limit.bytes_per_second),
+            last: now,
+        }
+    }
+
+    /// Returns whether a packet of `len` bytes is within `limit`, and takes it from the buckets if so.
+    pub(crate) fn admit(&mut self, limit: &RateLimit, now: Instant, len: usize) -> bool {
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+
+        let messages_per_second = f64::from(limit.messages_per_second);
+        let bytes_per_second = f64::from(limit.bytes_per_second);
+        self.messages = (self.messages + elapsed * messages_per_second).min(messages_per_second);
+        self.bytes = (self.bytes + elapsed * bytes_per_second).min(bytes_per_second);
+
+        let len = len as f64;
+        if self.messages < 1.0 || self.bytes < len {
+            return false;
+        }
+
+        self.messages -= 1.0;
+        self.bytes -= len;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bucket, RateLimit, RateLimitAction};
+
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_bucket() {
+        let limit = RateLimit {
+            messages_per_second: 10,
+            bytes_per_second: 1000,
+            action: RateLimitAction::Drop,
+        };
+        let start = Instant::now();
+        let mut bucket = Bucket::new(&limit, start);
+
+        // A burst of a second's worth of packets passes, the next one doesn't.
+        assert!((0..10).all(|_| bucket.admit(&limit, start, 10)));
+        assert!(!bucket.admit(&limit, start, 10));
+
+        // After 100ms, there is room for one more packet.
+        let later = start + Duration::from_millis(100);
+        assert!(bucket.admit(&limit, later, 10));
+        assert!(!bucket.admit(&limit, later, 10));
+
+        // Bytes are limited as well.
+        let much_later = start + Duration::from_secs(10);
+        assert!(!bucket.admit(&limit, much_later, 1001));
+        assert!(bucket.admit(&limit, much_later, 1000));
+    }
+}
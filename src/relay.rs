@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::rate_limit::Bucket;
+use crate::{Event, EventKind, Host, Packet, PeerID, RateLimit, RateLimitAction};
+
+/// The traffic a `Relay` forwarded from one peer of a pair to the other.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct RelayStats {
+    /// The number of packets forwarded.
+    pub packets: u64,
+    /// The number of bytes forwarded.
+    pub bytes: u64,
+    /// The number of packets dropped, because they exceeded the pair's bandwidth limit or could
+    /// not be sent.
+    pub dropped: u64,
+}
+
+#[derive(Debug)]
+struct Link {
+    partner: PeerID,
+    channels: HashMap<u8, u8>,
+    stats: RelayStats,
+    bucket: Option<Bucket>,
+}
+
+/// Forwards packets between pairs of peers of a `Host`, e.g. for players that can't reach each
+/// other directly.
+///
+/// Packets a paired peer sends are forwarded to its partner with the same mode, and on the same
+/// channel unless mapped otherwise with `map_channel`. Pairs end when either peer disconnects.
+#[derive(Debug, Default)]
+pub struct Relay {
+    links: HashMap<PeerID, Link>,
+    limit: Option<RateLimit>,
+}
+
+impl Relay {
+    /// Creates a relay without any pairs.
+    pub fn new() -> Relay {
+        Relay::default()
+    }
+
+    /// Pairs `a` and `b`, unpairing them from their previous partners.
+    pub fn pair(&mut self, a: PeerID, b: PeerID) {
+        self.unpair(a);
+        self.unpair(b);
+
+        let now = Instant::now();
+        for &(peer_id, partner) in &[(a, b), (b, a)] {
+            let link = Link {
+                partner,
+                channels: HashMap::new(),
+                stats: RelayStats::default(),
+                bucket: self.limit.as_ref().map(|limit| Bucket::new(limit, now)),
+            };
+            self.links.insert(peer_id, link);
+        }
+    }
+
+    /// Ends the pair of `peer_id`, returning its partner.
+    pub fn unpair(&mut self, peer_id: PeerID) -> Option<PeerID> {
+        let partner = self.links.remove(&peer_id)?.partner;
+        self.links.remove(&partner);
+        Some(partner)
+    }
+
+    /// Returns the partner of `peer_id`, if it is paired.
+    pub fn partner(&self, peer_id: PeerID) -> Option<PeerID> {
+        self.links.get(&peer_id).map(|link| link.partner)
+    }
+
+    /// Forwards the packets `from` sends on `incoming` to its partner on `outgoing`.
+    ///
+    /// Returns `false` if `from` is not paired. The mapping ends with the pair.
+    pub fn map_channel(&mut self, from: PeerID, incoming: u8, outgoing: u8) -> bool {
+        match self.links.get_mut(&from) {
+            Some(link) => {
+                link.channels.insert(incoming, outgoing);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the traffic forwarded from `from` to its partner, if it is paired.
+    pub fn stats(&self, from: PeerID) -> Option<RelayStats> {
+        self.links.get(&from).map(|link| link.stats)
+    }
+
+    /// Limits the bytes per second forwarded in each direction of a pair, or lifts the limit for
+    /// `None`.
+    ///
+    /// The limit applies to pairs made afterwards. Packets over the limit are dropped.
+    pub fn set_bandwidth_limit(&mut self, bytes_per_second: Option<u32>) {
+        self.limit = bytes_per_second.map(|bytes_per_second| RateLimit {
+            messages_per_second: u32::MAX,
+            bytes_per_second,
+            action: RateLimitAction::Drop,
+        });
+    }
+
+    /// Processes an event returned by `Host::service`.
+    ///
+    /// Returns `true` if the event was a packet that was forwarded, or dropped, and needs no
+    /// further handling. Disconnects end the pair, but are left to the caller.
+    pub fn handle_event<T>(&mut self, host: &mut Host<T>, event: &Event) -> bool {
+        let peer_id = event.peer_id;
+        match event.kind {
+            EventKind::Receive {
+                channel_id,
+                ref packet,
+            } => {
+                let link = match self.links.get_mut(&peer_id) {
+                    Some(link) => link,
+                    None => return false,
+                };
+
+                let len = packet.data().len();
+                let within_limit = match (&mut link.bucket, &self.limit) {
+                    (Some(bucket), Some(limit)) => bucket.admit(limit, Instant::now(), len),
+                    _ => true,
+                };
+                let outgoing = link
+                    .channels
+                    .get(&channel_id)
+                    .cloned()
+                    .unwrap_or(channel_id);
+                let sent = within_limit
+                    && Packet::new(packet.data().to_vec(), packet.mode())
+                        .ok()
+                        .zip(host.peer_mut(link.partner))
+                        .is_some_and(|(packet, partner)| {
+                            partner.send_packet(packet, outgoing).is_ok()
+                        });
+
+                if sent {
+                    link.stats.packets += 1;
+                    link.stats.bytes += len as u64;
+                } else {
+                    link.stats.dropped += 1;
+                }
+                true
+            }
+            ref kind if kind.is_disconnect() => {
+                self.unpair(peer_id);
+                false
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Relay, RelayStats};
+    use crate::tests::{create_host, pump_until};
+    use crate::{Address, EventKind, Packet, PacketMode};
+
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_relay() {
+        let relay_address = Address::new(Ipv4Addr::LOCALHOST, 12368);
+        let mut host = create_host(Some(&relay_address), 2);
+        let mut a = create_host(None, 1);
+        let mut b = create_host(None, 1);
+        let a_peer = a.connect(&relay_address, 2, 0).unwrap().1;
+        b.connect(&relay_address, 2, 0).unwrap();
+
+        let mut relay = Relay::new();
+        let mut connected = Vec::new();
+        pump_until(&mut [&mut host, &mut a, &mut b], |index, host, event| {
+            if index == 0 {
+                if let EventKind::Connect = event.kind {
+                    connected.push(event.peer_id);
+                    if let [first, second] = connected[..] {
+                        relay.pair(first, second);
+                        relay.map_channel(first, 0, 1);
+                        relay.map_channel(second, 0, 1);
+                    }
+                }
+                relay.handle_event(host, &event);
+            }
+            connected.len() == 2
+        });
+
+        // Once paired, packets from channel 0 reach the partner on the mapped channel 1.
+        let packet = Packet::new(b"hello".to_vec(), PacketMode::ReliableSequenced);
+        a[a_peer].send_packet(packet.unwrap(), 0).unwrap();
+        let mut received = None;
+        pump_until(&mut [&mut host, &mut a, &mut b], |index, host, event| {
+            if index == 0 {
+                relay.handle_event(host, &event);
+            } else if let (2, EventKind::Receive { channel_id, packet }) = (index, event.kind) {
+                received = Some((channel_id, packet.data().to_vec(), packet.mode()));
+            }
+            received.is_some()
+        });
+
+        assert_eq!(
+            received,
+            Some((1, b"hello".to_vec(), PacketMode::ReliableSequenced))
+        );
+        let from_a = connected
+            .iter()
+            .filter_map(|&peer_id| relay.stats(peer_id))
+            .find(|stats| stats.packets > 0);
+        assert_eq!(
+            from_a,
+            Some(RelayStats {
+                packets: 1,
+                bytes: 5,
+                dropped: 0,
+            })
+        );
+
+        assert_eq!(relay.unpair(connected[0]), Some(connected[1]));
+        assert_eq!(relay.partner(connected[1]), None);
+    }
+}
@@ -0,0 +1,343 @@
+//! Snapshot replication with deltas against acknowledged baselines.
+//!
+//! A [Replicator](struct.Replicator.html) sends each peer snapshots of the state that peer sees.
+//! Once a peer acknowledged a snapshot, later snapshots are encoded as deltas against it, using a
+//! function the application provides. If the peer's baseline is too old, e.g. after losing
+//! several snapshots, a full snapshot is sent instead. A
+//! [ReplicaReceiver](struct.ReplicaReceiver.html) decodes the snapshots again, and acknowledges
+//! them.
+//!
+//! Snapshots are sent unreliably, since only the latest one matters. Acknowledgements are part of
+//! this protocol, on the same channel.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::{Event, EventKind, Host, Packet, PacketMode, PeerID};
+
+/// Marks replication packets.
+const MAGIC: &[u8] = b"\xffREP";
+const KIND_FULL: u8 = 0;
+const KIND_DELTA: u8 = 1;
+const KIND_ACK: u8 = 2;
+
+/// Identifies a snapshot sent to a peer. Sequences increase by one with every snapshot.
+pub type Sequence = u32;
+
+/// Decodes a delta against a baseline, returning `None` if it is invalid.
+type DeltaDecoder = Box<dyn FnMut(&[u8], &[u8]) -> Option<Vec<u8>>>;
+
+/// How a snapshot was sent by `Replicator::send`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    /// The snapshot was sent in full.
+    Full,
+    /// The snapshot was sent as a delta against the acknowledged snapshot `baseline`.
+    Delta {
+        /// The sequence of the baseline.
+        baseline: Sequence,
+    },
+}
+
+fn header(kind: u8, sequence: Sequence) -> Vec<u8> {
+    let mut data = MAGIC.to_vec();
+    data.push(kind);
+    data.extend_from_slice(&sequence.to_be_bytes());
+    data
+}
+
+fn read_sequence(data: &[u8]) -> Option<(Sequence, &[u8])> {
+    if data.len() < 4 {
+        return None;
+    }
+    let (sequence, rest) = data.split_at(4);
+    Some((
+        Sequence::from_be_bytes([sequence[0], sequence[1], sequence[2], sequence[3]]),
+        rest,
+    ))
+}
+
+#[derive(Debug, Default)]
+struct Baselines {
+    next_sequence: Sequence,
+    acked: Option<Sequence>,
+    sent: VecDeque<(Sequence, Vec<u8>)>,
+}
+
+/// Sends snapshots to peers, as deltas against their acknowledged baselines where possible.
+#[derive(Debug)]
+pub struct Replicator {
+    channel_id: u8,
+    history: usize,
+    peers: HashMap<PeerID, Baselines>,
+}
+
+impl Replicator {
+    /// Creates a replicator on `channel_id`, keeping the last `history` snapshots of every peer
+    /// as possible baselines.
+    pub fn new(channel_id: u8, history: usize) -> Replicator {
+        Replicator {
+            channel_id,
+            history: history.max(1),
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Returns the latest snapshot `peer_id` acknowledged.
+    pub fn acked(&self, peer_id: PeerID) -> Option<Sequence> {
+        self.peers.get(&peer_id)?.acked
+    }
+
+    /// Sends `snapshot` to `peer_id`, encoding it as a delta with `encode_delta` if the peer has
+    /// an acknowledged baseline.
+    ///
+    /// `encode_delta` gets the baseline and the snapshot. Returns how the snapshot was encoded,
+    /// or `None` if it couldn't be sent, e.g. because the peer is disconnecting.
+    pub fn send<T, F>(
+        &mut self,
+        host: &mut Host<T>,
+        peer_id: PeerID,
+        snapshot: &[u8],
+        encode_delta: F,
+    ) -> Option<Encoding>
+    where
+        F: FnOnce(&[u8], &[u8]) -> Vec<u8>,
+    {
+        let baselines = self.peers.entry(peer_id).or_default();
+        let sequence = baselines.next_sequence;
+        baselines.next_sequence = sequence.wrapping_add(1);
+
+        let baseline = baselines
+            .acked
+            .and_then(|acked| baselines.sent.iter().find(|&&(sent, _)| sent == acked));
+        let (data, encoding) = match baseline {
+            Some(&(baseline, ref data)) => {
+                let mut packet = header(KIND_DELTA, sequence);
+                packet.extend_from_slice(&baseline.to_be_bytes());
+                packet.extend_from_slice(&encode_delta(data, snapshot));
+                (packet, Encoding::Delta { baseline })
+            }
+            None => {
+                let mut packet = header(KIND_FULL, sequence);
+                packet.extend_from_slice(snapshot);
+                (packet, Encoding::Full)
+            }
+        };
+
+        if baselines.sent.len() == self.history {
+            baselines.sent.pop_front();
+        }
+        baselines.sent.push_back((sequence, snapshot.to_vec()));
+
+        let packet = Packet::new(data, PacketMode::UnreliableSequenced).ok()?;
+        host.peer_mut(peer_id)?
+            .send_packet(packet, self.channel_id)
+            .ok()?;
+        Some(encoding)
+    }
+
+    /// Processes an event returned by `Host::service`.
+    ///
+    /// Returns `true` if the event was an acknowledgement, which needs no further handling. Peers
+    /// that disconnect are forgotten, but the disconnect is left to the caller.
+    pub fn handle_event(&mut self, event: &Event) -> bool {
+        match event.kind {
+            EventKind::Receive {
+                channel_id,
+                ref packet,
+            } if channel_id == self.channel_id => {
+                let acked = packet
+                    .data()
+                    .strip_prefix(MAGIC)
+                    .and_then(|data| data.strip_prefix(&[KIND_ACK]))
+                    .and_then(read_sequence);
+                if let (Some((sequence, _)), Some(baselines)) =
+                    (acked, self.peers.get_mut(&event.peer_id))
+                {
+                    // Acknowledgements may arrive out of order, only newer ones move the baseline.
+                    let newer = baselines
+                        .acked
+                        .map_or(true, |acked| (sequence.wrapping_sub(acked) as i32) > 0);
+                    if newer {
+                        baselines.acked = Some(sequence);
+                    }
+                }
+                true
+            }
+            ref kind if kind.is_disconnect() => {
+                self.peers.remove(&event.peer_id);
+                false
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Receives snapshots sent by a `Replicator`, and acknowledges them.
+pub struct ReplicaReceiver {
+    channel_id: u8,
+    history: usize,
+    decode_delta: DeltaDecoder,
+    received: VecDeque<(Sequence, Vec<u8>)>,
+    latest: Option<Sequence>,
+    updated: bool,
+}
+
+impl ReplicaReceiver {
+    /// Creates a receiver on `channel_id`, decoding deltas with `decode_delta`.
+    ///
+    /// `decode_delta` gets the baseline and the delta, and returns the snapshot. `history` must
+    /// be at least the history of the `Replicator`, so every baseline it picks is still known.
+    pub fn new<F>(channel_id: u8, history: usize, decode_delta: F) -> ReplicaReceiver
+    where
+        F: FnMut(&[u8], &[u8]) -> Option<Vec<u8>> + 'static,
+    {
+        ReplicaReceiver {
+            channel_id,
+            history: history.max(1),
+            decode_delta: Box::new(decode_delta),
+            received: VecDeque::new(),
+            latest: None,
+            updated: false,
+        }
+    }
+
+    /// Returns the latest snapshot, with its sequence.
+    pub fn latest(&self) -> Option<(Sequence, &[u8])> {
+        let latest = self.latest?;
+        self.received
+            .iter()
+            .find(|&&(sequence, _)| sequence == latest)
+            .map(|(sequence, data)| (*sequence, &data[..]))
+    }
+
+    /// Returns the latest snapshot if it changed since the last call.
+    pub fn take_update(&mut self) -> Option<(Sequence, &[u8])> {
+        if !std::mem::take(&mut self.updated) {
+            return None;
+        }
+        self.latest()
+    }
+
+    /// Processes an event returned by `Host::service`.
+    ///
+    /// Returns `true` if the event was a snapshot, which needs no further handling. Snapshots
+    /// older than the latest one, and deltas against unknown baselines, are dropped.
+    pub fn handle_event<T>(&mut self, host: &mut Host<T>, event: &Event) -> bool {
+        let data = match event.kind {
+            EventKind::Receive {
+                channel_id,
+                ref packet,
+            } if channel_id == self.channel_id => packet.data(),
+            _ => return false,
+        };
+
+        let (kind, data) = match data.strip_prefix(MAGIC).and_then(|data| data.split_first()) {
+            Some((&kind, data)) => (kind, data),
+            None => return true,
+        };
+        let (sequence, data) = match read_sequence(data) {
+            Some(read) => read,
+            None => return true,
+        };
+        if self
+            .latest
+            .is_some_and(|latest| (sequence.wrapping_sub(latest) as i32) <= 0)
+        {
+            return true;
+        }
+
+        let snapshot = match kind {
+            KIND_FULL => Some(data.to_vec()),
+            KIND_DELTA => read_sequence(data).and_then(|(baseline, delta)| {
+                let (_, baseline) = self
+                    .received
+                    .iter()
+                    .find(|&&(received, _)| received == baseline)?;
+                (self.decode_delta)(baseline, delta)
+            }),
+            _ => None,
+        };
+        let snapshot = match snapshot {
+            Some(snapshot) => snapshot,
+            None => return true,
+        };
+
+        if self.received.len() == self.history {
+            self.received.pop_front();
+        }
+        self.received.push_back((sequence, snapshot));
+        self.latest = Some(sequence);
+        self.updated = true;
+
+        if let (Ok(packet), Some(peer)) = (
+            Packet::new(header(KIND_ACK, sequence), PacketMode::UnreliableSequenced),
+            host.peer_mut(event.peer_id),
+        ) {
+            // A lost acknowledgement only means the next delta uses an older baseline.
+            let _ = peer.send_packet(packet, self.channel_id);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Encoding, ReplicaReceiver, Replicator};
+    use crate::tests::{create_host, pump_until};
+    use crate::{Address, EventKind};
+
+    use std::net::Ipv4Addr;
+
+    fn xor(baseline: &[u8], data: &[u8]) -> Vec<u8> {
+        data.iter().zip(baseline).map(|(a, b)| a ^ b).collect()
+    }
+
+    #[test]
+    fn test_replication() {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12375);
+        let mut server = create_host(Some(&server_address), 1);
+        let mut client = create_host(None, 1);
+        client.connect(&server_address, 1, 0).unwrap();
+
+        let mut replicator = Replicator::new(0, 2);
+        let mut receiver = ReplicaReceiver::new(0, 2, |baseline, delta| Some(xor(baseline, delta)));
+        let mut peer_id = None;
+        let mut encodings = Vec::new();
+        let mut snapshots = Vec::new();
+        pump_until(&mut [&mut server, &mut client], |index, host, event| {
+            if index == 1 {
+                receiver.handle_event(host, &event);
+                if let Some((_, snapshot)) = receiver.take_update() {
+                    snapshots.push(snapshot.to_vec());
+                }
+                return snapshots.len() == 3;
+            }
+
+            if let EventKind::Connect = event.kind {
+                peer_id = Some(event.peer_id);
+            }
+            replicator.handle_event(&event);
+            // Send the next snapshot once the previous one was acknowledged.
+            if let Some(peer_id) = peer_id {
+                let sent = encodings.len() as u32;
+                let acked = replicator.acked(peer_id).map_or(0, |acked| acked + 1);
+                if sent < 3 && acked == sent {
+                    let snapshot = [sent as u8; 4];
+                    encodings.push(replicator.send(host, peer_id, &snapshot, xor));
+                }
+            }
+            false
+        });
+
+        assert_eq!(
+            encodings,
+            vec![
+                Some(Encoding::Full),
+                Some(Encoding::Delta { baseline: 0 }),
+                Some(Encoding::Delta { baseline: 1 }),
+            ]
+        );
+        assert_eq!(snapshots, vec![vec![0; 4], vec![1; 4], vec![2; 4]]);
+        assert_eq!(receiver.latest(), Some((2, &[2u8; 4][..])));
+    }
+}
@@ -0,0 +1,344 @@
+//! Session resumption, so a client that reconnects after a short drop keeps its session.
+//!
+//! The server issues every client an opaque token. When a client's connection drops, the server
+//! parks the data associated with its peer for a grace period. If the client reconnects within
+//! it and presents its token, the data is moved to the new peer, instead of starting a new
+//! session. This handles the common "blip" disconnects of mobile connections.
+//!
+//! The server passes its events through [Resumption](struct.Resumption.html), and the client
+//! through [ResumptionClient](struct.ResumptionClient.html), which keeps the token across
+//! reconnects, e.g. those of a `Client`.
+
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+use crate::{Event, EventKind, Host, Packet, PacketMode, PeerID};
+
+/// Marks resumption packets.
+const MAGIC: &[u8] = b"\xffRSM";
+const KIND_NEW: u8 = 0;
+const KIND_RESUME: u8 = 1;
+const KIND_TOKEN: u8 = 2;
+
+const TOKEN_LEN: usize = 16;
+
+/// An opaque token, identifying a session across connections.
+pub type Token = [u8; TOKEN_LEN];
+
+/// Returns a fresh, unguessable token.
+///
+/// The standard library's `RandomState` is seeded from the operating system's random source.
+fn new_token() -> Token {
+    let mut token = [0; TOKEN_LEN];
+    for (i, half) in token.chunks_mut(8).enumerate() {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_usize(i);
+        half.copy_from_slice(&hasher.finish().to_be_bytes());
+    }
+    token
+}
+
+fn encode(kind: u8, token: Option<&Token>) -> Vec<u8> {
+    let mut data = MAGIC.to_vec();
+    data.push(kind);
+    if let Some(token) = token {
+        data.extend_from_slice(token);
+    }
+    data
+}
+
+fn decode(data: &[u8]) -> Option<(u8, Option<Token>)> {
+    let (&kind, rest) = data.strip_prefix(MAGIC)?.split_first()?;
+    match rest.len() {
+        0 => Some((kind, None)),
+        TOKEN_LEN => {
+            let mut token = [0; TOKEN_LEN];
+            token.copy_from_slice(rest);
+            Some((kind, Some(token)))
+        }
+        _ => None,
+    }
+}
+
+fn send<T>(host: &mut Host<T>, peer_id: PeerID, channel_id: u8, data: Vec<u8>) {
+    if let (Ok(packet), Some(peer)) = (
+        Packet::new(data, PacketMode::ReliableSequenced),
+        host.peer_mut(peer_id),
+    ) {
+        // A peer that can't be sent to is disconnecting, and will be parked again.
+        let _ = peer.send_packet(packet, channel_id);
+    }
+}
+
+/// The outcome of a client joining or leaving, on the server.
+#[derive(Debug)]
+pub enum ResumeEvent<T> {
+    /// The client started a new session.
+    Started(PeerID),
+    /// The client resumed its previous session, and its peer has the session's data again.
+    Resumed(PeerID),
+    /// A dropped session was not resumed within the grace period, with its data.
+    Expired(Option<T>),
+}
+
+struct Parked<T> {
+    data: Option<T>,
+    deadline: Instant,
+}
+
+/// Resumes the sessions of clients that reconnect, on the server.
+pub struct Resumption<T> {
+    channel_id: u8,
+    grace: Duration,
+    joining: HashSet<PeerID>,
+    tokens: HashMap<PeerID, Token>,
+    parked: HashMap<Token, Parked<T>>,
+    events: VecDeque<ResumeEvent<T>>,
+}
+
+impl<T> Resumption<T> {
+    /// Creates the server side, using `channel_id` exclusively and parking dropped sessions for `grace`.
+    pub fn new(channel_id: u8, grace: Duration) -> Resumption<T> {
+        Resumption {
+            channel_id,
+            grace,
+            joining: HashSet::new(),
+            tokens: HashMap::new(),
+            parked: HashMap::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Returns the next client that joined, and the next session that expired.
+    pub fn next_event(&mut self) -> Option<ResumeEvent<T>> {
+        self.events.pop_front()
+    }
+
+    /// Returns the number of dropped sessions that can still be resumed.
+    pub fn parked_sessions(&self) -> usize {
+        self.parked.len()
+    }
+
+    /// Processes an event returned by `Host::service`.
+    ///
+    /// Returns `true` if the event needs no further handling: the `Connect` event and all packets
+    /// of a client that has not told yet whether it resumes, and resumption packets. The
+    /// `Disconnect` of a session is left to the caller, but its peer's data is parked already.
+    pub fn handle_event(&mut self, host: &mut Host<T>, event: &Event) -> bool {
+        let peer_id = event.peer_id;
+        match event.kind {
+            EventKind::Connect => {
+                self.joining.insert(peer_id);
+                true
+            }
+            EventKind::Receive {
+                channel_id,
+                ref packet,
+            } => {
+                if !self.joining.contains(&peer_id) {
+                    return channel_id == self.channel_id;
+                }
+
+                if channel_id == self.channel_id {
+                    match decode(packet.data()) {
+                        Some((KIND_RESUME, Some(token))) => self.join(host, peer_id, Some(token)),
+                        Some((KIND_NEW, None)) => self.join(host, peer_id, None),
+                        _ => (),
+                    }
+                }
+                true
+            }
+            ref kind if kind.is_disconnect() => {
+                if self.joining.remove(&peer_id) {
+                    return true;
+                }
+
+                if let Some(token) = self.tokens.remove(&peer_id) {
+                    let data = host.peer_mut(peer_id).and_then(|peer| peer.take_data());
+                    let deadline = Instant::now() + self.grace;
+                    self.parked.insert(token, Parked { data, deadline });
+                }
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Expires the parked sessions whose grace period has passed.
+    ///
+    /// Call this regularly, e.g. after every `Host::service`.
+    pub fn expire(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<_> = self
+            .parked
+            .iter()
+            .filter(|(_, parked)| parked.deadline <= now)
+            .map(|(&token, _)| token)
+            .collect();
+
+        for token in expired {
+            let parked = self.parked.remove(&token).unwrap();
+            self.events.push_back(ResumeEvent::Expired(parked.data));
+        }
+    }
+
+    fn join(&mut self, host: &mut Host<T>, peer_id: PeerID, token: Option<Token>) {
+        self.joining.remove(&peer_id);
+        self.expire();
+
+        let parked = token.and_then(|token| self.parked.remove(&token));
+        let event = match parked {
+            Some(parked) => {
+                if let Some(peer) = host.peer_mut(peer_id) {
+                    peer.set_data(parked.data);
+                }
+                ResumeEvent::Resumed(peer_id)
+            }
+            None => ResumeEvent::Started(peer_id),
+        };
+
+        // Tokens are used once, so a leaked token can't take over the session later.
+        let token = new_token();
+        send(
+            host,
+            peer_id,
+            self.channel_id,
+            encode(KIND_TOKEN, Some(&token)),
+        );
+        self.tokens.insert(peer_id, token);
+        self.events.push_back(event);
+    }
+}
+
+/// Presents the resumption token when reconnecting, on the client.
+#[derive(Debug)]
+pub struct ResumptionClient {
+    channel_id: u8,
+    token: Option<Token>,
+}
+
+impl ResumptionClient {
+    /// Creates the client side, using `channel_id` exclusively.
+    pub fn new(channel_id: u8) -> ResumptionClient {
+        ResumptionClient {
+            channel_id,
+            token: None,
+        }
+    }
+
+    /// Returns the token of the current session, once the server issued one.
+    pub fn token(&self) -> Option<&Token> {
+        self.token.as_ref()
+    }
+
+    /// Forgets the token, so the next connection starts a new session.
+    pub fn forget(&mut self) {
+        self.token = None;
+    }
+
+    /// Processes an event returned by `Host::service`.
+    ///
+    /// Returns `true` if the event was a resumption packet, which needs no further handling.
+    /// On connecting, the token is presented to the server, if there is one.
+    pub fn handle_event<T>(&mut self, host: &mut Host<T>, event: &Event) -> bool {
+        match event.kind {
+            EventKind::Connect => {
+                let data = match self.token {
+                    Some(ref token) => encode(KIND_RESUME, Some(token)),
+                    None => encode(KIND_NEW, None),
+                };
+                send(host, event.peer_id, self.channel_id, data);
+                false
+            }
+            EventKind::Receive {
+                channel_id,
+                ref packet,
+            } if channel_id == self.channel_id => {
+                if let Some((KIND_TOKEN, Some(token))) = decode(packet.data()) {
+                    self.token = Some(token);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ResumeEvent, Resumption, ResumptionClient};
+    use crate::tests::ENET;
+    use crate::{Address, BandwidthLimit, ChannelLimit, EventKind};
+
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    #[test]
+    fn test_resume_session() {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12367);
+        let mut server = ENET
+            .create_host::<&'static str>(
+                Some(&server_address),
+                2,
+                ChannelLimit::Maximum,
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap();
+        let mut client = ENET
+            .create_host::<()>(
+                None,
+                1,
+                ChannelLimit::Maximum,
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap();
+        let mut resumption = Resumption::new(0, Duration::from_secs(5));
+        let mut resumption_client = ResumptionClient::new(0);
+
+        let timeout = Some(Duration::from_millis(2));
+        let mut joins = Vec::new();
+        let mut dropped = false;
+        let mut peer_id = client.connect(&server_address, 1, 0).unwrap().1;
+        for _ in 0..1000 {
+            if let Some(event) = client.service(timeout).unwrap() {
+                resumption_client.handle_event(&mut client, &event);
+                if let EventKind::Disconnect { .. } = event.kind {
+                    peer_id = client.connect(&server_address, 1, 0).unwrap().1;
+                }
+            }
+            if let Some(event) = server.service(timeout).unwrap() {
+                resumption.handle_event(&mut server, &event);
+            }
+
+            // Drop the first connection once it has a token, so the client reconnects.
+            if !dropped && resumption_client.token().is_some() {
+                client[peer_id].disconnect(0);
+                dropped = true;
+            }
+
+            while let Some(event) = resumption.next_event() {
+                match event {
+                    ResumeEvent::Started(id) => {
+                        server[id].set_data(Some("player one"));
+                        joins.push("started");
+                    }
+                    ResumeEvent::Resumed(id) => {
+                        assert_eq!(server[id].data(), Some(&"player one"));
+                        joins.push("resumed");
+                    }
+                    ResumeEvent::Expired(_) => panic!("session expired"),
+                }
+            }
+            if joins.len() == 2 {
+                break;
+            }
+        }
+
+        assert_eq!(joins, vec!["started", "resumed"]);
+        assert_eq!(resumption.parked_sessions(), 0);
+    }
+}
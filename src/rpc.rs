@@ -0,0 +1,351 @@
+//! Request/response calls on top of ENet packets.
+//!
+//! An [Rpc](struct.Rpc.html) both issues calls to remote peers and serves the calls it receives.
+//! It is driven by the regular service loop: every event is passed to `Rpc::handle_event`, which
+//! consumes the packets belonging to calls, answers requests and completes call futures.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use crate::{Event, EventKind, Host, Packet, PacketMode, Peer, PeerID, SendError};
+
+/// Marks RPC packets, so they can share a channel with other traffic.
+const MAGIC: &[u8] = b"\xffRPC";
+const KIND_REQUEST: u8 = 0;
+const KIND_RESPONSE: u8 = 1;
+const KIND_FAILURE: u8 = 2;
+const KIND_UNKNOWN_METHOD: u8 = 3;
+/// Magic, kind, call id and method.
+const HEADER_LEN: usize = 4 + 1 + 4 + 2;
+
+/// The error of a failed call.
+#[derive(Fail, Debug, Clone, PartialEq, Eq)]
+pub enum RpcError {
+    #[fail(display = "no response within the call's timeout")]
+    /// There was no response within the call's timeout.
+    TimedOut,
+    #[fail(display = "the peer disconnected before responding")]
+    /// The peer disconnected before responding.
+    Disconnected,
+    #[fail(display = "the peer has no handler for method {}", _0)]
+    /// The remote side has no handler registered for the method.
+    UnknownMethod(u16),
+    #[fail(display = "the call failed remotely: {}", _0)]
+    /// The remote handler returned an error.
+    Remote(String),
+}
+
+/// A handler for requests of one method, see `Rpc::register`.
+pub type Handler = Box<dyn FnMut(PeerID, &[u8]) -> Result<Vec<u8>, String>>;
+
+#[derive(Default)]
+struct CallState {
+    result: Option<Result<Vec<u8>, RpcError>>,
+    waker: Option<Waker>,
+}
+
+impl CallState {
+    fn complete(state: &Mutex<CallState>, result: Result<Vec<u8>, RpcError>) {
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// The pending response of a call, resolving to the response data.
+///
+/// The future does not need to be polled for the call to make progress, and dropping it
+/// merely discards the response.
+pub struct RpcCall {
+    state: Arc<Mutex<CallState>>,
+}
+
+impl Future for RpcCall {
+    type Output = Result<Vec<u8>, RpcError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+struct PendingCall {
+    peer_id: PeerID,
+    deadline: Instant,
+    state: Arc<Mutex<CallState>>,
+}
+
+fn encode(kind: u8, call_id: u32, method: u16, body: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(HEADER_LEN + body.len());
+    data.extend_from_slice(MAGIC);
+    data.push(kind);
+    data.extend_from_slice(&call_id.to_be_bytes());
+    data.extend_from_slice(&method.to_be_bytes());
+    data.extend_from_slice(body);
+    data
+}
+
+fn decode(data: &[u8]) -> Option<(u8, u32, u16, &[u8])> {
+    if data.len() < HEADER_LEN || !data.starts_with(MAGIC) {
+        return None;
+    }
+
+    let call_id = u32::from_be_bytes([data[5], data[6], data[7], data[8]]);
+    let method = u16::from_be_bytes([data[9], data[10]]);
+    Some((data[4], call_id, method, &data[HEADER_LEN..]))
+}
+
+/// Issues calls to remote peers, and serves calls from them with registered handlers.
+///
+/// Calls and responses are sent as reliable packets, on the channel the call was made on.
+#[derive(Default)]
+pub struct Rpc {
+    next_call_id: u32,
+    pending: HashMap<u32, PendingCall>,
+    handlers: HashMap<u16, Handler>,
+}
+
+impl Rpc {
+    /// Creates an `Rpc` without handlers or pending calls.
+    pub fn new() -> Rpc {
+        Rpc::default()
+    }
+
+    /// Registers the handler serving requests for `method`, replacing any previous one.
+    ///
+    /// The handler receives the calling peer and the request, and returns the response or an
+    /// error message for the caller.
+    pub fn register<F>(&mut self, method: u16, handler: F)
+    where
+        F: FnMut(PeerID, &[u8]) -> Result<Vec<u8>, String> + 'static,
+    {
+        self.handlers.insert(method, Box::new(handler));
+    }
+
+    /// Calls `method` on `peer` with `request`, on channel `channel_id`.
+    ///
+    /// The returned future resolves once the response was received by `Rpc::handle_event`,
+    /// or fails once `timeout` has passed without one.
+    pub fn call<T>(
+        &mut self,
+        peer: &mut Peer<T>,
+        peer_id: PeerID,
+        channel_id: u8,
+        method: u16,
+        request: &[u8],
+        timeout: Duration,
+    ) -> Result<RpcCall, SendError> {
+        let call_id = self.next_call_id;
+        self.next_call_id = self.next_call_id.wrapping_add(1);
+
+        let packet = Packet::new(
+            encode(KIND_REQUEST, call_id, method, request),
+            PacketMode::ReliableSequenced,
+        )
+        .map_err(|e| SendError::Error(e.0))?;
+        peer.send_packet(packet, channel_id)?;
+
+        let state = Arc::new(Mutex::new(CallState::default()));
+        self.pending.insert(
+            call_id,
+            PendingCall {
+                peer_id,
+                deadline: Instant::now() + timeout,
+                state: state.clone(),
+            },
+        );
+
+        Ok(RpcCall { state })
+    }
+
+    /// Processes an event returned by `Host::service`.
+    ///
+    /// Returns `true` if the event was a packet belonging to a call, which needs no further handling.
+    /// Calls to peers that disconnected fail, and calls whose timeout has passed time out.
+    pub fn handle_event<T>(&mut self, host: &mut Host<T>, event: &Event) -> bool {
+        self.expire();
+
+        match event.kind {
+            EventKind::Receive {
+                channel_id,
+                ref packet,
+            } => match decode(packet.data()) {
+                Some((kind, call_id, method, body)) => {
+                    self.handle_packet(
+                        host,
+                        event.peer_id,
+                        channel_id,
+                        kind,
+                        call_id,
+                        method,
+                        body,
+                    );
+                    true
+                }
+                None => false,
+            },
+            ref kind if kind.is_disconnect() => {
+                let peer_id = event.peer_id;
+                self.fail_calls(|call| call.peer_id == peer_id, RpcError::Disconnected);
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Fails all pending calls whose timeout has passed.
+    ///
+    /// `Rpc::handle_event` does this as well, this is only needed while no events arrive.
+    pub fn expire(&mut self) {
+        let now = Instant::now();
+        self.fail_calls(|call| call.deadline <= now, RpcError::TimedOut);
+    }
+
+    /// Returns the number of calls still waiting for their response.
+    pub fn pending_calls(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn fail_calls(&mut self, condition: impl Fn(&PendingCall) -> bool, error: RpcError) {
+        let failed: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(_, call)| condition(call))
+            .map(|(&call_id, _)| call_id)
+            .collect();
+
+        for call_id in failed {
+            let call = self.pending.remove(&call_id).unwrap();
+            CallState::complete(&call.state, Err(error.clone()));
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn handle_packet<T>(
+        &mut self,
+        host: &mut Host<T>,
+        peer_id: PeerID,
+        channel_id: u8,
+        kind: u8,
+        call_id: u32,
+        method: u16,
+        body: &[u8],
+    ) {
+        match kind {
+            KIND_REQUEST => {
+                let (kind, response) = match self.handlers.get_mut(&method) {
+                    Some(handler) => match handler(peer_id, body) {
+                        Ok(response) => (KIND_RESPONSE, response),
+                        Err(message) => (KIND_FAILURE, message.into_bytes()),
+                    },
+                    None => (KIND_UNKNOWN_METHOD, Vec::new()),
+                };
+
+                let data = encode(kind, call_id, method, &response);
+                if let (Ok(packet), Some(peer)) = (
+                    Packet::new(data, PacketMode::ReliableSequenced),
+                    host.peer_mut(peer_id),
+                ) {
+                    // The peer can't be waiting for a response once it is gone, so errors don't matter.
+                    let _ = peer.send_packet(packet, channel_id);
+                }
+            }
+            KIND_RESPONSE | KIND_FAILURE | KIND_UNKNOWN_METHOD => {
+                // Responses for calls that already failed, or from a different peer, are ignored.
+                let matches = self
+                    .pending
+                    .get(&call_id)
+                    .is_some_and(|call| call.peer_id == peer_id);
+                if !matches {
+                    return;
+                }
+
+                let call = self.pending.remove(&call_id).unwrap();
+                let result = match kind {
+                    KIND_RESPONSE => Ok(body.to_vec()),
+                    KIND_UNKNOWN_METHOD => Err(RpcError::UnknownMethod(method)),
+                    _ => Err(RpcError::Remote(String::from_utf8_lossy(body).into_owned())),
+                };
+                CallState::complete(&call.state, result);
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rpc, RpcError};
+    use crate::tests::{connected_pair, pump_until};
+
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::time::Duration;
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    #[test]
+    fn test_call() {
+        let (mut server, mut client, _, peer_id) = connected_pair(12355, 1);
+
+        let mut server_rpc = Rpc::new();
+        server_rpc.register(1, |_, request| Ok(request.iter().rev().cloned().collect()));
+        server_rpc.register(2, |_, _| Err("denied".to_string()));
+        server_rpc.register(4, |_, _| Err(String::new()));
+        let mut client_rpc = Rpc::new();
+
+        let peer = &mut client[peer_id];
+        let long = Duration::from_secs(5);
+        let mut calls = [
+            client_rpc.call(peer, peer_id, 0, 1, b"abc", long).unwrap(),
+            client_rpc.call(peer, peer_id, 0, 2, b"", long).unwrap(),
+            client_rpc.call(peer, peer_id, 0, 3, b"", long).unwrap(),
+            client_rpc.call(peer, peer_id, 0, 4, b"", long).unwrap(),
+        ];
+        pump_until(&mut [&mut server, &mut client], |index, host, event| {
+            if index == 0 {
+                server_rpc.handle_event(host, &event);
+            } else {
+                client_rpc.handle_event(host, &event);
+            }
+            client_rpc.pending_calls() == 0
+        });
+
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut cx = Context::from_waker(&waker);
+        let results: Vec<_> = calls
+            .iter_mut()
+            .map(|call| match Pin::new(call).poll(&mut cx) {
+                Poll::Ready(result) => result,
+                Poll::Pending => panic!("call did not complete"),
+            })
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                Ok(b"cba".to_vec()),
+                Err(RpcError::Remote("denied".to_string())),
+                Err(RpcError::UnknownMethod(3)),
+                Err(RpcError::Remote(String::new())),
+            ]
+        );
+    }
+}
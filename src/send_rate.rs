@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use enet_sys::{
+    ENET_PEER_PACKET_THROTTLE_ACCELERATION, ENET_PEER_PACKET_THROTTLE_DECELERATION,
+    ENET_PEER_PACKET_THROTTLE_INTERVAL,
+};
+
+use crate::{Event, Host, PeerID, PeerState};
+
+/// The share of its rate a peer keeps when it loses packets.
+const LOSS_BACKOFF: f64 = 0.7;
+/// The share of its rate a peer keeps when its round trip time rises.
+const LATENCY_BACKOFF: f64 = 0.85;
+/// The number of steps a recovering peer takes from the minimum to the maximum rate.
+const RECOVERY_STEPS: u32 = 16;
+
+/// The settings of a `SendRateTuner`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SendRateConfig {
+    /// The lowest rate recommended, in bytes/second.
+    pub minimum: u32,
+    /// The highest rate recommended, in bytes/second.
+    pub maximum: u32,
+    /// How often the rates are reconsidered.
+    pub interval: Duration,
+    /// Packet loss above this share lowers the rate, see `Peer::packet_loss`.
+    pub loss_threshold: f32,
+    /// A round trip time this much above the lowest one seen lowers the rate.
+    pub rtt_increase: Duration,
+    /// A channel to keep the budget of at the recommended rate, see `Peer::set_channel_budget`.
+    pub budget_channel: Option<u8>,
+    /// Whether ENet's packet throttle backs off twice as fast while the rate is lowered, see
+    /// `Peer::configure_throttle`.
+    pub tune_throttle: bool,
+}
+
+impl Default for SendRateConfig {
+    fn default() -> SendRateConfig {
+        SendRateConfig {
+            minimum: 8 * 1024,
+            maximum: 256 * 1024,
+            interval: Duration::from_secs(1),
+            loss_threshold: 0.05,
+            rtt_increase: Duration::from_millis(50),
+            budget_channel: None,
+            tune_throttle: false,
+        }
+    }
+}
+
+/// Why a `SendRateTuner` changed the rate of a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SendRateReason {
+    /// The packet loss exceeded `SendRateConfig::loss_threshold`.
+    Loss,
+    /// The round trip time rose by more than `SendRateConfig::rtt_increase`.
+    Latency,
+    /// The link was healthy, so the rate grows back towards the maximum.
+    Recovery,
+}
+
+/// A change of the recommended rate of a peer, passed to the callback of `SendRateTuner::update`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SendRateDecision {
+    /// The peer whose rate changed.
+    pub peer_id: PeerID,
+    /// The rate recommended before, in bytes/second.
+    pub previous: u32,
+    /// The rate recommended now, in bytes/second.
+    pub recommended: u32,
+    /// Why the rate changed.
+    pub reason: SendRateReason,
+    /// The packet loss the decision was based on.
+    pub packet_loss: f32,
+    /// The round trip time the decision was based on.
+    pub rtt: Duration,
+}
+
+#[derive(Debug)]
+struct PeerRate {
+    recommended: u32,
+    lowest_rtt: Duration,
+    backing_off: bool,
+}
+
+/// Recommends a send rate for every peer, following the packet loss and round trip times of its
+/// link.
+///
+/// ENet's throttle only drops unreliable packets once a link is congested. The tuner instead tells
+/// the application how much to send, so it can degrade gracefully, e.g. by sending snapshots less
+/// often or encoding voice at a lower bitrate. Rates back off multiplicatively when packets are
+/// lost or round trip times rise, and grow back in steps while the link is healthy.
+#[derive(Debug)]
+pub struct SendRateTuner {
+    config: SendRateConfig,
+    peers: HashMap<PeerID, PeerRate>,
+    next_update: Instant,
+}
+
+impl SendRateTuner {
+    /// Creates a tuner with `config`.
+    pub fn new(config: SendRateConfig) -> SendRateTuner {
+        assert!(
+            config.minimum <= config.maximum,
+            "the minimum rate must not exceed the maximum"
+        );
+        SendRateTuner {
+            config,
+            peers: HashMap::new(),
+            next_update: Instant::now(),
+        }
+    }
+
+    /// Returns the settings of the tuner.
+    pub fn config(&self) -> &SendRateConfig {
+        &self.config
+    }
+
+    /// Returns the rate recommended for `peer_id` in bytes/second, once `SendRateTuner::update` saw
+    /// it connected.
+    pub fn recommended(&self, peer_id: PeerID) -> Option<u32> {
+        self.peers.get(&peer_id).map(|peer| peer.recommended)
+    }
+
+    /// Reconsiders the rates of the connected peers of `host`, at most once per
+    /// `SendRateConfig::interval`. Call this regularly, e.g. after every `Host::service`.
+    ///
+    /// `on_decision` is called for every rate that changed. New peers start at their
+    /// `Peer::estimated_bandwidth`, clamped to the configured range. Returns the number of
+    /// decisions.
+    pub fn update<T>(
+        &mut self,
+        host: &mut Host<T>,
+        mut on_decision: impl FnMut(&SendRateDecision),
+    ) -> usize {
+        let now = Instant::now();
+        if now < self.next_update {
+            return 0;
+        }
+        self.next_update = now + self.config.interval;
+
+        let config = self.config;
+        let peer_ids: Vec<_> = host.peer_ids().collect();
+        let mut decisions = 0;
+        for peer_id in peer_ids {
+            let peer = &mut host[peer_id];
+            if peer.state() != PeerState::Connected {
+                continue;
+            }
+
+            let (packet_loss, rtt) = (peer.packet_loss(), peer.mean_rtt());
+            let state = match self.peers.get_mut(&peer_id) {
+                Some(state) => state,
+                None => {
+                    let recommended = peer
+                        .estimated_bandwidth()
+                        .clamp(config.minimum, config.maximum);
+                    if let Some(channel_id) = config.budget_channel {
+                        peer.set_channel_budget(channel_id, Some(recommended));
+                    }
+                    self.peers.insert(
+                        peer_id,
+                        PeerRate {
+                            recommended,
+                            lowest_rtt: rtt,
+                            backing_off: false,
+                        },
+                    );
+                    continue;
+                }
+            };
+
+            let (recommended, reason) = match decide(&config, state, packet_loss, rtt) {
+                Some(decision) => decision,
+                None => continue,
+            };
+            let decision = SendRateDecision {
+                peer_id,
+                previous: state.recommended,
+                recommended,
+                reason,
+                packet_loss,
+                rtt,
+            };
+            state.recommended = recommended;
+
+            if let Some(channel_id) = config.budget_channel {
+                peer.set_channel_budget(channel_id, Some(recommended));
+            }
+            let backing_off = reason != SendRateReason::Recovery;
+            if config.tune_throttle && backing_off != state.backing_off {
+                let factor = if backing_off { 2 } else { 1 };
+                peer.configure_throttle(
+                    Duration::from_millis(u64::from(ENET_PEER_PACKET_THROTTLE_INTERVAL)),
+                    ENET_PEER_PACKET_THROTTLE_ACCELERATION,
+                    ENET_PEER_PACKET_THROTTLE_DECELERATION * factor,
+                );
+            }
+            state.backing_off = backing_off;
+
+            on_decision(&decision);
+            decisions += 1;
+        }
+        decisions
+    }
+
+    /// Processes an event returned by `Host::service`, forgetting the rates of peers that
+    /// disconnect.
+    ///
+    /// Always returns `false`, the event still needs handling.
+    pub fn handle_event(&mut self, event: &Event) -> bool {
+        if event.kind.is_disconnect() {
+            self.peers.remove(&event.peer_id);
+        }
+        false
+    }
+}
+
+/// Returns the new rate of a peer and why, if it changes.
+fn decide(
+    config: &SendRateConfig,
+    state: &mut PeerRate,
+    packet_loss: f32,
+    rtt: Duration,
+) -> Option<(u32, SendRateReason)> {
+    state.lowest_rtt = state.lowest_rtt.min(rtt);
+
+    let current = f64::from(state.recommended);
+    let (rate, reason) = if packet_loss > config.loss_threshold {
+        (current * LOSS_BACKOFF, SendRateReason::Loss)
+    } else if rtt > state.lowest_rtt + config.rtt_increase {
+        (current * LATENCY_BACKOFF, SendRateReason::Latency)
+    } else {
+        let step = (config.maximum - config.minimum) / RECOVERY_STEPS;
+        (current + f64::from(step.max(1)), SendRateReason::Recovery)
+    };
+
+    let rate = (rate as u32).clamp(config.minimum, config.maximum);
+    if rate == state.recommended {
+        None
+    } else {
+        Some((rate, reason))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decide, PeerRate, SendRateConfig, SendRateReason, SendRateTuner};
+    use crate::tests::connected_pair;
+
+    use std::time::Duration;
+
+    #[test]
+    fn test_decide() {
+        let config = SendRateConfig {
+            minimum: 1000,
+            maximum: 17000,
+            ..SendRateConfig::default()
+        };
+        let mut state = PeerRate {
+            recommended: 10000,
+            lowest_rtt: Duration::from_millis(20),
+            backing_off: false,
+        };
+        let decide = |state: &mut PeerRate, loss, rtt| {
+            let decision = decide(&config, state, loss, Duration::from_millis(rtt));
+            if let Some((rate, _)) = decision {
+                state.recommended = rate;
+            }
+            decision
+        };
+
+        let loss = SendRateReason::Loss;
+        assert_eq!(decide(&mut state, 0.1, 20), Some((7000, loss)));
+        let latency = SendRateReason::Latency;
+        assert_eq!(decide(&mut state, 0.0, 100), Some((5950, latency)));
+        let recovery = SendRateReason::Recovery;
+        assert_eq!(decide(&mut state, 0.0, 30), Some((6950, recovery)));
+        // The lowest round trip time is the baseline.
+        assert_eq!(decide(&mut state, 0.0, 10), Some((7950, recovery)));
+        assert_eq!(decide(&mut state, 0.0, 61), Some((6757, latency)));
+
+        state.recommended = 17000;
+        assert_eq!(decide(&mut state, 0.0, 10), None);
+        state.recommended = 1000;
+        assert_eq!(decide(&mut state, 0.5, 10), None);
+    }
+
+    #[test]
+    fn test_send_rate_tuner() {
+        let (_server, mut client, _, peer_id) = connected_pair(12387, 1);
+
+        let config = SendRateConfig {
+            minimum: 1000,
+            maximum: 5000,
+            interval: Duration::ZERO,
+            budget_channel: Some(0),
+            tune_throttle: true,
+            ..SendRateConfig::default()
+        };
+        let mut tuner = SendRateTuner::new(config);
+        assert_eq!(tuner.update(&mut client, |_| ()), 0);
+        // Locally, the estimated bandwidth exceeds the maximum.
+        assert_eq!(tuner.recommended(peer_id), Some(5000));
+        let budget = client[peer_id].budget_usage(0).unwrap();
+        assert_eq!(budget.bytes_per_second, 5000);
+
+        // A healthy link stays at the maximum.
+        let mut decisions = Vec::new();
+        tuner.update(&mut client, |decision| decisions.push(*decision));
+        assert!(decisions.is_empty());
+    }
+}
@@ -0,0 +1,342 @@
+//! Session management for a listening `Host`.
+//!
+//! A [Server](struct.Server.html) decides for every incoming connection whether to accept it,
+//! and gives each accepted connection, a session, its own [SessionHandler](trait.SessionHandler.html).
+//! Sessions are keyed by ids the server assigns in the order it accepts connections, so an id
+//! never refers to a later connection that reuses the same peer slot.
+
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use crate::{Address, Error, EventKind, Host, Packet, PacketMode, Peer, PeerID};
+
+/// Identifies a session, assigned by the `Server` when it accepts the connection.
+pub type SessionId = u32;
+
+/// Handles the events of one session.
+pub trait SessionHandler<T> {
+    /// Called once the session was accepted.
+    fn connected(&mut self, _session: SessionId, _peer: &mut Peer<T>) {}
+
+    /// Called for every packet the session receives.
+    fn received(&mut self, session: SessionId, peer: &mut Peer<T>, channel_id: u8, packet: Packet);
+
+    /// Called once the session ended, with the data of the disconnect.
+    ///
+    /// Sessions ended with `Server::kick` or `Server::ban` are disconnected as well.
+    fn disconnected(&mut self, _session: SessionId, _data: u32) {}
+}
+
+/// An incoming connection, waiting to be accepted or rejected.
+#[derive(Debug, Clone)]
+pub struct ConnectRequest {
+    /// The session the connection would become, if accepted.
+    pub session: SessionId,
+    /// The address the connection comes from.
+    pub address: Address,
+    /// The data the remote host passed to `Host::connect`.
+    pub data: u32,
+}
+
+/// Whether to accept an incoming connection.
+pub enum Admission<T> {
+    /// Accepts the connection, with the handler of the new session.
+    Accept(Box<dyn SessionHandler<T>>),
+    /// Rejects the connection, disconnecting it with the contained data.
+    Reject(u32),
+}
+
+type AdmissionPolicy<T> = Box<dyn FnMut(&ConnectRequest) -> Admission<T>>;
+
+struct Session<T> {
+    peer_id: PeerID,
+    handler: Box<dyn SessionHandler<T>>,
+}
+
+/// A `Host` that tracks its connections as sessions, and routes their events to per-session handlers.
+pub struct Server<T> {
+    host: Host<T>,
+    policy: AdmissionPolicy<T>,
+    sessions: HashMap<SessionId, Session<T>>,
+    peers: HashMap<PeerID, SessionId>,
+    next_session: SessionId,
+}
+
+impl<T> Server<T> {
+    /// Creates a server on top of `host`, deciding about incoming connections with `policy`.
+    pub fn new<F>(host: Host<T>, policy: F) -> Server<T>
+    where
+        F: FnMut(&ConnectRequest) -> Admission<T> + 'static,
+    {
+        Server {
+            host,
+            policy: Box::new(policy),
+            sessions: HashMap::new(),
+            peers: HashMap::new(),
+            next_session: 0,
+        }
+    }
+
+    /// Returns the underlying `Host`.
+    pub fn host(&self) -> &Host<T> {
+        &self.host
+    }
+
+    /// Returns the underlying `Host`, mutably.
+    pub fn host_mut(&mut self) -> &mut Host<T> {
+        &mut self.host
+    }
+
+    /// Returns the ids of all current sessions.
+    pub fn sessions(&self) -> impl Iterator<Item = SessionId> + '_ {
+        self.sessions.keys().cloned()
+    }
+
+    /// Returns the number of current sessions.
+    pub fn session_count(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Returns the `PeerID` of `session`, if it is a current session.
+    pub fn peer_id(&self, session: SessionId) -> Option<PeerID> {
+        self.sessions.get(&session).map(|session| session.peer_id)
+    }
+
+    /// Returns the peer of `session`, if it is a current session.
+    pub fn peer_mut(&mut self, session: SessionId) -> Option<&mut Peer<T>> {
+        let peer_id = self.peer_id(session)?;
+        self.host.peer_mut(peer_id)
+    }
+
+    /// Sends `data` to all sessions on `channel_id`, returning how many sessions it was sent to.
+    ///
+    /// Sending to an individual session can fail, e.g. because it is disconnecting; such sessions
+    /// are skipped.
+    pub fn broadcast(&mut self, channel_id: u8, data: &[u8], mode: PacketMode) -> usize {
+        let host = &mut self.host;
+        self.sessions
+            .values()
+            .filter(|session| {
+                let packet = match Packet::new(data.to_vec(), mode) {
+                    Ok(packet) => packet,
+                    Err(_) => return false,
+                };
+
+                host.peer_mut(session.peer_id)
+                    .is_some_and(|peer| peer.send_packet(packet, channel_id).is_ok())
+            })
+            .count()
+    }
+
+    /// Disconnects `session`, passing `data` as the reason.
+    ///
+    /// The session ends once the disconnect completes, like any other.
+    pub fn kick(&mut self, session: SessionId, data: u32) {
+        if let Some(peer) = self.peer_mut(session) {
+            peer.disconnect(data);
+        }
+    }
+
+    /// Bans `ip` for `duration` like `Host::ban`, and ends its current sessions with `data`.
+    ///
+    /// The sessions are disconnected immediately, their handlers are told right away.
+    pub fn ban(&mut self, ip: Ipv4Addr, duration: Duration, data: u32) {
+        let banned: Vec<_> = self
+            .sessions
+            .values()
+            .map(|session| session.peer_id)
+            .filter(|&peer_id| {
+                self.host
+                    .peer(peer_id)
+                    .is_some_and(|peer| *peer.address().ip() == ip)
+            })
+            .collect();
+        self.host.ban(ip, duration, data);
+        for peer_id in banned {
+            self.end(peer_id, data);
+        }
+    }
+
+    /// Lifts the ban of `ip`.
+    pub fn unban(&mut self, ip: Ipv4Addr) {
+        self.host.unban(&ip);
+    }
+
+    /// Returns whether `ip` is banned.
+    pub fn is_banned(&self, ip: &Ipv4Addr) -> bool {
+        self.host.bans().iter().any(|ban| ban.address == *ip)
+    }
+
+    /// Services the host like `Host::service`, and routes the event to its session, if any.
+    ///
+    /// Returns whether an event was handled.
+    pub fn service(&mut self, timeout: Option<Duration>) -> Result<bool, Error> {
+        let event = match self.host.service(timeout)? {
+            Some(event) => event,
+            None => return Ok(false),
+        };
+
+        let peer_id = event.peer_id;
+        match event.kind {
+            EventKind::Connect => self.admit(peer_id),
+            EventKind::Receive { channel_id, packet } => {
+                // Packets of rejected connections arrive before their disconnect, and are dropped.
+                let id = match self.peers.get(&peer_id) {
+                    Some(&id) => id,
+                    None => return Ok(true),
+                };
+                if let (Some(session), Some(peer)) =
+                    (self.sessions.get_mut(&id), self.host.peer_mut(peer_id))
+                {
+                    session.handler.received(id, peer, channel_id, packet);
+                }
+            }
+            EventKind::Disconnect { data } => self.end(peer_id, data),
+            EventKind::ConnectTimeout => self.end(peer_id, 0),
+            EventKind::RateLimited { .. } => (),
+        }
+
+        Ok(true)
+    }
+
+    fn admit(&mut self, peer_id: PeerID) {
+        let peer = match self.host.peer_mut(peer_id) {
+            Some(peer) => peer,
+            None => return,
+        };
+
+        // Skip ids still in use, after the counter wrapped around.
+        while self.sessions.contains_key(&self.next_session) {
+            self.next_session = self.next_session.wrapping_add(1);
+        }
+        let request = ConnectRequest {
+            session: self.next_session,
+            address: peer.address(),
+            data: peer.event_data(),
+        };
+
+        match (self.policy)(&request) {
+            Admission::Accept(mut handler) => {
+                handler.connected(request.session, peer);
+                self.next_session = self.next_session.wrapping_add(1);
+                self.peers.insert(peer_id, request.session);
+                self.sessions
+                    .insert(request.session, Session { peer_id, handler });
+            }
+            Admission::Reject(data) => self.host.disconnect_now(peer_id, data),
+        }
+    }
+
+    fn end(&mut self, peer_id: PeerID, data: u32) {
+        if let Some(id) = self.peers.remove(&peer_id) {
+            if let Some(mut session) = self.sessions.remove(&id) {
+                session.handler.disconnected(id, data);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Admission, Server, SessionHandler, SessionId};
+    use crate::tests::create_host;
+    use crate::{Address, EventKind, Packet, PacketMode, Peer};
+
+    use std::cell::RefCell;
+    use std::net::Ipv4Addr;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    struct Echo {
+        disconnects: Rc<RefCell<Vec<u32>>>,
+    }
+
+    impl SessionHandler<()> for Echo {
+        fn received(&mut self, _: SessionId, peer: &mut Peer<()>, channel_id: u8, packet: Packet) {
+            let echo = Packet::new(packet.data().to_vec(), PacketMode::ReliableSequenced).unwrap();
+            peer.send_packet(echo, channel_id).unwrap();
+        }
+
+        fn disconnected(&mut self, _: SessionId, data: u32) {
+            self.disconnects.borrow_mut().push(data);
+        }
+    }
+
+    #[test]
+    fn test_sessions() {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12360);
+        let disconnects = Rc::new(RefCell::new(Vec::new()));
+        let handler_disconnects = disconnects.clone();
+        let mut server = Server::new(create_host(Some(&server_address), 2), move |request| {
+            if request.data == 1 {
+                Admission::Accept(Box::new(Echo {
+                    disconnects: handler_disconnects.clone(),
+                }))
+            } else {
+                Admission::Reject(9)
+            }
+        });
+        let mut accepted = create_host(None, 1);
+        let mut rejected = create_host(None, 1);
+        accepted.connect(&server_address, 1, 1).unwrap();
+        rejected.connect(&server_address, 1, 2).unwrap();
+
+        let timeout = Some(Duration::from_millis(5));
+        let mut rejection = None;
+        let mut echo = None;
+        for _ in 0..400 {
+            server.service(timeout).unwrap();
+            if let Some(event) = rejected.service(timeout).unwrap() {
+                if let EventKind::Disconnect { data } = event.kind {
+                    rejection = Some(data);
+                }
+            }
+            if let Some(event) = accepted.service(timeout).unwrap() {
+                match event.kind {
+                    EventKind::Connect => {
+                        let packet = Packet::new(b"ping".to_vec(), PacketMode::ReliableSequenced);
+                        accepted[event.peer_id]
+                            .send_packet(packet.unwrap(), 0)
+                            .unwrap();
+                    }
+                    EventKind::Receive { ref packet, .. } => echo = Some(packet.data().to_vec()),
+                    _ => (),
+                }
+            }
+            if rejection.is_some() && echo.is_some() {
+                break;
+            }
+        }
+
+        assert_eq!(rejection, Some(9));
+        assert_eq!(echo, Some(b"ping".to_vec()));
+        assert_eq!(server.sessions().collect::<Vec<_>>(), vec![0]);
+        assert_eq!(
+            server.broadcast(0, b"all", PacketMode::ReliableSequenced),
+            1
+        );
+
+        server.ban(Ipv4Addr::LOCALHOST, Duration::from_secs(60), 3);
+        assert!(server.is_banned(&Ipv4Addr::LOCALHOST));
+        assert_eq!(server.session_count(), 0);
+        assert_eq!(*disconnects.borrow(), vec![3]);
+        let mut kicked = None;
+        for _ in 0..400 {
+            server.service(timeout).unwrap();
+            if let Some(event) = accepted.service(timeout).unwrap() {
+                if let EventKind::Disconnect { data } = event.kind {
+                    kicked = Some(data);
+                }
+            }
+            if kicked.is_some() {
+                break;
+            }
+        }
+
+        assert_eq!(kicked, Some(3));
+        assert_eq!(*disconnects.borrow(), vec![3]);
+        server.unban(Ipv4Addr::LOCALHOST);
+        assert!(!server.is_banned(&Ipv4Addr::LOCALHOST));
+    }
+}
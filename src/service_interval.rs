@@ -0,0 +1,164 @@
+use std::time::{Duration, Instant};
+
+use crate::{EnetTime, Error, Event, Host};
+
+/// The settings of a `ServiceInterval`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ServiceIntervalConfig {
+    /// The shortest timeout, used while events arrive faster than this.
+    pub minimum: Duration,
+    /// The longest timeout, used while the host is idle.
+    pub maximum: Duration,
+    /// How long the event rate takes to follow a change of the load, roughly.
+    pub smoothing: Duration,
+}
+
+impl Default for ServiceIntervalConfig {
+    fn default() -> ServiceIntervalConfig {
+        ServiceIntervalConfig {
+            minimum: Duration::from_millis(1),
+            maximum: Duration::from_millis(250),
+            smoothing: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Chooses the timeouts of `Host::service` from the recent event rate and the timers of the peers.
+///
+/// A fixed short timeout keeps idle servers, e.g. lobbies, busy waking up for nothing, while a long
+/// one delays the resends and pings ENet only does while it is serviced. The timeout follows the
+/// average time between events instead, from `ServiceIntervalConfig::minimum` under load to
+/// `ServiceIntervalConfig::maximum` when idle, and never sleeps past the next timer of a peer, see
+/// `Peer::next_timer`. Packets that arrive still end the wait right away.
+#[derive(Debug)]
+pub struct ServiceInterval {
+    config: ServiceIntervalConfig,
+    rate: f64,
+    last_update: Instant,
+}
+
+impl ServiceInterval {
+    /// Creates a strategy with `config`, starting out idle.
+    pub fn new(config: ServiceIntervalConfig) -> ServiceInterval {
+        assert!(
+            config.minimum <= config.maximum,
+            "the minimum timeout must not exceed the maximum"
+        );
+        ServiceInterval {
+            config,
+            rate: 0.0,
+            last_update: Instant::now(),
+        }
+    }
+
+    /// Returns the settings of the strategy.
+    pub fn config(&self) -> &ServiceIntervalConfig {
+        &self.config
+    }
+
+    /// Returns the smoothed number of events per second recorded.
+    pub fn event_rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// Records that `events` events were returned since the last call.
+    pub fn record(&mut self, events: usize) {
+        let now = Instant::now();
+        let elapsed = (now - self.last_update).as_secs_f64();
+        self.last_update = now;
+        if elapsed <= 0.0 {
+            return;
+        }
+
+        let smoothing = self.config.smoothing.as_secs_f64().max(f64::EPSILON);
+        let weight = 1.0 - (-elapsed / smoothing).exp();
+        self.rate += (events as f64 / elapsed - self.rate) * weight;
+    }
+
+    /// Returns the timeout to pass to the next `Host::service` call of `host`.
+    pub fn timeout<T>(&self, host: &Host<T>) -> Duration {
+        let gap = if self.rate > 0.0 {
+            Duration::from_secs_f64((1.0 / self.rate).min(self.config.maximum.as_secs_f64()))
+        } else {
+            self.config.maximum
+        };
+
+        let now = EnetTime::now();
+        let timer = host
+            .peers()
+            .filter_map(|peer| peer.next_timer())
+            .map(|timer| {
+                if timer.is_before(now) {
+                    Duration::ZERO
+                } else {
+                    timer.difference(now)
+                }
+            })
+            .min();
+
+        let timeout = timer.map_or(gap, |timer| timer.min(gap));
+        timeout.clamp(self.config.minimum, self.config.maximum)
+    }
+
+    /// Services `host` with the current timeout, collecting the events into `events` like
+    /// `Host::service_batch`, and records them. Returns the number of events collected.
+    pub fn service<T>(
+        &mut self,
+        host: &mut Host<T>,
+        events: &mut Vec<Event>,
+    ) -> Result<usize, Error> {
+        let timeout = self.timeout(host);
+        let collected = host.service_batch(Some(timeout), events)?;
+        self.record(collected);
+        Ok(collected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ServiceInterval, ServiceIntervalConfig};
+    use crate::tests::{create_host, pump_until};
+    use crate::{Address, EventKind};
+
+    use std::net::Ipv4Addr;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_service_interval() {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12397);
+        let mut server = create_host(Some(&server_address), 1);
+        let mut client = create_host(None, 1);
+
+        let config = ServiceIntervalConfig {
+            maximum: Duration::from_secs(5),
+            smoothing: Duration::from_millis(10),
+            ..ServiceIntervalConfig::default()
+        };
+        let mut interval = ServiceInterval::new(config);
+        assert_eq!(interval.timeout(&server), Duration::from_secs(5));
+
+        // A busy host is serviced often.
+        thread::sleep(Duration::from_millis(20));
+        interval.record(1000);
+        assert!(interval.event_rate() > 10_000.0);
+        assert_eq!(interval.timeout(&server), config.minimum);
+
+        thread::sleep(Duration::from_millis(200));
+        interval.record(0);
+        assert!(interval.event_rate() < 1.0);
+
+        client.connect(&server_address, 1, 0).unwrap();
+        pump_until(&mut [&mut client, &mut server], |index, _, event| {
+            index == 1 && matches!(event.kind, EventKind::Connect)
+        });
+
+        // A connected peer needs a ping every 500 ms.
+        let next = interval.timeout(&server);
+        assert!(next <= Duration::from_millis(500), "{:?}", next);
+
+        let mut events = Vec::new();
+        client.service(Some(Duration::from_millis(2))).unwrap();
+        interval.service(&mut server, &mut events).unwrap();
+    }
+}
@@ -0,0 +1,183 @@
+//! Stopping a service loop cleanly on Ctrl-C.
+//!
+//! A [ShutdownSignal](struct.ShutdownSignal.html) is a flag that is set on SIGINT or SIGTERM, or
+//! by the application itself. `ShutdownSignal::run` services a `Host` until the flag is set, and
+//! then shuts it down with `Host::shutdown`, so the peers learn about it right away instead of
+//! timing out.
+//!
+//! ```no_run
+//! # use enet::*;
+//! # use enet::shutdown::ShutdownSignal;
+//! # use std::time::Duration;
+//! # fn run(host: Host<()>) -> Result<(), Error> {
+//! let signal = ShutdownSignal::install()?;
+//! signal.run(host, 0, Duration::from_secs(3), |host, event| {
+//!     // Handle the event.
+//! })?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{Error, Event, Host};
+
+/// How long `ShutdownSignal::service` waits at once, so it notices the signal soon enough.
+const SLICE: Duration = Duration::from_millis(100);
+
+/// Set by the signal handler of `ShutdownSignal::install`.
+static SIGNALLED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn handle_signal(_signal: libc::c_int) {
+    SIGNALLED.store(true, Ordering::Relaxed);
+}
+
+/// A flag that requests a service loop to shut its `Host` down.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownSignal {
+    flag: Arc<AtomicBool>,
+    signals: bool,
+}
+
+impl ShutdownSignal {
+    /// Creates a signal that is only triggered by `ShutdownSignal::trigger`.
+    pub fn new() -> ShutdownSignal {
+        ShutdownSignal::default()
+    }
+
+    /// Creates a signal that is triggered once `flag` is set, e.g. by an admin command.
+    pub fn from_flag(flag: Arc<AtomicBool>) -> ShutdownSignal {
+        ShutdownSignal {
+            flag,
+            signals: false,
+        }
+    }
+
+    /// Creates a signal that is also triggered by SIGINT and SIGTERM.
+    ///
+    /// Replaces the handlers of both signals for the whole process, so the process no longer
+    /// exits on Ctrl-C by itself. On other platforms than Unix, set a flag from a console handler
+    /// and use `ShutdownSignal::from_flag` instead.
+    #[cfg(unix)]
+    pub fn install() -> Result<ShutdownSignal, Error> {
+        for &signal in &[libc::SIGINT, libc::SIGTERM] {
+            let handler = handle_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            if unsafe { libc::signal(signal, handler) } == libc::SIG_ERR {
+                return Err(Error(-1));
+            }
+        }
+
+        Ok(ShutdownSignal {
+            flag: Arc::new(AtomicBool::new(false)),
+            signals: true,
+        })
+    }
+
+    /// Returns the flag of this signal, to trigger it from another thread.
+    pub fn flag(&self) -> Arc<AtomicBool> {
+        self.flag.clone()
+    }
+
+    /// Triggers this signal.
+    pub fn trigger(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns whether this signal was triggered.
+    pub fn is_triggered(&self) -> bool {
+        self.flag.load(Ordering::Relaxed) || (self.signals && SIGNALLED.load(Ordering::Relaxed))
+    }
+
+    /// Services `host` like `Host::service`, but returns `None` early once this signal is
+    /// triggered.
+    ///
+    /// Waits for at most 100 milliseconds at once, so a signal is noticed even without traffic.
+    pub fn service<T>(
+        &self,
+        host: &mut Host<T>,
+        timeout: Option<Duration>,
+    ) -> Result<Option<Event>, Error> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            if self.is_triggered() {
+                return Ok(None);
+            }
+            let remaining =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            let slice = remaining.map_or(SLICE, |remaining| remaining.min(SLICE));
+            if let Some(event) = host.service(Some(slice))? {
+                return Ok(Some(event));
+            }
+            if remaining.is_some_and(|remaining| remaining <= SLICE) {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Services `host`, passing every event to `handle`, until this signal is triggered. Then
+    /// shuts `host` down like `Host::shutdown` with `data` and `drain_timeout`.
+    ///
+    /// Returns the number of peers that did not acknowledge the disconnect in time.
+    pub fn run<T, F>(
+        &self,
+        mut host: Host<T>,
+        data: u32,
+        drain_timeout: Duration,
+        mut handle: F,
+    ) -> Result<usize, Error>
+    where
+        F: FnMut(&mut Host<T>, Event),
+    {
+        while !self.is_triggered() {
+            if let Some(event) = self.service(&mut host, None)? {
+                handle(&mut host, event);
+            }
+        }
+        host.shutdown(data, drain_timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ShutdownSignal;
+    use crate::tests::{create_host, pump_until};
+    use crate::{Address, EventKind};
+
+    use std::net::Ipv4Addr;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_shutdown_signal() {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12410);
+        let signal = ShutdownSignal::new();
+        let server = {
+            let signal = signal.clone();
+            thread::spawn(move || {
+                let server = create_host(Some(&Address::new(Ipv4Addr::LOCALHOST, 12410)), 1);
+                signal.run(server, 9, Duration::from_secs(3), |_, event| {
+                    // The server shuts down once the client connected.
+                    if let EventKind::Connect = event.kind {
+                        signal.trigger();
+                    }
+                })
+            })
+        };
+
+        let mut client = create_host(None, 1);
+        client.connect(&server_address, 1, 0).unwrap();
+        let mut disconnected = None;
+        pump_until(&mut [&mut client], |_, _, event| {
+            if let EventKind::Disconnect { data } = event.kind {
+                disconnected = Some(data);
+            }
+            disconnected.is_some()
+        });
+        assert_eq!(disconnected, Some(9));
+        assert_eq!(server.join().unwrap().unwrap(), 0);
+        assert!(signal.is_triggered());
+    }
+}
@@ -0,0 +1,298 @@
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::Duration;
+
+use crate::Transport;
+
+const SOCKS_VERSION: u8 = 5;
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_USERNAME_PASSWORD: u8 = 0x02;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xff;
+const COMMAND_UDP_ASSOCIATE: u8 = 0x03;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+fn proxy_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("SOCKS5: {}", message))
+}
+
+fn write_address(out: &mut Vec<u8>, address: &SocketAddr) {
+    match address.ip() {
+        IpAddr::V4(ip) => {
+            out.push(ATYP_IPV4);
+            out.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            out.push(ATYP_IPV6);
+            out.extend_from_slice(&ip.octets());
+        }
+    }
+    out.extend_from_slice(&address.port().to_be_bytes());
+}
+
+/// Reads the address of a SOCKS5 reply from `stream`.
+fn read_address(stream: &mut impl Read) -> io::Result<SocketAddr> {
+    let mut atyp = [0; 1];
+    stream.read_exact(&mut atyp)?;
+
+    let ip = match atyp[0] {
+        ATYP_IPV4 => {
+            let mut octets = [0; 4];
+            stream.read_exact(&mut octets)?;
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        ATYP_IPV6 => {
+            let mut octets = [0; 16];
+            stream.read_exact(&mut octets)?;
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return Err(proxy_error("unsupported address type in reply")),
+    };
+
+    let mut port = [0; 2];
+    stream.read_exact(&mut port)?;
+    Ok(SocketAddr::new(ip, u16::from_be_bytes(port)))
+}
+
+/// Prepends the UDP request header for `destination` to `data`.
+fn encode_datagram(out: &mut Vec<u8>, data: &[u8], destination: &SocketAddr) {
+    out.clear();
+    // RSV (2 bytes) and FRAG, fragmentation is not used.
+    out.extend_from_slice(&[0, 0, 0]);
+    write_address(out, destination);
+    out.extend_from_slice(data);
+}
+
+/// Splits a datagram from the relay into its origin and the offset of its payload.
+///
+/// Returns `None` for malformed and fragmented datagrams, as well as domain name origins.
+fn decode_datagram(datagram: &[u8]) -> Option<(SocketAddr, usize)> {
+    if datagram.len() < 4 || datagram[2] != 0 {
+        return None;
+    }
+
+    let (ip, offset) = match datagram[3] {
+        ATYP_IPV4 if datagram.len() >= 10 => {
+            let mut octets = [0; 4];
+            octets.copy_from_slice(&datagram[4..8]);
+            (IpAddr::V4(Ipv4Addr::from(octets)), 8)
+        }
+        ATYP_IPV6 if datagram.len() >= 22 => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(&datagram[4..20]);
+            (IpAddr::V6(Ipv6Addr::from(octets)), 20)
+        }
+        // ENet peers are identified by IP address, so there is nothing to map a domain name to.
+        ATYP_DOMAIN => return None,
+        _ => return None,
+    };
+
+    let port = u16::from_be_bytes([datagram[offset], datagram[offset + 1]]);
+    Some((SocketAddr::new(ip, port), offset + 2))
+}
+
+/// Username and password for proxies that require authentication (RFC 1929).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Socks5Credentials {
+    /// The username, at most 255 bytes.
+    pub username: String,
+    /// The password, at most 255 bytes.
+    pub password: String,
+}
+
+/// A `Transport` that routes its datagrams through a SOCKS5 proxy, using UDP ASSOCIATE.
+///
+/// Create a `Host` with it via [Enet::create_host_with_transport](struct.Enet.html#method.create_host_with_transport).
+/// Endpoints are the addresses of the remote hosts, as seen from the proxy.
+///
+/// The proxy keeps the association alive only as long as the control connection is open,
+/// which this transport holds on to until it is dropped.
+pub struct Socks5Transport {
+    _control: TcpStream,
+    socket: UdpSocket,
+    relay: SocketAddr,
+    buffer: Vec<u8>,
+}
+
+impl Socks5Transport {
+    /// Negotiates a UDP association with the SOCKS5 proxy at `proxy`.
+    ///
+    /// This blocks during the handshake, each step being limited by `timeout`.
+    pub fn connect(
+        proxy: SocketAddr,
+        credentials: Option<&Socks5Credentials>,
+        timeout: Duration,
+    ) -> io::Result<Socks5Transport> {
+        let mut control = TcpStream::connect_timeout(&proxy, timeout)?;
+        control.set_read_timeout(Some(timeout))?;
+        control.set_write_timeout(Some(timeout))?;
+
+        let method = if credentials.is_some() {
+            METHOD_USERNAME_PASSWORD
+        } else {
+            METHOD_NO_AUTH
+        };
+        control.write_all(&[SOCKS_VERSION, 1, method])?;
+
+        let mut reply = [0; 2];
+        control.read_exact(&mut reply)?;
+        if reply[0] != SOCKS_VERSION {
+            return Err(proxy_error("not a SOCKS5 proxy"));
+        }
+        if reply[1] == METHOD_NONE_ACCEPTABLE || reply[1] != method {
+            return Err(proxy_error("authentication method rejected"));
+        }
+
+        if let Some(credentials) = credentials {
+            let (username, password) = (
+                credentials.username.as_bytes(),
+                credentials.password.as_bytes(),
+            );
+            if username.len() > 255 || password.len() > 255 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "SOCKS5: username and password may be at most 255 bytes",
+                ));
+            }
+
+            let mut request = vec![1, username.len() as u8];
+            request.extend_from_slice(username);
+            request.push(password.len() as u8);
+            request.extend_from_slice(password);
+            control.write_all(&request)?;
+
+            control.read_exact(&mut reply)?;
+            if reply[1] != 0 {
+                return Err(proxy_error("authentication failed"));
+            }
+        }
+
+        let local: SocketAddr = match proxy {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.set_nonblocking(true)?;
+
+        // Tell the proxy which port we send from; the address is left unspecified, as we may be behind NAT.
+        let mut request = vec![SOCKS_VERSION, COMMAND_UDP_ASSOCIATE, 0];
+        write_address(
+            &mut request,
+            &SocketAddr::new(local.ip(), socket.local_addr()?.port()),
+        );
+        control.write_all(&request)?;
+
+        let mut header = [0; 3];
+        control.read_exact(&mut header)?;
+        if header[0] != SOCKS_VERSION {
+            return Err(proxy_error("not a SOCKS5 proxy"));
+        }
+        if header[1] != 0 {
+            return Err(proxy_error(&format!(
+                "UDP ASSOCIATE failed with reply code {}",
+                header[1]
+            )));
+        }
+
+        let mut relay = read_address(&mut control)?;
+        // Proxies answer with an unspecified address to mean "the address you connected to".
+        if relay.ip().is_unspecified() {
+            relay.set_ip(proxy.ip());
+        }
+
+        Ok(Socks5Transport {
+            _control: control,
+            socket,
+            relay,
+            buffer: Vec::new(),
+        })
+    }
+
+    /// Returns the proxy's UDP relay address that all datagrams are sent to.
+    pub fn relay_addr(&self) -> SocketAddr {
+        self.relay
+    }
+}
+
+impl Transport for Socks5Transport {
+    type Endpoint = SocketAddr;
+    type Error = io::Error;
+
+    fn send_to(&mut self, data: &[u8], endpoint: &SocketAddr) -> io::Result<()> {
+        encode_datagram(&mut self.buffer, data, endpoint);
+        let relay = self.relay;
+        Transport::send_to(&mut self.socket, &self.buffer, &relay)
+    }
+
+    fn recv_from(&mut self, buffer: &mut [u8]) -> io::Result<Option<(usize, SocketAddr)>> {
+        // Room for the largest header, so payloads that fit into `buffer` are never truncated.
+        self.buffer.resize(buffer.len() + 22, 0);
+
+        loop {
+            let (len, from) = match Transport::recv_from(&mut self.socket, &mut self.buffer)? {
+                Some(received) => received,
+                None => return Ok(None),
+            };
+
+            if from != self.relay {
+                continue;
+            }
+
+            if let Some((origin, offset)) = decode_datagram(&self.buffer[..len]) {
+                let payload = &self.buffer[offset..len];
+                let copied = payload.len().min(buffer.len());
+                buffer[..copied].copy_from_slice(&payload[..copied]);
+                return Ok(Some((copied, origin)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_datagram, encode_datagram, Socks5Transport, ATYP_DOMAIN};
+
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::time::Duration;
+
+    #[test]
+    fn test_datagram_header() {
+        let destination: SocketAddr = ([192, 0, 2, 7], 4321).into();
+        let mut datagram = Vec::new();
+        encode_datagram(&mut datagram, b"payload", &destination);
+
+        assert_eq!(&datagram[..10], &[0, 0, 0, 1, 192, 0, 2, 7, 0x10, 0xe1]);
+        assert_eq!(decode_datagram(&datagram), Some((destination, 10)));
+
+        datagram[3] = ATYP_DOMAIN;
+        assert_eq!(decode_datagram(&datagram), None);
+    }
+
+    #[test]
+    fn test_udp_associate_handshake() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let proxy = listener.local_addr().unwrap();
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut greeting = [0; 3];
+            stream.read_exact(&mut greeting).unwrap();
+            assert_eq!(greeting, [5, 1, 0]);
+            stream.write_all(&[5, 0]).unwrap();
+
+            let mut request = [0; 10];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(&request[..4], &[5, 3, 0, 1]);
+            stream
+                .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0x30, 0x39])
+                .unwrap();
+            stream
+        });
+
+        let transport = Socks5Transport::connect(proxy, None, Duration::from_secs(1)).unwrap();
+        assert_eq!(transport.relay_addr(), ([127, 0, 0, 1], 12345).into());
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use crate::{Event, EventKind, Host, Packet, PacketMode, PeerID, SendError};
+
+/// The most data put into a single packet by `ChannelStream::write`.
+const MAX_WRITE_LEN: usize = 64 * 1024;
+
+fn send_error_to_io(error: SendError) -> io::Error {
+    let kind = match error {
+        SendError::NotConnected(_) => io::ErrorKind::NotConnected,
+        SendError::InvalidChannel(_) | SendError::Unconfigured(_) => io::ErrorKind::InvalidInput,
+        SendError::OverBudget(_) | SendError::WouldBlock => io::ErrorKind::WouldBlock,
+        SendError::Error(_) => io::ErrorKind::Other,
+    };
+
+    io::Error::new(kind, error.to_string())
+}
+
+/// A byte stream over one channel of a connection, implementing `Read` and `Write`.
+///
+/// Writes are sent as reliable, sequenced packets, and the data of received packets is read in
+/// order, so stream-oriented code can run over an ENet connection unchanged. The stream uses its
+/// channel exclusively.
+///
+/// Reading services the `Host`. Events that don't belong to the stream are kept, and can be
+/// retrieved with `ChannelStream::next_event`. Once the peer disconnects, reads return end-of-file.
+pub struct ChannelStream<'a, T> {
+    host: &'a mut Host<T>,
+    peer_id: PeerID,
+    channel_id: u8,
+    packet: Vec<u8>,
+    position: usize,
+    events: VecDeque<Event>,
+    read_timeout: Option<Duration>,
+    closed: bool,
+}
+
+impl<'a, T> ChannelStream<'a, T> {
+    /// Creates a stream over channel `channel_id` of the connection to `peer_id`.
+    pub fn new(host: &'a mut Host<T>, peer_id: PeerID, channel_id: u8) -> ChannelStream<'a, T> {
+        ChannelStream {
+            host,
+            peer_id,
+            channel_id,
+            packet: Vec::new(),
+            position: 0,
+            events: VecDeque::new(),
+            read_timeout: None,
+            closed: false,
+        }
+    }
+
+    /// Sets how long a read waits for data, before failing with `io::ErrorKind::TimedOut`.
+    ///
+    /// With `None`, which is the default, reads wait until data arrives or the peer disconnects.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    /// Returns the `Host` the stream services.
+    pub fn host(&mut self) -> &mut Host<T> {
+        self.host
+    }
+
+    /// Returns the next event that was received while reading, but does not belong to the stream.
+    pub fn next_event(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+
+    /// Services the host until the next packet of the stream arrives.
+    fn receive(&mut self) -> io::Result<()> {
+        let deadline = self.read_timeout.map(|timeout| Instant::now() + timeout);
+
+        loop {
+            let timeout = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) => Some(remaining),
+                    None => return Err(io::ErrorKind::TimedOut.into()),
+                },
+                None => None,
+            };
+
+            let event = match self.host.service(timeout) {
+                Ok(Some(event)) => event,
+                Ok(None) => continue,
+                Err(e) => return Err(io::Error::new(io::ErrorKind::Other, e.to_string())),
+            };
+
+            if event.peer_id == self.peer_id {
+                match event.kind {
+                    EventKind::Receive {
+                        channel_id,
+                        ref packet,
+                    } if channel_id == self.channel_id => {
+                        self.packet = packet.data().to_vec();
+                        self.position = 0;
+                        return Ok(());
+                    }
+                    ref kind if kind.is_disconnect() => {
+                        self.closed = true;
+                        self.events.push_back(event);
+                        return Ok(());
+                    }
+                    _ => (),
+                }
+            }
+
+            self.events.push_back(event);
+        }
+    }
+}
+
+impl<'a, T> Read for ChannelStream<'a, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position >= self.packet.len() {
+            if self.closed || buf.is_empty() {
+                return Ok(0);
+            }
+
+            self.receive()?;
+        }
+
+        let len = buf.len().min(self.packet.len() - self.position);
+        buf[..len].copy_from_slice(&self.packet[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+impl<'a, T> Write for ChannelStream<'a, T> {
+    /// Sends up to 64 KiB of `buf` as a single packet.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let len = buf.len().min(MAX_WRITE_LEN);
+        let packet = Packet::new(buf[..len].to_vec(), PacketMode::ReliableSequenced)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+
+        let peer = self
+            .host
+            .peer_mut(self.peer_id)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+        peer.send_packet(packet, self.channel_id)
+            .map_err(send_error_to_io)?;
+
+        Ok(len)
+    }
+
+    /// Sends all queued packets right away, see `Host::flush`.
+    fn flush(&mut self) -> io::Result<()> {
+        self.host.flush();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChannelStream;
+    use crate::tests::create_host;
+    use crate::{Address, EventKind};
+
+    use std::io::{Read, Write};
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    #[test]
+    fn test_stream_roundtrip() {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12358);
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+
+        let expected = data.clone();
+        let address = server_address.clone();
+        let server = std::thread::spawn(move || {
+            let mut host = create_host(Some(&address), 1);
+            let peer_id = loop {
+                if let Some(event) = host.service(None).unwrap() {
+                    if let EventKind::Connect = event.kind {
+                        break event.peer_id;
+                    }
+                }
+            };
+
+            let mut stream = ChannelStream::new(&mut host, peer_id, 0);
+            stream.set_read_timeout(Some(Duration::from_secs(10)));
+            let mut received = vec![0; expected.len()];
+            stream.read_exact(&mut received).unwrap();
+            assert!(received == expected);
+        });
+
+        let mut client = create_host(None, 1);
+        let (_, peer_id) = client.connect(&server_address, 1, 0).unwrap();
+        loop {
+            if let Some(event) = client.service(None).unwrap() {
+                if let EventKind::Connect = event.kind {
+                    break;
+                }
+            }
+        }
+
+        let mut stream = ChannelStream::new(&mut client, peer_id, 0);
+        stream.write_all(&data).unwrap();
+        stream.flush().unwrap();
+
+        while !server.is_finished() {
+            client.service(Some(Duration::from_millis(5))).unwrap();
+        }
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,435 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::Transport;
+
+/// Datagrams queued on a connection beyond this many bytes are dropped instead of buffered.
+const MAX_QUEUED_BYTES: usize = 256 * 1024;
+
+/// Each datagram on the stream is preceded by its length as a big-endian `u16`.
+const FRAME_HEADER_LEN: usize = 2;
+
+struct Connection {
+    stream: TcpStream,
+    read_buffer: Vec<u8>,
+    write_buffer: Vec<u8>,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> io::Result<Connection> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+
+        Ok(Connection {
+            stream,
+            read_buffer: Vec::new(),
+            write_buffer: Vec::new(),
+        })
+    }
+
+    fn queue(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.len() > usize::from(u16::MAX) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "datagram too large for TCP framing",
+            ));
+        }
+
+        // Like a congested UDP socket, rather drop datagrams than delay everything behind them.
+        if self.write_buffer.len() + FRAME_HEADER_LEN + data.len() <= MAX_QUEUED_BYTES {
+            self.write_buffer
+                .extend_from_slice(&(data.len() as u16).to_be_bytes());
+            self.write_buffer.extend_from_slice(data);
+        }
+
+        self.flush()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.write_buffer.is_empty() {
+            match self.stream.write(&self.write_buffer) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    self.write_buffer.drain(..written);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads everything available, returning `false` once the remote closed the connection.
+    fn fill(&mut self) -> io::Result<bool> {
+        let mut chunk = [0; 4096];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Ok(false),
+                Ok(read) => self.read_buffer.extend_from_slice(&chunk[..read]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(true),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn next_frame(&mut self, buffer: &mut [u8]) -> Option<usize> {
+        if self.read_buffer.len() < FRAME_HEADER_LEN {
+            return None;
+        }
+
+        let len = usize::from(u16::from_be_bytes([
+            self.read_buffer[0],
+            self.read_buffer[1],
+        ]));
+        if self.read_buffer.len() < FRAME_HEADER_LEN + len {
+            return None;
+        }
+
+        let frame = &self.read_buffer[FRAME_HEADER_LEN..FRAME_HEADER_LEN + len];
+        let copied = frame.len().min(buffer.len());
+        buffer[..copied].copy_from_slice(&frame[..copied]);
+        self.read_buffer.drain(..FRAME_HEADER_LEN + len);
+
+        Some(copied)
+    }
+}
+
+/// A `Transport` that tunnels datagrams over TCP connections, for networks that block UDP.
+///
+/// Every datagram is framed with a 2-byte length prefix. Both ends of a connection have to use a
+/// `TcpTransport`, so a server that should be reachable this way needs an extra `Host` created with
+/// [TcpTransport::listen](#method.listen), next to its regular UDP `Host`.
+///
+/// Endpoints are the remote addresses of the TCP connections.
+pub struct TcpTransport {
+    listener: Option<TcpListener>,
+    connections: HashMap<SocketAddr, Connection>,
+}
+
+impl TcpTransport {
+    /// Creates a transport that accepts incoming connections on `address`.
+    pub fn listen(address: SocketAddr) -> io::Result<TcpTransport> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+
+        Ok(TcpTransport {
+            listener: Some(listener),
+            connections: HashMap::new(),
+        })
+    }
+
+    /// Creates a transport with a single connection to the server at `address`.
+    ///
+    /// This blocks until the connection is established, or `timeout` has passed.
+    pub fn connect(address: SocketAddr, timeout: Duration) -> io::Result<TcpTransport> {
+        let stream = TcpStream::connect_timeout(&address, timeout)?;
+
+        let mut connections = HashMap::new();
+        connections.insert(address, Connection::new(stream)?);
+
+        Ok(TcpTransport {
+            listener: None,
+            connections,
+        })
+    }
+
+    /// Returns the local address the transport listens on, if it was created with `listen`.
+    pub fn local_addr(&self) -> Option<io::Result<SocketAddr>> {
+        self.listener.as_ref().map(TcpListener::local_addr)
+    }
+
+    fn accept(&mut self) -> io::Result<()> {
+        if let Some(listener) = &self.listener {
+            loop {
+                match listener.accept() {
+                    Ok((stream, address)) => {
+                        // A connection that cannot be set up is dropped, like a failed one.
+                        if let Ok(connection) = Connection::new(stream) {
+                            self.connections.insert(address, connection);
+                        }
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Transport for TcpTransport {
+    type Endpoint = SocketAddr;
+    type Error = io::Error;
+
+    /// Queues a datagram on the connection to `endpoint`. Datagrams for endpoints without a
+    /// connection are dropped, just like UDP would drop them.
+    fn send_to(&mut self, data: &[u8], endpoint: &SocketAddr) -> io::Result<()> {
+        let result = match self.connections.get_mut(endpoint) {
+            Some(connection) => connection.queue(data),
+            None => return Ok(()),
+        };
+
+        if result.is_err() {
+            self.connections.remove(endpoint);
+        }
+
+        result
+    }
+
+    fn recv_from(&mut self, buffer: &mut [u8]) -> io::Result<Option<(usize, SocketAddr)>> {
+        self.accept()?;
+
+        let mut closed = Vec::new();
+        let mut received = None;
+
+        for (address, connection) in &mut self.connections {
+            if let Some(len) = connection.next_frame(buffer) {
+                received = Some((len, *address));
+                break;
+            }
+
+            // A failed connection is dropped like a closed one, without affecting the others.
+            match connection.flush().and_then(|_| connection.fill()) {
+                Ok(true) => (),
+                Ok(false) | Err(_) => closed.push(*address),
+            }
+
+            if let Some(len) = connection.next_frame(buffer) {
+                received = Some((len, *address));
+                break;
+            }
+        }
+
+        for address in closed {
+            self.connections.remove(&address);
+        }
+
+        Ok(received)
+    }
+}
+
+enum FallbackMode {
+    /// Uses UDP, and connects over TCP in the background once `fallback_after` passed.
+    Probing {
+        started: Instant,
+        connecting: Option<Receiver<io::Result<TcpTransport>>>,
+    },
+    Udp,
+    Tcp(TcpTransport),
+}
+
+/// A client-side `Transport` to a single server, which uses UDP and falls back to a
+/// [TcpTransport](struct.TcpTransport.html) if the server does not answer over UDP in time.
+///
+/// The server is expected to accept ENet over UDP and over TCP on the same address. The TCP
+/// connection is established on a helper thread, and the transport keeps using UDP until it is.
+/// If it fails, the transport tries again after another `fallback_after`. When the transport
+/// switches to TCP, ENet simply sees its unanswered connection attempt being resent, so
+/// `fallback_after` should be well below ENet's peer timeout (5 seconds by default). Once the TCP
+/// connection closes, the transport probes UDP again, as if it was just created.
+pub struct FallbackTransport {
+    udp: UdpSocket,
+    server: SocketAddr,
+    fallback_after: Duration,
+    mode: FallbackMode,
+}
+
+impl FallbackTransport {
+    /// Creates a transport to `server` that falls back to TCP after `fallback_after` without
+    /// receiving anything over UDP.
+    pub fn new(server: SocketAddr, fallback_after: Duration) -> io::Result<FallbackTransport> {
+        let local: SocketAddr = match server {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        let udp = UdpSocket::bind(local)?;
+        udp.set_nonblocking(true)?;
+
+        Ok(FallbackTransport {
+            udp,
+            server,
+            fallback_after,
+            mode: FallbackMode::Probing {
+                started: Instant::now(),
+                connecting: None,
+            },
+        })
+    }
+
+    /// Returns the address of the server this transport connects to.
+    pub fn server(&self) -> SocketAddr {
+        self.server
+    }
+
+    /// Returns whether the transport switched to TCP.
+    pub fn uses_tcp(&self) -> bool {
+        matches!(self.mode, FallbackMode::Tcp(_))
+    }
+
+    /// Starts connecting over TCP once `fallback_after` passed while probing, and switches to the
+    /// connection once it is established. Goes back to probing if the connection closed.
+    fn poll_fallback(&mut self) {
+        if let FallbackMode::Tcp(tcp) = &self.mode {
+            if tcp.connections.is_empty() {
+                self.mode = FallbackMode::Probing {
+                    started: Instant::now(),
+                    connecting: None,
+                };
+            }
+        }
+
+        let (started, connecting) = match &mut self.mode {
+            FallbackMode::Probing {
+                started,
+                connecting,
+            } => (started, connecting),
+            _ => return,
+        };
+
+        let result = match connecting {
+            Some(receiver) => match receiver.try_recv() {
+                Ok(result) => result,
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "TCP connect thread panicked",
+                )),
+            },
+            None => {
+                if started.elapsed() >= self.fallback_after {
+                    let (sender, receiver) = mpsc::channel();
+                    let (server, timeout) = (self.server, self.fallback_after);
+                    thread::spawn(move || {
+                        let _ = sender.send(TcpTransport::connect(server, timeout));
+                    });
+                    *connecting = Some(receiver);
+                }
+                return;
+            }
+        };
+
+        match result {
+            Ok(tcp) => self.mode = FallbackMode::Tcp(tcp),
+            Err(_) => {
+                *started = Instant::now();
+                *connecting = None;
+            }
+        }
+    }
+}
+
+impl Transport for FallbackTransport {
+    type Endpoint = SocketAddr;
+    type Error = io::Error;
+
+    fn send_to(&mut self, data: &[u8], endpoint: &SocketAddr) -> io::Result<()> {
+        self.poll_fallback();
+
+        match &mut self.mode {
+            FallbackMode::Tcp(tcp) => tcp.send_to(data, endpoint),
+            _ => Transport::send_to(&mut self.udp, data, endpoint),
+        }
+    }
+
+    fn recv_from(&mut self, buffer: &mut [u8]) -> io::Result<Option<(usize, SocketAddr)>> {
+        self.poll_fallback();
+
+        match &mut self.mode {
+            FallbackMode::Tcp(tcp) => tcp.recv_from(buffer),
+            FallbackMode::Udp => Transport::recv_from(&mut self.udp, buffer),
+            FallbackMode::Probing { .. } => {
+                // While probing, errors such as ICMP port unreachable mean UDP does not work (yet).
+                match Transport::recv_from(&mut self.udp, buffer) {
+                    Ok(Some((len, from))) if from == self.server => {
+                        self.mode = FallbackMode::Udp;
+                        Ok(Some((len, from)))
+                    }
+                    Ok(_) | Err(_) => Ok(None),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FallbackTransport, TcpTransport};
+    use crate::Transport;
+
+    use std::time::Duration;
+
+    #[test]
+    fn test_tcp_framing() {
+        let mut server = TcpTransport::listen(([127, 0, 0, 1], 0).into()).unwrap();
+        let address = server.local_addr().unwrap().unwrap();
+        let mut client = TcpTransport::connect(address, Duration::from_secs(1)).unwrap();
+
+        client.send_to(b"first", &address).unwrap();
+        client.send_to(b"second", &address).unwrap();
+
+        let mut buffer = [0; 16];
+        let mut received = Vec::new();
+        for _ in 0..1000 {
+            if let Some((len, _)) = server.recv_from(&mut buffer).unwrap() {
+                received.push(buffer[..len].to_vec());
+                if received.len() == 2 {
+                    break;
+                }
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(received, vec![b"first".to_vec(), b"second".to_vec()]);
+    }
+
+    #[test]
+    fn test_fallback_to_tcp() {
+        // Only TCP listens on this port, so the datagrams sent over UDP get lost.
+        let mut server = TcpTransport::listen(([127, 0, 0, 1], 0).into()).unwrap();
+        let address = server.local_addr().unwrap().unwrap();
+        let mut client = FallbackTransport::new(address, Duration::from_millis(20)).unwrap();
+
+        let mut buffer = [0; 16];
+        let mut received = None;
+        for _ in 0..1000 {
+            client.send_to(b"hello", &address).unwrap();
+            if let Some((len, from)) = server.recv_from(&mut buffer).unwrap() {
+                received = Some((buffer[..len].to_vec(), from));
+                break;
+            }
+            assert!(client.recv_from(&mut buffer).unwrap().is_none());
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(client.uses_tcp());
+        let (data, from) = received.unwrap();
+        assert_eq!(data, b"hello");
+
+        server.send_to(b"welcome", &from).unwrap();
+        let mut answer = None;
+        for _ in 0..1000 {
+            if let Some((len, _)) = client.recv_from(&mut buffer).unwrap() {
+                answer = Some(buffer[..len].to_vec());
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(answer, Some(b"welcome".to_vec()));
+
+        // Without the server, the connection closes and the client probes UDP again.
+        drop(server);
+        for _ in 0..1000 {
+            assert!(client.recv_from(&mut buffer).unwrap().is_none());
+            if !client.uses_tcp() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(!client.uses_tcp());
+    }
+}
@@ -0,0 +1,126 @@
+use std::time::{Duration, Instant};
+
+use crate::Event;
+
+/// The number of ticks a tick loop catches up on, before it starts over from the current time.
+const MAX_CATCH_UP: u64 = 5;
+
+/// What `Host::run_ticks` passes to its handler.
+#[derive(Debug)]
+pub enum TickEvent {
+    /// An event returned by `Host::service` between two ticks.
+    Event(Event),
+    /// A tick is due, with the number of ticks since the loop started.
+    Tick(u64),
+}
+
+/// Schedules ticks at a fixed rate, relative to a fixed start, so they don't drift.
+#[derive(Debug)]
+pub(crate) struct TickClock {
+    period: Duration,
+    start: Instant,
+    next: u64,
+    skipped: u64,
+}
+
+impl TickClock {
+    pub(crate) fn new(ticks_per_second: u32, now: Instant) -> TickClock {
+        assert!(ticks_per_second > 0, "the tick rate must not be 0");
+        TickClock {
+            period: Duration::from_secs(1) / ticks_per_second,
+            start: now,
+            next: 0,
+            skipped: 0,
+        }
+    }
+
+    fn deadline(&self) -> Instant {
+        self.start + self.period * (self.next - self.skipped) as u32
+    }
+
+    /// Returns the next tick if it is due at `now`.
+    ///
+    /// Ticks that are late are still returned, back to back, unless the loop fell too far behind.
+    /// The ticks it then skips keep their numbers.
+    pub(crate) fn due(&mut self, now: Instant) -> Option<u64> {
+        let deadline = self.deadline();
+        if now < deadline {
+            return None;
+        }
+
+        let behind = (now - deadline).as_nanos() / self.period.as_nanos().max(1);
+        if behind as u64 > MAX_CATCH_UP {
+            self.start = now;
+            self.skipped = self.next;
+        }
+
+        let tick = self.next;
+        self.next += 1;
+        Some(tick)
+    }
+
+    /// Returns how long until the next tick is due.
+    pub(crate) fn timeout(&self, now: Instant) -> Duration {
+        self.deadline().saturating_duration_since(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TickClock, TickEvent};
+    use crate::tests::ENET;
+    use crate::{BandwidthLimit, ChannelLimit};
+
+    use std::ops::ControlFlow;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_tick_clock() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut clock = TickClock::new(100, start);
+
+        assert_eq!(clock.due(at(0)), Some(0));
+        assert_eq!(clock.due(at(0)), None);
+        assert_eq!(clock.timeout(at(4)), Duration::from_millis(6));
+        // A late tick doesn't delay the ones after it.
+        assert_eq!(clock.due(at(13)), Some(1));
+        assert_eq!(clock.timeout(at(13)), Duration::from_millis(7));
+        assert_eq!(clock.due(at(20)), Some(2));
+
+        // Far behind, the clock starts over instead of running every missed tick.
+        assert_eq!(clock.due(at(200)), Some(3));
+        assert_eq!(clock.due(at(200)), None);
+        assert_eq!(clock.timeout(at(200)), Duration::from_millis(10));
+        assert_eq!(clock.due(at(210)), Some(4));
+    }
+
+    #[test]
+    fn test_run_ticks() {
+        let mut host = ENET
+            .create_host::<()>(
+                None,
+                1,
+                ChannelLimit::Maximum,
+                BandwidthLimit::Unlimited,
+                BandwidthLimit::Unlimited,
+            )
+            .unwrap();
+
+        let start = Instant::now();
+        let mut ticks = Vec::new();
+        host.run_ticks(100, |_, event| {
+            if let TickEvent::Tick(tick) = event {
+                ticks.push(tick);
+                if tick == 5 {
+                    return ControlFlow::Break(());
+                }
+            }
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+
+        assert_eq!(ticks, vec![0, 1, 2, 3, 4, 5]);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+}
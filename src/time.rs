@@ -0,0 +1,128 @@
+use std::time::{Duration, Instant};
+
+use enet_sys::{enet_time_get, enet_time_set};
+
+/// Mirrors `ENET_TIME_OVERFLOW` from ENet's private `time.h`: differences of at least this many
+/// milliseconds are treated as the clock having wrapped around.
+const ENET_TIME_OVERFLOW: u32 = 86_400_000;
+
+/// A timestamp on ENet's internal millisecond clock, as used for peer timers and throttle epochs.
+///
+/// ENet's clock is a wrapping `u32`. Like ENet itself, comparisons and differences assume that two
+/// timestamps are less than a day apart; anything further apart is interpreted as having wrapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EnetTime(u32);
+
+impl EnetTime {
+    /// Returns the current time of ENet's clock.
+    pub fn now() -> EnetTime {
+        EnetTime(unsafe { enet_time_get() })
+    }
+
+    /// Moves ENet's clock so that it currently reads `time`.
+    ///
+    /// This affects every `Host` in the process, since it changes the base of all of ENet's timers.
+    pub fn set_now(time: EnetTime) {
+        unsafe { enet_time_set(time.0) }
+    }
+
+    /// Creates a timestamp from a raw clock value in milliseconds.
+    pub fn from_millis(millis: u32) -> EnetTime {
+        EnetTime(millis)
+    }
+
+    /// Returns the raw clock value in milliseconds.
+    pub fn as_millis(self) -> u32 {
+        self.0
+    }
+
+    /// Returns whether this timestamp lies before `other`.
+    pub fn is_before(self, other: EnetTime) -> bool {
+        self.0.wrapping_sub(other.0) >= ENET_TIME_OVERFLOW
+    }
+
+    /// Returns the (absolute) amount of time between this timestamp and `other`.
+    pub fn difference(self, other: EnetTime) -> Duration {
+        let millis = if self.is_before(other) {
+            other.0.wrapping_sub(self.0)
+        } else {
+            self.0.wrapping_sub(other.0)
+        };
+
+        Duration::from_millis(u64::from(millis))
+    }
+
+    /// Converts this timestamp to an `Instant`, by relating it to the current time of both clocks.
+    ///
+    /// Returns `None` if the resulting point in time cannot be represented by `Instant`.
+    pub fn to_instant(self) -> Option<Instant> {
+        let (enet_now, now) = (EnetTime::now(), Instant::now());
+        let difference = self.difference(enet_now);
+
+        if self.is_before(enet_now) {
+            now.checked_sub(difference)
+        } else {
+            now.checked_add(difference)
+        }
+    }
+
+    /// Converts an `Instant` to a timestamp on ENet's clock, by relating it to the current time of both clocks.
+    pub fn from_instant(instant: Instant) -> EnetTime {
+        let (enet_now, now) = (EnetTime::now(), Instant::now());
+
+        if instant < now {
+            enet_now - (now - instant)
+        } else {
+            enet_now + (instant - now)
+        }
+    }
+}
+
+impl std::ops::Add<Duration> for EnetTime {
+    type Output = EnetTime;
+
+    fn add(self, rhs: Duration) -> EnetTime {
+        EnetTime(self.0.wrapping_add(rhs.as_millis() as u32))
+    }
+}
+
+impl std::ops::Sub<Duration> for EnetTime {
+    type Output = EnetTime;
+
+    fn sub(self, rhs: Duration) -> EnetTime {
+        EnetTime(self.0.wrapping_sub(rhs.as_millis() as u32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EnetTime;
+
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_wrapping_order() {
+        let before = EnetTime::from_millis(u32::MAX - 10);
+        let after = before + Duration::from_millis(20);
+
+        assert_eq!(after.as_millis(), 9);
+        assert!(before.is_before(after));
+        assert!(!after.is_before(before));
+        assert_eq!(after.difference(before), Duration::from_millis(20));
+        assert_eq!(before.difference(after), Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_instant_roundtrip() {
+        let instant = Instant::now() - Duration::from_secs(2);
+        let time = EnetTime::from_instant(instant);
+        let back = time.to_instant().unwrap();
+
+        let error = if back > instant {
+            back - instant
+        } else {
+            instant - back
+        };
+        assert!(error < Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,246 @@
+//! Estimates of the clock offsets between peers.
+//!
+//! A [ClockSync](struct.ClockSync.html) regularly pings the peers of a `Host` on a reserved
+//! channel, NTP-style: every ping records when it was sent, received, answered and when the
+//! answer arrived. From the most recent samples, those with the lowest round trip times are kept,
+//! which rejects samples delayed by queueing, and the offset is estimated from them.
+//!
+//! Both sides of a connection need to run a `ClockSync`, since it answers the pings of the other side.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::{Event, EventKind, Host, Packet, PacketMode, PeerID};
+
+/// Marks clock sync packets.
+const MAGIC: &[u8] = b"\xffSYN";
+const KIND_PING: u8 = 0;
+const KIND_PONG: u8 = 1;
+/// Magic, kind and three timestamps.
+const PACKET_LEN: usize = 4 + 1 + 3 * 8;
+/// How many recent samples are kept per peer.
+const MAX_SAMPLES: usize = 16;
+
+/// The estimated clock offset to a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockEstimate {
+    /// The microseconds to add to `ClockSync::now` to get the peer's `ClockSync::now`.
+    pub offset: i64,
+    /// How far the actual offset may be from the estimate, i.e. half the best round trip time.
+    pub uncertainty: Duration,
+    /// The number of samples the estimate is based on.
+    pub samples: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    offset: i64,
+    rtt: u64,
+}
+
+fn encode(kind: u8, timestamps: [u64; 3]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(PACKET_LEN);
+    data.extend_from_slice(MAGIC);
+    data.push(kind);
+    for timestamp in &timestamps {
+        data.extend_from_slice(&timestamp.to_be_bytes());
+    }
+    data
+}
+
+fn decode(data: &[u8]) -> Option<(u8, [u64; 3])> {
+    if data.len() != PACKET_LEN || !data.starts_with(MAGIC) {
+        return None;
+    }
+
+    let mut timestamps = [0; 3];
+    for (i, timestamp) in timestamps.iter_mut().enumerate() {
+        let start = MAGIC.len() + 1 + i * 8;
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&data[start..start + 8]);
+        *timestamp = u64::from_be_bytes(bytes);
+    }
+    Some((data[4], timestamps))
+}
+
+/// Estimates the offset of the clocks of a `Host`'s peers relative to the local clock.
+pub struct ClockSync {
+    channel_id: u8,
+    interval: Duration,
+    epoch: Instant,
+    next_ping: Instant,
+    peers: HashMap<PeerID, VecDeque<Sample>>,
+}
+
+impl ClockSync {
+    /// Creates a `ClockSync` pinging every `interval` on `channel_id`, which it uses exclusively.
+    pub fn new(channel_id: u8, interval: Duration) -> ClockSync {
+        let now = Instant::now();
+        ClockSync {
+            channel_id,
+            interval,
+            epoch: now,
+            next_ping: now,
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Returns the local clock that offsets refer to, in microseconds since this `ClockSync` was created.
+    pub fn now(&self) -> u64 {
+        self.epoch.elapsed().as_micros() as u64
+    }
+
+    /// Returns the clock offset estimated for `peer_id`, once at least one ping was answered.
+    pub fn estimate(&self, peer_id: PeerID) -> Option<ClockEstimate> {
+        let samples = self.peers.get(&peer_id)?;
+        if samples.is_empty() {
+            return None;
+        }
+
+        // Delays only ever add to the round trip time, so the fastest samples are the most accurate.
+        let mut best: Vec<_> = samples.iter().cloned().collect();
+        best.sort_by_key(|sample| sample.rtt);
+        best.truncate((best.len() + 1) / 2);
+
+        let mut offsets: Vec<_> = best.iter().map(|sample| sample.offset).collect();
+        offsets.sort_unstable();
+
+        Some(ClockEstimate {
+            offset: offsets[offsets.len() / 2],
+            uncertainty: Duration::from_micros(best[0].rtt / 2),
+            samples: samples.len(),
+        })
+    }
+
+    /// Processes an event returned by `Host::service`.
+    ///
+    /// Returns `true` if the event was a clock sync packet, which needs no further handling.
+    /// Connected peers are pinged from then on, and disconnected ones are forgotten.
+    pub fn handle_event<T>(&mut self, host: &mut Host<T>, event: &Event) -> bool {
+        match event.kind {
+            EventKind::Connect => {
+                self.peers.entry(event.peer_id).or_default();
+                false
+            }
+            EventKind::Receive {
+                channel_id,
+                ref packet,
+            } if channel_id == self.channel_id => {
+                let now = self.now();
+                match decode(packet.data()) {
+                    Some((KIND_PING, [sent, _, _])) => {
+                        self.peers.entry(event.peer_id).or_default();
+                        self.send(host, event.peer_id, KIND_PONG, [sent, now, self.now()]);
+                    }
+                    Some((KIND_PONG, [sent, received, answered])) => {
+                        self.add_sample(event.peer_id, sent, received, answered, now);
+                    }
+                    _ => (),
+                }
+                true
+            }
+            ref kind if kind.is_disconnect() => {
+                self.peers.remove(&event.peer_id);
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Pings all known peers, if the interval has passed since the last pings.
+    ///
+    /// Call this regularly, e.g. after every `Host::service`.
+    pub fn poll<T>(&mut self, host: &mut Host<T>) {
+        if Instant::now() < self.next_ping {
+            return;
+        }
+        self.next_ping = Instant::now() + self.interval;
+
+        let peers: Vec<_> = self.peers.keys().cloned().collect();
+        for peer_id in peers {
+            let now = self.now();
+            self.send(host, peer_id, KIND_PING, [now, 0, 0]);
+        }
+    }
+
+    fn send<T>(&self, host: &mut Host<T>, peer_id: PeerID, kind: u8, timestamps: [u64; 3]) {
+        let packet = Packet::new(encode(kind, timestamps), PacketMode::UnreliableUnsequenced);
+        if let (Ok(packet), Some(peer)) = (packet, host.peer_mut(peer_id)) {
+            // A lost ping only means one sample less.
+            let _ = peer.send_packet(packet, self.channel_id);
+        }
+    }
+
+    fn add_sample(&mut self, peer_id: PeerID, sent: u64, received: u64, answered: u64, now: u64) {
+        let samples = match self.peers.get_mut(&peer_id) {
+            Some(samples) => samples,
+            None => return,
+        };
+
+        // Timestamps that don't add up are from a peer that misbehaves, or a corrupted packet.
+        let (rtt, processing) = match (now.checked_sub(sent), answered.checked_sub(received)) {
+            (Some(rtt), Some(processing)) if processing <= rtt => (rtt, processing),
+            _ => return,
+        };
+
+        let offset = ((received as i64 - sent as i64) + (answered as i64 - now as i64)) / 2;
+        samples.push_back(Sample {
+            offset,
+            rtt: rtt - processing,
+        });
+        if samples.len() > MAX_SAMPLES {
+            samples.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ClockSync;
+    use crate::tests::create_host;
+    use crate::Address;
+
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    #[test]
+    fn test_offset_estimate() {
+        let server_address = Address::new(Ipv4Addr::LOCALHOST, 12362);
+        let mut server = create_host(Some(&server_address), 1);
+        let mut client = create_host(None, 1);
+
+        // The server's clock starts 50ms later, so it is 50ms behind the client's.
+        let interval = Duration::from_millis(10);
+        let mut client_sync = ClockSync::new(1, interval);
+        std::thread::sleep(Duration::from_millis(50));
+        let mut server_sync = ClockSync::new(1, interval);
+
+        let (_, peer_id) = client.connect(&server_address, 2, 0).unwrap();
+        let timeout = Some(Duration::from_millis(2));
+        for _ in 0..1000 {
+            if let Some(event) = client.service(timeout).unwrap() {
+                client_sync.handle_event(&mut client, &event);
+            }
+            if let Some(event) = server.service(timeout).unwrap() {
+                server_sync.handle_event(&mut server, &event);
+            }
+            client_sync.poll(&mut client);
+            server_sync.poll(&mut server);
+
+            if client_sync
+                .estimate(peer_id)
+                .is_some_and(|estimate| estimate.samples >= 8)
+            {
+                break;
+            }
+        }
+
+        let estimate = client_sync.estimate(peer_id).unwrap();
+        assert!(
+            (estimate.offset + 50_000).abs() < 10_000,
+            "unexpected offset {:?}",
+            estimate
+        );
+        assert!(estimate.uncertainty < Duration::from_millis(10));
+    }
+}
@@ -0,0 +1,48 @@
+use std::ops::AddAssign;
+
+/// The packets sent and received on one channel, see `Host::channel_traffic`.
+///
+/// Counts the data of the packets, not the headers ENet adds, and only packets that ENet accepted:
+/// packets rejected by a budget or a queue limit are not counted as sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ChannelTraffic {
+    /// The bytes queued to be sent.
+    pub sent_bytes: u64,
+    /// The packets queued to be sent.
+    pub sent_packets: u64,
+    /// The bytes received, including packets dropped afterwards by a rate limit.
+    pub received_bytes: u64,
+    /// The packets received, including packets dropped afterwards by a rate limit.
+    pub received_packets: u64,
+}
+
+impl ChannelTraffic {
+    pub(crate) fn record_sent(&mut self, bytes: usize) {
+        self.sent_bytes += bytes as u64;
+        self.sent_packets += 1;
+    }
+
+    pub(crate) fn record_received(&mut self, bytes: usize) {
+        self.received_bytes += bytes as u64;
+        self.received_packets += 1;
+    }
+}
+
+impl AddAssign for ChannelTraffic {
+    fn add_assign(&mut self, other: ChannelTraffic) {
+        self.sent_bytes += other.sent_bytes;
+        self.sent_packets += other.sent_packets;
+        self.received_bytes += other.received_bytes;
+        self.received_packets += other.received_packets;
+    }
+}
+
+/// Adds the traffic of every channel in `traffic` to the same channel in `totals`.
+pub(crate) fn add_traffic(totals: &mut Vec<ChannelTraffic>, traffic: &[ChannelTraffic]) {
+    if totals.len() < traffic.len() {
+        totals.resize(traffic.len(), ChannelTraffic::default());
+    }
+    for (total, &channel) in totals.iter_mut().zip(traffic) {
+        *total += channel;
+    }
+}
@@ -0,0 +1,618 @@
+//! Transfers of large byte blobs, such as maps, replays or mod files.
+//!
+//! A blob is offered to a peer with `Transfers::send`, the peer accepts or rejects the offer,
+//! and the blob is then sent in chunks over a dedicated channel. Chunks are only queued while
+//! ENet's queue for the peer is short, so a transfer doesn't delay other traffic for long.
+//!
+//! Interrupted incoming transfers are kept, and resumed when the same blob (by name and size)
+//! is offered again, e.g. after a reconnect. Progress is reported to a callback on both ends.
+
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto;
+
+use crate::{Event, EventKind, Host, Packet, PacketMode, PeerID, PeerState, SendError};
+
+/// Marks transfer packets.
+const MAGIC: &[u8] = b"\xffXFR";
+const KIND_OFFER: u8 = 0;
+const KIND_ACCEPT: u8 = 1;
+const KIND_REJECT: u8 = 2;
+const KIND_CHUNK: u8 = 3;
+const KIND_CANCEL: u8 = 4;
+
+/// The amount of blob data per chunk.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// No more chunks are queued while this many commands are waiting to be sent to the peer.
+const MAX_QUEUED_COMMANDS: usize = 32;
+
+/// Identifies a transfer, together with the peer it is exchanged with.
+pub type TransferId = u32;
+
+/// Whether a transfer is sent or received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// The blob is sent to the peer.
+    Outgoing,
+    /// The blob is received from the peer.
+    Incoming,
+}
+
+/// Why a transfer did not complete.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransferError {
+    /// The receiving side rejected the offer.
+    Rejected,
+    /// One of the sides cancelled the transfer.
+    Cancelled,
+    /// The peer disconnected.
+    Disconnected,
+    /// The peer sent data that does not fit the transfer.
+    Protocol,
+}
+
+/// The progress of a transfer, as reported to the callback set with `Transfers::on_progress`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Progress {
+    /// The peer the blob is exchanged with.
+    pub peer_id: PeerID,
+    /// The transfer.
+    pub id: TransferId,
+    /// Whether the blob is sent or received.
+    pub direction: Direction,
+    /// The number of bytes sent or received so far, including resumed ones.
+    pub transferred: u64,
+    /// The size of the blob.
+    pub size: u64,
+}
+
+/// Something that happened to a transfer, see `Transfers::next_event`.
+#[derive(Debug)]
+pub enum TransferEvent {
+    /// The peer offers a blob. Answer with `Transfers::accept` or `Transfers::reject`.
+    Offered {
+        /// The offering peer.
+        peer_id: PeerID,
+        /// The offered transfer.
+        id: TransferId,
+        /// The name of the blob.
+        name: String,
+        /// The size of the blob.
+        size: u64,
+    },
+    /// A blob was received completely.
+    Received {
+        /// The sending peer.
+        peer_id: PeerID,
+        /// The completed transfer.
+        id: TransferId,
+        /// The name of the blob.
+        name: String,
+        /// The blob.
+        data: Vec<u8>,
+    },
+    /// A blob was queued completely. ENet delivers it reliably, unless the peer disconnects.
+    Sent {
+        /// The receiving peer.
+        peer_id: PeerID,
+        /// The completed transfer.
+        id: TransferId,
+    },
+    /// A transfer did not complete.
+    Failed {
+        /// The peer the blob was exchanged with.
+        peer_id: PeerID,
+        /// The failed transfer.
+        id: TransferId,
+        /// Whether the blob was sent or received.
+        direction: Direction,
+        /// Why the transfer failed.
+        error: TransferError,
+    },
+}
+
+struct Outgoing {
+    data: Vec<u8>,
+    /// The offset of the next chunk, `None` until the offer was accepted.
+    offset: Option<usize>,
+}
+
+struct Incoming {
+    name: String,
+    size: u64,
+    data: Vec<u8>,
+    accepted: bool,
+}
+
+fn header(kind: u8, id: TransferId) -> Vec<u8> {
+    let mut data = MAGIC.to_vec();
+    data.push(kind);
+    data.extend_from_slice(&id.to_be_bytes());
+    data
+}
+
+fn read_u64(data: &[u8]) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(..8)?.try_into().ok()?))
+}
+
+type ProgressCallback = Box<dyn FnMut(&Progress)>;
+
+/// The transfers of a `Host`, in both directions.
+pub struct Transfers {
+    channel_id: u8,
+    next_id: TransferId,
+    outgoing: HashMap<(PeerID, TransferId), Outgoing>,
+    incoming: HashMap<(PeerID, TransferId), Incoming>,
+    /// Incoming transfers that were interrupted, by name and size of their blob.
+    interrupted: HashMap<(String, u64), Vec<u8>>,
+    events: VecDeque<TransferEvent>,
+    on_progress: Option<ProgressCallback>,
+}
+
+impl Transfers {
+    /// Creates a new `Transfers`, using channel `channel_id` exclusively.
+    pub fn new(channel_id: u8) -> Transfers {
+        Transfers {
+            channel_id,
+            next_id: 0,
+            outgoing: HashMap::new(),
+            incoming: HashMap::new(),
+            interrupted: HashMap::new(),
+            events: VecDeque::new(),
+            on_progress: None,
+        }
+    }
+
+    /// Sets the callback that is invoked whenever a transfer progresses, in either direction.
+    pub fn on_progress<F: FnMut(&Progress) + 'static>(&mut self, callback: F) {
+        self.on_progress = Some(Box::new(callback));
+    }
+
+    /// Offers the blob `data` named `name` to a peer.
+    pub fn send<T>(
+        &mut self,
+        host: &mut Host<T>,
+        peer_id: PeerID,
+        name: &str,
+        data: Vec<u8>,
+    ) -> Result<TransferId, SendError> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        let mut offer = header(KIND_OFFER, id);
+        offer.extend_from_slice(&(data.len() as u64).to_be_bytes());
+        offer.extend_from_slice(name.as_bytes());
+        self.send_packet(host, peer_id, offer)?;
+
+        self.outgoing
+            .insert((peer_id, id), Outgoing { data, offset: None });
+        Ok(id)
+    }
+
+    /// Accepts an offered transfer, resuming it if the same blob was partially received before.
+    pub fn accept<T>(
+        &mut self,
+        host: &mut Host<T>,
+        peer_id: PeerID,
+        id: TransferId,
+    ) -> Result<(), SendError> {
+        let partial = match self.incoming.get(&(peer_id, id)) {
+            Some(transfer) => self
+                .interrupted
+                .remove(&(transfer.name.clone(), transfer.size))
+                .unwrap_or_default(),
+            None => return Ok(()),
+        };
+
+        self.accept_from(host, peer_id, id, partial)
+    }
+
+    /// Accepts an offered transfer, resuming after the already received `partial` data.
+    ///
+    /// Use this to resume with data that was persisted elsewhere, see `Transfers::take_interrupted`.
+    pub fn accept_from<T>(
+        &mut self,
+        host: &mut Host<T>,
+        peer_id: PeerID,
+        id: TransferId,
+        partial: Vec<u8>,
+    ) -> Result<(), SendError> {
+        let transfer = match self.incoming.get_mut(&(peer_id, id)) {
+            Some(transfer) if !transfer.accepted => transfer,
+            _ => return Ok(()),
+        };
+
+        let mut accept = header(KIND_ACCEPT, id);
+        accept.extend_from_slice(&(partial.len() as u64).to_be_bytes());
+        transfer.data = partial;
+        transfer.accepted = true;
+
+        self.send_packet(host, peer_id, accept)
+    }
+
+    /// Rejects an offered transfer.
+    pub fn reject<T>(
+        &mut self,
+        host: &mut Host<T>,
+        peer_id: PeerID,
+        id: TransferId,
+    ) -> Result<(), SendError> {
+        if self.incoming.remove(&(peer_id, id)).is_none() {
+            return Ok(());
+        }
+
+        self.send_packet(host, peer_id, header(KIND_REJECT, id))
+    }
+
+    /// Cancels a transfer in either direction. Incoming data received so far is discarded.
+    pub fn cancel<T>(
+        &mut self,
+        host: &mut Host<T>,
+        peer_id: PeerID,
+        id: TransferId,
+        direction: Direction,
+    ) -> Result<(), SendError> {
+        let known = match direction {
+            Direction::Outgoing => self.outgoing.remove(&(peer_id, id)).is_some(),
+            Direction::Incoming => self.incoming.remove(&(peer_id, id)).is_some(),
+        };
+        if !known {
+            return Ok(());
+        }
+
+        let mut cancel = header(KIND_CANCEL, id);
+        cancel.push(direction as u8);
+        self.send_packet(host, peer_id, cancel)
+    }
+
+    /// Removes and returns the data of an interrupted incoming transfer, e.g. to persist it.
+    pub fn take_interrupted(&mut self, name: &str, size: u64) -> Option<Vec<u8>> {
+        self.interrupted.remove(&(name.to_string(), size))
+    }
+
+    /// Returns the next thing that happened to a transfer, if any.
+    pub fn next_event(&mut self) -> Option<TransferEvent> {
+        self.events.pop_front()
+    }
+
+    /// Processes an event returned by `Host::service`.
+    ///
+    /// Returns `true` if the event belonged to a transfer, which needs no further handling.
+    pub fn handle_event<T>(&mut self, host: &mut Host<T>, event: &Event) -> bool {
+        let consumed = match event.kind {
+            EventKind::Receive {
+                channel_id,
+                ref packet,
+            } if channel_id == self.channel_id && packet.data().starts_with(MAGIC) => {
+                self.handle_packet(host, event.peer_id, &packet.data()[MAGIC.len()..]);
+                true
+            }
+            ref kind if kind.is_disconnect() => {
+                self.peer_disconnected(event.peer_id);
+                false
+            }
+            _ => false,
+        };
+
+        self.poll(host);
+        consumed
+    }
+
+    /// Queues chunks of accepted outgoing transfers, as far as flow control allows.
+    ///
+    /// `Transfers::handle_event` does this as well, but this should also be called after each
+    /// `Host::service` that returned no event.
+    pub fn poll<T>(&mut self, host: &mut Host<T>) {
+        let channel_id = self.channel_id;
+        let mut finished = Vec::new();
+
+        for (&(peer_id, id), transfer) in &mut self.outgoing {
+            let mut offset = match transfer.offset {
+                Some(offset) => offset,
+                None => continue,
+            };
+
+            let peer = match host.peer_mut(peer_id) {
+                Some(peer) => peer,
+                None => continue,
+            };
+
+            while offset < transfer.data.len()
+                && peer.queued_reliable_commands() < MAX_QUEUED_COMMANDS
+            {
+                let end = (offset + CHUNK_SIZE).min(transfer.data.len());
+                let mut chunk = header(KIND_CHUNK, id);
+                chunk.extend_from_slice(&(offset as u64).to_be_bytes());
+                chunk.extend_from_slice(&transfer.data[offset..end]);
+
+                let sent = Packet::new(chunk, PacketMode::ReliableSequenced)
+                    .map_err(|e| SendError::Error(e.0))
+                    .and_then(|packet| peer.send_packet(packet, channel_id));
+                if sent.is_err() {
+                    break;
+                }
+
+                offset = end;
+                if let Some(callback) = self.on_progress.as_mut() {
+                    callback(&Progress {
+                        peer_id,
+                        id,
+                        direction: Direction::Outgoing,
+                        transferred: offset as u64,
+                        size: transfer.data.len() as u64,
+                    });
+                }
+            }
+
+            transfer.offset = Some(offset);
+            if offset >= transfer.data.len() {
+                finished.push((peer_id, id));
+            }
+        }
+
+        for (peer_id, id) in finished {
+            self.outgoing.remove(&(peer_id, id));
+            self.events.push_back(TransferEvent::Sent { peer_id, id });
+        }
+    }
+
+    fn send_packet<T>(
+        &self,
+        host: &mut Host<T>,
+        peer_id: PeerID,
+        data: Vec<u8>,
+    ) -> Result<(), SendError> {
+        let packet =
+            Packet::new(data, PacketMode::ReliableSequenced).map_err(|e| SendError::Error(e.0))?;
+        match host.peer_mut(peer_id) {
+            Some(peer) => peer.send_packet(packet, self.channel_id),
+            None => Err(SendError::NotConnected(PeerState::Disconnected)),
+        }
+    }
+
+    fn fail(
+        &mut self,
+        peer_id: PeerID,
+        id: TransferId,
+        direction: Direction,
+        error: TransferError,
+    ) {
+        self.events.push_back(TransferEvent::Failed {
+            peer_id,
+            id,
+            direction,
+            error,
+        });
+    }
+
+    fn peer_disconnected(&mut self, peer_id: PeerID) {
+        let outgoing: Vec<_> = self
+            .outgoing
+            .keys()
+            .filter(|key| key.0 == peer_id)
+            .cloned()
+            .collect();
+        for key in outgoing {
+            self.outgoing.remove(&key);
+            self.fail(
+                peer_id,
+                key.1,
+                Direction::Outgoing,
+                TransferError::Disconnected,
+            );
+        }
+
+        let incoming: Vec<_> = self
+            .incoming
+            .keys()
+            .filter(|key| key.0 == peer_id)
+            .cloned()
+            .collect();
+        for key in incoming {
+            let transfer = self.incoming.remove(&key).unwrap();
+            if transfer.accepted && !transfer.data.is_empty() {
+                self.interrupted
+                    .insert((transfer.name, transfer.size), transfer.data);
+            }
+            self.fail(
+                peer_id,
+                key.1,
+                Direction::Incoming,
+                TransferError::Disconnected,
+            );
+        }
+    }
+
+    fn handle_packet<T>(&mut self, host: &mut Host<T>, peer_id: PeerID, data: &[u8]) {
+        let (kind, id, body) = match (data.first(), data.get(1..5), data.get(5..)) {
+            (Some(&kind), Some(id), Some(body)) => {
+                (kind, u32::from_be_bytes(id.try_into().unwrap()), body)
+            }
+            _ => return,
+        };
+        let key = (peer_id, id);
+
+        match kind {
+            KIND_OFFER => {
+                if let Some(size) = read_u64(body) {
+                    let name = String::from_utf8_lossy(&body[8..]).into_owned();
+                    self.incoming.insert(
+                        key,
+                        Incoming {
+                            name: name.clone(),
+                            size,
+                            data: Vec::new(),
+                            accepted: false,
+                        },
+                    );
+                    self.events.push_back(TransferEvent::Offered {
+                        peer_id,
+                        id,
+                        name,
+                        size,
+                    });
+                }
+            }
+            KIND_ACCEPT => {
+                let transfer = match self.outgoing.get_mut(&key) {
+                    Some(transfer) => transfer,
+                    None => return,
+                };
+
+                match read_u64(body) {
+                    Some(offset) if offset <= transfer.data.len() as u64 => {
+                        transfer.offset = Some(offset as usize);
+                    }
+                    _ => {
+                        self.outgoing.remove(&key);
+                        self.fail(peer_id, id, Direction::Outgoing, TransferError::Protocol);
+                    }
+                }
+            }
+            KIND_REJECT if self.outgoing.remove(&key).is_some() => {
+                self.fail(peer_id, id, Direction::Outgoing, TransferError::Rejected);
+            }
+            KIND_CANCEL => {
+                // The direction is the sender's, so an outgoing transfer there is incoming here.
+                let removed = match body.first() {
+                    Some(&d) if d == Direction::Outgoing as u8 => {
+                        self.incoming.remove(&key).map(|_| Direction::Incoming)
+                    }
+                    _ => self.outgoing.remove(&key).map(|_| Direction::Outgoing),
+                };
+                if let Some(direction) = removed {
+                    self.fail(peer_id, id, direction, TransferError::Cancelled);
+                }
+            }
+            KIND_CHUNK => self.handle_chunk(host, peer_id, id, body),
+            _ => (),
+        }
+    }
+
+    fn handle_chunk<T>(
+        &mut self,
+        host: &mut Host<T>,
+        peer_id: PeerID,
+        id: TransferId,
+        body: &[u8],
+    ) {
+        let key = (peer_id, id);
+        let transfer = match self.incoming.get_mut(&key) {
+            Some(transfer) if transfer.accepted => transfer,
+            _ => return,
+        };
+
+        let chunk = &body[8.min(body.len())..];
+        let fits = read_u64(body) == Some(transfer.data.len() as u64)
+            && transfer.data.len() as u64 + chunk.len() as u64 <= transfer.size;
+        if !fits {
+            self.incoming.remove(&key);
+            self.fail(peer_id, id, Direction::Incoming, TransferError::Protocol);
+            let mut cancel = header(KIND_CANCEL, id);
+            cancel.push(Direction::Incoming as u8);
+            let _ = self.send_packet(host, peer_id, cancel);
+            return;
+        }
+
+        transfer.data.extend_from_slice(chunk);
+        let (transferred, size) = (transfer.data.len() as u64, transfer.size);
+        if let Some(callback) = self.on_progress.as_mut() {
+            callback(&Progress {
+                peer_id,
+                id,
+                direction: Direction::Incoming,
+                transferred,
+                size,
+            });
+        }
+
+        if transferred == size {
+            let transfer = self.incoming.remove(&key).unwrap();
+            self.events.push_back(TransferEvent::Received {
+                peer_id,
+                id,
+                name: transfer.name,
+                data: transfer.data,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Direction, TransferEvent, Transfers};
+    use crate::tests::connected_pair;
+    use crate::{PeerID, PeerState, SendError};
+
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_blob_transfer() {
+        let (mut server, mut client, _, server_peer) = connected_pair(12357, 2);
+
+        let blob: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        let mut sender = Transfers::new(1);
+        let mut receiver = Transfers::new(1);
+        let progress = Rc::new(Cell::new(0));
+        let reported = progress.clone();
+        receiver.on_progress(move |p| {
+            assert_eq!(p.direction, Direction::Incoming);
+            reported.set(p.transferred);
+        });
+
+        sender
+            .send(&mut client, server_peer, "map.bin", blob.clone())
+            .unwrap();
+        let timeout = Some(Duration::from_millis(2));
+        let mut received = None;
+        for _ in 0..2000 {
+            if let Some(event) = client.service(timeout).unwrap() {
+                sender.handle_event(&mut client, &event);
+            }
+            sender.poll(&mut client);
+
+            if let Some(event) = server.service(timeout).unwrap() {
+                receiver.handle_event(&mut server, &event);
+            }
+            match receiver.next_event() {
+                Some(TransferEvent::Offered {
+                    peer_id, id, size, ..
+                }) => {
+                    assert_eq!(size, blob.len() as u64);
+                    receiver.accept(&mut server, peer_id, id).unwrap();
+                }
+                Some(TransferEvent::Received { name, data, .. }) => {
+                    received = Some((name, data));
+                    break;
+                }
+                _ => (),
+            }
+        }
+
+        let (name, data) = received.expect("transfer did not complete");
+        assert_eq!(name, "map.bin");
+        assert!(data == blob);
+        assert_eq!(progress.get(), blob.len() as u64);
+        assert!(matches!(
+            sender.next_event(),
+            Some(TransferEvent::Sent { .. })
+        ));
+    }
+
+    #[test]
+    fn test_unknown_peer() {
+        let (_server, mut client, _, server_peer) = connected_pair(12427, 1);
+        let unknown = PeerID {
+            index: 1,
+            ..server_peer
+        };
+
+        let mut transfers = Transfers::new(0);
+        assert!(matches!(
+            transfers.send(&mut client, unknown, "blob", vec![1; 10]),
+            Err(SendError::NotConnected(PeerState::Disconnected))
+        ));
+    }
+}
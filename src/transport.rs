@@ -0,0 +1,288 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::time::{Duration, Instant};
+
+use enet_sys::ENET_PROTOCOL_MAXIMUM_MTU;
+
+use crate::Address;
+
+/// How long a link may wait for its peer to connect, matching ENet's longest peer timeout.
+const LINK_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a link may go without datagrams from its endpoint before it is dropped.
+const LINK_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// An unreliable datagram transport, e.g. a UDP socket or a platform-specific network API.
+///
+/// This is the boundary between ENet traffic and the network: an implementation only has to
+/// move whole datagrams to and from remote endpoints. The trait only uses `core` types, so
+/// implementations for platforms without std sockets can provide their own `Endpoint` and `Error`.
+pub trait Transport {
+    /// Identifies a remote endpoint, such as a socket address.
+    type Endpoint: Clone + Eq + Hash + Debug;
+    /// The error type of the transport's operations.
+    type Error: Debug;
+
+    /// Sends one datagram containing `data` to `endpoint`.
+    ///
+    /// Datagrams may be dropped, so an implementation can choose to silently drop datagrams it cannot send right away.
+    fn send_to(&mut self, data: &[u8], endpoint: &Self::Endpoint) -> Result<(), Self::Error>;
+
+    /// Receives one datagram into `buffer`, without blocking.
+    ///
+    /// Returns the length of the datagram and its origin, or `None` if no datagram is available.
+    /// Datagrams that do not fit into `buffer` may be truncated.
+    fn recv_from(
+        &mut self,
+        buffer: &mut [u8],
+    ) -> Result<Option<(usize, Self::Endpoint)>, Self::Error>;
+}
+
+/// Uses a std `UdpSocket` as transport. The socket is expected to be in non-blocking mode.
+impl Transport for UdpSocket {
+    type Endpoint = SocketAddr;
+    type Error = io::Error;
+
+    fn send_to(&mut self, data: &[u8], endpoint: &SocketAddr) -> io::Result<()> {
+        match UdpSocket::send_to(self, data, endpoint) {
+            Ok(_) => Ok(()),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn recv_from(&mut self, buffer: &mut [u8]) -> io::Result<Option<(usize, SocketAddr)>> {
+        match UdpSocket::recv_from(self, buffer) {
+            Ok(received) => Ok(Some(received)),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Relays the traffic of a `Host` over a `Transport`.
+///
+/// ENet itself always talks UDP on its own socket. A `Host` created with
+/// [Enet::create_host_with_transport](struct.Enet.html#method.create_host_with_transport) listens on
+/// a loopback address instead, and every remote endpoint of the transport is represented by a
+/// loopback address of its own. This way ENet sees each endpoint as a separate peer, while the
+/// bridge moves the datagrams between ENet and the transport whenever the `Host` is serviced or flushed.
+///
+/// Every loopback address is backed by a socket, so the bridge keeps at most as many of them as
+/// the host has peers, see `TransportBridge::set_link_limit`. Datagrams from further endpoints are
+/// dropped. Addresses whose peer didn't connect within 30 seconds, or whose endpoint has been
+/// silent for two minutes, are given up.
+///
+/// Retrieve the bridge of a host with [Host::transport](struct.Host.html#method.transport).
+#[derive(Debug)]
+pub struct TransportBridge<Tr: Transport> {
+    transport: Tr,
+    host_address: SocketAddr,
+    links: HashMap<Tr::Endpoint, Link>,
+    endpoints: HashMap<SocketAddr, Tr::Endpoint>,
+    link_limit: usize,
+    buffer: Vec<u8>,
+    error: Option<Tr::Error>,
+}
+
+/// The loopback socket that represents an endpoint to ENet.
+#[derive(Debug)]
+struct Link {
+    socket: UdpSocket,
+    created: Instant,
+    last_received: Instant,
+    /// Whether ENet connected a peer through this link.
+    established: bool,
+}
+
+impl Link {
+    fn is_expired(&self, now: Instant) -> bool {
+        (!self.established && now.duration_since(self.created) >= LINK_HANDSHAKE_TIMEOUT)
+            || now.duration_since(self.last_received) >= LINK_IDLE_TIMEOUT
+    }
+}
+
+impl<Tr: Transport> TransportBridge<Tr> {
+    pub(crate) fn new(
+        transport: Tr,
+        host_address: &Address,
+        link_limit: usize,
+    ) -> TransportBridge<Tr> {
+        TransportBridge {
+            transport,
+            host_address: socket_addr(host_address),
+            links: HashMap::new(),
+            endpoints: HashMap::new(),
+            link_limit,
+            buffer: vec![0; ENET_PROTOCOL_MAXIMUM_MTU as usize],
+            error: None,
+        }
+    }
+
+    /// Returns a reference to the underlying transport.
+    pub fn transport(&self) -> &Tr {
+        &self.transport
+    }
+
+    /// Returns a mutable reference to the underlying transport.
+    pub fn transport_mut(&mut self) -> &mut Tr {
+        &mut self.transport
+    }
+
+    /// Returns the address that represents `endpoint` to ENet, e.g. to pass to `Host::connect`.
+    pub fn address_of(&mut self, endpoint: &Tr::Endpoint) -> io::Result<Address> {
+        let link = self.link(endpoint)?;
+        match link.local_addr()? {
+            SocketAddr::V4(addr) => Ok(Address::from(addr)),
+            SocketAddr::V6(_) => unreachable!("links are bound to IPv4 loopback"),
+        }
+    }
+
+    /// Returns the endpoint represented by `address`, e.g. the result of `Peer::address`.
+    pub fn endpoint_of(&self, address: &Address) -> Option<&Tr::Endpoint> {
+        self.endpoints.get(&socket_addr(address))
+    }
+
+    /// Sets how many endpoints the bridge represents at once, by default the peer count of the host.
+    pub fn set_link_limit(&mut self, limit: usize) {
+        self.link_limit = limit;
+    }
+
+    /// Returns the number of endpoints the bridge currently represents.
+    pub fn link_count(&self) -> usize {
+        self.links.len()
+    }
+
+    /// Returns the most recent error of the transport since the last call, if any.
+    ///
+    /// Transport errors do not make `Host::service` fail, since ENet recovers from lost datagrams anyway.
+    pub fn take_error(&mut self) -> Option<Tr::Error> {
+        self.error.take()
+    }
+
+    fn link(&mut self, endpoint: &Tr::Endpoint) -> io::Result<&UdpSocket> {
+        if !self.links.contains_key(endpoint) {
+            if self.links.len() >= self.link_limit {
+                self.expire_links(Instant::now());
+            }
+            if self.links.len() >= self.link_limit {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "the transport bridge is at its link limit",
+                ));
+            }
+
+            let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+            socket.set_nonblocking(true)?;
+            self.endpoints
+                .insert(socket.local_addr()?, endpoint.clone());
+            let now = Instant::now();
+            let link = Link {
+                socket,
+                created: now,
+                last_received: now,
+                established: false,
+            };
+            self.links.insert(endpoint.clone(), link);
+        }
+
+        Ok(&self.links[endpoint].socket)
+    }
+
+    fn expire_links(&mut self, now: Instant) {
+        let endpoints = &mut self.endpoints;
+        self.links.retain(|_, link| {
+            let expired = link.is_expired(now);
+            if expired {
+                if let Ok(address) = link.socket.local_addr() {
+                    endpoints.remove(&address);
+                }
+            }
+            !expired
+        });
+    }
+
+    fn forward_incoming(&mut self) {
+        loop {
+            let (len, endpoint) = match self.transport.recv_from(&mut self.buffer) {
+                Ok(Some(received)) => received,
+                Ok(None) => return,
+                Err(e) => {
+                    self.error = Some(e);
+                    return;
+                }
+            };
+
+            if self.link(&endpoint).is_ok() {
+                let link = self.links.get_mut(&endpoint).unwrap();
+                link.last_received = Instant::now();
+                let _ = link.socket.send_to(&self.buffer[..len], self.host_address);
+            }
+        }
+    }
+
+    fn forward_outgoing(&mut self) {
+        for (endpoint, link) in &self.links {
+            while let Ok((len, from)) = link.socket.recv_from(&mut self.buffer) {
+                // Only relay what ENet sent, anything else reaching a link is stray traffic.
+                if from != self.host_address {
+                    continue;
+                }
+
+                if let Err(e) = self.transport.send_to(&self.buffer[..len], endpoint) {
+                    self.error = Some(e);
+                }
+            }
+        }
+    }
+}
+
+/// The type-erased interface through which a `Host` drives its `TransportBridge`.
+pub(crate) trait Bridge {
+    /// Moves all pending datagrams in both directions.
+    fn pump(&mut self);
+    /// Marks the endpoint represented by `address` as connected, so it no longer expires as a
+    /// stuck handshake.
+    fn establish(&mut self, address: &Address);
+    /// Forgets the endpoint represented by `address`, after its peer disconnected.
+    fn release(&mut self, address: &Address);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<Tr: Transport + 'static> Bridge for TransportBridge<Tr> {
+    fn pump(&mut self) {
+        self.forward_incoming();
+        self.forward_outgoing();
+        self.expire_links(Instant::now());
+    }
+
+    fn establish(&mut self, address: &Address) {
+        if let Some(endpoint) = self.endpoints.get(&socket_addr(address)) {
+            if let Some(link) = self.links.get_mut(endpoint) {
+                link.established = true;
+            }
+        }
+    }
+
+    fn release(&mut self, address: &Address) {
+        if let Some(endpoint) = self.endpoints.remove(&socket_addr(address)) {
+            self.links.remove(&endpoint);
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+fn socket_addr(address: &Address) -> SocketAddr {
+    SocketAddr::V4(SocketAddrV4::new(*address.ip(), address.port()))
+}
@@ -0,0 +1,118 @@
+use std::fmt::{self, Display, Formatter};
+
+use enet_sys::{enet_linked_version, ENET_VERSION_MAJOR, ENET_VERSION_MINOR, ENET_VERSION_PATCH};
+
+use crate::EnetVersion;
+
+/// The version of the ENet headers this crate was compiled against, in ENet's packed representation.
+///
+/// Compare with [linked_version](fn.linked_version.html) to find out which library is actually in use.
+pub const ENET_VERSION: EnetVersion =
+    (ENET_VERSION_MAJOR << 16) | (ENET_VERSION_MINOR << 8) | ENET_VERSION_PATCH;
+
+/// Returns the version of the linked ENet library.
+pub fn linked_version() -> EnetVersion {
+    unsafe { enet_linked_version() }
+}
+
+/// An ENet version, split into its components.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    /// The major version.
+    pub major: u8,
+    /// The minor version.
+    pub minor: u8,
+    /// The patch version.
+    pub patch: u8,
+}
+
+impl Version {
+    /// Creates a new `Version` from its components.
+    pub const fn new(major: u8, minor: u8, patch: u8) -> Version {
+        Version {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Returns the version of the ENet headers this crate was compiled against.
+    pub fn compiled() -> Version {
+        Version::from_enet_version(ENET_VERSION)
+    }
+
+    /// Returns the version of the linked ENet library.
+    pub fn linked() -> Version {
+        Version::from_enet_version(linked_version())
+    }
+
+    /// Splits ENet's packed version representation into its components.
+    pub fn from_enet_version(version: EnetVersion) -> Version {
+        Version::new((version >> 16) as u8, (version >> 8) as u8, version as u8)
+    }
+
+    /// Returns ENet's packed representation of this version.
+    pub fn to_enet_version(self) -> EnetVersion {
+        (u32::from(self.major) << 16) | (u32::from(self.minor) << 8) | u32::from(self.patch)
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Optional functionality of an ENet library, depending on its version and flavor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Capabilities {
+    /// The built-in range coder compressor is available (ENet 1.3.0).
+    pub range_coder: bool,
+    /// Raw received datagrams can be intercepted before ENet processes them (ENet 1.3.6).
+    pub intercept: bool,
+    /// The number of peers from the same IP can be limited (ENet 1.3.9).
+    pub duplicate_peer_limit: bool,
+    /// Packet sizes and per-peer waiting data can be limited (ENet 1.3.12).
+    pub packet_size_limits: bool,
+    /// Addresses can be IPv6. Only some ENet forks support this, the bundled ENet does not.
+    pub ipv6: bool,
+}
+
+impl Capabilities {
+    /// Returns the capabilities of an upstream ENet library of the given version.
+    pub fn of_version(version: Version) -> Capabilities {
+        Capabilities {
+            range_coder: version >= Version::new(1, 3, 0),
+            intercept: version >= Version::new(1, 3, 6),
+            duplicate_peer_limit: version >= Version::new(1, 3, 9),
+            packet_size_limits: version >= Version::new(1, 3, 12),
+            ipv6: false,
+        }
+    }
+
+    /// Returns the capabilities of the linked ENet library.
+    pub fn linked() -> Capabilities {
+        Capabilities::of_version(Version::linked())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Capabilities, Version, ENET_VERSION};
+
+    #[test]
+    fn test_version_roundtrip() {
+        let version = Version::from_enet_version(ENET_VERSION);
+        assert_eq!(version.to_enet_version(), ENET_VERSION);
+        assert_eq!(Version::new(1, 3, 15).to_string(), "1.3.15");
+        assert_eq!(Version::linked(), Version::compiled());
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let old = Capabilities::of_version(Version::new(1, 3, 8));
+        assert!(old.intercept);
+        assert!(!old.duplicate_peer_limit);
+        assert!(Capabilities::linked().packet_size_limits);
+    }
+}